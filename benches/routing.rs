@@ -0,0 +1,145 @@
+//! The routing hot path at scale: rule matching, backend scoring and whole
+//! decisions, with thousands of rules and backends.
+//!
+//! `cargo bench --bench routing`; compare runs with `-- --save-baseline` and
+//! `--baseline`.
+
+use std::hint::black_box;
+use std::net::{IpAddr, Ipv4Addr};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use gold_dust_gateway::config::{Balance, RoutingConfig, RuleConfig};
+use gold_dust_gateway::health::HealthSource;
+use gold_dust_gateway::matcher::RuleMatcher;
+use gold_dust_gateway::router::{score, BackendHealth, BackendKind, Router};
+use gold_dust_gateway::target::{Host, Target};
+
+const RULES: usize = 5_000;
+const BACKENDS: usize = 2_000;
+
+/// `RULES` host patterns of every kind, roughly in the mix real rule files
+/// have: mostly names, a few networks.
+fn patterns() -> Vec<String> {
+    (0..RULES)
+        .map(|i| match i % 10 {
+            0..=3 => format!("host{i}.example{}.com", i % 97),
+            4..=6 => format!("*.svc{i}.example.net"),
+            7 | 8 => format!(".site{i}.example.org"),
+            _ => format!("10.{}.{}.0/24", (i / 256) % 256, i % 256),
+        })
+        .collect()
+}
+
+fn rules() -> Vec<RuleConfig> {
+    patterns()
+        .into_iter()
+        .enumerate()
+        .map(|(i, host)| {
+            let balance = ["random", "round-robin", "weighted-rr", "p2c"][i % 4];
+            toml::from_str(&format!("host = {host:?}\nbalance = {balance:?}"))
+                .expect("generated rule parses")
+        })
+        .collect()
+}
+
+/// Targets that hit an exact rule, a wildcard, a network rule, and nothing.
+fn targets() -> Vec<(&'static str, Target)> {
+    let domain = |name: &str| Target::new(Host::Domain(name.to_string()), 443);
+    vec![
+        ("exact", domain("host40.example40.com")),
+        ("subdomain", domain("a.b.svc4004.example.net")),
+        (
+            "network",
+            Target::new(Host::Ipv4(Ipv4Addr::new(10, 3, 231, 7)), 443),
+        ),
+        ("miss", domain("deep.nothing.matches.here.invalid")),
+    ]
+}
+
+/// `BACKENDS` backends of every kind, with spread-out health.
+struct Fleet;
+
+impl HealthSource for Fleet {
+    fn snapshot(&mut self) -> Vec<BackendHealth> {
+        let kinds = [BackendKind::Oxen, BackendKind::Tor, BackendKind::Masque];
+        (0..BACKENDS)
+            .map(|i| BackendHealth {
+                name: format!("node{i}"),
+                kind: kinds[i % kinds.len()],
+                latency_ms: 20.0 + (i * 37 % 900) as f64,
+                failure_rate: (i * 13 % 100) as f64 / 400.0,
+                enabled: i % 17 != 0,
+                ipv6: i % 2 == 0,
+            })
+            .collect()
+    }
+}
+
+fn matching(c: &mut Criterion) {
+    let matcher = RuleMatcher::compile(patterns().iter().map(String::as_str)).unwrap();
+    let prioritized = matcher
+        .clone()
+        .with_priorities((0..RULES).map(|i| (i % 5) as i32).collect());
+    let resolved = [IpAddr::V4(Ipv4Addr::new(10, 9, 9, 9))];
+
+    let mut group = c.benchmark_group("matching");
+    group.bench_function("compile", |b| {
+        let patterns = patterns();
+        b.iter(|| RuleMatcher::compile(patterns.iter().map(String::as_str)).unwrap())
+    });
+    for (name, target) in targets() {
+        group.bench_with_input(BenchmarkId::new("first_match", name), &target, |b, t| {
+            b.iter(|| matcher.first_match(black_box(&t.host), &resolved))
+        });
+        group.bench_with_input(BenchmarkId::new("by_priority", name), &target, |b, t| {
+            b.iter(|| prioritized.first_match(black_box(&t.host), &resolved))
+        });
+    }
+    group.finish();
+}
+
+fn scoring(c: &mut Criterion) {
+    let backends = Fleet.snapshot();
+    c.bench_function("scoring/all_backends", |b| {
+        b.iter(|| backends.iter().map(score).fold(0.0, f64::max))
+    });
+}
+
+fn deciding(c: &mut Criterion) {
+    let rules = rules();
+    let mut group = c.benchmark_group("decision");
+    for balance in [
+        Balance::Random,
+        Balance::RoundRobin,
+        Balance::WeightedRr,
+        Balance::WeightedRandom,
+        Balance::P2c,
+    ] {
+        let routing = RoutingConfig {
+            seed: Some(7),
+            balance,
+            ..RoutingConfig::default()
+        };
+        let mut router = Router::from_source(&mut Fleet, &routing);
+        let target = &targets()[3].1;
+        group.bench_function(BenchmarkId::new("balance", balance.as_str()), |b| {
+            b.iter(|| router.choose_backend_for(black_box(target)))
+        });
+    }
+
+    let routing = RoutingConfig {
+        seed: Some(7),
+        ..RoutingConfig::default()
+    };
+    let mut router = Router::from_source(&mut Fleet, &routing);
+    router.set_balance_rules(&rules);
+    for (name, target) in targets() {
+        group.bench_with_input(BenchmarkId::new("with_rules", name), &target, |b, t| {
+            b.iter(|| router.choose_backend_for(black_box(t)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, matching, scoring, deciding);
+criterion_main!(benches);
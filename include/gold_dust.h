@@ -0,0 +1,38 @@
+/* C API for the gold-dust-gateway routing core.
+ *
+ * Build: cargo build --release --features ffi
+ * Link:  -L target/release -lgold_dust_gateway
+ *
+ * Strings returned by gd_* functions are owned by the caller: release them
+ * with gd_string_free. On failure functions return NULL; gd_last_error()
+ * then describes the problem (valid until the next failure on that thread).
+ */
+#ifndef GOLD_DUST_H
+#define GOLD_DUST_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct GdRouter GdRouter;
+
+/* Router from a config TOML path (NULL = built-in demo config). NULL if the
+ * config doesn't load or configures no backends. */
+GdRouter *gd_router_new(const char *config_path);
+void gd_router_free(GdRouter *router);
+
+/* Backend for "host:port", "[v6]:port", "host" (port 443) or an http(s) URL.
+ * JSON: {"backend", "kind", "latency_ms", "failure_rate", "ipv6"} */
+char *gd_router_route(GdRouter *router, const char *target);
+
+/* Backend health as a JSON array. */
+char *gd_router_status_json(const GdRouter *router);
+
+const char *gd_last_error(void);
+void gd_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GOLD_DUST_H */
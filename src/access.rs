@@ -0,0 +1,117 @@
+//! Roles on the dispatcher's HTTP APIs: reading its state is one thing,
+//! changing what it does (switching the egress, feeding it health) another.
+//! A request's role comes from the token it carries, from the local user
+//! behind it, or from its admin certificate.
+
+use std::fmt;
+
+use crate::peer;
+
+/// What a request may do. `Operate` includes `Read`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    /// Status, decisions, events, metrics.
+    Read,
+    /// Anything that changes the dispatcher's behaviour.
+    Operate,
+}
+
+impl Access {
+    /// What a request with this method needs: reads are `GET`s.
+    pub fn needed(method: &str) -> Self {
+        match method {
+            "GET" | "HEAD" => Access::Read,
+            _ => Access::Operate,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Operate => "operate",
+        }
+    }
+}
+
+/// A local user by login name or uid, or every member of a group
+/// (`@wheel`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Principal {
+    User(String),
+    Uid(u32),
+    Group(String),
+}
+
+impl Principal {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        match text.strip_prefix('@') {
+            Some("") => Err("`@` needs a group name".to_string()),
+            Some(group) => Ok(Principal::Group(group.to_string())),
+            None if text.is_empty() => Err("empty user name".to_string()),
+            None => Ok(match text.parse() {
+                Ok(uid) => Principal::Uid(uid),
+                Err(_) => Principal::User(text.to_string()),
+            }),
+        }
+    }
+
+    fn covers(&self, uid: u32, name: Option<&str>, groups: &[String]) -> bool {
+        match self {
+            Principal::User(user) => name == Some(user.as_str()),
+            Principal::Uid(id) => *id == uid,
+            Principal::Group(group) => groups.contains(group),
+        }
+    }
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Principal::User(name) => write!(f, "{name}"),
+            Principal::Uid(uid) => write!(f, "{uid}"),
+            Principal::Group(group) => write!(f, "@{group}"),
+        }
+    }
+}
+
+/// Which local users get which role without a token (`[api] operators`
+/// and `viewers`).
+#[derive(Debug, Clone, Default)]
+pub struct Grants {
+    operators: Vec<Principal>,
+    viewers: Vec<Principal>,
+}
+
+impl Grants {
+    pub fn new(operators: &[String], viewers: &[String]) -> Result<Self, String> {
+        let parse = |list: &[String]| {
+            list.iter()
+                .map(|p| Principal::parse(p).map_err(|e| format!("`{p}`: {e}")))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            operators: parse(operators)?,
+            viewers: parse(viewers)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operators.is_empty() && self.viewers.is_empty()
+    }
+
+    /// The role of local user `uid`, if they have one.
+    pub fn for_uid(&self, uid: u32) -> Option<Access> {
+        let name = peer::user_name(uid);
+        let groups = peer::groups(uid);
+        let listed =
+            |list: &[Principal]| list.iter().any(|p| p.covers(uid, name.as_deref(), &groups));
+        if listed(&self.operators) {
+            Some(Access::Operate)
+        } else if listed(&self.viewers) {
+            Some(Access::Read)
+        } else {
+            None
+        }
+    }
+}
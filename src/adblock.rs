@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use crate::blocklist::{self, SourceStatus};
+use crate::config::DnsBlockConfig;
+
+/// Names hosts files map to themselves rather than block.
+const LOCAL_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "0.0.0.0",
+];
+
+/// One blocklist line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    /// Hosts-format or bare name: that name only.
+    Exact(String),
+    /// `||example.com^`: the name and its subdomains.
+    Domain(String),
+    /// `@@||example.com^`: never blocked, nor its subdomains.
+    Allow(String),
+}
+
+fn is_name(s: &str) -> bool {
+    s.contains('.')
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'))
+}
+
+/// Entries of a hosts-format (`0.0.0.0 ads.example.com`), ABP-style
+/// (`||ads.example.com^`, `@@||...^` exceptions) or one-name-per-line list.
+/// Comments, cosmetic and path rules, and what hosts files map to
+/// themselves are skipped. Names are lowercased.
+pub fn parse_list(text: &str) -> impl Iterator<Item = Entry> + '_ {
+    text.lines().flat_map(|line| {
+        let line = line.trim();
+        let mut entries = Vec::new();
+        if line.is_empty() || line.starts_with(['!', '[', '#']) || line.contains("##") {
+            return entries;
+        }
+        if let Some(rule) = line.strip_prefix("@@||").or(line.strip_prefix("||")) {
+            // Only whole-domain rules (`^` and nothing, or options, after)
+            let Some((name, rest)) = rule.split_once('^') else {
+                return entries;
+            };
+            let name = name.to_ascii_lowercase();
+            if is_name(&name) && (rest.is_empty() || rest.starts_with('$')) {
+                entries.push(match line.starts_with("@@") {
+                    true => Entry::Allow(name),
+                    false => Entry::Domain(name),
+                });
+            }
+            return entries;
+        }
+        let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+        let first = fields.next().unwrap_or("");
+        let names: Vec<&str> = match first.parse::<IpAddr>() {
+            Ok(_) => fields.collect(),
+            Err(_) if fields.next().is_none() => vec![first],
+            Err(_) => Vec::new(),
+        };
+        for name in names {
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            if is_name(&name) && !LOCAL_NAMES.contains(&name.as_str()) {
+                entries.push(Entry::Exact(name));
+            }
+        }
+        entries
+    })
+}
+
+/// Ad and tracker names, merged from `[dns.block]`.
+#[derive(Debug, Clone, Default)]
+pub struct NameBlocklist {
+    exact: HashSet<String>,
+    domains: HashSet<String>,
+    allowed: HashSet<String>,
+}
+
+impl NameBlocklist {
+    /// Just the config's own `entries` and `allow`.
+    pub fn from_config(cfg: &DnsBlockConfig) -> Self {
+        let mut list = NameBlocklist::default();
+        list.extend(
+            cfg.entries
+                .iter()
+                .map(|n| Entry::Domain(n.to_ascii_lowercase())),
+        );
+        list.extend(
+            cfg.allow
+                .iter()
+                .map(|n| Entry::Allow(n.to_ascii_lowercase())),
+        );
+        list
+    }
+
+    /// The config's entries plus every source. A failing source is reported,
+    /// not fatal.
+    pub async fn fetch(cfg: &DnsBlockConfig) -> (Self, Vec<SourceStatus>) {
+        let mut list = Self::from_config(cfg);
+        let mut sources = Vec::new();
+        for source in &cfg.sources {
+            let status = match blocklist::load_source(source).await {
+                Ok(text) => {
+                    let before = list.len();
+                    list.extend(parse_list(&text));
+                    SourceStatus {
+                        source: source.clone(),
+                        entries: list.len() - before,
+                        error: None,
+                    }
+                }
+                Err(e) => SourceStatus {
+                    source: source.clone(),
+                    entries: 0,
+                    error: Some(e.to_string()),
+                },
+            };
+            sources.push(status);
+        }
+        (list, sources)
+    }
+
+    pub fn extend(&mut self, entries: impl IntoIterator<Item = Entry>) {
+        for entry in entries {
+            match entry {
+                Entry::Exact(name) => self.exact.insert(name),
+                Entry::Domain(name) => self.domains.insert(name),
+                Entry::Allow(name) => self.allowed.insert(name),
+            };
+        }
+    }
+
+    /// Blocked names (exact and with subdomains).
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.domains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Is `name` blocked? An allowed name or parent domain wins over any
+    /// block.
+    pub fn is_blocked(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let mut suffixes = vec![name.as_str()];
+        let mut rest = name.as_str();
+        while let Some((_, parent)) = rest.split_once('.') {
+            suffixes.push(parent);
+            rest = parent;
+        }
+        if suffixes.iter().any(|s| self.allowed.contains(*s)) {
+            return false;
+        }
+        self.exact.contains(&name) || suffixes.iter().any(|s| self.domains.contains(*s))
+    }
+}
@@ -0,0 +1,230 @@
+//! Remote admin: the dashboard's API on a non-loopback address, open only to
+//! clients presenting a certificate from the admin CA (mutual TLS). `admin
+//! cert` runs that CA, issuing client certificates and the dispatcher's own.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SerialNumber,
+};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+
+use crate::config::AdminConfig;
+
+/// The CA's certificate: what routers trust clients by, and clients trust
+/// routers by. Its key, on the machine that issues certificates only, is
+/// the same path ending in `.key`.
+pub const CA_CERT_PATH: &str = "gold-dust-admin-ca.pem";
+
+/// The dispatcher's own certificate and key (`admin cert --server`).
+pub const CERT_PATH: &str = "gold-dust-admin.pem";
+pub const KEY_PATH: &str = "gold-dust-admin.key";
+
+/// How long the CA is good for.
+const CA_DAYS: i64 = 3650;
+
+/// Common name of the CA.
+const CA_NAME: &str = "gold-dust admin CA";
+
+/// Who a certificate is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    /// Someone calling the API.
+    Client,
+    /// A dispatcher serving it.
+    Server,
+}
+
+/// A certificate and its key, PEM encoded.
+pub struct Issued {
+    pub cert: String,
+    pub key: String,
+}
+
+/// The CA that signs admin certificates.
+pub struct Authority {
+    cert: Certificate,
+    key: KeyPair,
+}
+
+impl Authority {
+    /// The CA kept at `cert_path` (and its key beside it), created there if
+    /// neither exists. The flag tells whether it was just created.
+    pub fn open_or_create(cert_path: &Path) -> Result<(Self, bool), String> {
+        let key_path = &cert_path.with_extension("key");
+        let err = |path: &Path, e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        match fs::read_to_string(key_path) {
+            Ok(pem) => {
+                if !cert_path.exists() {
+                    return Err(err(cert_path, &"missing, but the CA key is there"));
+                }
+                let key = KeyPair::from_pem(&pem).map_err(|e| err(key_path, &e))?;
+                // Signing needs only the CA's name and key, both unchanged, so
+                // certificates issued now chain to the one on disk
+                let cert = ca_params()
+                    .self_signed(&key)
+                    .map_err(|e| err(key_path, &e))?;
+                Ok((Self { cert, key }, false))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if cert_path.exists() {
+                    return Err(err(key_path, &"missing, so no certificates can be issued"));
+                }
+                let key = KeyPair::generate().map_err(|e| e.to_string())?;
+                let mut params = ca_params();
+                let now = time::OffsetDateTime::now_utc();
+                params.not_before = now - time::Duration::minutes(5);
+                params.not_after = now + time::Duration::days(CA_DAYS);
+                let cert = params.self_signed(&key).map_err(|e| e.to_string())?;
+                write_new(key_path, &key.serialize_pem())?;
+                write_new(cert_path, &cert.pem())?;
+                Ok((Self { cert, key }, true))
+            }
+            Err(e) => Err(err(key_path, &e)),
+        }
+    }
+
+    /// A certificate for `name`, good for `days`. A server one also answers
+    /// on `more` (host names or addresses), and on `name` itself.
+    pub fn issue(
+        &self,
+        name: &str,
+        usage: Usage,
+        more: &[String],
+        days: u32,
+    ) -> Result<Issued, String> {
+        let names = match usage {
+            Usage::Client => vec![],
+            Usage::Server => std::iter::once(name.to_string())
+                .chain(more.iter().cloned())
+                .collect(),
+        };
+        let mut params = CertificateParams::new(names).map_err(|e| format!("{name}: {e}"))?;
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![match usage {
+            Usage::Client => ExtendedKeyUsagePurpose::ClientAuth,
+            Usage::Server => ExtendedKeyUsagePurpose::ServerAuth,
+        }];
+        params.use_authority_key_identifier_extension = true;
+        params.serial_number = Some(SerialNumber::from_slice(&rand::random::<[u8; 16]>()));
+        let now = time::OffsetDateTime::now_utc();
+        params.not_before = now - time::Duration::minutes(5);
+        params.not_after = now + time::Duration::days(days.into());
+        let key = KeyPair::generate().map_err(|e| e.to_string())?;
+        let cert = params
+            .signed_by(&key, &self.cert, &self.key)
+            .map_err(|e| format!("{name}: {e}"))?;
+        Ok(Issued {
+            cert: cert.pem(),
+            key: key.serialize_pem(),
+        })
+    }
+}
+
+fn ca_params() -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, CA_NAME);
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params
+}
+
+/// Write `contents` to a new file at `path`, readable by us alone; never
+/// over an existing one.
+pub fn write_new(path: &Path, contents: &str) -> Result<(), String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// TLS for the admin listener: our certificate, and only clients with one
+/// from the CA in `ca_cert`.
+pub fn server_config(cfg: &AdminConfig) -> Result<Arc<ServerConfig>, String> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let certs = |path: &Path| {
+        CertificateDer::pem_file_iter(path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("{}: {e}", path.display()))
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in certs(&cfg.ca_cert)? {
+        roots
+            .add(cert)
+            .map_err(|e| format!("{}: {e}", cfg.ca_cert.display()))?;
+    }
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| format!("{}: {e}", cfg.ca_cert.display()))?;
+    let chain = certs(&cfg.cert)?;
+    let key = PrivateKeyDer::from_pem_file(&cfg.key)
+        .map_err(|e| format!("{}: {e}", cfg.key.display()))?;
+    let mut tls = ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|e| e.to_string())?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(chain, key)
+        .map_err(|e| format!("{}: {e}", cfg.cert.display()))?;
+    tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(tls))
+}
+
+/// The subject common name of a DER certificate: who a client is.
+pub fn common_name(cert: &[u8]) -> Option<String> {
+    let (_, cert, _) = tlv(cert)?;
+    let (_, mut tbs, _) = tlv(cert)?;
+    // Version, if present, then serial, signature, issuer and validity
+    if tlv(tbs)?.0 == 0xa0 {
+        tbs = tlv(tbs)?.2;
+    }
+    for _ in 0..4 {
+        tbs = tlv(tbs)?.2;
+    }
+    let (_, mut subject, _) = tlv(tbs)?;
+    while !subject.is_empty() {
+        let (_, set, rest) = tlv(subject)?;
+        subject = rest;
+        let (_, pair, _) = tlv(set)?;
+        let (tag, oid, value) = tlv(pair)?;
+        if tag == 0x06 && oid == [0x55, 0x04, 0x03] {
+            return String::from_utf8(tlv(value)?.1.to_vec()).ok();
+        }
+    }
+    None
+}
+
+/// One DER element: its tag, its contents, and what follows it.
+fn tlv(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        0x81..=0x84 => {
+            let n = (first & 0x7f) as usize;
+            let bytes = rest.get(..n)?;
+            let len = bytes.iter().fold(0usize, |len, &b| len << 8 | b as usize);
+            (len, &rest[n..])
+        }
+        _ => return None,
+    };
+    let contents = rest.get(..len)?;
+    Some((tag, contents, &rest[len..]))
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AlertMetric, AlertRule, AlertsConfig};
+use crate::http::{self, Url};
+use crate::router::BackendHealth;
+use crate::stats::now_unix;
+
+/// Where the dispatcher publishes alert state for `alerts status`.
+pub const ALERTS_PATH: &str = "gold-dust-alerts.json";
+
+/// Subject of dispatcher-wide rules (`kill_switch`).
+pub const DISPATCHER: &str = "dispatcher";
+
+/// Since when the dispatcher has been holding traffic back, if it is.
+///
+/// Engaged when a session is refused or its egress can't be reached, and
+/// released by the next session that gets through.
+#[derive(Debug, Default)]
+pub struct KillSwitch(AtomicU64);
+
+impl KillSwitch {
+    /// Returns whether it was released until now.
+    pub fn engage(&self) -> bool {
+        self.0
+            .compare_exchange(0, now_unix(), Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+
+    /// Returns whether it was engaged until now.
+    pub fn release(&self) -> bool {
+        self.0.swap(0, Ordering::SeqCst) != 0
+    }
+
+    pub fn since(&self) -> Option<u64> {
+        match self.0.load(Ordering::SeqCst) {
+            0 => None,
+            since => Some(since),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Ok,
+    /// Condition holds, but not for `for_mins` yet.
+    Pending,
+    Firing,
+}
+
+/// One rule applied to one subject, as listed by `alerts status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertStatus {
+    pub rule: String,
+    pub condition: String,
+    /// Backend name or `dispatcher`; `None` for a rule with nothing amiss.
+    pub subject: Option<String>,
+    pub state: AlertState,
+    /// Latest observed value (ms, rate or seconds engaged).
+    pub value: Option<f64>,
+    /// When the condition started to hold.
+    pub since: Option<u64>,
+}
+
+/// A rule starting or stopping to fire, as sent to webhooks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertEvent {
+    pub rule: String,
+    pub condition: String,
+    pub subject: String,
+    /// `firing` or `ok` (resolved).
+    pub state: AlertState,
+    pub value: f64,
+    pub since: u64,
+    pub unix: u64,
+}
+
+impl AlertEvent {
+    /// One line for logs and plain-text notifications.
+    pub fn message(&self) -> String {
+        match self.state {
+            AlertState::Firing => format!(
+                "[FIRING] {}: {} ({}, value {:.3})",
+                self.rule, self.subject, self.condition, self.value
+            ),
+            _ => format!("[RESOLVED] {}: {}", self.rule, self.subject),
+        }
+    }
+}
+
+/// Published alert state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AlertSnapshot {
+    pub updated_unix: u64,
+    pub interval_secs: u64,
+    pub alerts: Vec<AlertStatus>,
+}
+
+impl AlertSnapshot {
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let text = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, json)
+    }
+
+    /// Written within the last few evaluation rounds?
+    pub fn is_fresh(&self) -> bool {
+        now_unix().saturating_sub(self.updated_unix) <= self.interval_secs.max(1) * 3
+    }
+}
+
+/// Where a rule stands for one subject.
+#[derive(Debug, Clone)]
+struct Active {
+    since: u64,
+    value: f64,
+    firing: bool,
+}
+
+/// Checks the rules against fresh observations and tracks how long each
+/// condition has held.
+#[derive(Debug, Clone)]
+pub struct Evaluator {
+    rules: Vec<AlertRule>,
+    /// Keyed by rule index and subject.
+    active: BTreeMap<(usize, String), Active>,
+}
+
+impl Evaluator {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Self {
+            rules,
+            active: BTreeMap::new(),
+        }
+    }
+
+    /// Evaluate every rule at `now`. Returns the rules that started or
+    /// stopped firing.
+    pub fn evaluate(
+        &mut self,
+        now: u64,
+        backends: &[BackendHealth],
+        kill_switch: Option<u64>,
+    ) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            // (subject, value, condition holds since)
+            let breaches: Vec<(String, f64, Option<u64>)> = match rule.metric {
+                AlertMetric::KillSwitch => kill_switch
+                    .map(|since| {
+                        (
+                            DISPATCHER.to_string(),
+                            now.saturating_sub(since) as f64,
+                            Some(since),
+                        )
+                    })
+                    .into_iter()
+                    .collect(),
+                metric => backends
+                    .iter()
+                    .filter(|b| {
+                        rule.backend
+                            .as_ref()
+                            .is_none_or(|w| &b.name == w || b.kind.as_str() == w)
+                    })
+                    .filter_map(|b| {
+                        let value = match metric {
+                            AlertMetric::Latency => b.latency_ms,
+                            _ => b.failure_rate,
+                        };
+                        (value > rule.above.unwrap_or(f64::INFINITY))
+                            .then(|| (b.name.clone(), value, None))
+                    })
+                    .collect(),
+            };
+
+            let mut seen = Vec::new();
+            for (subject, value, since) in breaches {
+                let active = self.active.entry((i, subject.clone())).or_insert(Active {
+                    since: since.unwrap_or(now),
+                    value,
+                    firing: false,
+                });
+                active.value = value;
+                let held = now.saturating_sub(active.since);
+                if !active.firing && held >= rule.for_mins * 60 {
+                    active.firing = true;
+                    events.push(event(rule, &subject, AlertState::Firing, active, now));
+                }
+                seen.push(subject);
+            }
+
+            // Conditions that stopped holding (or subjects no longer reported)
+            let gone: Vec<String> = self
+                .active
+                .keys()
+                .filter(|(r, subject)| *r == i && !seen.contains(subject))
+                .map(|(_, subject)| subject.clone())
+                .collect();
+            for subject in gone {
+                if let Some(active) = self.active.remove(&(i, subject.clone())) {
+                    if active.firing {
+                        events.push(event(rule, &subject, AlertState::Ok, &active, now));
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    /// Every rule's state, one row per subject it applies to.
+    pub fn status(&self) -> Vec<AlertStatus> {
+        let mut rows = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            let before = rows.len();
+            for ((r, subject), active) in &self.active {
+                if *r != i {
+                    continue;
+                }
+                rows.push(AlertStatus {
+                    rule: rule.name(),
+                    condition: rule.condition(),
+                    subject: Some(subject.clone()),
+                    state: if active.firing {
+                        AlertState::Firing
+                    } else {
+                        AlertState::Pending
+                    },
+                    value: Some(active.value),
+                    since: Some(active.since),
+                });
+            }
+            if rows.len() == before {
+                rows.push(AlertStatus {
+                    rule: rule.name(),
+                    condition: rule.condition(),
+                    subject: None,
+                    state: AlertState::Ok,
+                    value: None,
+                    since: None,
+                });
+            }
+        }
+        rows
+    }
+}
+
+fn event(
+    rule: &AlertRule,
+    subject: &str,
+    state: AlertState,
+    active: &Active,
+    now: u64,
+) -> AlertEvent {
+    AlertEvent {
+        rule: rule.name(),
+        condition: rule.condition(),
+        subject: subject.to_string(),
+        state,
+        value: active.value,
+        since: active.since,
+        unix: now,
+    }
+}
+
+/// Send `event` to every webhook (JSON) and notify URL (plain text).
+/// Failures are returned per URL, so one dead receiver doesn't stop the rest.
+pub async fn dispatch(cfg: &AlertsConfig, event: &AlertEvent) -> Vec<String> {
+    let json = serde_json::to_vec(event).expect("alert events serialize");
+    let text = event.message();
+    let targets = cfg
+        .webhooks
+        .iter()
+        .map(|url| (url, "application/json", json.as_slice()))
+        .chain(
+            cfg.notify
+                .iter()
+                .map(|url| (url, "text/plain; charset=utf-8", text.as_bytes())),
+        );
+    let mut errors = Vec::new();
+    for (url, content_type, body) in targets {
+        let sent = match Url::parse(url) {
+            Ok(parsed) => http::request("POST", &parsed, &[("Content-Type", content_type)], body)
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|resp| match resp.status {
+                    200..=299 => Ok(()),
+                    status => Err(format!("HTTP {status}")),
+                }),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            errors.push(format!("{url}: {e}"));
+        }
+    }
+    errors
+}
@@ -0,0 +1,115 @@
+//! Audit trail of administrative actions: who switched the egress, changed
+//! the firewall, issued a certificate, and what it was before. One JSON line
+//! per action, appended by the dispatcher and the CLI alike; `audit list`
+//! reads it back. (Not to be confused with `dispatcher --audit`, which is
+//! about sessions.)
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::logfile;
+use crate::seal::{self, Sealer};
+use crate::stats::now_unix;
+
+/// Default trail, in the state directory.
+pub const AUDIT_PATH: &str = "gold-dust-audit.jsonl";
+
+/// One administrative action.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Action {
+    pub unix: u64,
+    /// A local user, `admin <name>` for an admin client, or an address.
+    pub who: String,
+    /// Where it came from: `cli`, `dashboard`, `admin`.
+    pub via: String,
+    /// What was done, e.g. `mode`, `firewall apply`.
+    pub action: String,
+    /// The value before, where there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// The value after, or what was acted on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+impl Action {
+    /// `action` by `who` through `via`, now.
+    pub fn new(who: impl Into<String>, via: &str, action: &str) -> Self {
+        Self {
+            unix: now_unix(),
+            who: who.into(),
+            via: via.to_string(),
+            action: action.to_string(),
+            from: None,
+            to: None,
+        }
+    }
+
+    pub fn from(mut self, value: impl Into<String>) -> Self {
+        self.from = Some(value.into());
+        self
+    }
+
+    pub fn to(mut self, value: impl Into<String>) -> Self {
+        self.to = Some(value.into());
+        self
+    }
+
+    /// When, in RFC 3339.
+    pub fn time(&self) -> String {
+        crate::syslog::timestamp(UNIX_EPOCH + Duration::from_secs(self.unix))
+    }
+}
+
+/// Append `action` to the trail at `path`, creating it (readable by us
+/// alone) if need be, sealed if there is a `sealer`. One write per line, so
+/// the dispatcher and the CLI can't interleave.
+pub fn record(path: &Path, action: &Action, sealer: Option<&Sealer>) -> io::Result<()> {
+    let mut line = serde_json::to_string(action).map_err(io::Error::other)?;
+    if let Some(sealer) = sealer {
+        line = sealer.seal(&line);
+    }
+    line.push('\n');
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(line.as_bytes())
+}
+
+/// Every action in the trail at `path` (and any rotated copies), oldest
+/// first. Lines that don't parse are skipped.
+pub fn read(path: &Path, sealer: Option<&Sealer>) -> Result<Vec<Action>, String> {
+    let lines = logfile::read_lines(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let lines =
+        seal::open_lines(lines, sealer).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(lines
+        .iter()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// The local user running this process, and who they sudo'd from.
+pub fn invoker() -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let uid = std::fs::metadata("/proc/self").map(|m| m.uid()).ok();
+        let name =
+            uid.map(|uid| crate::peer::user_name(uid).unwrap_or_else(|| format!("uid {uid}")));
+        let sudo = std::env::var("SUDO_USER").ok();
+        match (name, sudo) {
+            (Some(name), Some(sudo)) if sudo != name => format!("{name} (sudo from {sudo})"),
+            (Some(name), _) => name,
+            (None, sudo) => sudo
+                .or_else(|| std::env::var("USER").ok())
+                .unwrap_or_else(|| "?".to_string()),
+        }
+    }
+    #[cfg(not(unix))]
+    std::env::var("USERNAME").unwrap_or_else(|_| "?".to_string())
+}
@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::FailoverConfig;
+use crate::stats::write_atomic;
+
+/// Where the dispatcher keeps per-backend availability across restarts.
+pub const AVAILABILITY_PATH: &str = "gold-dust-availability.json";
+
+/// Windows shown by `status`: label and length in seconds.
+pub const WINDOWS: [(&str, u64); 3] = [("1h", 3_600), ("24h", 86_400), ("30d", 30 * 86_400)];
+
+/// Per-minute buckets kept (the 1h window).
+const MINUTES_KEPT: usize = 60;
+
+/// Per-hour buckets kept (the 24h and 30d windows).
+const HOURS_KEPT: usize = 30 * 24;
+
+/// Health reports in one time slot, and how many found the backend up.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Bucket {
+    /// Slot start, in minutes or hours since the epoch.
+    pub slot: u64,
+    pub up: u32,
+    pub total: u32,
+}
+
+/// Report counts for one backend at two resolutions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackendAvailability {
+    pub minutes: VecDeque<Bucket>,
+    pub hours: VecDeque<Bucket>,
+}
+
+/// Rolling availability per backend, written to [`AVAILABILITY_PATH`].
+///
+/// Availability is the share of health reports (gossip probes, feed
+/// updates) that found the backend enabled, so it is only as dense as the
+/// reports are.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AvailabilityLedger {
+    pub backends: BTreeMap<String, BackendAvailability>,
+}
+
+impl AvailabilityLedger {
+    /// Read the ledger, starting empty if missing/unreadable.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    // Compact: 30 days of hourly buckets per backend
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        write_atomic(path.as_ref(), &serde_json::to_string(self)?)
+    }
+
+    /// Count one report of `backend` observed at `unix`.
+    pub fn record(&mut self, backend: &str, unix: u64, up: bool) {
+        let entry = self.backends.entry(backend.to_string()).or_default();
+        add(&mut entry.minutes, unix / 60, up, MINUTES_KEPT);
+        add(&mut entry.hours, unix / 3_600, up, HOURS_KEPT);
+    }
+
+    /// Share of reports within `window_secs` before `now` that found
+    /// `backend` up, or `None` without reports in that window.
+    ///
+    /// Windows up to an hour are counted per minute, longer ones per hour
+    /// (including the current, partial hour).
+    pub fn uptime(&self, backend: &str, now: u64, window_secs: u64) -> Option<f64> {
+        let (up, total) = self.counts(backend, now, window_secs);
+        (total > 0).then(|| up as f64 / total as f64)
+    }
+
+    /// How much of its `[failover]` error budget `backend` has spent over
+    /// the window before `now` (above 1.0: burnt), or `None` without a
+    /// budget or with too few reports to judge.
+    pub fn budget_spent(&self, backend: &str, now: u64, cfg: &FailoverConfig) -> Option<f64> {
+        let budget = cfg.error_budget?;
+        let (up, total) = self.counts(backend, now, cfg.window_secs);
+        if total == 0 || total < cfg.min_reports {
+            return None;
+        }
+        let down = (total - up) as f64 / total as f64;
+        Some(down / budget)
+    }
+
+    /// Reports within the window that found `backend` up, out of all of them.
+    fn counts(&self, backend: &str, now: u64, window_secs: u64) -> (u64, u64) {
+        let Some(entry) = self.backends.get(backend) else {
+            return (0, 0);
+        };
+        let (buckets, slot_secs) = if window_secs <= 3_600 {
+            (&entry.minutes, 60)
+        } else {
+            (&entry.hours, 3_600)
+        };
+        let first = (now / slot_secs + 1).saturating_sub(window_secs.div_ceil(slot_secs));
+        buckets
+            .iter()
+            .filter(|b| b.slot >= first)
+            .fold((0, 0), |(up, total), b| {
+                (up + u64::from(b.up), total + u64::from(b.total))
+            })
+    }
+
+    /// Reports counted for `backend` over everything kept (30 days).
+    pub fn reports(&self, backend: &str) -> u64 {
+        self.backends
+            .get(backend)
+            .map_or(0, |e| e.hours.iter().map(|b| u64::from(b.total)).sum())
+    }
+}
+
+fn add(buckets: &mut VecDeque<Bucket>, slot: u64, up: bool, keep: usize) {
+    // Reports arrive roughly in order; a late one joins the newest slot
+    if buckets.back().is_none_or(|last| last.slot < slot) {
+        buckets.push_back(Bucket {
+            slot,
+            ..Bucket::default()
+        });
+    }
+    let last = buckets.back_mut().expect("bucket just ensured");
+    last.total += 1;
+    last.up += u32::from(up);
+    while buckets.len() > keep {
+        buckets.pop_front();
+    }
+}
@@ -1,14 +1,26 @@
 use std::fs;
 use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::OnceLock;
 
 use axum::{
+    extract::ConnectInfo,
     response::{Html, Redirect},
     routing::get,
     Router,
 };
+use gold_dust_gateway::audit::{self, Action};
+use gold_dust_gateway::config::GoldDustConfig;
+use gold_dust_gateway::peer;
+use gold_dust_gateway::seal::Sealer;
 use tokio::net::TcpListener;
 
 const FLAG_PATH: &str = "gold-dust-tor.flag";
+const LISTEN: &str = "127.0.0.1:3000";
+const CONFIG_PATH: &str = "gold-dust-gateway.toml";
+
+/// The `[encryption]` key for the audit trail, if the config has one.
+static SEALER: OnceLock<Option<Sealer>> = OnceLock::new();
 
 fn read_flag() -> bool {
     match fs::read_to_string(FLAG_PATH) {
@@ -17,8 +29,24 @@ fn read_flag() -> bool {
     }
 }
 
-fn write_flag(on: bool) {
-    let _ = fs::write(FLAG_PATH, if on { "on\n" } else { "off\n" });
+fn write_flag(on: bool, by: SocketAddr) {
+    let mode = |on| if on { "tor" } else { "direct" };
+    let before = read_flag();
+    if fs::write(FLAG_PATH, if on { "on\n" } else { "off\n" }).is_err() {
+        return;
+    }
+    // The local user who clicked, where the kernel can tell
+    let who = LISTEN
+        .parse()
+        .ok()
+        .and_then(|local| peer::tcp_uid(local, by))
+        .and_then(peer::user_name)
+        .unwrap_or_else(|| by.ip().to_string());
+    let action = Action::new(who, "dashboard", "mode")
+        .from(mode(before))
+        .to(mode(on));
+    let sealer = SEALER.get().and_then(Option::as_ref);
+    let _ = audit::record(Path::new(audit::AUDIT_PATH), &action, sealer);
 }
 
 async fn index() -> Html<String> {
@@ -140,26 +168,40 @@ async fn index() -> Html<String> {
     Html(html)
 }
 
-async fn set_on() -> Redirect {
-    write_flag(true);
+async fn set_on(ConnectInfo(by): ConnectInfo<SocketAddr>) -> Redirect {
+    write_flag(true, by);
     Redirect::to("/")
 }
 
-async fn set_off() -> Redirect {
-    write_flag(false);
+async fn set_off(ConnectInfo(by): ConnectInfo<SocketAddr>) -> Redirect {
+    write_flag(false, by);
     Redirect::to("/")
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
+    // The keychain may start a runtime of its own, so before ours
+    let cfg = GoldDustConfig::load(CONFIG_PATH).ok();
+    let sealer = cfg
+        .filter(|cfg| cfg.encryption.enabled)
+        .map(|cfg| Sealer::load(&cfg.encryption.key, true).unwrap().0);
+    let _ = SEALER.set(sealer);
+    tokio::runtime::Runtime::new().unwrap().block_on(serve());
+}
+
+async fn serve() {
     let app = Router::new()
         .route("/", get(index))
         .route("/on", get(set_on))
         .route("/off", get(set_off));
 
-    let addr: SocketAddr = "127.0.0.1:3000".parse().unwrap();
+    let addr: SocketAddr = LISTEN.parse().unwrap();
     let listener = TcpListener::bind(addr).await.unwrap();
 
     println!("[dashboard] Web UI listening on http://{addr}");
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
@@ -1,23 +1,1043 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::Infallible;
 use std::error::Error;
 use std::fs;
-use std::net::SocketAddr;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use axum::extract::connect_info::{ConnectInfo, Connected};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::Request;
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::serve::IncomingStream;
+use axum::{Extension, Json};
+use clap::Parser;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
 use tokio_socks::tcp::Socks5Stream;
 
+use gold_dust_gateway::access::{Access, Grants};
+use gold_dust_gateway::adblock::NameBlocklist;
+use gold_dust_gateway::admin;
+use gold_dust_gateway::alerts::{self, AlertSnapshot, Evaluator, KillSwitch, ALERTS_PATH};
+use gold_dust_gateway::audit;
+use gold_dust_gateway::availability::{AvailabilityLedger, AVAILABILITY_PATH};
+use gold_dust_gateway::blocklist::{Blocklist, BLOCKLIST_PATH};
+use gold_dust_gateway::cache::CacheStats;
+use gold_dust_gateway::chaos::{Chaos, Fault};
+use gold_dust_gateway::config::DotProfile;
+use gold_dust_gateway::config::{
+    AdminConfig, AlertsConfig, AppConfig, BlockAnswer, BlocklistConfig, DashboardConfig,
+    DnsBlockConfig, DnsMode, EgressKind, FailoverConfig, GoldDustConfig, GossipConfig, HaConfig,
+    HealthFeedConfig, KeepaliveConfig, LanAction, LimitConfig, MetricsPushConfig, PortalConfig,
+    PowerMode, RetryConfig, StandbyConfig, TimeoutConfig, TorControlConfig,
+};
+use gold_dust_gateway::dashboard::{self, HealthHistory, ModeRequest, DECISIONS_KEPT};
+use gold_dust_gateway::dns::{DohResolver, DotResolver, EgressResolver, Hosts};
+use gold_dust_gateway::eventlog::{self, EventKind, EventLog};
+use gold_dust_gateway::feed::{self, FEED_NODE};
+use gold_dust_gateway::firewall::{self, Ruleset};
+use gold_dust_gateway::gossip::{Gossip, HealthBoard, Report, HEALTH_PATH};
+use gold_dust_gateway::ha::{self, HaStatus, Pair, Role, VirtualAddress};
+use gold_dust_gateway::handover::{
+    self, Inherited, Offer, Predecessor, Successor, Tail, HANDOVER_PATH,
+};
+use gold_dust_gateway::health::TcpProber;
+use gold_dust_gateway::http::{self, Url};
+use gold_dust_gateway::journal::{self, Priority};
+use gold_dust_gateway::lan::Lan;
+use gold_dust_gateway::logfile::RotatingLog;
+use gold_dust_gateway::lokinet;
+use gold_dust_gateway::masque::MasqueClient;
+use gold_dust_gateway::metrics::{Push, Sample};
+use gold_dust_gateway::monitor::{Event, Failover, EVENTS_BUFFERED};
+use gold_dust_gateway::peer;
+use gold_dust_gateway::pidfile::{InheritedLock, PidLock, PID_PATH};
+use gold_dust_gateway::portal::{self, PortalMode, PORTAL_PATH};
+use gold_dust_gateway::power::Power;
+use gold_dust_gateway::quota::{UsageLedger, USAGE_PATH, USER_USAGE_PATH};
+use gold_dust_gateway::ratelimit::{
+    bandwidth_bucket, ApiThrottle, RateLimiter, Refusal, SharedBucket,
+};
+use gold_dust_gateway::relay::{
+    self, relay, relay_watched, Activity, IdleTimeouts, Tracked, Upstream, Watchdog,
+};
+use gold_dust_gateway::retry::{DialTimeout, FailureClass};
+use gold_dust_gateway::rotation::{Rotation, ROTATION_PATH};
+use gold_dust_gateway::router::BackendHealth;
+use gold_dust_gateway::sandbox;
+use gold_dust_gateway::seal::Sealer;
+use gold_dust_gateway::socks;
+use gold_dust_gateway::stats::{
+    now_unix, Canary, EgressUsage, Meter, TrafficEntry, TrafficSnapshot, STATS_PATH,
+};
+use gold_dust_gateway::suspend::SleepWatch;
+use gold_dust_gateway::syslog::Syslog;
+use gold_dust_gateway::target::{Host, Target};
+use gold_dust_gateway::token::{self, ApiToken};
+use gold_dust_gateway::torctl::{self, Control, ExitPins, ExitsUnavailable};
+use gold_dust_gateway::uring::{self, Ring};
+
+/// `println!` for the dispatcher's log, which goes to journald when
+/// `[logging] journald` connected it.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        journal::log(Priority::Info, format_args!($($arg)*))
+    };
+}
+
+/// `eprintln!`, likewise.
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        journal::log(Priority::Warning, format_args!($($arg)*))
+    };
+}
+
 const FLAG_PATH: &str = "gold-dust-tor.flag";
+const CONFIG_PATH: &str = "gold-dust-gateway.toml";
+
+/// A canary slower than this counts as failed.
+const CANARY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Stats publishing period (on mains power).
+const STATS_EVERY: Duration = Duration::from_secs(1);
+
+/// A metrics push taking longer than this is abandoned.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An admin client that hasn't finished its TLS handshake by now is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often `[power] mode = "auto"` looks at the power supply.
+const POWER_CHECK: Duration = Duration::from_secs(60);
+
+/// One way out of the dispatcher (`tor`, `direct` or `masque`).
+struct Egress {
+    meter: Meter,
+    limits: LimitConfig,
+    keepalive: KeepaliveConfig,
+    timeouts: TimeoutConfig,
+    bandwidth: Option<SharedBucket>,
+    /// Sessions relaying through this egress.
+    active: AtomicUsize,
+    /// Dials through this egress, and those that overran a `[timeouts]`
+    /// budget.
+    dials: AtomicU64,
+    overruns: AtomicU64,
+    /// Sessions closed for sitting idle.
+    idle_closed: AtomicU64,
+}
+
+impl Egress {
+    fn new(cfg: &GoldDustConfig, name: &str) -> Self {
+        let limits = cfg.limits_for(name);
+        Self {
+            meter: Meter::default(),
+            bandwidth: limits.bandwidth_kbps.map(bandwidth_bucket),
+            limits,
+            keepalive: cfg.keepalive_for(name),
+            timeouts: cfg.timeouts_for(name),
+            active: AtomicUsize::new(0),
+            dials: AtomicU64::new(0),
+            overruns: AtomicU64::new(0),
+            idle_closed: AtomicU64::new(0),
+        }
+    }
+
+    /// Run one phase of a dial within its budget; an overrun counts
+    /// against the egress.
+    async fn within<T, E>(
+        &self,
+        phase: &'static str,
+        limit: Option<Duration>,
+        step: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, Box<dyn Error + Send + Sync>>
+    where
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let Some(after) = limit else {
+            return step.await.map_err(Into::into);
+        };
+        match tokio::time::timeout(after, step).await {
+            Ok(done) => done.map_err(Into::into),
+            Err(_) => {
+                self.overruns.fetch_add(1, Ordering::Relaxed);
+                Err(DialTimeout { phase, after }.into())
+            }
+        }
+    }
+
+    /// Share of dials that overran a budget, once there were any.
+    fn overrun_rate(&self) -> Option<f64> {
+        let dials = self.dials.load(Ordering::Relaxed);
+        let overruns = self.overruns.load(Ordering::Relaxed);
+        (dials > 0).then(|| overruns as f64 / dials as f64)
+    }
+
+    /// Count a session against `max_sessions`, or `None` if it is full.
+    fn claim(&self) -> Option<Session<'_>> {
+        let session = Session::start(&self.active);
+        match self.limits.max_sessions {
+            Some(max) if self.active.load(Ordering::SeqCst) > max => None,
+            _ => Some(session),
+        }
+    }
+}
+
+/// Shared dispatcher state.
+struct State {
+    limiter: RateLimiter,
+    egress: BTreeMap<&'static str, Egress>,
+    usage: Mutex<UsageLedger>,
+    chaos: Option<Chaos>,
+    resolver: Option<DohResolver>,
+    /// Set in `dot` mode; lookups go through the session's egress.
+    dot: Option<DotResolver>,
+    /// Per-egress resolvers (`[dns.resolvers]`), used instead of the above.
+    resolvers: HashMap<&'static str, EgressResolver>,
+    /// Names with fixed addresses; no lookup for them.
+    hosts: Hosts,
+    /// Ad/tracker names refused before any lookup (`[dns.block]`).
+    dns_block: RwLock<NameBlocklist>,
+    /// Whether sessions are checked when their app profile doesn't say.
+    block_ads: bool,
+    block_answer: BlockAnswer,
+    /// Sessions refused by `[dns.block]`, by app profile (`-` for none).
+    dns_blocked: Mutex<BTreeMap<String, u64>>,
+    /// Private networks and local names, kept out of Tor and Oxen.
+    lan: Lan,
+    /// Captive portal probe, run when the kill switch engages.
+    portal: PortalConfig,
+    /// Probing and timer profile (`[power]`).
+    power: Arc<Power>,
+    dns_mode: DnsMode,
+    dns_profile: DotProfile,
+    tor_socks: SocketAddr,
+    /// Per-destination Tor circuits on a schedule (`[rotation]`).
+    rotation: Rotation,
+    /// Tor exits pinned by `[[rules]]`, if any rule pins one.
+    exit_pins: Option<ExitPins>,
+    /// How long sessions may sit idle, by destination.
+    idle: IdleTimeouts,
+    /// App profiles by SOCKS username (`[apps]`).
+    apps: HashMap<String, AppConfig>,
+    /// Local users' policies (`[users]`), by config key.
+    users: HashMap<String, UserPolicy>,
+    /// Per-user quota usage, by config key.
+    user_usage: Mutex<UsageLedger>,
+    /// Set when `[backends.masque]` is configured and the feature is built.
+    masque: Option<MasqueClient>,
+    /// Set when `[dispatcher] io_uring` is on and a ring could be set up.
+    ring: Option<Ring>,
+    /// Newest health report per backend (gossip, external feed).
+    board: Mutex<HealthBoard>,
+    /// Recent reports per backend, for the dashboard graphs.
+    history: Mutex<HealthHistory>,
+    /// Up/down report counts per backend, for uptime in `status`.
+    availability: Mutex<AvailabilityLedger>,
+    /// Error budget a backend burns before it leaves rotation.
+    failover: FailoverConfig,
+    /// What each kind of dial failure gets (`[retry]`).
+    retry: RetryConfig,
+    /// Last canary fetched through Tor (`[standby]`).
+    standby: Mutex<Option<Canary>>,
+    /// Where this daemon stands in its `[ha]` pair.
+    ha: Mutex<Option<HaStatus>>,
+    published: Mutex<Published>,
+    /// Client connections currently being handled.
+    sessions: AtomicUsize,
+    /// Set while sessions are being refused rather than rerouted.
+    kill_switch: KillSwitch,
+    /// Live events for `monitor` (`/api/events`).
+    events: broadcast::Sender<Event>,
+    /// Decide every session, but refuse to forward any (`--dry-run`).
+    dry_run: bool,
+    /// Decide every session, but forward all of them direct (`--audit`).
+    audit: bool,
+    /// Audit mode: sessions by what enforcement would have done.
+    audited: Mutex<BTreeMap<String, u64>>,
+    traffic_log: Option<Mutex<RotatingLog>>,
+    health_log: Option<Mutex<RotatingLog>>,
+    syslog: Option<Mutex<Syslog>>,
+    /// One journal entry per session (`journald_decisions`).
+    journal_decisions: bool,
+    /// Windows Event Log source (`[logging] event_log`).
+    event_log: Option<EventLog>,
+    /// Finished sessions, newest first.
+    decisions: Mutex<VecDeque<TrafficEntry>>,
+    /// Set once a successor has our sockets: it owns the state files now.
+    handed_over: AtomicBool,
+    /// Per-caller limits on the HTTP APIs (`[api]`).
+    api_throttle: ApiThrottle,
+    /// Where administrative actions are recorded (`[logging] audit_log`).
+    audit_trail: PathBuf,
+    /// Seals logs and snapshots at rest (`[encryption]`).
+    sealer: Option<Arc<Sealer>>,
+}
+
+/// What the client spoke, so answers go back in kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Proto {
+    Http,
+    Socks,
+}
+
+/// Answers to a client, named after their HTTP statuses.
+#[derive(Debug, Clone, Copy)]
+enum Reply {
+    Established,
+    BadRequest,
+    Forbidden,
+    MethodNotAllowed,
+    TooManyRequests,
+    Unavailable,
+    BadGateway,
+    /// As if the target refused the connection.
+    Refused,
+    GatewayTimeout,
+}
+
+impl Reply {
+    fn bytes(self, proto: Proto) -> Vec<u8> {
+        let (status, code) = match self {
+            Reply::Established => ("200 Connection Established", socks::SUCCEEDED),
+            Reply::BadRequest => ("400 Bad Request", socks::ADDRESS_NOT_SUPPORTED),
+            Reply::Forbidden => ("403 Forbidden", socks::NOT_ALLOWED),
+            Reply::MethodNotAllowed => ("405 Method Not Allowed", socks::COMMAND_NOT_SUPPORTED),
+            Reply::TooManyRequests => ("429 Too Many Requests", socks::NOT_ALLOWED),
+            Reply::Unavailable => ("503 Service Unavailable", socks::GENERAL_FAILURE),
+            Reply::BadGateway => ("502 Bad Gateway", socks::HOST_UNREACHABLE),
+            Reply::Refused => ("502 Bad Gateway", socks::CONNECTION_REFUSED),
+            Reply::GatewayTimeout => ("504 Gateway Timeout", socks::TTL_EXPIRED),
+        };
+        match proto {
+            Proto::Http => format!("HTTP/1.1 {status}\r\n\r\n").into_bytes(),
+            Proto::Socks => socks::reply(code).to_vec(),
+        }
+    }
+}
+
+/// How a dial that failed for good is answered.
+impl From<FailureClass> for Reply {
+    fn from(class: FailureClass) -> Self {
+        match class {
+            FailureClass::Refused => Reply::Refused,
+            FailureClass::Unreachable => Reply::BadGateway,
+            FailureClass::Timeout | FailureClass::TtlExpired => Reply::GatewayTimeout,
+            FailureClass::GeneralFailure | FailureClass::Other => Reply::Unavailable,
+        }
+    }
+}
+
+/// A `[users]` entry, compiled.
+struct UserPolicy {
+    egress: Option<EgressKind>,
+    limits: LimitConfig,
+    limiter: RateLimiter,
+}
+
+/// Counts a client connection as active for as long as it lives.
+struct Session<'a>(&'a AtomicUsize);
+
+impl<'a> Session<'a> {
+    fn start(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Self(count)
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
-fn should_use_tor() -> bool {
+/// Egress picked by the flag file: `on` → tor, `off` → direct, `masque`.
+fn flag_egress() -> &'static str {
     match fs::read_to_string(FLAG_PATH) {
-        Ok(s) => s.trim() == "on",
-        Err(_) => true, // default: ON if file missing
+        Ok(s) if s.trim() == "masque" => "masque",
+        Ok(s) if s.trim() != "on" => "direct",
+        _ => "tor", // default: ON if file missing
+    }
+}
+
+/// Serve one client. `user` is the key of its `[users]` policy, if any.
+async fn handle_client<I: Upstream + 'static>(
+    mut inbound: I,
+    state: Arc<State>,
+    entry: &mut TrafficEntry,
+    user: Option<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let policy = user.as_ref().map(|u| &state.users[u]);
+
+    // 1) Read the request: SOCKS5 (where apps can name themselves) or HTTP
+    // CONNECT, on the same port
+    let Ok(first) = inbound.read_u8().await else {
+        return Err("client closed before sending request".into());
+    };
+    let (proto, connect, target, username) = if first == socks::VERSION {
+        let req = socks::accept(&mut inbound).await?;
+        let connect = req.command == socks::CMD_CONNECT;
+        (Proto::Socks, connect, req.address, req.username)
+    } else {
+        let (method, target) = read_connect(&mut inbound, first).await?;
+        (Proto::Http, method == "CONNECT", target, None)
+    };
+    entry.target.clone_from(&target);
+
+    if !connect {
+        entry.outcome = "not_connect".to_string();
+        inbound
+            .write_all(&Reply::MethodNotAllowed.bytes(proto))
+            .await?;
+        return Ok(());
+    }
+
+    // App profile, for clients that gave a SOCKS username
+    let app = match username {
+        Some(user) if state.apps.contains_key(&user) => Some(user),
+        Some(user) => {
+            info!("[dispatcher] no app profile {:?}, using defaults", user);
+            None
+        }
+        None => None,
+    };
+    let profile = app.as_ref().map(|a| &state.apps[a]);
+    let isolation = app.as_ref().map(|a| {
+        profile
+            .and_then(|p| p.isolation.clone())
+            .unwrap_or(a.clone())
+    });
+    entry.app.clone_from(&app);
+
+    let target: Target = match target.parse() {
+        Ok(t) => t,
+        Err(e) => {
+            info!("[dispatcher] bad CONNECT target {:?}: {}", target, e);
+            entry.outcome = "bad_request".to_string();
+            inbound.write_all(&Reply::BadRequest.bytes(proto)).await?;
+            return Ok(());
+        }
+    };
+    let host = target.host_str();
+
+    // Ad/tracker names are answered before anything is looked up
+    let blocking = profile.and_then(|p| p.block_ads).unwrap_or(state.block_ads);
+    if blocking
+        && target.host.ip().is_none()
+        && state
+            .dns_block
+            .read()
+            .expect("dns block list poisoned")
+            .is_blocked(&host)
+    {
+        *state
+            .dns_blocked
+            .lock()
+            .expect("dns block counts poisoned")
+            .entry(app.clone().unwrap_or_else(|| "-".to_string()))
+            .or_default() += 1;
+        info!("[dispatcher] {} is on a block list, refusing", host);
+        let reply = match state.block_answer {
+            BlockAnswer::Nxdomain => Reply::BadGateway,
+            BlockAnswer::Null => Reply::Refused,
+        };
+        return refuse(&state, inbound, proto, &target, entry, reply, "dns_blocked").await;
+    }
+
+    // Backend: the user's, the app's, else the flag's
+    let mut name = policy
+        .and_then(|p| p.egress)
+        .or(profile.and_then(|p| p.egress))
+        .map_or_else(flag_egress, |e| e.as_str());
+    // A LAN name isn't looked up at all, so it can't leak to a resolver;
+    // nor is a captive portal's (`portal login`), which only its own DNS knows
+    let mut lan = state.lan.check(&target.host, &[]);
+    let portal = PortalMode::load(PORTAL_PATH).filter(|m| m.allows(&target.host));
+
+    // 2) Fixed addresses ([dns.hosts]), else optional pre-resolution, so
+    // IP/CIDR rules see named targets: by the backend's own resolver
+    // ([dns.resolvers]), else DoH / DoT. DoT asks through the backend the
+    // session will use
+    let fixed = state.hosts.get(&host);
+    let own = state.resolvers.get(name);
+    let via = |server: Target| {
+        let state = &state;
+        async move { dial(state, name, &server, None, None, 0).await }
+    };
+    let lookup = match (fixed, own, &state.resolver, &state.dot, target.host.ip()) {
+        (Some(ips), ..) => Some(("hosts", Ok(ips.to_vec()))),
+        _ if lan.is_some() || portal.is_some() => None,
+        (.., Some(_)) => None,
+        (_, Some(EgressResolver::Plain(dns)), ..) => {
+            Some(("DNS", dns.resolve(&host).await.map(|r| r.ips)))
+        }
+        (_, Some(EgressResolver::Doh(doh)), ..) => {
+            Some(("DoH", doh.resolve_via(&host, via).await.map(|r| r.ips)))
+        }
+        (_, Some(EgressResolver::Dot(dot)), ..) => {
+            Some(("DoT", dot.resolve(&host, name, via).await.map(|r| r.ips)))
+        }
+        (_, Some(EgressResolver::Remote), ..) => None,
+        (_, _, Some(doh), _, None) => Some(("DoH", doh.resolve(&host).await.map(|r| r.ips))),
+        (_, _, _, Some(dot), None) => {
+            Some(("DoT", dot.resolve(&host, name, via).await.map(|r| r.ips)))
+        }
+        _ => None,
+    };
+    let resolved = match lookup {
+        Some((_, Ok(ips))) => ips,
+        Some((how, Err(e))) => {
+            warn!("[dispatcher] {} lookup for {} failed: {}", how, host, e);
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+    // LAN destinations never go through Tor or Oxen: direct, or not at all
+    if lan.is_none() {
+        lan = state.lan.check(&target.host, &resolved);
+    }
+    if let Some(why) = &lan {
+        if state.lan.action() == LanAction::Reject && portal.is_none() {
+            info!("[dispatcher] {} is on the LAN ({}), refusing", target, why);
+            return refuse(
+                &state,
+                inbound,
+                proto,
+                &target,
+                entry,
+                Reply::Forbidden,
+                "lan_rejected",
+            )
+            .await;
+        }
+        if name != "direct" {
+            info!(
+                "[dispatcher] {} is on the LAN ({}), going direct instead of {}",
+                target, why, name
+            );
+            name = "direct";
+        }
+    }
+    if let Some(mode) = portal.as_ref().filter(|_| name != "direct") {
+        info!(
+            "[dispatcher] portal mode: {} goes direct instead of {} ({}s left)",
+            target,
+            name,
+            mode.remaining_secs()
+        );
+        name = "direct";
+    }
+    // A fixed address is dialed whatever the backend. Direct sessions
+    // connect to the DoT or their own resolver's answer, so the system
+    // resolver isn't asked; strict DoT refuses rather than fall back to it
+    let dial_target = match resolved.first() {
+        Some(ip) if fixed.is_some() => Target::new(Host::from(*ip), target.port),
+        Some(ip) if (state.dot.is_some() || own.is_some()) && name == "direct" => {
+            Target::new(Host::from(*ip), target.port)
+        }
+        _ => target.clone(),
+    };
+    if state.dot.is_some()
+        && own.is_none()
+        && state.dns_profile == DotProfile::Strict
+        && name == "direct"
+        && target.host.ip().is_none()
+        && resolved.is_empty()
+        && lan.is_none()
+        && portal.is_none()
+    {
+        info!(
+            "[dispatcher] strict DoT: no answer for {}, refusing direct session",
+            host
+        );
+        return refuse(
+            &state,
+            inbound,
+            proto,
+            &target,
+            entry,
+            Reply::BadGateway,
+            "dns_failed",
+        )
+        .await;
+    }
+
+    let exits = state
+        .exit_pins
+        .as_ref()
+        .and_then(|pins| pins.exits_for(&target.host, &resolved))
+        .map(<[String]>::to_vec);
+
+    // 3) Per-destination rate limits
+    let bandwidth = match state.limiter.admit(&target, &resolved) {
+        Ok(bucket) => bucket,
+        Err(rule) => {
+            info!("[dispatcher] rate limited {} (rule {})", target, rule);
+            return refuse(
+                &state,
+                inbound,
+                proto,
+                &target,
+                entry,
+                Reply::TooManyRequests,
+                "rate_limited",
+            )
+            .await;
+        }
+    };
+    // ... and the user's own
+    let user_bandwidth = match policy.map(|p| p.limiter.admit(&target, &resolved)) {
+        Some(Err(rule)) => {
+            info!(
+                "[dispatcher] rate limited {} for user {} (rule {})",
+                target,
+                user.as_deref().unwrap_or("?"),
+                rule
+            );
+            return refuse(
+                &state,
+                inbound,
+                proto,
+                &target,
+                entry,
+                Reply::TooManyRequests,
+                "rate_limited",
+            )
+            .await;
+        }
+        Some(Ok(bucket)) => bucket,
+        None => None,
+    };
+    if let (Some(user), Some(policy)) = (&user, policy) {
+        let exhausted = state
+            .user_usage
+            .lock()
+            .expect("user usage ledger poisoned")
+            .exhausted(user, &policy.limits);
+        if exhausted {
+            info!(
+                "[dispatcher] {}'s quota exhausted, refusing {}",
+                user, target
+            );
+            return refuse(
+                &state,
+                inbound,
+                proto,
+                &target,
+                entry,
+                Reply::Unavailable,
+                "user_quota_exhausted",
+            )
+            .await;
+        }
+    }
+
+    // 4) The backend, unless its monthly quota is used up
+    let mut egress = &state.egress[name];
+    entry.egress = Some(name.to_string());
+    let exhausted = state
+        .usage
+        .lock()
+        .expect("usage ledger poisoned")
+        .exhausted(name, &egress.limits);
+    if exhausted {
+        info!("[dispatcher] {} quota exhausted, refusing {}", name, target);
+        return refuse(
+            &state,
+            inbound,
+            proto,
+            &target,
+            entry,
+            Reply::Unavailable,
+            "quota_exhausted",
+        )
+        .await;
+    }
+
+    // The flag pins the egress, so a full one can't overflow: spilling Tor
+    // sessions onto direct would de-anonymize them
+    let Some(mut _slot) = egress.claim() else {
+        info!(
+            "[dispatcher] {} is at max_sessions, refusing {}",
+            name, target
+        );
+        return refuse(
+            &state,
+            inbound,
+            proto,
+            &target,
+            entry,
+            Reply::Unavailable,
+            "saturated",
+        )
+        .await;
+    };
+
+    // 5) Chaos mode: injected faults
+    match state.chaos.as_ref().and_then(|c| c.fault(name)) {
+        Some(Fault::Killed) => {
+            info!("[dispatcher] chaos: {} is killed, failing {}", name, target);
+            return refuse(
+                &state,
+                inbound,
+                proto,
+                &target,
+                entry,
+                Reply::BadGateway,
+                "chaos_killed",
+            )
+            .await;
+        }
+        Some(Fault::Degraded(_)) if state.audit => {}
+        Some(Fault::Degraded(delay)) => tokio::time::sleep(delay).await,
+        None => {}
+    }
+
+    if name == "masque" && state.masque.is_none() {
+        info!(
+            "[dispatcher] egress is masque, but no relay is configured (or the \
+             `masque` feature is off); refusing {}",
+            target
+        );
+        return refuse(
+            &state,
+            inbound,
+            proto,
+            &target,
+            entry,
+            Reply::Unavailable,
+            "no_relay",
+        )
+        .await;
+    }
+    if exits.is_some() && name != "tor" {
+        info!(
+            "[dispatcher] {} pins Tor exits, but goes via {}",
+            target, name
+        );
+    }
+    if name == "direct"
+        && state.dns_mode == DnsMode::Remote
+        && target.host.ip().is_none()
+        && lan.is_none()
+    {
+        info!(
+            "[dispatcher] dns.mode=remote but Tor is off: {} resolves locally",
+            host
+        );
+    }
+
+    if state.dry_run {
+        info!("[dispatcher] dry run: would dial {} via {}", target, name);
+        entry.outcome = "dry_run".to_string();
+        inbound.write_all(&Reply::Unavailable.bytes(proto)).await?;
+        return Ok(());
     }
+    if state.audit {
+        return pass_through(&state, inbound, proto, &target, entry, "allowed").await;
+    }
+
+    // An error from here on means the egress is unreachable, unless
+    // `[retry]` says otherwise for the kind of failure
+    entry.outcome = "connecting".to_string();
+    let mut attempt = 0;
+    let mut fell_back = false;
+    let outbound = loop {
+        let dialing = dial(
+            &state,
+            name,
+            &dial_target,
+            isolation.as_deref(),
+            exits.as_deref(),
+            attempt,
+        );
+        let dialed = match state.retry.connect_timeout_secs {
+            0 => dialing.await,
+            secs => {
+                let limit = Duration::from_secs(secs);
+                tokio::time::timeout(limit, dialing)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(DialTimeout {
+                            phase: "dial",
+                            after: limit,
+                        }
+                        .into())
+                    })
+            }
+        };
+        let e = match dialed {
+            Ok(outbound) => break outbound,
+            // Tor is up, the destination just can't be reached the way it must be
+            Err(e) if e.downcast_ref::<ExitsUnavailable>().is_some() => {
+                info!("[dispatcher] tor: refusing {}: {}", target, e);
+                emit_failover(&state, name, format!("{} for {}", e, target));
+                entry.outcome = "exit_unavailable".to_string();
+                inbound.write_all(&Reply::Unavailable.bytes(proto)).await?;
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        let class = FailureClass::of(&*e);
+        let retry = state.retry.policy(class);
+        if attempt < retry.retries {
+            attempt += 1;
+            let backoff = retry.backoff_ms.saturating_mul(1 << (attempt - 1).min(16));
+            info!(
+                "[dispatcher] {} via {}: {} ({}), retrying in {}ms ({}/{})",
+                target, name, e, class, backoff, attempt, retry.retries
+            );
+            tokio::time::sleep(Duration::from_millis(backoff)).await;
+            continue;
+        }
+        // Once, and only onto an egress with room and quota left
+        let fallback = retry
+            .fallback
+            .map(|kind| kind.as_str())
+            .filter(|fallback| !fell_back && *fallback != name)
+            .filter(|fallback| {
+                let egress = &state.egress[fallback];
+                !state
+                    .usage
+                    .lock()
+                    .expect("usage ledger poisoned")
+                    .exhausted(fallback, &egress.limits)
+            })
+            .and_then(|fallback| Some((fallback, state.egress[fallback].claim()?)));
+        if let Some((fallback, slot)) = fallback {
+            info!(
+                "[dispatcher] {} via {}: {} ({}), falling back to {}",
+                target, name, e, class, fallback
+            );
+            let reason = format!(
+                "{} ({}) for {}, falling back to {}",
+                e, class, target, fallback
+            );
+            emit_failover(&state, name, reason);
+            (name, egress, _slot) = (fallback, &state.egress[fallback], slot);
+            (attempt, fell_back) = (0, true);
+            entry.egress = Some(name.to_string());
+            continue;
+        }
+        if !retry.kill_switch {
+            entry.outcome = "dial_failed".to_string();
+        }
+        inbound.write_all(&Reply::from(class).bytes(proto)).await?;
+        return Err(e);
+    };
+
+    if state.kill_switch.release() {
+        emit_kill_switch(&state, false, "kill switch released".to_string());
+    }
+    // Through a backend again: the portal is passed, normal policy resumes
+    if name != "direct" && PortalMode::end(PORTAL_PATH).is_ok_and(|was_on| was_on) {
+        info!(
+            "[dispatcher] {} gets through {}, portal mode ended",
+            target, name
+        );
+        emit_failover(
+            &state,
+            alerts::DISPATCHER,
+            format!("portal mode ended ({} reachable again)", name),
+        );
+    }
+    entry.outcome = "established".to_string();
+    inbound.write_all(&Reply::Established.bytes(proto)).await?;
+
+    // 7) Relay, shaped by the rule and backend ceilings
+    let limits: Vec<_> = bandwidth
+        .into_iter()
+        .chain(user_bandwidth)
+        .chain(egress.bandwidth.clone())
+        .collect();
+    let keepalive = &egress.keepalive;
+    let first_byte = egress.timeouts.first_byte();
+    if let Some(kib) = egress.limits.buffer_kib {
+        relay::cap_buffers(inbound.as_any(), kib)?;
+    }
+    let activity = Arc::new(Activity::default());
+    let inbound = Tracked::new(inbound, activity.clone());
+    let relaying = async {
+        if keepalive.dead_after_secs == 0 && first_byte.is_none() {
+            return match &state.ring {
+                Some(ring) => ring.relay(inbound, outbound, &limits, &egress.meter).await,
+                None => relay(inbound, outbound, &limits, &egress.meter).await,
+            };
+        }
+        let watchdog = Watchdog {
+            dead_after: Duration::from_secs(keepalive.dead_after_secs),
+            first_byte: first_byte.unwrap_or_default(),
+            redials: keepalive.redials,
+        };
+        // Whichever comes first, before any answer
+        let unanswered = match (watchdog.dead_after, first_byte) {
+            (dead, Some(first)) if dead.is_zero() || first <= dead => (first, true),
+            (dead, _) => (dead, false),
+        };
+        let relayed = relay_watched(
+            inbound,
+            outbound,
+            &limits,
+            &egress.meter,
+            watchdog,
+            |attempt| {
+                info!(
+                    "[dispatcher] {} left {} unanswered for {}s, redialing ({}/{})",
+                    name,
+                    target,
+                    unanswered.0.as_secs(),
+                    attempt,
+                    keepalive.redials
+                );
+                let what = if unanswered.1 {
+                    egress.overruns.fetch_add(1, Ordering::Relaxed);
+                    "no first byte"
+                } else {
+                    "dead peer"
+                };
+                let reason = format!(
+                    "{} on {}, redialing ({}/{})",
+                    what, target, attempt, keepalive.redials
+                );
+                emit_failover(&state, name, reason);
+                let (state, target) = (state.clone(), dial_target.clone());
+                let (isolation, exits) = (isolation.clone(), exits.clone());
+                async move {
+                    dial(
+                        &state,
+                        name,
+                        &target,
+                        isolation.as_deref(),
+                        exits.as_deref(),
+                        attempt,
+                    )
+                    .await
+                    .map_err(io::Error::other)
+                }
+            },
+        )
+        .await;
+        if let Err(e) = &relayed {
+            if e.get_ref().is_some_and(|e| e.is::<DialTimeout>()) {
+                egress.overruns.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        relayed
+    };
+    // Idle past its limit, the session gives its slot back
+    let (up, down, reaped) = match state.idle.for_host(&target.host, &resolved) {
+        None => {
+            let (up, down) = relaying.await?;
+            (up, down, false)
+        }
+        Some(limit) => tokio::select! {
+            relayed = relaying => {
+                let (up, down) = relayed?;
+                (up, down, false)
+            }
+            _ = activity.idle_for(limit) => {
+                info!(
+                    "[dispatcher] {} via {} idle for {}s, closing",
+                    target,
+                    name,
+                    limit.as_secs()
+                );
+                egress.idle_closed.fetch_add(1, Ordering::Relaxed);
+                let (up, down) = activity.bytes();
+                (up, down, true)
+            }
+        },
+    };
+    (entry.bytes_up, entry.bytes_down) = (up, down);
+    if name == "tor" {
+        state.rotation.record(&target.host_str(), up + down);
+    }
+    if let (Some(user), Some(policy)) = (&user, policy) {
+        state
+            .user_usage
+            .lock()
+            .expect("user usage ledger poisoned")
+            .record(user, up + down, &policy.limits);
+    }
+    entry.outcome = match reaped {
+        true => "idle_closed",
+        false => "relayed",
+    }
+    .to_string();
+
+    Ok(())
+}
+
+/// Refuse the session with `reply`, recording `outcome`; in audit mode, pass
+/// it through instead.
+async fn refuse<I: Upstream + 'static>(
+    state: &State,
+    mut inbound: I,
+    proto: Proto,
+    target: &Target,
+    entry: &mut TrafficEntry,
+    reply: Reply,
+    outcome: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if state.audit {
+        return pass_through(state, inbound, proto, target, entry, outcome).await;
+    }
+    entry.outcome = outcome.to_string();
+    inbound.write_all(&reply.bytes(proto)).await?;
+    Ok(())
+}
+
+/// Audit mode: forward the session direct and unshaped, as if there were no
+/// policy, and record what enforcement `would` have done.
+async fn pass_through<I: Upstream + 'static>(
+    state: &State,
+    mut inbound: I,
+    proto: Proto,
+    target: &Target,
+    entry: &mut TrafficEntry,
+    would: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    entry.audit = Some(would.to_string());
+    *state
+        .audited
+        .lock()
+        .expect("audit counts poisoned")
+        .entry(would.to_string())
+        .or_default() += 1;
+    let outbound = dial(state, "direct", target, None, None, 0).await?;
+    inbound.write_all(&Reply::Established.bytes(proto)).await?;
+    let (up, down) = relay(inbound, outbound, &[], &state.egress["direct"].meter).await?;
+    (entry.bytes_up, entry.bytes_down) = (up, down);
+    entry.outcome = "relayed".to_string();
+    Ok(())
 }
 
-async fn handle_client(mut inbound: TcpStream) -> Result<(), Box<dyn Error + Send + Sync>> {
-    // 1) Read HTTP CONNECT request header
+/// Read an HTTP request header, after its `first` byte; returns its method
+/// and target.
+async fn read_connect<I: Upstream>(
+    inbound: &mut I,
+    first: u8,
+) -> Result<(String, String), Box<dyn Error + Send + Sync>> {
     let mut buf = Vec::with_capacity(1024);
+    buf.push(first);
     loop {
         let mut byte = [0u8; 1];
         let n = inbound.read(&mut byte).await?;
@@ -41,52 +1061,2722 @@ async fn handle_client(mut inbound: TcpStream) -> Result<(), Box<dyn Error + Sen
     let method = parts.next().unwrap_or("");
     let target = parts.next().unwrap_or("");
     let _version = parts.next().unwrap_or("");
+    Ok((method.to_string(), target.to_string()))
+}
 
-    if method != "CONNECT" {
-        let resp = b"HTTP/1.1 405 Method Not Allowed\r\n\r\n";
-        inbound.write_all(resp).await?;
-        return Ok(());
+/// Open a connection to `target` through egress `name`. Through Tor, the
+/// app's `isolation` key is the SOCKS username, and redials (`attempt` above
+/// 0) use a fresh password; Tor's default `IsolateSOCKSAuth` puts different
+/// credentials on different circuits. Pinned `exits` are only for Tor.
+async fn dial(
+    state: &State,
+    name: &str,
+    target: &Target,
+    isolation: Option<&str>,
+    exits: Option<&[String]>,
+    attempt: u32,
+) -> Result<Box<dyn Upstream>, Box<dyn Error + Send + Sync>> {
+    let egress = &state.egress[name];
+    egress.dials.fetch_add(1, Ordering::Relaxed);
+    let (keepalive, timeouts) = (&egress.keepalive, &egress.timeouts);
+    // Keepalive probes and buffer caps, on the socket the session relays over
+    let tune = |stream: &TcpStream| {
+        if keepalive.interval_secs > 0 {
+            relay::set_keepalive(stream, Duration::from_secs(keepalive.interval_secs))?;
+        }
+        match egress.limits.buffer_kib {
+            Some(kib) => relay::cap_buffers(stream, kib),
+            None => Ok(()),
+        }
+    };
+    Ok(match name {
+        // 6a) VIA TOR (SOCKS5 → tor_socks, 127.0.0.1:9050 by default)
+        "tor" => {
+            let password = match attempt {
+                0 if state.rotation.enabled() => {
+                    let host = target.host_str();
+                    let (password, rotated) = state.rotation.isolation(&host, now_unix());
+                    if rotated {
+                        info!("[dispatcher] tor: new circuit for {}", host);
+                    }
+                    Some(password)
+                }
+                0 => None,
+                n => Some(format!("redial-{}-{}", n, now_unix())),
+            };
+            let user = isolation.unwrap_or("gold-dust");
+            let pins = exits.zip(state.exit_pins.as_ref());
+            if pins.is_some_and(|(_, pins)| !pins.attached()) {
+                let why = "no connection to Tor's control port";
+                return Err(Box::new(ExitsUnavailable(why.to_string())));
+            }
+            let socket = egress
+                .within(
+                    "connect",
+                    timeouts.connect(),
+                    TcpStream::connect(state.tor_socks),
+                )
+                .await?;
+            // Tor names the stream by this port to the exit pinning attacher
+            let pinned = match pins {
+                Some((exits, pins)) => Some(pins.register(socket.local_addr()?.port(), exits)),
+                None => None,
+            };
+            let handshake = async {
+                match (password, isolation) {
+                    (None, None) => {
+                        Socks5Stream::connect_with_socket(socket, target.socks_addr()).await
+                    }
+                    (password, _) => {
+                        Socks5Stream::connect_with_password_and_socket(
+                            socket,
+                            target.socks_addr(),
+                            user,
+                            password.as_deref().unwrap_or(user),
+                        )
+                        .await
+                    }
+                }
+            };
+            let connected = egress
+                .within("handshake", timeouts.handshake(), handshake)
+                .await;
+            let stream = match (connected, pinned.and_then(|p| p.failure())) {
+                (Err(_), Some(why)) => return Err(Box::new(ExitsUnavailable(why))),
+                (connected, _) => connected?.into_inner(),
+            };
+            tune(&stream)?;
+            Box::new(stream)
+        }
+        // 6b) VIA THE HTTP/3 RELAY (plain CONNECT, TCP only; the relay resolves the name)
+        "masque" => {
+            let masque = state.masque.as_ref().ok_or("no HTTP/3 relay")?;
+            Box::new(
+                egress
+                    .within("handshake", timeouts.handshake(), masque.connect(target))
+                    .await?,
+            )
+        }
+        // 6c) DIRECT TCP (not proxied, so the system resolver is used)
+        _ => {
+            let host = target.host_str();
+            let connect = TcpStream::connect((host.as_str(), target.port));
+            let stream = egress
+                .within("connect", timeouts.connect(), connect)
+                .await?;
+            tune(&stream)?;
+            Box::new(stream)
+        }
+    })
+}
+
+/// Publish `event` to `monitor` clients, if any are following.
+fn emit(state: &State, event: Event) {
+    let _ = state.events.send(event);
+}
+
+/// Probe for a captive portal behind a failing egress and say how to log in.
+async fn detect_portal(state: Arc<State>) {
+    let probe = portal::detect(&state.portal);
+    match tokio::time::timeout(Duration::from_secs(10), probe).await {
+        Ok(Ok(Some(found))) => {
+            let reason = format!(
+                "captive portal at {} ({}); run `gold-dust-gateway portal login`",
+                found.host, found.login_url
+            );
+            info!("[dispatcher] {}", reason);
+            emit_failover(&state, alerts::DISPATCHER, reason);
+        }
+        Ok(Ok(None)) => {}
+        Ok(Err(e)) => info!("[dispatcher] captive portal probe: {}", e),
+        Err(_) => info!("[dispatcher] captive portal probe timed out"),
     }
+}
 
-    let target = target.to_string();
+fn emit_failover(state: &State, subject: &str, reason: String) {
+    report_event(
+        state,
+        EventKind::Warning,
+        eventlog::FAILOVER,
+        &format!("{}: {}", subject, reason),
+    );
+    emit(
+        state,
+        Event::Failover(Failover {
+            unix: now_unix(),
+            subject: subject.to_string(),
+            reason,
+        }),
+    );
+}
 
-    if should_use_tor() {
-        // 2a) VIA TOR (SOCKS5 → 127.0.0.1:9050)
-        let mut outbound = Socks5Stream::connect("127.0.0.1:9050", target.clone()).await?;
-        inbound
-            .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
-            .await?;
+/// The kill switch engaging or releasing: a failover event, and an error
+/// (engaged) or information (released) in the Event Log.
+fn emit_kill_switch(state: &State, engaged: bool, reason: String) {
+    let kind = match engaged {
+        true => EventKind::Error,
+        false => EventKind::Information,
+    };
+    report_event(state, kind, eventlog::KILL_SWITCH, &reason);
+    emit(
+        state,
+        Event::Failover(Failover {
+            unix: now_unix(),
+            subject: alerts::DISPATCHER.to_string(),
+            reason,
+        }),
+    );
+}
 
-        io::copy_bidirectional(&mut inbound, &mut outbound).await?;
-    } else {
-        // 2b) DIRECT TCP
-        let mut outbound = TcpStream::connect(target.clone()).await?;
-        inbound
-            .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
-            .await?;
+/// Write to the Windows Event Log, if `[logging] event_log` opened it.
+fn report_event(state: &State, kind: EventKind, id: u32, message: &str) {
+    if let Some(log) = &state.event_log {
+        if let Err(e) = log.report(kind, id, message) {
+            warn!("[dispatcher] Event Log: {}", e);
+        }
+    }
+}
 
-        io::copy_bidirectional(&mut inbound, &mut outbound).await?;
+/// Append one JSON record to `log`, if that log is configured.
+fn append_log<T: serde::Serialize>(log: &Option<Mutex<RotatingLog>>, record: &T) {
+    let Some(log) = log else {
+        return;
+    };
+    let line = serde_json::to_string(record).expect("log records serialize");
+    let mut log = log.lock().expect("log file poisoned");
+    if let Err(e) = log.write_line(&line) {
+        warn!("[dispatcher] log {}: {}", log.path().display(), e);
     }
+}
 
-    Ok(())
+/// Send one record of the `msgid` log (`traffic`, `health`) to syslog, if
+/// `[logging.syslog]` takes it. A failure is logged once, until sends work
+/// again.
+fn send_syslog<T: serde::Serialize>(state: &State, msgid: &str, record: &T) {
+    let Some(syslog) = &state.syslog else {
+        return;
+    };
+    let mut syslog = syslog.lock().expect("syslog poisoned");
+    if !syslog.wants(msgid) {
+        return;
+    }
+    let record = serde_json::to_value(record).expect("log records serialize");
+    let failing = syslog.failing();
+    match syslog.send(msgid, &record) {
+        Ok(()) if failing => info!("[dispatcher] syslog: sending again"),
+        Err(e) if !failing => {
+            warn!(
+                "[dispatcher] syslog: {} (dropping records until it works)",
+                e
+            )
+        }
+        _ => {}
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    let addr: SocketAddr = "127.0.0.1:7777".parse()?;
-    let listener = TcpListener::bind(addr).await?;
-    println!(
-        "[dispatcher] HTTP CONNECT proxy on {} (flag: {}, 'on' = Tor, 'off' = direct)",
-        addr, FLAG_PATH
+/// Record a finished session in the journal, if connected: what was decided
+/// as fields, the target only as a hash.
+fn journal_decision(entry: &TrafficEntry) {
+    let Some(journal) = journal::get() else {
+        return;
+    };
+    let egress = entry.egress.as_deref().unwrap_or("none");
+    let priority = match entry.outcome.as_str() {
+        "relayed" => Priority::Info,
+        _ => Priority::Notice,
+    };
+    let hash = journal::target_hash(&entry.target);
+    let (up, down, ms) = (
+        entry.bytes_up.to_string(),
+        entry.bytes_down.to_string(),
+        entry.duration_ms.to_string(),
     );
+    let message = format!("session {} via {}: {}", hash, egress, entry.outcome);
+    let mut fields = vec![
+        ("GD_BACKEND", egress),
+        ("GD_TARGET_HASH", hash.as_str()),
+        ("GD_DECISION", entry.outcome.as_str()),
+        ("GD_BYTES_UP", up.as_str()),
+        ("GD_BYTES_DOWN", down.as_str()),
+        ("GD_DURATION_MS", ms.as_str()),
+    ];
+    if let Some(app) = &entry.app {
+        fields.push(("GD_APP", app));
+    }
+    if let Some(would) = &entry.audit {
+        fields.push(("GD_AUDIT", would));
+    }
+    if let Err(e) = journal.send(priority, &message, &fields) {
+        warn!("[dispatcher] journald: {}", e);
+    }
+}
+
+/// Byte counters as of the last stats publish, for rates and ledger deltas.
+struct Published {
+    bytes: BTreeMap<&'static str, u64>,
+    at: Instant,
+    last: TrafficSnapshot,
+}
+
+/// Publish per-egress throughput for `gold-dust-gateway status`, fold it
+/// into the persisted quota ledger, and save circuit rotation state.
+fn publish(state: &State) {
+    if state.handed_over.load(Ordering::SeqCst) {
+        return;
+    }
+    let mut published = state.published.lock().expect("stats poisoned");
+    let elapsed = published.at.elapsed().as_secs_f64().max(0.001);
+    published.at = Instant::now();
+
+    let mut snapshot = TrafficSnapshot {
+        updated_unix: now_unix(),
+        interval_secs: state.power.interval(STATS_EVERY).as_secs(),
+        low_power: state.power.is_low(),
+        standby: state.standby.lock().expect("standby poisoned").clone(),
+        ha: state.ha.lock().expect("ha poisoned").clone(),
+        ..Default::default()
+    };
+    let mut usage = state.usage.lock().expect("usage ledger poisoned");
+    for (name, egress) in &state.egress {
+        let bytes = egress.meter.bytes();
+        let delta = bytes - published.bytes.insert(name, bytes).unwrap_or(0);
+        usage.record(name, delta, &egress.limits);
+        snapshot.egress.insert(
+            name.to_string(),
+            EgressUsage {
+                bytes_total: bytes,
+                rate_kbps: delta as f64 * 8.0 / 1000.0 / elapsed,
+                limit_kbps: egress.limits.bandwidth_kbps,
+                sessions: egress.active.load(Ordering::SeqCst),
+                max_sessions: egress.limits.max_sessions,
+                dials: egress.dials.load(Ordering::Relaxed),
+                overruns: egress.overruns.load(Ordering::Relaxed),
+                idle_closed: egress.idle_closed.load(Ordering::Relaxed),
+            },
+        );
+    }
+    // Per egress only; what says where traffic went, or whose, is sealed
+    if let Err(e) = usage.save(USAGE_PATH, None) {
+        warn!("[dispatcher] could not write {}: {}", USAGE_PATH, e);
+    }
+    drop(usage);
+    if !state.users.is_empty() {
+        let user_usage = state.user_usage.lock().expect("user usage ledger poisoned");
+        if let Err(e) = user_usage.save(USER_USAGE_PATH, state.sealer.as_deref()) {
+            warn!("[dispatcher] could not write {}: {}", USER_USAGE_PATH, e);
+        }
+    }
+
+    if let Err(e) = state.rotation.save(ROTATION_PATH, state.sealer.as_deref()) {
+        warn!("[dispatcher] could not write {}: {}", ROTATION_PATH, e);
+    }
+
+    if let Err(e) = snapshot.save(STATS_PATH) {
+        warn!("[dispatcher] could not write {}: {}", STATS_PATH, e);
+    }
+    published.last = snapshot;
+}
 
+/// Publish stats every `STATS_EVERY`.
+async fn publish_stats(state: Arc<State>) {
+    let mut ticker = state.power.ticker(STATS_EVERY);
     loop {
-        let (socket, peer) = listener.accept().await?;
-        println!("[dispatcher] new client from {}", peer);
-        tokio::spawn(async move {
-            if let Err(e) = handle_client(socket).await {
-                eprintln!("[dispatcher] error: {}", e);
+        ticker.tick().await;
+        publish(&state);
+    }
+}
+
+/// Fetch the `[standby]` canary through Tor every interval while Tor carries
+/// no sessions. Tor stays bootstrapped, with fresh circuits built ahead for
+/// the ports it has seen used, so switching to it costs a stream rather than
+/// a bootstrap. Skipped in low power: a fetch wakes the radio for a page.
+async fn run_standby(cfg: StandbyConfig, state: Arc<State>) {
+    let url = match Url::parse(&cfg.canary) {
+        Ok(url) => url,
+        Err(e) => {
+            warn!("[dispatcher] standby: bad canary URL: {}", e);
+            return;
+        }
+    };
+    let mut ticker = state
+        .power
+        .probe_ticker(Duration::from_secs(cfg.interval_secs.max(10)));
+    let mut warm = None;
+
+    loop {
+        ticker.tick().await;
+        // Sessions keep it warm as it is
+        if state.egress["tor"].active.load(Ordering::SeqCst) > 0 || state.power.is_low() {
+            continue;
+        }
+        let started = Instant::now();
+        let fetched = tokio::time::timeout(CANARY_TIMEOUT, fetch_canary(state.tor_socks, &url))
+            .await
+            .unwrap_or_else(|_| Err("timed out".into()));
+        let canary = Canary {
+            unix: now_unix(),
+            latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+            error: fetched.err().map(|e| e.to_string()),
+        };
+        match &canary.error {
+            None if warm != Some(true) => info!(
+                "[dispatcher] standby: tor is warm (canary {:.0} ms)",
+                canary.latency_ms
+            ),
+            Some(e) if warm != Some(false) => {
+                warn!("[dispatcher] standby: tor canary failed: {}", e)
+            }
+            _ => {}
+        }
+        warm = Some(canary.error.is_none());
+        *state.standby.lock().expect("standby poisoned") = Some(canary);
+    }
+}
+
+/// GET `url` through Tor, on the circuits sessions without credentials use.
+async fn fetch_canary(
+    tor_socks: SocketAddr,
+    url: &Url,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let tcp = Socks5Stream::connect(tor_socks, (url.host.as_str(), url.port))
+        .await?
+        .into_inner();
+    let resp = http::request_over(tcp, "GET", url, &[], &[]).await?;
+    if resp.status >= 400 {
+        return Err(format!("canary answered HTTP {}", resp.status).into());
+    }
+    Ok(())
+}
+
+/// Watch for the machine waking from sleep, then drop what went stale with
+/// it: health reports, the standby canary, DNS answers and idle connections.
+/// Tor is woken and told to build new circuits, and lokinet exits are
+/// mapped again, so sessions right after wake-up don't hang on dead paths.
+async fn watch_sleep(cfg: GoldDustConfig, state: Arc<State>) {
+    let mut watch = SleepWatch::new(Duration::from_secs(cfg.dispatcher.resume_after_secs));
+    let mut ticker = state.power.ticker(Duration::from_secs(5));
+    loop {
+        ticker.tick().await;
+        let Some(slept) = watch.check() else {
+            continue;
+        };
+        let reason = format!(
+            "woke after ~{}s asleep, re-checking backends",
+            slept.as_secs()
+        );
+        info!("[dispatcher] {}", reason);
+        emit_failover(&state, alerts::DISPATCHER, reason);
+
+        {
+            let mut board = state.board.lock().expect("health board poisoned");
+            for report in board.reports.values_mut() {
+                report.stale = true;
+            }
+            if let Err(e) = board.save(HEALTH_PATH) {
+                warn!("[dispatcher] could not write {}: {}", HEALTH_PATH, e);
+            }
+        }
+        state.standby.lock().expect("standby poisoned").take();
+        if let Some(doh) = &state.resolver {
+            doh.reset();
+        }
+        if let Some(dot) = &state.dot {
+            dot.reset().await;
+        }
+        for resolver in state.resolvers.values() {
+            resolver.reset().await;
+        }
+        if let Some(masque) = &state.masque {
+            masque.reset().await;
+        }
+
+        let patience = Duration::from_secs(10);
+        if cfg.backends.tor_enabled {
+            match tokio::time::timeout(patience, torctl::wake(&cfg.tor_control)).await {
+                Ok(Ok(true)) => info!("[dispatcher] tor: awake, new circuits for new streams"),
+                Ok(Ok(false)) => {
+                    info!("[dispatcher] tor: no circuit established, woken to rebuild")
+                }
+                Ok(Err(e)) => warn!(
+                    "[dispatcher] tor control port {}: {} (not woken)",
+                    cfg.tor_control.address, e
+                ),
+                Err(_) => warn!("[dispatcher] tor control port: timed out (not woken)"),
+            }
+        }
+        if !cfg.lokinet.exits.is_empty() {
+            remap_lokinet_exits(&cfg, patience).await;
+        }
+    }
+}
+
+/// Follow the power supply in `[power] mode = "auto"`.
+async fn watch_power(state: Arc<State>) {
+    let mut ticker = tokio::time::interval(POWER_CHECK);
+    loop {
+        ticker.tick().await;
+        match state.power.refresh() {
+            Some(true) => info!("[dispatcher] on battery: low-power probing"),
+            Some(false) => info!("[dispatcher] on mains power: normal probing"),
+            None => {}
+        }
+    }
+}
+
+/// Map the configured lokinet exits that were in use again, with their
+/// tokens: the daemon's exit sessions don't outlive a long sleep.
+async fn remap_lokinet_exits(cfg: &GoldDustConfig, patience: Duration) {
+    let rpc = cfg.lokinet.rpc;
+    let status = tokio::time::timeout(patience, lokinet::status(rpc))
+        .await
+        .unwrap_or_else(|_| Err("timed out".into()));
+    let active = match status {
+        Ok(status) => lokinet::active_exits(&status),
+        Err(e) => {
+            warn!("[dispatcher] lokinet RPC {}: {}", rpc, e);
+            return;
+        }
+    };
+    for exit in cfg
+        .lokinet
+        .exits
+        .iter()
+        .filter(|e| active.iter().any(|(_, a)| *a == e.address))
+    {
+        match tokio::time::timeout(patience, lokinet::set_exit(rpc, exit)).await {
+            Ok(Ok(())) => info!("[dispatcher] lokinet: exit {} mapped again", exit.address),
+            Ok(Err(e)) => warn!("[dispatcher] lokinet: exit {}: {}", exit.address, e),
+            Err(_) => warn!("[dispatcher] lokinet: exit {}: timed out", exit.address),
+        }
+    }
+}
+
+/// Re-fetch blocklist sources into the shared cache every `refresh_secs`.
+async fn refresh_blocklist(cfg: BlocklistConfig) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(cfg.refresh_secs.max(60)));
+
+    loop {
+        ticker.tick().await;
+        let list = Blocklist::fetch(&cfg).await;
+        for source in list.sources.iter().filter(|s| s.error.is_some()) {
+            warn!(
+                "[dispatcher] blocklist {}: {}",
+                source.source,
+                source.error.as_deref().unwrap_or("")
+            );
+        }
+        info!(
+            "[dispatcher] blocklist refreshed: {} entries",
+            list.entries.len()
+        );
+        if let Err(e) = list.save(BLOCKLIST_PATH) {
+            warn!("[dispatcher] could not write {}: {}", BLOCKLIST_PATH, e);
+        }
+    }
+}
+
+/// Re-fetch `[dns.block]` sources into the dispatcher's list every
+/// `refresh_secs`.
+async fn refresh_dns_block(cfg: DnsBlockConfig, state: Arc<State>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(cfg.refresh_secs.max(60)));
+
+    loop {
+        ticker.tick().await;
+        let (list, sources) = NameBlocklist::fetch(&cfg).await;
+        for source in sources.iter().filter(|s| s.error.is_some()) {
+            warn!(
+                "[dispatcher] dns block list {}: {}",
+                source.source,
+                source.error.as_deref().unwrap_or("")
+            );
+        }
+        info!(
+            "[dispatcher] dns block lists refreshed: {} names",
+            list.len()
+        );
+        *state.dns_block.write().expect("dns block list poisoned") = list;
+    }
+}
+
+/// Fold reports into the health board and publish it for `status`.
+///
+/// With an error budget, the board holds the verdict rather than the
+/// report: down while the budget is burnt, up otherwise.
+fn merge_reports(state: &State, reports: impl IntoIterator<Item = Report>) {
+    let mut board = state.board.lock().expect("health board poisoned");
+    let mut availability = state.availability.lock().expect("availability poisoned");
+    for report in reports {
+        let record = report.clone();
+        let name = &record.health.name;
+        let was = board.reports.get(name).map(|r| r.health.enabled);
+        if board.merge(report) {
+            emit(state, Event::Probe(record.clone()));
+            availability.record(name, record.observed_unix, record.health.enabled);
+            let spent = availability.budget_spent(name, record.observed_unix, &state.failover);
+            let up = spent.map_or(record.health.enabled, |spent| spent <= 1.0);
+            if let Some(kept) = board.reports.get_mut(name) {
+                kept.health.enabled = up;
+            }
+            let budget = |spent: f64| {
+                format!(
+                    "{:.0}% of the error budget spent over {}s",
+                    spent * 100.0,
+                    state.failover.window_secs
+                )
+            };
+            match (was, up, spent) {
+                (Some(true), false, Some(spent)) => emit_failover(
+                    state,
+                    name,
+                    format!("down, out of rotation: {}", budget(spent)),
+                ),
+                (Some(true), false, None) => emit_failover(
+                    state,
+                    name,
+                    format!("down, out of rotation (reported by {})", record.node),
+                ),
+                (Some(false), true, Some(spent)) => {
+                    emit_failover(state, name, format!("back up: {}", budget(spent)))
+                }
+                (Some(false), true, None) => emit_failover(
+                    state,
+                    name,
+                    format!("back up (reported by {})", record.node),
+                ),
+                _ => {}
+            }
+            state
+                .history
+                .lock()
+                .expect("health history poisoned")
+                .record(&record);
+            append_log(&state.health_log, &record);
+            send_syslog(state, "health", &record);
+        }
+    }
+    if state.handed_over.load(Ordering::SeqCst) {
+        return;
+    }
+    if let Err(e) = board.save(HEALTH_PATH) {
+        warn!("[dispatcher] could not write {}: {}", HEALTH_PATH, e);
+    }
+    if let Err(e) = availability.save(AVAILABILITY_PATH) {
+        warn!("[dispatcher] could not write {}: {}", AVAILABILITY_PATH, e);
+    }
+}
+
+/// File fed health under the feed's node name.
+fn feed_reports(healths: Vec<BackendHealth>) -> Vec<Report> {
+    let observed_unix = now_unix();
+    healths
+        .into_iter()
+        .map(|health| Report {
+            node: FEED_NODE.to_string(),
+            observed_unix,
+            health,
+            stale: false,
+        })
+        .collect()
+}
+
+/// Accept backend health from an external monitoring system: `POST /health`
+/// (JSON array or NDJSON) and, optionally, NDJSON on stdin.
+async fn run_health_feed(
+    cfg: HealthFeedConfig,
+    listener: std::net::TcpListener,
+    gate: Arc<Gate>,
+    state: Arc<State>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if cfg.stdin {
+        let state = state.clone();
+        tokio::task::spawn_blocking(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else { break };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match feed::parse_line(&line) {
+                    Ok(health) => merge_reports(&state, feed_reports(vec![health])),
+                    Err(e) => warn!("[dispatcher] bad health feed line: {}", e),
+                }
             }
         });
     }
+
+    let feed_state = state.clone();
+    let app = axum::Router::new().route(
+        "/health",
+        post(move |body: String| async move {
+            match feed::parse(&body) {
+                Ok(healths) => {
+                    let n = healths.len();
+                    merge_reports(&feed_state, feed_reports(healths));
+                    (StatusCode::OK, format!("accepted {}\n", n))
+                }
+                Err(e) => (StatusCode::BAD_REQUEST, format!("{}\n", e)),
+            }
+        }),
+    );
+    let app = with_gate(app, gate, state);
+    let listener = TcpListener::from_std(listener)?;
+    info!("[dispatcher] health feed on http://{}/health", cfg.listen);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<Caller>(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Everything the dashboard shows, right now.
+fn dashboard_status(state: &State) -> dashboard::Status {
+    let (egress, standby) = {
+        let published = state.published.lock().expect("stats poisoned");
+        (
+            published.last.egress.clone(),
+            published.last.standby.clone(),
+        )
+    };
+    let backends = state
+        .board
+        .lock()
+        .expect("health board poisoned")
+        .reports
+        .values()
+        .cloned()
+        .collect();
+    let mut modes = vec!["tor".to_string(), "direct".to_string()];
+    if state.masque.is_some() {
+        modes.push("masque".to_string());
+    }
+    dashboard::Status {
+        updated_unix: now_unix(),
+        mode: flag_egress().to_string(),
+        modes,
+        dry_run: state.dry_run,
+        audit: state.audit,
+        standby,
+        sessions: state.sessions.load(Ordering::SeqCst),
+        egress,
+        backends,
+        history: state
+            .history
+            .lock()
+            .expect("health history poisoned")
+            .clone(),
+        decisions: state
+            .decisions
+            .lock()
+            .expect("decisions poisoned")
+            .iter()
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Refuse requests not addressed to the dashboard on loopback.
+async fn local_only(req: Request, next: Next) -> Response {
+    let local = {
+        let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+        dashboard::local_request(header("host"), header("origin"))
+    };
+    if local {
+        next.run(req).await
+    } else {
+        (
+            StatusCode::FORBIDDEN,
+            "the dashboard only answers on loopback\n",
+        )
+            .into_response()
+    }
+}
+
+/// Push the status to a dashboard page once a second.
+async fn push_status(mut socket: WebSocket, state: Arc<State>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        ticker.tick().await;
+        let status = serde_json::to_string(&dashboard_status(&state)).expect("status serializes");
+        if socket.send(Message::Text(status)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Who is on an admin connection: their certificate's common name, and
+/// what `[admin] viewers` lets them do.
+#[derive(Debug, Clone)]
+struct AdminClient {
+    name: String,
+    access: Access,
+}
+
+/// Who is behind a connection to an HTTP API: the local user, if it came
+/// over loopback, else just its address. Looked up once per connection.
+#[derive(Debug, Clone)]
+struct Caller {
+    uid: Option<u32>,
+    /// What `[api]` limits and metrics know the caller by.
+    name: String,
+}
+
+impl Connected<IncomingStream<'_>> for Caller {
+    fn connect_info(stream: IncomingStream<'_>) -> Self {
+        let peer = stream.remote_addr();
+        let uid = match stream.local_addr() {
+            Ok(local) if peer.ip().is_loopback() => peer::tcp_uid(local, peer),
+            _ => None,
+        };
+        let name = match uid {
+            Some(uid) => peer::user_name(uid).unwrap_or_else(|| format!("uid {}", uid)),
+            None => peer.ip().to_string(),
+        };
+        Caller { uid, name }
+    }
+}
+
+/// How a request to the HTTP APIs gets its role (`[api]`): by the token it
+/// carries, else by the local user behind it.
+#[derive(Default)]
+struct Gate {
+    /// The `[api]` tokens and what each may do.
+    tokens: Vec<(ApiToken, Access)>,
+    grants: Grants,
+    /// No `[api] auth`: anyone who reaches a listener may do anything.
+    open: bool,
+}
+
+impl Gate {
+    fn access(&self, req: &Request) -> Option<Access> {
+        let authorization = authorization(req);
+        let by_token = self
+            .tokens
+            .iter()
+            .find(|(token, _)| token.allows(authorization, req.uri().query()))
+            .map(|(_, access)| *access);
+        let by_user = req
+            .extensions()
+            .get::<ConnectInfo<Caller>>()
+            .and_then(|ConnectInfo(caller)| self.grants.for_uid(caller.uid?));
+        // Whichever gives more
+        by_token
+            .max(by_user)
+            .or(self.open.then_some(Access::Operate))
+    }
+
+    /// Whether `req` carries a token that isn't one of ours. Such a request
+    /// is refused, and counts as a failed authentication, whatever its user
+    /// may do.
+    fn wrong_token(&self, req: &Request) -> bool {
+        let (authorization, query) = (authorization(req), req.uri().query());
+        !self.open
+            && token::presented(authorization, query).is_some()
+            && !self
+                .tokens
+                .iter()
+                .any(|(token, _)| token.allows(authorization, query))
+    }
+}
+
+fn authorization(req: &Request) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+}
+
+/// The dashboard: the page, `GET /api/status`, `GET /api/decisions`,
+/// `POST /api/mode`, the `/api/events` stream and the `/api/ws` live feed.
+fn dashboard_app(state: Arc<State>) -> axum::Router {
+    let (status_state, decisions_state, mode_state) = (state.clone(), state.clone(), state.clone());
+    let events_state = state.clone();
+    axum::Router::new()
+        .route("/", get(|| async { Html(dashboard::PAGE) }))
+        .route(
+            "/api/status",
+            get(move || async move { Json(dashboard_status(&status_state)) }),
+        )
+        .route(
+            "/api/decisions",
+            get(move || async move {
+                let decisions = decisions_state
+                    .decisions
+                    .lock()
+                    .expect("decisions poisoned");
+                Json(decisions.iter().cloned().collect::<Vec<_>>())
+            }),
+        )
+        // JSON only: a cross-site form can't send it, and a cross-site
+        // fetch would need a CORS preflight this server never grants
+        .route(
+            "/api/mode",
+            post(move |client, caller, Json(req)| {
+                set_mode(mode_state.clone(), client, caller, req)
+            }),
+        )
+        .route(
+            "/api/events",
+            get(move || async move {
+                let events = futures_util::stream::unfold(
+                    events_state.events.subscribe(),
+                    |mut rx| async move {
+                        let event = match rx.recv().await {
+                            Ok(event) => SseEvent::default()
+                                .data(serde_json::to_string(&event).expect("events serialize")),
+                            Err(RecvError::Lagged(n)) => {
+                                SseEvent::default().comment(format!("skipped {}", n))
+                            }
+                            Err(RecvError::Closed) => return None,
+                        };
+                        Some((Ok::<_, Infallible>(event), rx))
+                    },
+                );
+                Sse::new(events).keep_alive(KeepAlive::default())
+            }),
+        )
+        .route(
+            "/api/ws",
+            get(move |ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(move |socket| push_status(socket, state))
+            }),
+        )
+}
+
+/// `POST /api/mode`: switch the egress by rewriting the flag file.
+async fn set_mode(
+    state: Arc<State>,
+    client: Option<Extension<AdminClient>>,
+    caller: Option<ConnectInfo<Caller>>,
+    req: ModeRequest,
+) -> (StatusCode, String) {
+    let flag = match req.mode.as_str() {
+        "tor" => "on",
+        "direct" => "off",
+        "masque" if state.masque.is_some() => "masque",
+        other => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("unknown mode `{}`\n", other),
+            )
+        }
+    };
+    let (who, via) = match (client, caller) {
+        (Some(Extension(AdminClient { name, .. })), _) => (format!("admin {}", name), "admin"),
+        (None, Some(ConnectInfo(caller))) => (caller.name, "dashboard"),
+        (None, None) => ("?".to_string(), "dashboard"),
+    };
+    let by = match via {
+        "admin" => who.clone(),
+        _ => via.to_string(),
+    };
+    let previous = flag_egress();
+    match fs::write(FLAG_PATH, format!("{}\n", flag)) {
+        Ok(()) => {
+            info!("[dispatcher] {} switched egress to {}", by, req.mode);
+            let action = audit::Action::new(who, via, "mode")
+                .from(previous)
+                .to(req.mode.as_str());
+            if let Err(e) = audit::record(&state.audit_trail, &action, state.sealer.as_deref()) {
+                warn!(
+                    "[dispatcher] could not record to {}: {}",
+                    state.audit_trail.display(),
+                    e
+                );
+            }
+            let reason = format!("egress switched to {} ({})", req.mode, by);
+            emit_failover(&state, alerts::DISPATCHER, reason);
+            (StatusCode::OK, format!("{}\n", req.mode))
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("{}: {}\n", FLAG_PATH, e),
+        ),
+    }
+}
+
+/// Let requests to `app` through as far as their role allows: reads need
+/// [`Access::Read`], anything else [`Access::Operate`]. The dashboard page
+/// itself carries no data and is served to anyone. Every caller is held to
+/// the `[api]` request budget first, and locked out after too many failed
+/// authentications.
+fn with_gate(app: axum::Router, gate: Arc<Gate>, state: Arc<State>) -> axum::Router {
+    app.layer(middleware::from_fn(move |req: Request, next: Next| {
+        let (gate, state) = (gate.clone(), state.clone());
+        async move {
+            let admin = req.extensions().get::<AdminClient>();
+            let caller = match (admin, req.extensions().get::<ConnectInfo<Caller>>()) {
+                (Some(client), _) => format!("admin {}", client.name),
+                (None, Some(ConnectInfo(caller))) => caller.name.clone(),
+                (None, None) => "-".to_string(),
+            };
+            let stream = matches!(req.uri().path(), "/api/events" | "/api/ws");
+            if let Err(refusal) = state.api_throttle.admit(&caller, stream) {
+                return too_many_requests(refusal);
+            }
+            let granted = match admin {
+                Some(client) => Some(client.access),
+                None if gate.wrong_token(&req) => None,
+                None => gate.access(&req),
+            };
+            if req.uri().path() == "/" {
+                return next.run(req).await;
+            }
+            let needed = Access::needed(req.method().as_str());
+            match granted {
+                Some(access) if access >= needed => {
+                    state.api_throttle.succeeded(&caller);
+                    next.run(req).await
+                }
+                Some(access) => {
+                    state.api_throttle.succeeded(&caller);
+                    warn!(
+                        "[dispatcher] refused {} {}: {} access only",
+                        req.method(),
+                        req.uri().path(),
+                        access.as_str()
+                    );
+                    (
+                        StatusCode::FORBIDDEN,
+                        format!(
+                            "{} access only; this needs {}\n",
+                            access.as_str(),
+                            needed.as_str()
+                        ),
+                    )
+                        .into_response()
+                }
+                None => {
+                    if state.api_throttle.failed(&caller) {
+                        warn!(
+                            "[dispatcher] API client {} locked out for {}s after repeated failed authentication",
+                            caller,
+                            state.api_throttle.lockout().as_secs()
+                        );
+                    }
+                    (
+                        StatusCode::UNAUTHORIZED,
+                        [(header::WWW_AUTHENTICATE, "Bearer")],
+                        "missing or wrong API token ([api] token_file)\n",
+                    )
+                        .into_response()
+                }
+            }
+        }
+    }))
+}
+
+/// `429`, with when to come back.
+fn too_many_requests(refusal: Refusal) -> Response {
+    let secs = refusal.retry_after().as_secs_f64().ceil() as u64;
+    let why = match refusal {
+        Refusal::Throttled(_) => "too many requests",
+        Refusal::LockedOut(_) => "locked out after repeated failed authentication",
+    };
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.to_string())],
+        format!("{}; try again in {}s\n", why, secs),
+    )
+        .into_response()
+}
+
+/// Serve the dashboard on loopback.
+async fn run_dashboard(
+    cfg: DashboardConfig,
+    listener: std::net::TcpListener,
+    gate: Arc<Gate>,
+    state: Arc<State>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let app =
+        with_gate(dashboard_app(state.clone()), gate, state).layer(middleware::from_fn(local_only));
+    let listener = TcpListener::from_std(listener)?;
+    info!("[dispatcher] dashboard on http://{}/", cfg.listen);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<Caller>(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Serve the dashboard to admin clients over mutual TLS: only those with a
+/// certificate from `[admin] ca_cert`, and not `revoked`, get past the
+/// handshake. `operators` may change things, `viewers` may only read, and
+/// anyone else is refused.
+async fn run_admin(
+    cfg: AdminConfig,
+    listener: std::net::TcpListener,
+    tls: Arc<rustls::ServerConfig>,
+    state: Arc<State>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let listener = TcpListener::from_std(listener)?;
+    let acceptor = tokio_rustls::TlsAcceptor::from(tls);
+    // Roles come from the certificate, never from `[api]`
+    let app = with_gate(dashboard_app(state.clone()), Arc::default(), state);
+    let (revoked, operators, viewers) = (
+        Arc::new(cfg.revoked),
+        Arc::new(cfg.operators),
+        Arc::new(cfg.viewers),
+    );
+    if operators.is_empty() && viewers.is_empty() {
+        warn!("[dispatcher] [admin] lists no operators or viewers: every client will be refused");
+    }
+    info!(
+        "[dispatcher] admin API on https://{}/ (clients need a certificate from {})",
+        cfg.listen,
+        cfg.ca_cert.display()
+    );
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("[dispatcher] admin accept error: {}", e);
+                continue;
+            }
+        };
+        let (acceptor, app) = (acceptor.clone(), app.clone());
+        let (revoked, operators, viewers) = (revoked.clone(), operators.clone(), viewers.clone());
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => return warn!("[dispatcher] admin: {} refused: {}", peer, e),
+                    Err(_) => {
+                        return warn!("[dispatcher] admin: {} refused: handshake timed out", peer)
+                    }
+                };
+            let name = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| admin::common_name(cert));
+            let Some(name) = name else {
+                return warn!(
+                    "[dispatcher] admin: {} refused: no common name in its certificate",
+                    peer
+                );
+            };
+            if revoked.contains(&name) {
+                return warn!(
+                    "[dispatcher] admin: {} refused: `{}` is revoked",
+                    peer, name
+                );
+            }
+            let access = if viewers.contains(&name) {
+                Access::Read
+            } else if operators.contains(&name) {
+                Access::Operate
+            } else {
+                return warn!(
+                    "[dispatcher] admin: {} refused: `{}` is neither an operator nor a viewer",
+                    peer, name
+                );
+            };
+            info!(
+                "[dispatcher] admin {} connected from {} ({} access)",
+                name,
+                peer,
+                access.as_str()
+            );
+            let client = AdminClient { name, access };
+            let service = TowerToHyperService::new(app.layer(Extension(client)));
+            let conn = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .with_upgrades();
+            if let Err(e) = conn.await {
+                warn!("[dispatcher] admin: {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Trade heartbeats with the `[ha]` peer and move the virtual address with
+/// our role. The standby follows the active one's egress mode. On `resign`,
+/// give the address up and tell the peer to take over now.
+async fn run_ha(
+    cfg: HaConfig,
+    socket: std::net::UdpSocket,
+    state: Arc<State>,
+    mut resign: watch::Receiver<bool>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let socket = UdpSocket::from_std(socket)?;
+    let peer = cfg.peer.ok_or("[ha] peer is not set")?;
+    let vip = match cfg.virtual_ip()? {
+        Some(vip) => match vip.addr() {
+            IpAddr::V4(ip) => {
+                let interface = cfg.interface.as_deref().unwrap_or_default();
+                match VirtualAddress::new(ip, vip.prefix(), interface) {
+                    Ok(address) => Some(address),
+                    Err(e) => {
+                        warn!("[dispatcher] ha: {} stays where it is: {}", ip, e);
+                        None
+                    }
+                }
+            }
+            IpAddr::V6(_) => None,
+        },
+        None => None,
+    };
+    let holding = vip.as_ref().is_some_and(|v| ha::holds(v.ip()));
+    let node = format!("{:016x}", rand::random::<u64>());
+    let mut pair = Pair::new(&cfg, node, holding, Instant::now())?;
+    let policy = fs::read_to_string(CONFIG_PATH)
+        .map(|text| ha::policy_digest(&text))
+        .unwrap_or_default();
+    info!(
+        "[dispatcher] ha: {} as {} (priority {}), heartbeats to {} every {}ms",
+        pair.role().as_str(),
+        pair.node(),
+        cfg.priority,
+        peer,
+        cfg.interval_ms
+    );
+    if let (Role::Active, Some(vip)) = (pair.role(), &vip) {
+        // Ours from before a restart; tell the LAN again
+        if let Err(e) = vip.claim() {
+            warn!("[dispatcher] ha: {}", e);
+        }
+    }
+
+    let mut ticker = tokio::time::interval(Duration::from_millis(cfg.interval_ms.max(100)));
+    let mut buf = vec![0u8; 2048];
+    let mut foreign_policy = None;
+    loop {
+        let changed = tokio::select! {
+            _ = ticker.tick() => {
+                let changed = pair.tick(Instant::now());
+                let heartbeat = pair.seal(Some(flag_egress().to_string()), &policy, false);
+                if let Err(e) = socket.send_to(&heartbeat, peer).await {
+                    warn!("[dispatcher] ha: heartbeat to {}: {}", peer, e);
+                }
+                changed
+            }
+            received = socket.recv_from(&mut buf) => {
+                let (n, from) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("[dispatcher] ha recv error: {}", e);
+                        continue;
+                    }
+                };
+                match pair.open(&buf[..n]) {
+                    Ok(Some(heartbeat)) => {
+                        if heartbeat.policy != policy
+                            && foreign_policy.as_ref() != Some(&heartbeat.policy)
+                        {
+                            warn!(
+                                "[dispatcher] ha: the peer's policy ({}) differs from ours ({}); \
+                                 whichever is active enforces its own",
+                                heartbeat.policy, policy
+                            );
+                            foreign_policy = Some(heartbeat.policy.clone());
+                        }
+                        let changed = pair.heard(&heartbeat, Instant::now());
+                        if pair.role() == Role::Standby && heartbeat.active {
+                            follow_mode(&state, heartbeat.mode.as_deref());
+                        }
+                        changed
+                    }
+                    Ok(None) => None,
+                    Err(e) => {
+                        warn!("[dispatcher] ha: heartbeat from {} rejected: {}", from, e);
+                        None
+                    }
+                }
+            }
+            _ = resign.changed() => {
+                if let (Role::Active, Some(vip)) = (pair.role(), &vip) {
+                    if let Err(e) = vip.release() {
+                        warn!("[dispatcher] ha: {}", e);
+                    }
+                }
+                let farewell = pair.seal(Some(flag_egress().to_string()), &policy, true);
+                let _ = socket.send_to(&farewell, peer).await;
+                info!("[dispatcher] ha: resigned, the peer takes over");
+                return Ok(());
+            }
+        };
+
+        if let Some(reason) = changed {
+            let role = pair.role();
+            info!("[dispatcher] ha: now {}: {}", role.as_str(), reason);
+            if let Some(vip) = &vip {
+                let moved = match role {
+                    Role::Active => vip.claim(),
+                    Role::Standby => vip.release(),
+                };
+                if let Err(e) = moved {
+                    warn!("[dispatcher] ha: {}", e);
+                }
+            }
+            emit_failover(&state, "ha", format!("now {}: {}", role.as_str(), reason));
+            // Let the peer know at once rather than next interval
+            let heartbeat = pair.seal(Some(flag_egress().to_string()), &policy, false);
+            let _ = socket.send_to(&heartbeat, peer).await;
+        }
+        *state.ha.lock().expect("ha poisoned") = Some(pair.status());
+    }
+}
+
+/// Standby: switch to the egress the active peer uses, so taking over
+/// changes nothing for clients.
+fn follow_mode(state: &State, mode: Option<&str>) {
+    let Some(mode) = mode.filter(|&m| m != flag_egress()) else {
+        return;
+    };
+    let flag = match mode {
+        "tor" => "on",
+        "direct" => "off",
+        "masque" if state.masque.is_some() => "masque",
+        _ => return,
+    };
+    match fs::write(FLAG_PATH, format!("{}\n", flag)) {
+        Ok(()) => info!("[dispatcher] ha: following the active peer to {}", mode),
+        Err(e) => warn!("[dispatcher] ha: {}: {}", FLAG_PATH, e),
+    }
+}
+
+/// Share probe results with peer dispatchers and merge theirs.
+///
+/// A backend a peer reported on within the last interval is not probed
+/// again here, so a fleet spreads the probing between its members.
+async fn run_gossip(
+    cfg: GossipConfig,
+    socket: std::net::UdpSocket,
+    state: Arc<State>,
+    gossip: Gossip,
+    prober: TcpProber,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let socket = Arc::new(UdpSocket::from_std(socket)?);
+    let peers = if cfg.peers.is_empty() {
+        socket.set_broadcast(true)?;
+        vec![SocketAddr::from(([255, 255, 255, 255], cfg.bind.port()))]
+    } else {
+        cfg.peers.clone()
+    };
+    info!(
+        "[dispatcher] gossip as {} on {} (peers: {})",
+        gossip.node(),
+        cfg.bind,
+        peers
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let gossip = Arc::new(gossip);
+
+    let (rx_socket, rx_gossip, rx_state) = (socket.clone(), gossip.clone(), state.clone());
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let (n, from) = match rx_socket.recv_from(&mut buf).await {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("[dispatcher] gossip recv error: {}", e);
+                    continue;
+                }
+            };
+            match rx_gossip.open(&buf[..n]) {
+                Ok(Some(envelope)) => merge_reports(&rx_state, envelope.reports),
+                Ok(None) => {}
+                Err(e) => warn!("[dispatcher] gossip from {} rejected: {}", from, e),
+            }
+        }
+    });
+
+    let mut ticker = state
+        .power
+        .probe_ticker(Duration::from_secs(cfg.interval_secs.max(1)));
+    loop {
+        ticker.tick().await;
+        let interval = ticker.period().as_secs();
+
+        let mut fresh = Vec::new();
+        for target in prober.targets() {
+            let covered = state
+                .board
+                .lock()
+                .expect("health board poisoned")
+                .fresh(&target.name, interval)
+                .is_some_and(|r| r.node != gossip.node());
+            if covered {
+                continue;
+            }
+            let (p, t) = (prober.clone(), target.clone());
+            let mut health = tokio::task::spawn_blocking(move || p.probe(&t)).await?;
+            // Sessions that got through too late count as well
+            let egress = state.egress.get(health.kind.as_str());
+            if let Some(rate) = egress.and_then(Egress::overrun_rate) {
+                health.add_failures(rate);
+            }
+            fresh.push(Report {
+                node: gossip.node().to_string(),
+                observed_unix: now_unix(),
+                health,
+                stale: false,
+            });
+        }
+        if fresh.is_empty() {
+            continue;
+        }
+
+        merge_reports(&state, fresh.iter().cloned());
+        let datagram = gossip.seal(fresh);
+        for peer in &peers {
+            if let Err(e) = socket.send_to(&datagram, peer).await {
+                warn!("[dispatcher] gossip to {} failed: {}", peer, e);
+            }
+        }
+    }
+}
+
+/// Evaluate `[[alerts.rules]]` every interval, announce rules that start or
+/// stop firing and publish the state for `alerts status`.
+async fn run_alerts(cfg: AlertsConfig, state: Arc<State>) {
+    let mut evaluator = Evaluator::new(cfg.rules.clone());
+    let mut ticker = state
+        .power
+        .ticker(Duration::from_secs(cfg.interval_secs.max(1)));
+    let cfg = Arc::new(cfg);
+
+    loop {
+        ticker.tick().await;
+        let interval_secs = ticker.period().as_secs();
+        let backends: Vec<BackendHealth> = state
+            .board
+            .lock()
+            .expect("health board poisoned")
+            .reports
+            .values()
+            .filter(|r| !r.stale)
+            .map(|r| r.health.clone())
+            .collect();
+        let now = now_unix();
+        for event in evaluator.evaluate(now, &backends, state.kill_switch.since()) {
+            info!("[dispatcher] alert {}", event.message());
+            let cfg = cfg.clone();
+            tokio::spawn(async move {
+                for error in alerts::dispatch(&cfg, &event).await {
+                    warn!("[dispatcher] alert delivery to {}", error);
+                }
+            });
+        }
+        let snapshot = AlertSnapshot {
+            updated_unix: now,
+            interval_secs,
+            alerts: evaluator.status(),
+        };
+        if let Err(e) = snapshot.save(ALERTS_PATH) {
+            warn!("[dispatcher] could not write {}: {}", ALERTS_PATH, e);
+        }
+    }
+}
+
+/// The dispatcher's metrics as of the last stats publish.
+fn metric_samples(state: &State) -> Vec<Sample> {
+    let egress = state
+        .published
+        .lock()
+        .expect("stats poisoned")
+        .last
+        .egress
+        .clone();
+    let mut samples = Vec::new();
+    samples.extend(egress.iter().map(|(name, usage)| {
+        Sample::new(
+            "gold_dust_egress_bytes_total",
+            "Bytes relayed through the egress.",
+            usage.bytes_total as f64,
+        )
+        .label("egress", name)
+    }));
+    samples.extend(egress.iter().map(|(name, usage)| {
+        Sample::new(
+            "gold_dust_egress_rate_kbps",
+            "Current egress throughput in kilobits per second.",
+            usage.rate_kbps,
+        )
+        .label("egress", name)
+    }));
+    samples.extend(egress.iter().map(|(name, usage)| {
+        Sample::new(
+            "gold_dust_egress_sessions",
+            "Sessions open on the egress.",
+            usage.sessions as f64,
+        )
+        .label("egress", name)
+    }));
+    samples.extend(egress.iter().map(|(name, usage)| {
+        Sample::new(
+            "gold_dust_egress_idle_closed_total",
+            "Sessions on the egress closed for sitting idle.",
+            usage.idle_closed as f64,
+        )
+        .label("egress", name)
+    }));
+    let blocked = state.dns_blocked.lock().expect("dns block counts poisoned");
+    samples.extend(blocked.iter().map(|(profile, n)| {
+        Sample::new(
+            "gold_dust_dns_blocked_total",
+            "Sessions refused by [dns.block], by app profile.",
+            *n as f64,
+        )
+        .label("profile", profile)
+    }));
+    drop(blocked);
+    if let Some(ha) = state.ha.lock().expect("ha poisoned").as_ref() {
+        samples.push(Sample::new(
+            "gold_dust_ha_active",
+            "1 while this daemon is the active one of its [ha] pair.",
+            (ha.role == Role::Active) as u8 as f64,
+        ));
+    }
+    if state.audit {
+        let audited = state.audited.lock().expect("audit counts poisoned");
+        samples.extend(audited.iter().map(|(would, n)| {
+            Sample::new(
+                "gold_dust_audit_sessions_total",
+                "Sessions passed through in audit mode, by what enforcement would have done.",
+                *n as f64,
+            )
+            .label("decision", would)
+        }));
+    }
+
+    let caches: Vec<(&str, CacheStats)> = state
+        .resolver
+        .as_ref()
+        .map(|doh| ("doh", doh.cache_stats()))
+        .into_iter()
+        .chain(state.dot.as_ref().map(|dot| ("dot", dot.cache_stats())))
+        .chain(
+            state
+                .resolvers
+                .iter()
+                .filter_map(|(egress, r)| Some((*egress, r.cache_stats()?))),
+        )
+        .collect();
+    samples.extend(caches.iter().map(|(resolver, cache)| {
+        Sample::new(
+            "gold_dust_cache_hits_total",
+            "Lookups answered from the cache.",
+            cache.hits as f64,
+        )
+        .label("cache", "dns")
+        .label("resolver", *resolver)
+    }));
+    samples.extend(caches.iter().map(|(resolver, cache)| {
+        Sample::new(
+            "gold_dust_cache_misses_total",
+            "Lookups the cache could not answer.",
+            cache.misses as f64,
+        )
+        .label("cache", "dns")
+        .label("resolver", *resolver)
+    }));
+    samples.extend(caches.iter().map(|(resolver, cache)| {
+        Sample::new(
+            "gold_dust_cache_evictions_total",
+            "Entries dropped to make room.",
+            cache.evictions as f64,
+        )
+        .label("cache", "dns")
+        .label("resolver", *resolver)
+    }));
+    samples.extend(caches.iter().map(|(resolver, cache)| {
+        Sample::new(
+            "gold_dust_cache_entries",
+            "Entries held, out of the cache's capacity.",
+            cache.entries as f64,
+        )
+        .label("cache", "dns")
+        .label("resolver", *resolver)
+    }));
+
+    let backends: Vec<BackendHealth> = state
+        .board
+        .lock()
+        .expect("health board poisoned")
+        .reports
+        .values()
+        .filter(|r| !r.stale)
+        .map(|r| r.health.clone())
+        .collect();
+    samples.extend(backends.iter().map(|b| {
+        Sample::new(
+            "gold_dust_backend_enabled",
+            "Whether the backend is in rotation.",
+            b.enabled as u8 as f64,
+        )
+        .label("backend", &b.name)
+    }));
+    samples.extend(backends.iter().map(|b| {
+        Sample::new(
+            "gold_dust_backend_latency_ms",
+            "Last measured backend latency.",
+            b.latency_ms,
+        )
+        .label("backend", &b.name)
+    }));
+    samples.extend(backends.iter().map(|b| {
+        Sample::new(
+            "gold_dust_backend_failure_ratio",
+            "Share of failed backend probes.",
+            b.failure_rate,
+        )
+        .label("backend", &b.name)
+    }));
+
+    let callers = state.api_throttle.counts();
+    samples.extend(callers.iter().map(|(caller, counts)| {
+        Sample::new(
+            "gold_dust_api_requests_total",
+            "Requests to the HTTP APIs, by caller.",
+            counts.requests as f64,
+        )
+        .label("caller", caller)
+    }));
+    samples.extend(callers.iter().map(|(caller, counts)| {
+        Sample::new(
+            "gold_dust_api_throttled_total",
+            "Requests refused by the [api] limits or a lockout, by caller.",
+            counts.throttled as f64,
+        )
+        .label("caller", caller)
+    }));
+    samples.extend(callers.iter().map(|(caller, counts)| {
+        Sample::new(
+            "gold_dust_api_auth_failures_total",
+            "Requests without a valid token or granted user, by caller.",
+            counts.auth_failures as f64,
+        )
+        .label("caller", caller)
+    }));
+    samples.extend(callers.iter().map(|(caller, counts)| {
+        Sample::new(
+            "gold_dust_api_locked_out",
+            "Whether the caller is locked out of the HTTP APIs.",
+            counts.locked_out as u8 as f64,
+        )
+        .label("caller", caller)
+    }));
+
+    samples.push(Sample::new(
+        "gold_dust_sessions",
+        "Client connections being handled.",
+        state.sessions.load(Ordering::SeqCst) as f64,
+    ));
+    samples.push(Sample::new(
+        "gold_dust_kill_switch",
+        "Whether sessions are being refused.",
+        state.kill_switch.since().is_some() as u8 as f64,
+    ));
+    samples.push(Sample::new(
+        "gold_dust_low_power",
+        "Whether low-power probing is on.",
+        state.power.is_low() as u8 as f64,
+    ));
+    samples
+}
+
+/// Push metrics to a Pushgateway or remote-write endpoint every interval
+/// (`[metrics_push]`), directly or through `via`.
+async fn run_metrics_push(cfg: MetricsPushConfig, state: Arc<State>) {
+    let mut ticker = state
+        .power
+        .ticker(Duration::from_secs(cfg.interval_secs.max(1)));
+    let mut failing = false;
+    loop {
+        ticker.tick().await;
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        let pushed = match Push::new(&cfg, &metric_samples(&state), now_ms) {
+            Ok(push) => {
+                let sent = send_push(&cfg, &state, &push);
+                tokio::time::timeout(PUSH_TIMEOUT, sent)
+                    .await
+                    .unwrap_or_else(|_| Err("timed out".into()))
+            }
+            Err(e) => Err(e.into()),
+        };
+        match &pushed {
+            Ok(()) if failing => info!("[dispatcher] metrics push to {} works again", cfg.url),
+            Err(e) if !failing => warn!("[dispatcher] metrics push to {}: {}", cfg.url, e),
+            _ => {}
+        }
+        failing = pushed.is_err();
+    }
+}
+
+async fn send_push(
+    cfg: &MetricsPushConfig,
+    state: &State,
+    push: &Push,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let headers: Vec<(&str, &str)> = push
+        .headers
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    let resp = match cfg.via {
+        Some(via) => {
+            let target = push.url.target();
+            let stream = dial(
+                state,
+                via.as_str(),
+                &target,
+                Some("gold-dust-metrics"),
+                None,
+                0,
+            )
+            .await?;
+            http::request_over(stream, push.method, &push.url, &headers, &push.body).await?
+        }
+        None => http::request(push.method, &push.url, &headers, &push.body).await?,
+    };
+    match resp.status {
+        200..=299 => Ok(()),
+        status => Err(format!("HTTP {}", status).into()),
+    }
+}
+
+/// Periodically break backends at random (chaos mode).
+async fn run_chaos(state: Arc<State>) {
+    let Some(chaos) = &state.chaos else {
+        return;
+    };
+    let names: Vec<&str> = state.egress.keys().copied().collect();
+    let mut ticker = tokio::time::interval(chaos.interval());
+
+    loop {
+        ticker.tick().await;
+        for (name, fault) in chaos.tick(&names) {
+            info!("[dispatcher] chaos: injected {:?} into {}", fault, name);
+        }
+    }
+}
+
+/// HTTP CONNECT proxy routing via Tor or direct, per the flag file.
+#[derive(Parser, Debug)]
+#[command(name = "dispatcher", version)]
+struct Args {
+    /// Replace an instance already running in this directory
+    #[arg(long)]
+    takeover: bool,
+    /// Take the listening sockets of the instance running in this directory
+    /// over, and let it drain and exit (`handover` feature)
+    #[arg(long, conflicts_with = "takeover")]
+    upgrade: bool,
+    /// Skip privilege dropping and seccomp/landlock (for debugging)
+    #[arg(long)]
+    no_sandbox: bool,
+    /// Run the whole pipeline (probes, rules, decisions, logs) but forward
+    /// nothing, to validate a policy
+    #[arg(long)]
+    dry_run: bool,
+    /// Run the whole pipeline and log what it decides, but forward every
+    /// session direct and unshaped, to see a policy's impact first
+    #[arg(long, conflicts_with = "dry_run")]
+    audit: bool,
+}
+
+/// Every socket the dispatcher serves on, bound before the sandbox closes.
+struct Listeners {
+    proxy: std::net::TcpListener,
+    proxy_v6: Option<std::net::TcpListener>,
+    health_feed: Option<std::net::TcpListener>,
+    dashboard: Option<std::net::TcpListener>,
+    /// The admin API, with its TLS (loaded here: the sandbox may hide the
+    /// files later).
+    admin: Option<(std::net::TcpListener, Arc<rustls::ServerConfig>)>,
+    gossip: Option<std::net::UdpSocket>,
+    #[cfg(unix)]
+    unix: Option<std::os::unix::net::UnixListener>,
+    /// Heartbeats with the `[ha]` peer.
+    ha: Option<std::net::UdpSocket>,
+    /// The proxy on the `[ha]` virtual address, bound before it is ours.
+    virtual_proxy: Option<std::net::TcpListener>,
+    /// Where a successor asks for all of the above (`--upgrade`).
+    handover: Option<handover::Listener>,
+    /// The instance they were taken over from, still draining, and its
+    /// pid lock.
+    predecessor: Option<(Predecessor, InheritedLock)>,
+    /// Who may do what on the dashboard and health feed (`[api]`).
+    gate: Arc<Gate>,
+    /// The `[encryption]` key, looked up before the sandbox closes.
+    sealer: Option<Arc<Sealer>>,
+}
+
+impl Listeners {
+    /// Bind every socket `cfg` asks for, reusing those in `inherited` (from
+    /// the instance being upgraded) where the address matches.
+    fn bind(cfg: &GoldDustConfig, mut inherited: Vec<Inherited>) -> io::Result<Self> {
+        let tcp = |inherited: &mut Vec<Inherited>, addr: SocketAddr| {
+            let listener = match take_tcp(inherited, addr) {
+                Some(listener) => listener,
+                None => std::net::TcpListener::bind(addr)?,
+            };
+            listener.set_nonblocking(true)?;
+            Ok::<_, io::Error>(listener)
+        };
+        let udp = |inherited: &mut Vec<Inherited>, addr: SocketAddr| {
+            let taken = inherited.iter().position(
+                |s| matches!(s, Inherited::Udp(u) if u.local_addr().is_ok_and(|a| a == addr)),
+            );
+            let socket = match taken.map(|i| inherited.swap_remove(i)) {
+                Some(Inherited::Udp(socket)) => socket,
+                _ => std::net::UdpSocket::bind(addr)?,
+            };
+            socket.set_nonblocking(true)?;
+            Ok::<_, io::Error>(socket)
+        };
+        let gossip = match cfg.gossip.enabled {
+            true => Some(udp(&mut inherited, cfg.gossip.bind)?),
+            false => None,
+        };
+        let ha = match cfg.ha.enabled {
+            true => Some(udp(&mut inherited, cfg.ha.bind)?),
+            false => None,
+        };
+        let virtual_proxy = match (cfg.ha.enabled, cfg.ha.virtual_ip()) {
+            (true, Ok(Some(vip))) => {
+                let addr = SocketAddr::new(vip.addr(), cfg.ha.port);
+                let listener = match take_tcp(&mut inherited, addr) {
+                    Some(listener) => listener,
+                    None => ha::listen(addr)?,
+                };
+                listener.set_nonblocking(true)?;
+                Some(listener)
+            }
+            _ => None,
+        };
+        #[cfg(unix)]
+        let unix = match &cfg.dispatcher.unix_socket {
+            Some(path) => {
+                let taken = inherited.iter().position(|s| {
+                    matches!(s, Inherited::Unix(l)
+                        if l.local_addr().is_ok_and(|a| a.as_pathname() == Some(path.as_path())))
+                });
+                let listener = match taken.map(|i| inherited.swap_remove(i)) {
+                    Some(Inherited::Unix(listener)) => listener,
+                    _ => {
+                        // Left behind by an earlier run (the pid lock rules out a live one)
+                        if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                            fs::remove_file(path)?;
+                        }
+                        let listener = std::os::unix::net::UnixListener::bind(path)?;
+                        fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+                        listener
+                    }
+                };
+                listener.set_nonblocking(true)?;
+                Some(listener)
+            }
+            None => None,
+        };
+        #[cfg(not(unix))]
+        if let Some(path) = &cfg.dispatcher.unix_socket {
+            warn!(
+                "[dispatcher] no unix sockets on this system, not listening on {}",
+                path.display()
+            );
+        }
+        let proxy = tcp(&mut inherited, SocketAddr::from(([127, 0, 0, 1], 7777)))?;
+        // IPv6 loopback too, where the host has it
+        let proxy_v6 = match tcp(&mut inherited, "[::1]:7777".parse().expect("valid address")) {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                warn!("[dispatcher] no IPv6 listener on [::1]:7777: {}", e);
+                None
+            }
+        };
+        let health_feed = match cfg.health_feed.enabled {
+            true => Some(tcp(&mut inherited, cfg.health_feed.listen)?),
+            false => None,
+        };
+        let dashboard = match cfg.dashboard.enabled {
+            true => Some(tcp(&mut inherited, cfg.dashboard.listen)?),
+            false => None,
+        };
+        let admin = match cfg.admin.enabled {
+            true => {
+                let tls = admin::server_config(&cfg.admin)
+                    .map_err(|e| io::Error::other(format!("[admin] {}", e)))?;
+                Some((tcp(&mut inherited, cfg.admin.listen)?, tls))
+            }
+            false => None,
+        };
+        if !inherited.is_empty() {
+            info!(
+                "[dispatcher] closing {} inherited socket(s) the config no longer uses",
+                inherited.len()
+            );
+        }
+        let handover = match handover::listen(HANDOVER_PATH) {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                if handover::AVAILABLE {
+                    warn!("[dispatcher] no hot upgrades: {}: {}", HANDOVER_PATH, e);
+                }
+                None
+            }
+        };
+        Ok(Self {
+            proxy,
+            proxy_v6,
+            health_feed,
+            dashboard,
+            admin,
+            gossip,
+            #[cfg(unix)]
+            unix,
+            ha,
+            virtual_proxy,
+            handover,
+            predecessor: None,
+            gate: Arc::default(),
+            sealer: None,
+        })
+    }
+
+    /// Copies of every serving socket, to hand to a successor.
+    fn handover_copies(&self) -> io::Result<Vec<(handover::Kind, handover::Socket)>> {
+        let mut copies = vec![(handover::Kind::Tcp, self.proxy.try_clone()?.into())];
+        for listener in [
+            &self.proxy_v6,
+            &self.health_feed,
+            &self.dashboard,
+            &self.virtual_proxy,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.admin.as_ref().map(|(listener, _)| listener))
+        {
+            copies.push((handover::Kind::Tcp, listener.try_clone()?.into()));
+        }
+        for socket in [&self.gossip, &self.ha].into_iter().flatten() {
+            copies.push((handover::Kind::Udp, socket.try_clone()?.into()));
+        }
+        #[cfg(unix)]
+        if let Some(listener) = &self.unix {
+            copies.push((handover::Kind::Unix, listener.try_clone()?.into()));
+        }
+        Ok(copies)
+    }
+}
+
+/// The listener on `addr` among `inherited`, if there is one.
+fn take_tcp(inherited: &mut Vec<Inherited>, addr: SocketAddr) -> Option<std::net::TcpListener> {
+    let taken = inherited
+        .iter()
+        .position(|s| matches!(s, Inherited::Tcp(l) if l.local_addr().is_ok_and(|a| a == addr)));
+    match taken.map(|i| inherited.swap_remove(i)) {
+        Some(Inherited::Tcp(listener)) => Some(listener),
+        _ => None,
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let args = Args::parse();
+    // One dispatcher per state directory; held until exit. An upgrade takes
+    // the running one's sockets now, and its lock once it has drained
+    let (_pid_lock, inherited, predecessor) = match args.upgrade {
+        false => (
+            Some(PidLock::acquire(PID_PATH, args.takeover)?),
+            vec![],
+            None,
+        ),
+        true => {
+            let (sockets, predecessor) = handover::request(HANDOVER_PATH)?;
+            let lock = PidLock::inherit(PID_PATH)?;
+            (None, sockets, Some((predecessor, lock)))
+        }
+    };
+
+    let mut cfg = match GoldDustConfig::load_with_warnings(CONFIG_PATH) {
+        Ok((cfg, warnings)) => {
+            for warning in warnings {
+                warn!("[dispatcher] {}", warning);
+            }
+            cfg
+        }
+        Err(e) => {
+            warn!("[dispatcher] using demo config ({CONFIG_PATH}):\n{e}");
+            GoldDustConfig::default_for_demo()
+        }
+    };
+    // Before the sandbox, which may not let the socket be reached later
+    match journal::init(cfg.logging.journald, "dispatcher") {
+        Ok(true) => info!("[dispatcher] logging to journald"),
+        Ok(false) => {}
+        Err(e) => warn!("[dispatcher] {}, logging to stderr", e),
+    }
+    // Only the ones file order settles; a `priority` says which was meant
+    for conflict in cfg.rule_conflicts().iter().filter(|c| !c.by_priority) {
+        warn!("[dispatcher] {}", conflict);
+    }
+    if predecessor.is_some() {
+        info!(
+            "[dispatcher] upgrading: took {} socket(s) over from the running instance",
+            inherited.len()
+        );
+    }
+    let mut listeners = Listeners::bind(&cfg, inherited)?;
+    listeners.predecessor = predecessor;
+    let mut gate = Gate {
+        tokens: vec![],
+        grants: Grants::new(&cfg.api.operators, &cfg.api.viewers)?,
+        open: !cfg.api.auth,
+    };
+    // Created by whoever starts us (root, usually), before privileges drop
+    if cfg.api.auth && (cfg.dashboard.enabled || cfg.health_feed.enabled) {
+        for (path, access) in [
+            (&cfg.api.token_file, Access::Operate),
+            (&cfg.api.read_token_file, Access::Read),
+        ] {
+            let (token, created) = ApiToken::load_or_create(path)?;
+            if created {
+                info!(
+                    "[dispatcher] API token ({} access) created in {}",
+                    access.as_str(),
+                    path.display()
+                );
+            }
+            gate.tokens.push((token, access));
+        }
+    }
+    listeners.gate = Arc::new(gate);
+    // The keychain may start a runtime of its own, so before ours
+    cfg.resolve_secrets()?;
+    if cfg.encryption.enabled {
+        let (sealer, created) = Sealer::load(&cfg.encryption.key, true)?;
+        if created {
+            info!("[dispatcher] state key created in {}", cfg.encryption.key);
+        }
+        listeners.sealer = Some(Arc::new(sealer));
+    }
+    // Reads the relay CA, which the sandbox may not allow later
+    let masque = cfg.backends.masque.as_ref().and_then(|relay| {
+        let keep_alive = match cfg.keepalive_for("masque").interval_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        MasqueClient::new(relay, keep_alive, cfg.limits_for("masque").buffer_kib)
+            .map_err(|e| warn!("[dispatcher] HTTP/3 relay unavailable: {}", e))
+            .ok()
+    });
+    let hosts = Hosts::load(&cfg.dns)?;
+    let ring = cfg.dispatcher.io_uring.then(|| {
+        Ring::start(uring::ENTRIES)
+            .map_err(|e| {
+                warn!(
+                    "[dispatcher] io_uring unavailable, relaying as usual: {}",
+                    e
+                )
+            })
+            .ok()
+    });
+
+    // Confine before the runtime starts its threads, so they inherit it
+    if !cfg.sandbox.enabled || args.no_sandbox {
+        info!("[dispatcher] sandbox disabled");
+    } else {
+        let state_dir = std::env::current_dir()?;
+        // Log files may live outside the state directory; rotation needs
+        // to create and rename files next to them
+        let mut writable = vec![state_dir.clone()];
+        let audit_log = Some(cfg.logging.audit_log.clone());
+        for log in [
+            &cfg.logging.traffic_log,
+            &cfg.logging.health_log,
+            &audit_log,
+        ] {
+            if let Some(parent) = log.as_ref().and_then(|p| p.parent()) {
+                writable.push(state_dir.join(parent));
+            }
+        }
+        match sandbox::apply(&cfg.sandbox, &writable) {
+            Ok(applied) => info!(
+                "[dispatcher] sandboxed: user={} landlock={} seccomp={}",
+                applied.user.as_deref().unwrap_or("unchanged"),
+                applied.landlock,
+                if applied.seccomp { "on" } else { "off" }
+            ),
+            Err(e) if !sandbox::AVAILABLE => {
+                warn!("[dispatcher] running unsandboxed: {}", e)
+            }
+            Err(e) => return Err(format!("sandbox: {}", e).into()),
+        }
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(
+            cfg,
+            listeners,
+            masque,
+            ring.flatten(),
+            hosts,
+            args.dry_run,
+            args.audit,
+        ))
+}
+
+async fn run(
+    cfg: GoldDustConfig,
+    listeners: Listeners,
+    masque: Option<MasqueClient>,
+    ring: Option<Ring>,
+    hosts: Hosts,
+    dry_run: bool,
+    audit: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let sealer = listeners.sealer.clone();
+    // Taken before the listeners move into their tasks
+    let copies = match listeners.handover {
+        Some(_) => listeners.handover_copies()?,
+        None => vec![],
+    };
+    let offer = match listeners.handover {
+        Some(listener) => Some(Offer::from_std(listener)?),
+        None => None,
+    };
+    let state = Arc::new(State {
+        limiter: RateLimiter::from_rules(&cfg.rules)?,
+        egress: EgressKind::ALL
+            .iter()
+            .map(|egress| (egress.as_str(), Egress::new(&cfg, egress.as_str())))
+            .collect(),
+        usage: Mutex::new(UsageLedger::load(USAGE_PATH, None)),
+        chaos: cfg.chaos.enabled.then(|| Chaos::new(&cfg.chaos)),
+        resolver: match cfg.dns.mode {
+            DnsMode::Doh => Some(DohResolver::new(&cfg.dns.doh_url, cfg.dns.cache())?),
+            DnsMode::System | DnsMode::Remote | DnsMode::Dot => None,
+        },
+        dot: (cfg.dns.mode == DnsMode::Dot).then(|| {
+            DotResolver::new(
+                cfg.dns.dot_servers.clone(),
+                cfg.dns.dot_profile,
+                cfg.dns.cache(),
+            )
+        }),
+        resolvers: cfg
+            .dns
+            .resolvers
+            .iter()
+            .map(|(egress, r)| {
+                let resolver = EgressResolver::new(r, cfg.dns.cache())?;
+                Ok((egress.as_str(), resolver))
+            })
+            .collect::<Result<_, String>>()?,
+        hosts,
+        dns_block: RwLock::new(NameBlocklist::from_config(&cfg.dns.block)),
+        block_ads: cfg.dns.block.enabled,
+        block_answer: cfg.dns.block.answer,
+        dns_blocked: Mutex::new(BTreeMap::new()),
+        lan: Lan::new(&cfg.lan)?,
+        portal: cfg.portal.clone(),
+        power: Arc::new(Power::new(&cfg.power)),
+        dns_mode: cfg.dns.mode,
+        dns_profile: cfg.dns.dot_profile,
+        tor_socks: cfg.backends.tor_socks,
+        rotation: Rotation::load(
+            cfg.rotation.clone(),
+            ROTATION_PATH,
+            now_unix(),
+            sealer.as_deref(),
+        ),
+        exit_pins: ExitPins::from_rules(&cfg.rules)?,
+        idle: IdleTimeouts::from_config(&cfg)?,
+        apps: cfg.apps.clone(),
+        users: cfg
+            .users
+            .iter()
+            .map(|(key, user)| {
+                let policy = UserPolicy {
+                    egress: user.egress,
+                    limits: user.limits(),
+                    limiter: RateLimiter::from_rules(&user.rules)
+                        .map_err(|e| format!("[users.{}]: {}", key, e))?,
+                };
+                Ok::<_, String>((key.clone(), policy))
+            })
+            .collect::<Result<_, _>>()?,
+        user_usage: Mutex::new(UsageLedger::load(USER_USAGE_PATH, sealer.as_deref())),
+        masque,
+        ring,
+        board: Mutex::new(HealthBoard::warm_start(HEALTH_PATH)),
+        history: Mutex::new(HealthHistory::default()),
+        availability: Mutex::new(AvailabilityLedger::load(AVAILABILITY_PATH)),
+        failover: cfg.failover.clone(),
+        retry: cfg.retry.clone(),
+        standby: Mutex::new(None),
+        ha: Mutex::new(None),
+        published: Mutex::new(Published {
+            bytes: BTreeMap::new(),
+            at: Instant::now(),
+            last: TrafficSnapshot::default(),
+        }),
+        sessions: AtomicUsize::new(0),
+        kill_switch: KillSwitch::default(),
+        events: broadcast::channel(EVENTS_BUFFERED).0,
+        dry_run,
+        audit,
+        audited: Mutex::new(BTreeMap::new()),
+        traffic_log: match &cfg.logging.traffic_log {
+            Some(path) => Some(Mutex::new(
+                RotatingLog::open(path, &cfg.logging)?.sealed(sealer.clone()),
+            )),
+            None => None,
+        },
+        health_log: match &cfg.logging.health_log {
+            Some(path) => Some(Mutex::new(
+                RotatingLog::open(path, &cfg.logging)?.sealed(sealer.clone()),
+            )),
+            None => None,
+        },
+        syslog: match &cfg.logging.syslog {
+            Some(syslog) => Some(Mutex::new(Syslog::new(syslog)?)),
+            None => None,
+        },
+        journal_decisions: cfg.logging.journald_decisions,
+        event_log: match cfg.logging.event_log {
+            true => EventLog::open(&cfg.logging.event_source)
+                .map_err(|e| warn!("[dispatcher] no Event Log: {}", e))
+                .ok(),
+            false => None,
+        },
+        decisions: Mutex::new(VecDeque::new()),
+        handed_over: AtomicBool::new(false),
+        api_throttle: ApiThrottle::new(&cfg.api),
+        audit_trail: cfg.logging.audit_log.clone(),
+        sealer: sealer.clone(),
+    });
+    if let Some((predecessor, lock)) = listeners.predecessor {
+        tokio::spawn(adopt(predecessor, lock, state.clone()));
+    }
+    let warm = state
+        .board
+        .lock()
+        .expect("health board poisoned")
+        .reports
+        .len();
+    if warm > 0 {
+        info!(
+            "[dispatcher] warm start: {} backend(s) from {} (stale until re-measured)",
+            warm, HEALTH_PATH
+        );
+        merge_reports(&state, []);
+    }
+    tokio::spawn(publish_stats(state.clone()));
+    if state.power.is_low() {
+        info!(
+            "[dispatcher] low-power probing{}",
+            match cfg.power.mode {
+                PowerMode::Auto => " (on battery)",
+                _ => "",
+            }
+        );
+    }
+    if cfg.power.mode == PowerMode::Auto {
+        tokio::spawn(watch_power(state.clone()));
+    }
+    if cfg.dispatcher.resume_after_secs > 0 {
+        tokio::spawn(watch_sleep(cfg.clone(), state.clone()));
+    }
+    if !cfg.blocklist.sources.is_empty() {
+        tokio::spawn(refresh_blocklist(cfg.blocklist.clone()));
+    }
+    if !cfg.dns.block.sources.is_empty() {
+        tokio::spawn(refresh_dns_block(cfg.dns.block.clone(), state.clone()));
+    }
+    if cfg.standby.enabled {
+        info!(
+            "[dispatcher] standby: keeping tor warm via {} every {}s",
+            cfg.standby.canary,
+            cfg.standby.interval_secs.max(10)
+        );
+        tokio::spawn(run_standby(cfg.standby.clone(), state.clone()));
+    }
+    if let Some(socket) = listeners.gossip {
+        let node = format!("{:016x}", rand::random::<u64>());
+        let gossip = Gossip::new(&cfg.gossip.secret, node)?;
+        let prober = TcpProber::local_daemons(&cfg);
+        let (gossip_cfg, state) = (cfg.gossip.clone(), state.clone());
+        tokio::spawn(async move {
+            if let Err(e) = run_gossip(gossip_cfg, socket, state, gossip, prober).await {
+                warn!("[dispatcher] gossip stopped: {}", e);
+            }
+        });
+    }
+    let (resign_tx, resign_rx) = watch::channel(false);
+    let ha_task = listeners.ha.map(|socket| {
+        let (ha_cfg, state) = (cfg.ha.clone(), state.clone());
+        tokio::spawn(async move {
+            if let Err(e) = run_ha(ha_cfg, socket, state, resign_rx).await {
+                warn!("[dispatcher] ha stopped: {}", e);
+            }
+        })
+    });
+    if let Some(listener) = listeners.health_feed {
+        let (feed_cfg, state) = (cfg.health_feed.clone(), state.clone());
+        let gate = listeners.gate.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_health_feed(feed_cfg, listener, gate, state).await {
+                warn!("[dispatcher] health feed stopped: {}", e);
+            }
+        });
+    }
+    if let Some(listener) = listeners.dashboard {
+        let (dashboard_cfg, state) = (cfg.dashboard.clone(), state.clone());
+        let gate = listeners.gate.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_dashboard(dashboard_cfg, listener, gate, state).await {
+                warn!("[dispatcher] dashboard stopped: {}", e);
+            }
+        });
+    }
+    if let Some((listener, tls)) = listeners.admin {
+        let (admin_cfg, state) = (cfg.admin.clone(), state.clone());
+        tokio::spawn(async move {
+            if let Err(e) = run_admin(admin_cfg, listener, tls, state).await {
+                warn!("[dispatcher] admin API stopped: {}", e);
+            }
+        });
+    }
+    if !cfg.alerts.rules.is_empty() {
+        info!(
+            "[dispatcher] {} alert rule(s), checked every {}s",
+            cfg.alerts.rules.len(),
+            cfg.alerts.interval_secs.max(1)
+        );
+        tokio::spawn(run_alerts(cfg.alerts.clone(), state.clone()));
+    }
+    if cfg.metrics_push.enabled() {
+        info!(
+            "[dispatcher] pushing metrics to {} every {}s{}",
+            cfg.metrics_push.url,
+            cfg.metrics_push.interval_secs,
+            match cfg.metrics_push.via {
+                Some(via) => format!(" via {}", via.as_str()),
+                None => String::new(),
+            }
+        );
+        tokio::spawn(run_metrics_push(cfg.metrics_push.clone(), state.clone()));
+    }
+    if state.exit_pins.is_some() {
+        info!(
+            "[dispatcher] tor: pinning exits per rule, attaching streams via {}",
+            cfg.tor_control.address
+        );
+        tokio::spawn(run_exit_pins(cfg.tor_control.clone(), state.clone()));
+    }
+    if cfg.rotation.enabled() {
+        info!(
+            "[dispatcher] tor circuits per destination, rotated every {} min / {} MB{}",
+            cfg.rotation.every_mins,
+            cfg.rotation.every_mb,
+            if cfg.rotation.stagger {
+                " (staggered)"
+            } else {
+                ""
+            }
+        );
+    }
+    if dry_run {
+        info!("[dispatcher] DRY RUN: every session is decided and logged, none is forwarded");
+        // Checked and shown, never loaded
+        match Ruleset::from_config(&cfg) {
+            Ok(ruleset) => info!(
+                "[dispatcher] dry run: kill-switch firewall rules (not applied):\n{}",
+                firewall::render(&ruleset, cfg.firewall.backend)
+            ),
+            Err(e) => warn!("[dispatcher] dry run: [firewall] is invalid: {}", e),
+        }
+    }
+    if audit {
+        info!(
+            "[dispatcher] AUDIT MODE: every session is decided and logged, all are forwarded direct"
+        );
+    }
+    if state.chaos.is_some() {
+        info!("[dispatcher] CHAOS MODE enabled: backends will fail at random");
+        tokio::spawn(run_chaos(state.clone()));
+    }
+
+    let listener = TcpListener::from_std(listeners.proxy)?;
+    info!(
+        "[dispatcher] HTTP CONNECT / SOCKS5 proxy on {} (flag: {}, 'on' = Tor, 'off' = direct, 'masque' = relay)",
+        listener.local_addr()?,
+        FLAG_PATH
+    );
+    report_event(
+        &state,
+        EventKind::Information,
+        eventlog::STARTED,
+        &format!("dispatcher started, proxy on {}", listener.local_addr()?),
+    );
+
+    let (stop_tx, stop_rx) = watch::channel(false);
+
+    if let Some(listener_v6) = listeners.proxy_v6 {
+        let listener_v6 = TcpListener::from_std(listener_v6)?;
+        info!(
+            "[dispatcher] also listening on {}",
+            listener_v6.local_addr()?
+        );
+        tokio::spawn(serve(listener_v6, state.clone(), stop_rx.clone()));
+    }
+    if let Some(listener) = listeners.virtual_proxy {
+        let listener = TcpListener::from_std(listener)?;
+        info!(
+            "[dispatcher] also listening on {} (the [ha] virtual address, while active)",
+            listener.local_addr()?
+        );
+        tokio::spawn(serve(listener, state.clone(), stop_rx.clone()));
+    }
+    #[cfg(unix)]
+    if let Some(unix) = listeners.unix {
+        let unix = UnixListener::from_std(unix)?;
+        if let Some(path) = cfg.dispatcher.unix_socket.as_ref() {
+            info!("[dispatcher] also listening on {}", path.display());
+        }
+        tokio::spawn(serve_unix(unix, state.clone(), stop_rx.clone()));
+    }
+    tokio::spawn(serve(listener, state.clone(), stop_rx));
+
+    let mut successor = None;
+    loop {
+        tokio::select! {
+            r = shutdown_signal() => {
+                r?;
+                break;
+            }
+            mut next = next_successor(offer.as_ref()) => {
+                // Counted before the flush: bytes in between are counted
+                // twice rather than not at all
+                let baseline = relayed(&state);
+                publish(&state);
+                merge_reports(&state, []);
+                state.handed_over.store(true, Ordering::SeqCst);
+                let sockets: Vec<_> = copies.iter().map(|(kind, socket)| (*kind, socket)).collect();
+                match next.hand_over(&sockets).await {
+                    Ok(()) => {
+                        successor = Some((next, baseline));
+                        break;
+                    }
+                    Err(e) => {
+                        state.handed_over.store(false, Ordering::SeqCst);
+                        warn!("[dispatcher] handover failed, carrying on: {}", e);
+                    }
+                }
+            }
+        }
+    }
+    drop(copies);
+    if let Some(task) = ha_task {
+        match successor {
+            // The address stays up for the successor, which sees it is ours
+            Some(_) => task.abort(),
+            None => {
+                let _ = resign_tx.send(true);
+                let _ = task.await;
+            }
+        }
+    }
+
+    // Stop accepting, let active sessions finish, then flush state
+    let _ = stop_tx.send(true);
+    let drain = Duration::from_secs(cfg.dispatcher.drain_secs);
+    info!(
+        "[dispatcher] {}: draining {} session(s) for up to {}s",
+        match successor {
+            Some(_) => "sockets handed over to a new instance",
+            None => "shutting down",
+        },
+        state.sessions.load(Ordering::SeqCst),
+        drain.as_secs()
+    );
+    let started = Instant::now();
+    while state.sessions.load(Ordering::SeqCst) > 0 && started.elapsed() < drain {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let left = state.sessions.load(Ordering::SeqCst);
+    if left > 0 {
+        info!(
+            "[dispatcher] drain period over, cutting {} session(s)",
+            left
+        );
+    }
+
+    if let Some((successor, baseline)) = successor {
+        // Our ledgers and exit pins are the new instance's now
+        let tail = relayed(&state).since(&baseline);
+        if let Err(e) = successor.finish(&tail).await {
+            warn!(
+                "[dispatcher] could not report the drain to the new instance: {}",
+                e
+            );
+        }
+        report_event(
+            &state,
+            EventKind::Information,
+            eventlog::STOPPED,
+            "dispatcher stopped, upgraded",
+        );
+        info!("[dispatcher] drained, the new instance carries on, bye");
+        return Ok(());
+    }
+    publish(&state);
+    merge_reports(&state, []);
+    if state.exit_pins.is_some() {
+        if let Err(e) = torctl::release(&cfg.tor_control).await {
+            warn!(
+                "[dispatcher] tor: could not hand stream attachment back ({}); \
+                 run RESETCONF __LeaveStreamsUnattached",
+                e
+            );
+        }
+    }
+    report_event(
+        &state,
+        EventKind::Information,
+        eventlog::STOPPED,
+        "dispatcher stopped",
+    );
+    info!("[dispatcher] state flushed, bye");
+    Ok(())
+}
+
+/// Attach Tor's streams for `tor_exits` pins, reconnecting to the control
+/// port whenever the connection is lost. Pinned sessions are refused while
+/// it is down, never sent through an arbitrary exit.
+async fn run_exit_pins(cfg: TorControlConfig, state: Arc<State>) {
+    let pins = state.exit_pins.as_ref().expect("exit pins configured");
+    let mut connected = true;
+    loop {
+        let result = match Control::connect(&cfg).await {
+            Ok(control) => {
+                if !connected {
+                    emit_failover(
+                        &state,
+                        "tor",
+                        "control port back, exits pinned again".to_string(),
+                    );
+                }
+                connected = true;
+                torctl::attach(control, pins).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!("[dispatcher] tor control port {}: {}", cfg.address, e);
+            if connected {
+                let reason = format!("control port unreachable, pinned sessions refused: {}", e);
+                emit_failover(&state, "tor", reason);
+            }
+            connected = false;
+        }
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+/// The first successor to ask for our sockets properly. Never resolves
+/// without an offer.
+async fn next_successor(offer: Option<&Offer>) -> Successor {
+    let Some(offer) = offer else {
+        return std::future::pending().await;
+    };
+    loop {
+        match offer.next().await {
+            Ok(successor) => return successor,
+            Err(e) => {
+                warn!("[dispatcher] handover refused: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Bytes relayed so far, by egress and by user.
+fn relayed(state: &State) -> Tail {
+    let user_usage = state.user_usage.lock().expect("user usage ledger poisoned");
+    Tail {
+        egress: state
+            .egress
+            .iter()
+            .map(|(name, egress)| (name.to_string(), egress.meter.bytes()))
+            .collect(),
+        users: state
+            .users
+            .iter()
+            .map(|(key, policy)| (key.clone(), user_usage.used(key, &policy.limits)))
+            .collect(),
+    }
+}
+
+/// After an upgrade: count what the old instance relayed while draining,
+/// then take the pid lock it leaves behind and hold it until exit.
+async fn adopt(predecessor: Predecessor, lock: InheritedLock, state: Arc<State>) {
+    match predecessor.tail().await {
+        Ok(tail) => {
+            let mut usage = state.usage.lock().expect("usage ledger poisoned");
+            for (name, bytes) in &tail.egress {
+                if let Some(egress) = state.egress.get(name.as_str()) {
+                    usage.record(name, *bytes, &egress.limits);
+                }
+            }
+            drop(usage);
+            let mut user_usage = state.user_usage.lock().expect("user usage ledger poisoned");
+            for (key, bytes) in &tail.users {
+                if let Some(policy) = state.users.get(key) {
+                    user_usage.record(key, *bytes, &policy.limits);
+                }
+            }
+            info!(
+                "[dispatcher] previous instance drained ({} bytes relayed meanwhile)",
+                tail.egress.values().sum::<u64>()
+            );
+        }
+        Err(e) => warn!(
+            "[dispatcher] previous instance exited without reporting its drain: {}",
+            e
+        ),
+    }
+    match tokio::task::spawn_blocking(move || lock.wait()).await {
+        // Like main's: held until exit
+        Ok(Ok(lock)) => std::mem::forget(lock),
+        Ok(Err(e)) => warn!("[dispatcher] could not take over {}: {}", PID_PATH, e),
+        Err(e) => warn!("[dispatcher] could not take over {}: {}", PID_PATH, e),
+    }
+}
+
+/// Resolve on SIGTERM or Ctrl-C.
+#[cfg(unix)]
+async fn shutdown_signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut term = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = term.recv() => {}
+        r = tokio::signal::ctrl_c() => r?,
+    }
+    Ok(())
+}
+
+/// Resolve on Ctrl-C.
+#[cfg(not(unix))]
+async fn shutdown_signal() -> io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+async fn serve(listener: TcpListener, state: Arc<State>, mut stop: watch::Receiver<bool>) {
+    loop {
+        let (socket, peer) = tokio::select! {
+            conn = listener.accept() => match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("[dispatcher] accept error: {}", e);
+                    continue;
+                }
+            },
+            _ = stop.changed() => return,
+        };
+        info!("[dispatcher] new client from {}", peer);
+        // Only loopback clients have a local user to look up
+        let uid = match socket.local_addr() {
+            Ok(local) if !state.users.is_empty() && peer.ip().is_loopback() => {
+                peer::tcp_uid(local, peer)
+            }
+            _ => None,
+        };
+        spawn_session(socket, peer.to_string(), uid, state.clone());
+    }
+}
+
+/// [`serve`], for clients on the unix socket, identified by `SO_PEERCRED`.
+#[cfg(unix)]
+async fn serve_unix(listener: UnixListener, state: Arc<State>, mut stop: watch::Receiver<bool>) {
+    loop {
+        let socket = tokio::select! {
+            conn = listener.accept() => match conn {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    warn!("[dispatcher] accept error: {}", e);
+                    continue;
+                }
+            },
+            _ = stop.changed() => return,
+        };
+        let cred = socket.peer_cred().ok();
+        let client = match cred.and_then(|c| c.pid()) {
+            Some(pid) => format!("unix:pid={}", pid),
+            None => "unix".to_string(),
+        };
+        info!("[dispatcher] new client from {}", client);
+        spawn_session(socket, client, cred.map(|c| c.uid()), state.clone());
+    }
+}
+
+/// Key of `uid`'s `[users]` policy (login name, else uid), if it has one.
+/// Records who the client is on `entry` either way.
+fn identify(state: &State, uid: u32, entry: &mut TrafficEntry) -> Option<String> {
+    let name = peer::user_name(uid);
+    entry.user = Some(name.clone().unwrap_or_else(|| uid.to_string()));
+    name.into_iter()
+        .chain([uid.to_string()])
+        .find(|key| state.users.contains_key(key))
+}
+
+/// Handle one client connection in the background, then log it.
+fn spawn_session<I: Upstream + 'static>(
+    socket: I,
+    client: String,
+    uid: Option<u32>,
+    state: Arc<State>,
+) {
+    tokio::spawn(async move {
+        let _session = Session::start(&state.sessions);
+        let started = Instant::now();
+        let mut entry = TrafficEntry {
+            unix: now_unix(),
+            client,
+            ..TrafficEntry::default()
+        };
+        let user = uid.and_then(|uid| identify(&state, uid, &mut entry));
+        let result = handle_client(socket, state.clone(), &mut entry, user).await;
+        // Refused, or the egress is down: traffic is held back, not rerouted
+        match entry.outcome.as_str() {
+            "quota_exhausted" | "saturated" | "no_relay" | "chaos_killed" | "connecting"
+                if entry.egress.is_some() && state.kill_switch.engage() =>
+            {
+                let reason = format!(
+                    "kill switch engaged ({} on {})",
+                    entry.outcome,
+                    entry.egress.as_deref().unwrap_or("-")
+                );
+                emit_kill_switch(&state, true, reason);
+                tokio::spawn(detect_portal(state.clone()));
+            }
+            _ => {}
+        }
+        if let Err(e) = result {
+            warn!("[dispatcher] error: {}", e);
+            entry.outcome = format!("error: {}", e);
+        }
+        entry.duration_ms = started.elapsed().as_millis() as u64;
+        append_log(&state.traffic_log, &entry);
+        send_syslog(&state, "traffic", &entry);
+        if state.journal_decisions {
+            journal_decision(&entry);
+        }
+        emit(&state, Event::Decision(entry.clone()));
+        let mut decisions = state.decisions.lock().expect("decisions poisoned");
+        decisions.push_front(entry);
+        decisions.truncate(DECISIONS_KEPT);
+    });
 }
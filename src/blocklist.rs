@@ -0,0 +1,96 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::BlocklistConfig;
+use crate::http::{self, Url};
+use crate::stats::{now_unix, write_atomic};
+
+/// Merged blocklist cache written by the dispatcher, read by the CLI.
+pub const BLOCKLIST_PATH: &str = "gold-dust-blocklist.json";
+
+/// Outcome of loading one source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceStatus {
+    pub source: String,
+    pub entries: usize,
+    pub error: Option<String>,
+}
+
+/// Known-bad relays/nodes, merged from every configured source.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Blocklist {
+    pub updated_unix: u64,
+    pub entries: BTreeSet<String>,
+    pub sources: Vec<SourceStatus>,
+}
+
+/// One entry per line (backend name, relay fingerprint or address);
+/// `#` starts a comment. Entries are case-insensitive.
+pub fn parse_list(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.lines()
+        .map(|l| l.split('#').next().unwrap_or("").trim())
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_ascii_lowercase())
+}
+
+pub(crate) async fn load_source(
+    source: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let resp = http::request("GET", &Url::parse(source)?, &[], &[]).await?;
+        if resp.status != 200 {
+            return Err(format!("HTTP {}", resp.status).into());
+        }
+        Ok(String::from_utf8_lossy(&resp.body).into_owned())
+    } else {
+        Ok(std::fs::read_to_string(source)?)
+    }
+}
+
+impl Blocklist {
+    /// Fetch every source. A failing source is reported, not fatal.
+    pub async fn fetch(cfg: &BlocklistConfig) -> Self {
+        let mut list = Blocklist {
+            updated_unix: now_unix(),
+            ..Default::default()
+        };
+        for source in &cfg.sources {
+            let status = match load_source(source).await {
+                Ok(text) => {
+                    let before = list.entries.len();
+                    list.entries.extend(parse_list(&text));
+                    SourceStatus {
+                        source: source.clone(),
+                        entries: list.entries.len() - before,
+                        error: None,
+                    }
+                }
+                Err(e) => SourceStatus {
+                    source: source.clone(),
+                    entries: 0,
+                    error: Some(e.to_string()),
+                },
+            };
+            list.sources.push(status);
+        }
+        list
+    }
+
+    /// Read the cache, or `None` if it was never written.
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let text = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        write_atomic(path.as_ref(), &serde_json::to_string_pretty(self)?)
+    }
+
+    /// Is `name` (backend name, fingerprint or address) listed?
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains(&name.to_ascii_lowercase())
+    }
+}
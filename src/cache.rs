@@ -0,0 +1,243 @@
+//! Bounded in-memory caches: at most a configured number of entries, the
+//! least recently or least frequently used dropped first, with hit and miss
+//! counts for metrics.
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+
+/// Which entry a full cache drops to make room.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Eviction {
+    /// The one used longest ago.
+    #[default]
+    Lru,
+    /// The one used fewest times; ties go to the one used longest ago.
+    Lfu,
+}
+
+impl Eviction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Eviction::Lru => "lru",
+            Eviction::Lfu => "lfu",
+        }
+    }
+
+    /// Where an entry used `uses` times, last at `at`, stands in the
+    /// eviction order.
+    fn rank(self, uses: u64, at: u64) -> (u64, u64) {
+        match self {
+            Eviction::Lru => (0, at),
+            Eviction::Lfu => (uses, at),
+        }
+    }
+}
+
+/// How big a cache may grow, and what it drops when full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Most entries kept (0: cache nothing).
+    pub capacity: usize,
+    pub eviction: Eviction,
+}
+
+/// What a cache holds and how well it has done.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room (not counting expired ones).
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// Share of lookups answered from the cache, 0.0 before any.
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+struct Slot<V> {
+    value: V,
+    uses: u64,
+    /// Key in `order`.
+    rank: (u64, u64),
+}
+
+/// A map of at most `capacity` entries. Lookups and inserts cost a hash
+/// probe plus an ordered-index update, however full it is.
+pub struct BoundedCache<K, V> {
+    entries: HashMap<K, Slot<V>>,
+    /// Eviction order: (uses under LFU, else 0; last use), oldest first.
+    order: BTreeMap<(u64, u64), K>,
+    limits: Limits,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl<K: Eq + Hash + Clone, V> BoundedCache<K, V> {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            limits,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether a new key would evict one.
+    pub fn is_full(&self) -> bool {
+        self.entries.len() >= self.limits.capacity
+    }
+
+    /// The entry for `key`, counted as a hit or a miss.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get_if(key, |_| true)
+    }
+
+    /// The entry for `key` if `usable` accepts it. One it rejects (expired,
+    /// say) is dropped, and the lookup counts as a miss.
+    pub fn get_if<Q>(&mut self, key: &Q, usable: impl FnOnce(&V) -> bool) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let Some(slot) = self.entries.get(key) else {
+            self.misses += 1;
+            return None;
+        };
+        if !usable(&slot.value) {
+            self.misses += 1;
+            self.remove(key);
+            return None;
+        }
+        self.hits += 1;
+        self.touch(key);
+        self.entries.get(key).map(|slot| &slot.value)
+    }
+
+    /// Keep `value` under `key`, making room if the cache is full.
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.limits.capacity == 0 {
+            return;
+        }
+        if let Some(slot) = self.entries.get_mut(&key) {
+            slot.value = value;
+            self.touch(&key);
+            return;
+        }
+        while self.entries.len() >= self.limits.capacity {
+            let Some((_, evicted)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&evicted);
+            self.evictions += 1;
+        }
+        self.clock += 1;
+        let rank = self.limits.eviction.rank(1, self.clock);
+        self.order.insert(rank, key.clone());
+        self.entries.insert(
+            key,
+            Slot {
+                value,
+                uses: 1,
+                rank,
+            },
+        );
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let slot = self.entries.remove(key)?;
+        self.order.remove(&slot.rank);
+        Some(slot.value)
+    }
+
+    /// Drop every entry `keep` rejects (not counted as evictions).
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        let order = &mut self.order;
+        self.entries.retain(|key, slot| {
+            let kept = keep(key, &slot.value);
+            if !kept {
+                order.remove(&slot.rank);
+            }
+            kept
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            capacity: self.limits.capacity,
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+        }
+    }
+
+    /// Record a use of `key`, which is present.
+    fn touch<Q>(&mut self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.clock += 1;
+        let clock = self.clock;
+        let eviction = self.limits.eviction;
+        let Some(slot) = self.entries.get_mut(key) else {
+            return;
+        };
+        slot.uses += 1;
+        let Some(owned) = self.order.remove(&slot.rank) else {
+            return;
+        };
+        slot.rank = eviction.rank(slot.uses, clock);
+        self.order.insert(slot.rank, owned);
+    }
+}
+
+impl<K, V> std::fmt::Debug for BoundedCache<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BoundedCache")
+            .field("entries", &self.entries.len())
+            .field("limits", &self.limits)
+            .field("hits", &self.hits)
+            .field("misses", &self.misses)
+            .field("evictions", &self.evictions)
+            .finish()
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::config::ChaosConfig;
+
+/// Fault currently injected into a backend.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// Every new connection fails.
+    Killed,
+    /// Every new connection is delayed by this much.
+    Degraded(Duration),
+}
+
+/// Opt-in fault injector for resilience testing.
+///
+/// Every `interval_secs` each backend rolls against the configured
+/// probabilities; a hit breaks it for `outage_secs`.
+#[derive(Debug)]
+pub struct Chaos {
+    cfg: ChaosConfig,
+    rng: Mutex<StdRng>,
+    faults: Mutex<HashMap<String, (Fault, Instant)>>,
+}
+
+impl Chaos {
+    pub fn new(cfg: &ChaosConfig) -> Self {
+        let rng = match cfg.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            cfg: cfg.clone(),
+            rng: Mutex::new(rng),
+            faults: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.cfg.interval_secs.max(1))
+    }
+
+    /// Roll the dice for every healthy backend. Returns newly injected faults.
+    pub fn tick(&self, backends: &[&str]) -> Vec<(String, Fault)> {
+        let mut rng = self.rng.lock().expect("chaos rng poisoned");
+        let mut faults = self.faults.lock().expect("chaos state poisoned");
+        let now = Instant::now();
+        faults.retain(|_, (_, until)| *until > now);
+
+        let until = now + Duration::from_secs(self.cfg.outage_secs);
+        let mut injected = Vec::new();
+        for &name in backends {
+            if faults.contains_key(name) {
+                continue;
+            }
+            let fault = if rng.gen_bool(self.cfg.kill_probability.clamp(0.0, 1.0)) {
+                Fault::Killed
+            } else if rng.gen_bool(self.cfg.degrade_probability.clamp(0.0, 1.0)) {
+                Fault::Degraded(Duration::from_millis(self.cfg.degrade_latency_ms))
+            } else {
+                continue;
+            };
+            faults.insert(name.to_string(), (fault, until));
+            injected.push((name.to_string(), fault));
+        }
+        injected
+    }
+
+    /// Fault currently affecting `backend`, if any.
+    pub fn fault(&self, backend: &str) -> Option<Fault> {
+        let faults = self.faults.lock().expect("chaos state poisoned");
+        faults
+            .get(backend)
+            .filter(|(_, until)| *until > Instant::now())
+            .map(|(fault, _)| *fault)
+    }
+}
@@ -1,40 +1,2401 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::access::Grants;
+use crate::admin;
+use crate::audit;
+use crate::cache::{Eviction, Limits};
+use crate::diagnostic::{self, Diagnostic};
+use crate::discovery::Version;
+use crate::dns::{self, DotServer};
+use crate::http::Url;
+use crate::keychain;
+use crate::lan::Lan;
+use crate::lint;
+use crate::matcher::{Pattern, RuleMatcher};
+use crate::metrics;
+use crate::pinning::SpkiPin;
+use crate::retry::FailureClass;
+use crate::router::BackendKind;
+use crate::script::Conditions;
+use crate::seal;
+use crate::syslog::SyslogAddress;
+use crate::target::{Cidr, Host, PortRange, Target};
+use crate::token;
 
 /// Per-backend toggle config.
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BackendConfig {
     /// Enable Oxen backends.
     pub oxen_enabled: bool,
     /// Enable Tor backends.
     pub tor_enabled: bool,
+    /// Local Tor SOCKS5 port.
+    #[serde(default = "default_tor_socks")]
+    pub tor_socks: SocketAddr,
+    /// Multi-hop backends, e.g. Tor then lokinet.
+    #[serde(default)]
+    pub chains: Vec<ChainConfig>,
+    /// HTTP/3 CONNECT relay, TCP only (needs the `masque` feature).
+    pub masque: Option<MasqueConfig>,
+}
+
+/// An HTTP/3 relay that tunnels CONNECT requests (`[backends.masque]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MasqueConfig {
+    /// `https://host[:port]` of the relay.
+    pub relay: String,
+    /// PEM file with the relay's CA, for self-hosted relays. Defaults to the
+    /// Mozilla root set.
+    pub ca_cert: Option<PathBuf>,
+}
+
+/// A chained backend: traffic enters the first hop, then the next.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainConfig {
+    /// Defaults to the hops joined with `>` (`tor>oxen`).
+    pub name: Option<String>,
+    /// At least two of `tor` / `oxen`, entry first.
+    pub chain: Vec<BackendKind>,
+}
+
+impl ChainConfig {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            self.chain
+                .iter()
+                .map(|k| k.as_str())
+                .collect::<Vec<_>>()
+                .join(">")
+        })
+    }
+}
+
+fn default_tor_socks() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 9050))
+}
+
+/// Backend selection settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingConfig {
+    /// Seed for randomized selection. Same seed + same inputs = same decisions.
+    pub seed: Option<u64>,
+    /// WASM module that ranks candidates (needs the `wasm-plugins` feature).
+    pub policy: Option<PathBuf>,
+    /// How to pick among equally eligible backends of one kind.
+    #[serde(default)]
+    pub balance: Balance,
+    /// Where this gateway is, to lean toward the nearest `[groups]` entry:
+    /// a group name, or `auto` for the group with the fastest usable member.
+    pub vantage: Option<String>,
+    /// Share of decisions (0.0–1.0) that try the nearest group first.
+    /// Defaults to 1.0 with a `vantage`.
+    pub vantage_bias: Option<f64>,
+    /// Record every decision with everything it was made from, one JSON
+    /// line each, for `replay --verify`.
+    pub record: Option<PathBuf>,
+}
+
+impl RoutingConfig {
+    /// `vantage_bias`, or its default.
+    pub fn vantage_bias(&self) -> f64 {
+        self.vantage_bias.unwrap_or(1.0)
+    }
+}
+
+/// Spreading policy within one backend kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Balance {
+    /// Uniformly random.
+    #[default]
+    Random,
+    /// Each node in turn, for homogeneous pools.
+    RoundRobin,
+    /// Smooth weighted round-robin: every healthy node gets connections in
+    /// proportion to its score.
+    WeightedRr,
+    /// Random, with probability proportional to score.
+    WeightedRandom,
+    /// Power of two choices: the better of two random candidates.
+    P2c,
+}
+
+impl Balance {
+    /// Config spelling (`round-robin`, `weighted-rr`, ...).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Balance::Random => "random",
+            Balance::RoundRobin => "round-robin",
+            Balance::WeightedRr => "weighted-rr",
+            Balance::WeightedRandom => "weighted-random",
+            Balance::P2c => "p2c",
+        }
+    }
+}
+
+/// How target host names are resolved before rules are evaluated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsMode {
+    /// Rules see the host name only; direct connections use the system resolver.
+    #[default]
+    System,
+    /// Resolve names via DNS-over-HTTPS first, so IP/CIDR rules apply to them.
+    Doh,
+    /// Never resolve locally: names go unresolved into the SOCKS handshake
+    /// (socks5h semantics) and the backend resolves them.
+    Remote,
+    /// Resolve names via DNS-over-TLS, through the session's egress, so
+    /// IP/CIDR rules apply to them.
+    Dot,
+}
+
+/// How a DNS-over-TLS server must identify itself (RFC 8310).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DotProfile {
+    /// A CA-issued certificate for the server's name, or no lookup at all.
+    #[default]
+    Strict,
+    /// Any certificate; plain DNS over TCP if the server has no TLS.
+    Opportunistic,
+}
+
+/// `[dns]` section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DnsConfig {
+    pub mode: DnsMode,
+    /// RFC 8484 endpoint used in `doh` mode.
+    pub doh_url: String,
+    /// Servers used in `dot` mode, tried in order.
+    pub dot_servers: Vec<DotServer>,
+    pub dot_profile: DotProfile,
+    /// Names each resolver remembers; 0 turns caching off.
+    pub cache_size: usize,
+    /// Which name a full cache forgets: `lru` (used longest ago) or `lfu`
+    /// (used least).
+    pub cache_eviction: Eviction,
+    /// Names with fixed addresses (`[dns.hosts]`), resolved before anything
+    /// else, whatever the mode.
+    pub hosts: HashMap<String, HostAddresses>,
+    /// An `/etc/hosts`-style file of the same; `[dns.hosts]` wins.
+    pub hosts_file: Option<PathBuf>,
+    /// Ad/tracker names refused before any lookup (`[dns.block]`).
+    pub block: DnsBlockConfig,
+    /// Resolvers for names routed through one egress
+    /// (`[dns.resolvers.tor]`), used instead of `mode`.
+    pub resolvers: HashMap<EgressKind, ResolverConfig>,
+}
+
+/// The resolver for one egress's names: exactly one of `dns`, `doh_url`,
+/// `dot_servers` or `remote`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResolverConfig {
+    /// Plain DNS over UDP at a local address: Tor's `DNSPort`, lokinet's
+    /// resolver (`127.3.2.1:53`).
+    pub dns: Option<SocketAddr>,
+    /// DoH endpoint, reached through the egress.
+    pub doh_url: Option<String>,
+    /// DoT servers, reached through the egress and tried in order.
+    pub dot_servers: Vec<DotServer>,
+    pub dot_profile: DotProfile,
+    /// No lookup beforehand: the name goes to the backend unresolved.
+    pub remote: bool,
+}
+
+impl DnsConfig {
+    /// Bounds for each resolver's cache.
+    pub fn cache(&self) -> Limits {
+        Limits {
+            capacity: self.cache_size,
+            eviction: self.cache_eviction,
+        }
+    }
+}
+
+impl ResolverConfig {
+    /// Which of the resolver keys are set.
+    pub fn kinds(&self) -> Vec<&'static str> {
+        let set = [
+            ("dns", self.dns.is_some()),
+            ("doh_url", self.doh_url.is_some()),
+            ("dot_servers", !self.dot_servers.is_empty()),
+            ("remote", self.remote),
+        ];
+        set.into_iter()
+            .filter_map(|(k, on)| on.then_some(k))
+            .collect()
+    }
+}
+
+/// One address or several (`"10.0.0.5"`, `["10.0.0.5", "fd00::5"]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum HostAddresses {
+    One(IpAddr),
+    Many(Vec<IpAddr>),
+}
+
+impl HostAddresses {
+    pub fn to_vec(&self) -> Vec<IpAddr> {
+        match self {
+            HostAddresses::One(ip) => vec![*ip],
+            HostAddresses::Many(ips) => ips.clone(),
+        }
+    }
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            mode: DnsMode::System,
+            doh_url: "https://1.1.1.1/dns-query".to_string(),
+            dot_servers: Vec::new(),
+            dot_profile: DotProfile::Strict,
+            cache_size: dns::DEFAULT_CACHE_SIZE,
+            cache_eviction: Eviction::default(),
+            hosts: HashMap::new(),
+            hosts_file: None,
+            block: DnsBlockConfig::default(),
+            resolvers: HashMap::new(),
+        }
+    }
+}
+
+/// What a blocked name looks like to the client (`[dns.block] answer`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockAnswer {
+    /// As if the name didn't exist: host unreachable.
+    #[default]
+    Nxdomain,
+    /// As if it resolved to 0.0.0.0 / `::`: connection refused.
+    Null,
+}
+
+/// `[dns.block]`: ad and tracker blocklists, enforced for named targets.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DnsBlockConfig {
+    /// Whether sessions are checked, unless their `[apps]` profile's
+    /// `block_ads` says otherwise.
+    pub enabled: bool,
+    /// Hosts-format (`0.0.0.0 ads.example.com`) or ABP-style
+    /// (`||ads.example.com^`) lists: URLs or local files.
+    pub sources: Vec<String>,
+    /// How often the dispatcher re-fetches the sources.
+    pub refresh_secs: u64,
+    /// Names always blocked, with their subdomains, on top of the sources.
+    pub entries: Vec<String>,
+    /// Names never blocked, with their subdomains, whatever the lists say.
+    pub allow: Vec<String>,
+    pub answer: BlockAnswer,
+}
+
+impl Default for DnsBlockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sources: Vec::new(),
+            refresh_secs: 86_400,
+            entries: Vec::new(),
+            allow: Vec::new(),
+            answer: BlockAnswer::Nxdomain,
+        }
+    }
+}
+
+/// What happens to sessions bound for the LAN (`[lan] action`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LanAction {
+    /// Connect directly, whatever the flag or profile says.
+    #[default]
+    Direct,
+    /// Refuse the session.
+    Reject,
+    /// No special treatment: LAN destinations are routed like any other.
+    Off,
+}
+
+/// `[lan]`: private networks and local names, kept out of Tor and Oxen.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LanConfig {
+    pub action: LanAction,
+    /// Networks counted as LAN (CIDR); replaces the built-in list when set.
+    pub networks: Option<Vec<String>>,
+    /// Name suffixes counted as LAN (`local` covers `printer.local`);
+    /// replaces the built-in list when set.
+    pub domains: Option<Vec<String>>,
+    /// Destinations routed normally even though they look local, as
+    /// `[[rules]]` host patterns.
+    pub exempt: Vec<String>,
+}
+
+/// Captive portal detection and portal mode (`[portal]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PortalConfig {
+    /// Plain-http URL fetched directly; a portal redirects it to its login
+    /// page.
+    pub probe_url: String,
+    /// What the probe answers on an open network.
+    pub expect_status: u16,
+    /// How long `portal login` lets the portal through by default.
+    pub login_mins: u64,
+}
+
+impl Default for PortalConfig {
+    fn default() -> Self {
+        Self {
+            probe_url: "http://connectivitycheck.gstatic.com/generate_204".to_string(),
+            expect_status: 204,
+            login_mins: 5,
+        }
+    }
+}
+
+/// `[blocklist]`: known-bad Tor exits / Oxen nodes to never route through.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlocklistConfig {
+    /// URLs or local files, one entry per line.
+    pub sources: Vec<String>,
+    /// How often the dispatcher re-fetches the sources.
+    pub refresh_secs: u64,
+    /// Entries always excluded, on top of the sources.
+    pub entries: Vec<String>,
+}
+
+impl Default for BlocklistConfig {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            refresh_secs: 3600,
+            entries: Vec::new(),
+        }
+    }
+}
+
+/// Firewall tool `firewall apply` drives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirewallBackend {
+    #[default]
+    Nftables,
+    Iptables,
+}
+
+/// Kill-switch firewall rules (`gold-dust-gateway firewall apply`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FirewallConfig {
+    pub backend: FirewallBackend,
+    /// Users whose traffic may leave directly (the Tor/lokinet daemons, and
+    /// the dispatcher's user if it should be allowed direct egress).
+    pub allow_uids: Vec<u32>,
+    /// Backend endpoints reachable by anyone: `ip`, `ip:port`, `[v6]:port`
+    /// or CIDR.
+    pub allow: Vec<String>,
+    /// Keep private/link-local networks reachable.
+    pub allow_lan: bool,
+}
+
+impl Default for FirewallConfig {
+    fn default() -> Self {
+        Self {
+            backend: FirewallBackend::Nftables,
+            allow_uids: Vec::new(),
+            allow: Vec::new(),
+            allow_lan: true,
+        }
+    }
+}
+
+/// Confinement the dispatcher applies to itself once its sockets are bound
+/// (needs the `sandbox` feature, Linux only).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SandboxConfig {
+    /// Turn off to debug (same as `dispatcher --no-sandbox`).
+    pub enabled: bool,
+    /// Switch to this user (and its primary group) when started as root.
+    pub user: Option<String>,
+    /// Extra paths the dispatcher may read (e.g. blocklist files outside the
+    /// state directory).
+    pub read_paths: Vec<PathBuf>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            user: None,
+            read_paths: Vec::new(),
+        }
+    }
+}
+
+/// Encryption at rest for the logs and snapshots that say where traffic went
+/// and who did what.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncryptionConfig {
+    pub enabled: bool,
+    /// A key file, created on first run and readable by its owner alone, or
+    /// `keyring:<service>/<account>` (`keyring` feature).
+    pub key: String,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            key: seal::KEY_PATH.to_string(),
+        }
+    }
+}
+
+/// Log files the dispatcher writes and rotates itself.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// One JSON line per client session (off when unset).
+    pub traffic_log: Option<PathBuf>,
+    /// One JSON line per accepted health report (gossip, feed).
+    pub health_log: Option<PathBuf>,
+    /// One JSON line per administrative action (`audit list`); never
+    /// rotated here.
+    pub audit_log: PathBuf,
+    /// Rotate once the file reaches this size.
+    pub max_size_mb: u64,
+    /// Rotate once the file is this old (0: size only).
+    pub max_age_hours: u64,
+    /// Rotated files to keep; older ones are deleted.
+    pub keep: usize,
+    /// Gzip rotated files.
+    pub compress: bool,
+    /// Also send the logs to syslog (`[logging.syslog]`).
+    pub syslog: Option<SyslogConfig>,
+    /// Log to journald's socket instead of stdout/stderr.
+    pub journald: JournaldMode,
+    /// With the journal, also one entry per client session.
+    pub journald_decisions: bool,
+    /// Write startup, shutdown, failovers and the kill switch to the Windows
+    /// Event Log.
+    pub event_log: bool,
+    /// Event Log source name.
+    pub event_source: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            traffic_log: None,
+            health_log: None,
+            audit_log: PathBuf::from(audit::AUDIT_PATH),
+            max_size_mb: 10,
+            max_age_hours: 24,
+            keep: 5,
+            compress: true,
+            syslog: None,
+            journald: JournaldMode::Auto,
+            journald_decisions: false,
+            event_log: false,
+            event_source: "Gold Dust Gateway".to_string(),
+        }
+    }
+}
+
+/// When the dispatcher logs to journald (`[logging] journald`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournaldMode {
+    /// When systemd connected stderr to the journal.
+    #[default]
+    Auto,
+    On,
+    Off,
+}
+
+/// Syslog facility (`[logging.syslog] facility`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    User,
+    #[default]
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    /// Numeric code (RFC 5424 section 6.2.1).
+    pub fn code(self) -> u8 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+/// The dispatcher's logs as RFC 5424 messages (`[logging.syslog]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyslogConfig {
+    /// `udp://host:port`, `tcp://host:port` or `unixgram:///dev/log`.
+    pub address: String,
+    pub facility: SyslogFacility,
+    pub app_name: String,
+    /// Send session records, whether or not `traffic_log` is set.
+    pub traffic: bool,
+    /// Send health reports, whether or not `health_log` is set.
+    pub health: bool,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            address: "unixgram:///dev/log".to_string(),
+            facility: SyslogFacility::Daemon,
+            app_name: "gold-dust".to_string(),
+            traffic: true,
+            health: true,
+        }
+    }
+}
+
+/// Dispatcher lifecycle settings.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DispatcherConfig {
+    /// On SIGTERM/Ctrl-C, how long active sessions get to finish.
+    pub drain_secs: u64,
+    /// Also accept clients on this unix socket (unix only; any local user
+    /// may connect, each is identified by uid).
+    pub unix_socket: Option<PathBuf>,
+    /// Suspends at least this long are followed by re-checking backends and
+    /// reconnecting (0: never).
+    pub resume_after_secs: u64,
+    /// Close sessions that move no data either way for this long (0:
+    /// never); `idle_timeout_secs` in `[[rules]]` overrides it.
+    pub idle_timeout_secs: u64,
+    /// Relay plain socket sessions through io_uring (Linux, `io-uring`
+    /// feature).
+    pub io_uring: bool,
+}
+
+impl Default for DispatcherConfig {
+    fn default() -> Self {
+        Self {
+            drain_secs: 30,
+            unix_socket: None,
+            resume_after_secs: 30,
+            idle_timeout_secs: 0,
+            io_uring: false,
+        }
+    }
+}
+
+/// When the dispatcher probes less (`[power] mode`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerMode {
+    /// Low power while running on battery, as far as the OS says.
+    #[default]
+    Auto,
+    Normal,
+    Low,
+}
+
+/// Probing and timers on battery (`[power]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PowerConfig {
+    pub mode: PowerMode,
+    /// Health probe intervals are multiplied by this in low power.
+    pub probe_stretch: u32,
+    /// In low power, periodic work runs on a shared grid this coarse, so the
+    /// CPU wakes once for all of it.
+    pub batch_secs: u64,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            mode: PowerMode::Auto,
+            probe_stretch: 4,
+            batch_secs: 10,
+        }
+    }
+}
+
+/// Health-state gossip between dispatchers on a LAN.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GossipConfig {
+    pub enabled: bool,
+    /// UDP address to receive peer reports on.
+    pub bind: SocketAddr,
+    /// Peers to send reports to. Empty means LAN broadcast on `bind`'s port.
+    pub peers: Vec<SocketAddr>,
+    /// Shared secret every instance in the fleet signs its reports with.
+    pub secret: String,
+    /// How often to probe (or skip, if a peer's report is fresh) and gossip.
+    pub interval_secs: u64,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: SocketAddr::from(([0, 0, 0, 0], 7946)),
+            peers: Vec::new(),
+            secret: String::new(),
+            interval_secs: 15,
+        }
+    }
+}
+
+/// Two dispatchers as an active/standby pair sharing one address (`[ha]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HaConfig {
+    pub enabled: bool,
+    /// The higher one is active while both are up (say 200 on the router,
+    /// 100 on the backup).
+    pub priority: u8,
+    /// UDP address to receive the peer's heartbeats on.
+    pub bind: SocketAddr,
+    /// Where the peer receives ours.
+    pub peer: Option<SocketAddr>,
+    /// Shared secret both sign their heartbeats with.
+    pub secret: String,
+    pub interval_ms: u64,
+    /// Heartbeats missed before the peer counts as dead.
+    pub dead_after: u32,
+    /// Take over from a lower-priority active peer, rather than only from a
+    /// dead one.
+    pub preempt: bool,
+    /// Address clients use (`192.168.1.1/24`), held by the active one.
+    pub virtual_ip: Option<String>,
+    /// Interface the virtual address goes on.
+    pub interface: Option<String>,
+    /// Proxy port on the virtual address.
+    pub port: u16,
+}
+
+impl HaConfig {
+    /// `virtual_ip`, parsed.
+    pub fn virtual_ip(&self) -> Result<Option<Cidr>, String> {
+        self.virtual_ip.as_deref().map(str::parse).transpose()
+    }
+
+    /// How long the peer may stay silent before it counts as dead.
+    pub fn dead_after(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.max(1) * u64::from(self.dead_after.max(1)))
+    }
+}
+
+impl Default for HaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            priority: 100,
+            bind: SocketAddr::from(([0, 0, 0, 0], 7947)),
+            peer: None,
+            secret: String::new(),
+            interval_ms: 1000,
+            dead_after: 3,
+            preempt: true,
+            virtual_ip: None,
+            interface: None,
+            port: 7777,
+        }
+    }
+}
+
+/// SLO-style failover on health reports (`[failover]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FailoverConfig {
+    /// Share of reports in the window that may find a backend down before
+    /// it leaves rotation (0.05: a 95% SLO). Unset: each report decides.
+    pub error_budget: Option<f64>,
+    /// Rolling window the budget applies to (an hour at most).
+    pub window_secs: u64,
+    /// Reports in the window needed to judge; below, each report decides.
+    pub min_reports: u64,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            error_budget: None,
+            window_secs: 600,
+            min_reports: 5,
+        }
+    }
+}
+
+/// Keep Tor warm while another egress carries the traffic (`[standby]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StandbyConfig {
+    pub enabled: bool,
+    /// Fetched through Tor every `interval_secs` while it carries no
+    /// sessions.
+    pub canary: String,
+    pub interval_secs: u64,
+}
+
+impl Default for StandbyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            canary: "https://check.torproject.org/api/ip".to_string(),
+            interval_secs: 120,
+        }
+    }
+}
+
+/// Dial failure handling by class of failure (`[retry]`, `[retry.timeout]`,
+/// `[retry.refused]`, `[retry.general_failure]`, `[retry.unreachable]`,
+/// `[retry.ttl_expired]`, `[retry.other]`).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Give up on a dial after this long, as a `timeout` failure (0: wait
+    /// for the OS or proxy).
+    pub connect_timeout_secs: u64,
+    pub timeout: RetryPolicy,
+    pub refused: RetryPolicy,
+    pub general_failure: RetryPolicy,
+    pub unreachable: RetryPolicy,
+    pub ttl_expired: RetryPolicy,
+    pub other: RetryPolicy,
+}
+
+impl RetryConfig {
+    pub fn policy(&self, class: FailureClass) -> &RetryPolicy {
+        match class {
+            FailureClass::Timeout => &self.timeout,
+            FailureClass::Refused => &self.refused,
+            FailureClass::GeneralFailure => &self.general_failure,
+            FailureClass::Unreachable => &self.unreachable,
+            FailureClass::TtlExpired => &self.ttl_expired,
+            FailureClass::Other => &self.other,
+        }
+    }
+}
+
+/// What to do about one class of dial failure.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Further dials through the same egress (on a fresh circuit, for Tor).
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after.
+    pub backoff_ms: u64,
+    /// Egress to try once the retries are spent. A Tor session that falls
+    /// back to `direct` leaves Tor.
+    pub fallback: Option<EgressKind>,
+    /// Whether a failure that remains engages the kill switch.
+    pub kill_switch: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff_ms: 500,
+            fallback: None,
+            kill_switch: true,
+        }
+    }
+}
+
+/// Backend health pushed by an external monitoring system.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthFeedConfig {
+    pub enabled: bool,
+    /// Where the dispatcher accepts `POST /health`.
+    pub listen: SocketAddr,
+    /// Also read NDJSON records from the dispatcher's stdin.
+    pub stdin: bool,
+    /// Reports older than this are ignored when routing.
+    pub max_age_secs: u64,
+}
+
+impl Default for HealthFeedConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::from(([127, 0, 0, 1], 7780)),
+            stdin: false,
+            max_age_secs: 120,
+        }
+    }
+}
+
+/// What an alert rule watches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertMetric {
+    /// Backend latency in ms.
+    Latency,
+    /// Backend failure rate (0.0–1.0).
+    FailureRate,
+    /// The dispatcher is holding traffic back: its egress is unusable and
+    /// it won't fall back to another.
+    KillSwitch,
+}
+
+impl AlertMetric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertMetric::Latency => "latency",
+            AlertMetric::FailureRate => "failure_rate",
+            AlertMetric::KillSwitch => "kill_switch",
+        }
+    }
+}
+
+/// One alert condition (`[[alerts.rules]]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    /// Defaults to a description of the condition.
+    pub name: Option<String>,
+    pub metric: AlertMetric,
+    /// Threshold for `latency` / `failure_rate`.
+    pub above: Option<f64>,
+    /// Only fire once the condition has held this long.
+    #[serde(default)]
+    pub for_mins: u64,
+    /// Backend name or kind to watch; every backend by default.
+    pub backend: Option<String>,
+}
+
+impl AlertRule {
+    /// `latency > 500 ms for 5m` and the like.
+    pub fn condition(&self) -> String {
+        let what = match (self.metric, self.above) {
+            (AlertMetric::Latency, Some(above)) => format!("latency > {above} ms"),
+            (AlertMetric::FailureRate, Some(above)) => format!("failure_rate > {above}"),
+            (AlertMetric::KillSwitch, _) => "kill switch engaged".to_string(),
+            (_, None) => "(no threshold)".to_string(),
+        };
+        match self.for_mins {
+            0 => what,
+            mins => format!("{what} for {mins}m"),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.condition())
+    }
+}
+
+/// Alerting evaluated by the dispatcher.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    /// How often rules are checked.
+    pub interval_secs: u64,
+    /// URLs that get each firing/resolved event as a JSON POST.
+    pub webhooks: Vec<String>,
+    /// URLs that get a plain-text POST (ntfy, Gotify-style push services).
+    pub notify: Vec<String>,
+    pub rules: Vec<AlertRule>,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 10,
+            webhooks: Vec::new(),
+            notify: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// How `[metrics_push]` sends metrics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PushFormat {
+    /// Text exposition, `PUT` to a Pushgateway's `/metrics/job/<job>`.
+    #[default]
+    Pushgateway,
+    /// Prometheus remote-write (protobuf, snappy), `POST` to `url` as is.
+    RemoteWrite,
+}
+
+/// Pushing the dispatcher's metrics, for machines nothing can scrape.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsPushConfig {
+    /// Pushgateway base URL or remote-write endpoint; empty: no pushing.
+    pub url: String,
+    pub format: PushFormat,
+    pub interval_secs: u64,
+    /// `job` label.
+    pub job: String,
+    /// More labels for every series (Pushgateway: the grouping key).
+    pub labels: BTreeMap<String, String>,
+    /// Extra request headers, e.g. `Authorization` or `X-Scope-OrgID`.
+    pub headers: BTreeMap<String, String>,
+    /// Push through this egress instead of directly.
+    pub via: Option<EgressKind>,
+}
+
+impl Default for MetricsPushConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            format: PushFormat::Pushgateway,
+            interval_secs: 30,
+            job: "gold-dust".to_string(),
+            labels: BTreeMap::new(),
+            headers: BTreeMap::new(),
+            via: None,
+        }
+    }
+}
+
+impl MetricsPushConfig {
+    pub fn enabled(&self) -> bool {
+        !self.url.is_empty()
+    }
+}
+
+/// The dispatcher's local web dashboard.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DashboardConfig {
+    pub enabled: bool,
+    /// Must be a loopback address: the dashboard can switch the egress.
+    pub listen: SocketAddr,
+}
+
+impl Default for DashboardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::from(([127, 0, 0, 1], 7781)),
+        }
+    }
+}
+
+/// Who may use the dispatcher's local HTTP APIs (dashboard, health feed).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Require a bearer token, or a local user granted a role.
+    pub auth: bool,
+    /// Token that may change things. Created on first run, readable by the
+    /// dispatcher's user alone, like `read_token_file`; or a `keyring:` entry.
+    pub token_file: PathBuf,
+    /// Token that may only read.
+    pub read_token_file: PathBuf,
+    /// Local users (name or uid) and `@groups` that may change things
+    /// without a token.
+    pub operators: Vec<String>,
+    /// Local users and `@groups` that may read without a token.
+    pub viewers: Vec<String>,
+    /// Requests each client (local user, admin, address) may make a minute,
+    /// in bursts of as many; 0 for no limit.
+    pub requests_per_minute: u32,
+    /// Event streams (`/api/events`, `/api/ws`) each client may open a
+    /// minute; 0 for no limit.
+    pub streams_per_minute: u32,
+    /// Failed authentications in a row before a client is locked out; 0
+    /// never locks anyone out.
+    pub max_auth_failures: u32,
+    pub lockout_secs: u64,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            auth: true,
+            token_file: PathBuf::from(token::TOKEN_PATH),
+            read_token_file: PathBuf::from(token::READ_TOKEN_PATH),
+            operators: Vec::new(),
+            viewers: Vec::new(),
+            requests_per_minute: 300,
+            streams_per_minute: 30,
+            max_auth_failures: 5,
+            lockout_secs: 300,
+        }
+    }
+}
+
+/// The dashboard's API for remote machines, over mutual TLS.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+    /// Clients need a certificate from this CA.
+    pub ca_cert: PathBuf,
+    /// Our certificate and key (`admin cert --server`).
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Client names (certificate common names) refused even with a valid
+    /// certificate.
+    pub revoked: Vec<String>,
+    /// Client names that may change things.
+    pub operators: Vec<String>,
+    /// Client names that may only read. Clients in neither list are refused.
+    pub viewers: Vec<String>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::from(([0, 0, 0, 0], 7782)),
+            ca_cert: PathBuf::from(admin::CA_CERT_PATH),
+            cert: PathBuf::from(admin::CERT_PATH),
+            key: PathBuf::from(admin::KEY_PATH),
+            revoked: Vec::new(),
+            operators: Vec::new(),
+            viewers: Vec::new(),
+        }
+    }
+}
+
+/// Opt-in chaos mode for the dispatcher (resilience testing).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    pub enabled: bool,
+    /// Seconds between dice rolls.
+    pub interval_secs: u64,
+    /// Chance per roll that a backend is killed outright.
+    pub kill_probability: f64,
+    /// Chance per roll that a backend gets extra connect latency.
+    pub degrade_probability: f64,
+    /// How long an injected fault lasts.
+    pub outage_secs: u64,
+    /// Latency added to connects on a degraded backend.
+    pub degrade_latency_ms: u64,
+    /// Seed for reproducible chaos runs.
+    pub seed: Option<u64>,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 10,
+            kill_probability: 0.05,
+            degrade_probability: 0.1,
+            outage_secs: 30,
+            degrade_latency_ms: 500,
+            seed: None,
+        }
+    }
+}
+
+/// Per-destination rule.
+///
+/// Of the rules matching a target, the one with the highest `priority` wins;
+/// ties go to the first in file order.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    /// Exact host (`example.com`), subdomains (`*.example.com`), domain and
+    /// subdomains (`.example.com`), IP, or IPv4/IPv6 CIDR (`10.0.0.0/8`).
+    pub host: String,
+    /// Max new connections per minute to matching hosts.
+    pub connections_per_minute: Option<u32>,
+    /// Bandwidth ceiling in kilobits per second (1 kbps = 1000 bit/s),
+    /// shared by all matching connections.
+    pub bandwidth_kbps: Option<u64>,
+    /// Extra condition as a Rhai expression, e.g. `target.port == 443 && hour >= 22`
+    /// (needs the `scripting` feature).
+    pub when: Option<String>,
+    /// Balancing for matching targets, overriding `[routing] balance`.
+    pub balance: Option<Balance>,
+    /// `[groups]` entry whose members are preferred for matching targets.
+    pub group: Option<String>,
+    /// Tor relay fingerprints; matching sessions through Tor leave by one of
+    /// them, tried in order (needs `[tor_control]`).
+    pub tor_exits: Option<Vec<String>>,
+    /// Close matching sessions after this long without data either way,
+    /// instead of `[dispatcher] idle_timeout_secs` (0: never).
+    pub idle_timeout_secs: Option<u64>,
+    /// Rules with a higher priority are checked first (default 0).
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl RuleConfig {
+    /// Does this rule's host pattern apply to `host`? (`when` is not
+    /// evaluated here; see `script::Conditions`.)
+    ///
+    /// IP/CIDR patterns also match any of the `resolved` addresses of a
+    /// named host (see `[dns] mode = "doh"`).
+    pub fn matches(&self, host: &Host, resolved: &[IpAddr]) -> bool {
+        self.host
+            .parse::<Pattern>()
+            .is_ok_and(|p| p.matches(host, resolved))
+    }
+
+    /// This rule's setting, as `rules dump` shows it, for each setting two
+    /// rules can disagree on.
+    fn settings(&self) -> [(&'static str, Option<String>); 5] {
+        let limits: Vec<String> = [
+            self.connections_per_minute.map(|n| format!("{}/min", n)),
+            self.bandwidth_kbps
+                .map(|kbps| format!("{} kbit/s shared", kbps)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let limits = (!limits.is_empty()).then(|| limits.join(", "));
+        [
+            ("rate limits", limits),
+            ("balance", self.balance.map(|b| b.as_str().to_string())),
+            ("group", self.group.clone()),
+            ("tor_exits", self.tor_exits.as_ref().map(|e| e.join("|"))),
+            (
+                "idle_timeout",
+                self.idle_timeout_secs.map(|secs| secs.to_string()),
+            ),
+        ]
+    }
+}
+
+/// Two `[[rules]]` whose host patterns overlap and which set one setting to
+/// different values: targets matching both get the winner's.
+#[derive(Debug, Clone)]
+pub struct RuleConflict {
+    pub setting: &'static str,
+    pub winner: usize,
+    pub winner_host: String,
+    pub winner_value: String,
+    pub loser: usize,
+    pub loser_host: String,
+    pub loser_value: String,
+    /// Won on `priority` rather than file order.
+    pub by_priority: bool,
+    /// The winner has a `when`, so only wins while it holds.
+    pub conditional: bool,
+}
+
+impl fmt::Display for RuleConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rules #{} (`{}`) and #{} (`{}`) overlap with different {} ({} vs {}); #{} wins by {}",
+            self.winner,
+            self.winner_host,
+            self.loser,
+            self.loser_host,
+            self.setting,
+            self.winner_value,
+            self.loser_value,
+            self.winner,
+            if self.by_priority {
+                "priority"
+            } else {
+                "file order"
+            }
+        )?;
+        if self.conditional {
+            write!(f, " when its condition holds")?;
+        }
+        Ok(())
+    }
+}
+
+/// A named set of backends (`[groups.oxen-eu]`), for rules to prefer.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupConfig {
+    /// Backend names (as in `status`); chains by their name.
+    pub backends: Vec<String>,
+}
+
+/// Per-backend limits, keyed by egress (`tor`, `direct`, `masque`).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitConfig {
+    /// Bandwidth ceiling in kilobits per second across all sessions on
+    /// this backend.
+    pub bandwidth_kbps: Option<u64>,
+    /// Monthly data quota in MiB; the backend is drained once it is used up.
+    pub monthly_quota_mb: Option<u64>,
+    /// Day of month (1–28) on which the quota resets. Defaults to 1.
+    pub quota_reset_day: Option<u32>,
+    /// Simultaneous sessions; new ones overflow to the next candidate.
+    pub max_sessions: Option<usize>,
+    /// Kernel buffer per socket and direction in KiB, for both ends of each
+    /// session (HTTP/3 relay: the QUIC stream window). Unset: the OS autotunes,
+    /// up to several MiB per socket.
+    pub buffer_kib: Option<u64>,
+}
+
+impl LimitConfig {
+    /// Quota reset day, clamped to a day every month has.
+    pub fn reset_day(&self) -> u32 {
+        self.quota_reset_day.unwrap_or(1).clamp(1, 28)
+    }
+}
+
+/// Smallest `buffer_kib` that still moves data at a useful pace.
+const MIN_BUFFER_KIB: u64 = 4;
+
+/// Where the dispatcher sends a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EgressKind {
+    Tor,
+    Direct,
+    Masque,
+}
+
+impl EgressKind {
+    /// Every egress, in the order the dispatcher sets them up.
+    pub const ALL: [EgressKind; 3] = [EgressKind::Tor, EgressKind::Direct, EgressKind::Masque];
+
+    /// Key for this egress in `[limits]`, stats and the usage ledger.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EgressKind::Tor => "tor",
+            EgressKind::Direct => "direct",
+            EgressKind::Masque => "masque",
+        }
+    }
+
+    /// Router backends whose traffic leaves through this egress. Lokinet
+    /// is routed by the system, so Oxen exits go out `direct`.
+    pub fn backend(&self) -> BackendKind {
+        match self {
+            EgressKind::Tor => BackendKind::Tor,
+            EgressKind::Direct => BackendKind::Oxen,
+            EgressKind::Masque => BackendKind::Masque,
+        }
+    }
+}
+
+/// A client application, recognized by the username it gives the SOCKS
+/// proxy (`[apps.browser]`).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppConfig {
+    /// Egress for this app whatever the flag file says.
+    pub egress: Option<EgressKind>,
+    /// Tor circuit isolation key; defaults to the app name, so apps never
+    /// share circuits.
+    pub isolation: Option<String>,
+    /// Check this app's sessions against `[dns.block]` (default: its
+    /// `enabled`).
+    pub block_ads: Option<bool>,
+}
+
+/// Policy for one local user of a system-wide dispatcher, keyed by login
+/// name (`[users.alice]`) or uid (`[users.1000]`).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    /// Egress for this user's sessions, over app profiles and the flag file.
+    pub egress: Option<EgressKind>,
+    /// Monthly data quota in MiB, across all egresses.
+    pub monthly_quota_mb: Option<u64>,
+    /// Day of month (1–28) on which the quota resets. Defaults to 1.
+    pub quota_reset_day: Option<u32>,
+    /// Rate-limit rules for this user, checked on top of the global
+    /// `[[rules]]`.
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+}
+
+impl UserConfig {
+    /// The quota, in the form the usage ledger takes.
+    pub fn limits(&self) -> LimitConfig {
+        LimitConfig {
+            monthly_quota_mb: self.monthly_quota_mb,
+            quota_reset_day: self.quota_reset_day,
+            ..LimitConfig::default()
+        }
+    }
+}
+
+/// Scheduled Tor circuit rotation, per destination (`[rotation]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RotationConfig {
+    /// New circuit for each destination this often (0: off).
+    pub every_mins: u64,
+    /// New circuit for a destination once this much went through it (0: off).
+    pub every_mb: u64,
+    /// Spread destinations' rotation times over the period instead of
+    /// rotating every destination at once.
+    pub stagger: bool,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        Self {
+            every_mins: 0,
+            every_mb: 0,
+            stagger: true,
+        }
+    }
+}
+
+impl RotationConfig {
+    pub fn enabled(&self) -> bool {
+        self.every_mins > 0 || self.every_mb > 0
+    }
+}
+
+/// Keepalive and dead-peer detection for one egress (`[keepalive.tor]`,
+/// `[keepalive.direct]`, `[keepalive.masque]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeepaliveConfig {
+    /// Probe interval: TCP keepalive on the upstream socket, QUIC keep-alive
+    /// to the HTTP/3 relay (0: off).
+    pub interval_secs: u64,
+    /// End a session once the upstream has left client data unanswered
+    /// this long (0: off).
+    pub dead_after_secs: u64,
+    /// Fresh dials (a new circuit, for Tor) while the upstream has answered
+    /// nothing yet, replaying what the client sent.
+    pub redials: u32,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 0,
+            dead_after_secs: 0,
+            redials: 1,
+        }
+    }
+}
+
+/// Time budgets for dialing through one egress (`[timeouts.tor]`,
+/// `[timeouts.direct]`, `[timeouts.masque]`). Unset fields keep the egress's
+/// defaults; 0 is no limit.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutConfig {
+    /// Reaching the egress: Tor's SOCKS port, or the destination itself for
+    /// `direct`.
+    pub connect_secs: Option<u64>,
+    /// Opening the stream through it: the SOCKS request (Tor picks or
+    /// builds a circuit and the exit connects), the relay's CONNECT.
+    pub handshake_secs: Option<u64>,
+    /// From the client's first byte to the upstream's first answer.
+    pub first_byte_secs: Option<u64>,
+}
+
+impl TimeoutConfig {
+    /// Tor's SOCKS port is local, but a circuit can take a while; `direct`
+    /// has no handshake.
+    pub fn defaults(egress: &str) -> Self {
+        let (connect, handshake) = match egress {
+            "tor" => (5, 60),
+            "masque" => (0, 20),
+            _ => (15, 0),
+        };
+        Self {
+            connect_secs: Some(connect),
+            handshake_secs: Some(handshake),
+            first_byte_secs: Some(0),
+        }
+    }
+
+    pub fn connect(&self) -> Option<Duration> {
+        budget(self.connect_secs)
+    }
+
+    pub fn handshake(&self) -> Option<Duration> {
+        budget(self.handshake_secs)
+    }
+
+    pub fn first_byte(&self) -> Option<Duration> {
+        budget(self.first_byte_secs)
+    }
+}
+
+fn budget(secs: Option<u64>) -> Option<Duration> {
+    secs.filter(|&s| s > 0).map(Duration::from_secs)
+}
+
+/// What a backend can carry, keyed by backend name or kind
+/// (`[capabilities.tor]`, `[capabilities.oxen-eu-1]`). Unset fields keep the
+/// kind's defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CapabilityConfig {
+    /// UDP to the destination.
+    pub udp: Option<bool>,
+    /// `.onion` addresses.
+    pub onion: Option<bool>,
+    /// `.loki` addresses.
+    pub loki: Option<bool>,
+    /// IPv6 destinations; overrides what health reports say.
+    pub ipv6: Option<bool>,
+    /// Destination ports allowed (`"443"`, `"1024-65535"`); all when unset.
+    pub ports: Option<Vec<PortRange>>,
+}
+
+/// What probes measure a backend kind against (`[probes.tor]`,
+/// `[probes.oxen]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProbeConfig {
+    /// Endpoints reached through the backend, e.g. your own server or an
+    /// onion / loki address. Empty: only the local daemon's port is checked.
+    pub targets: Vec<Target>,
+    /// Per connection; a new Tor circuit can take a few seconds.
+    pub timeout_secs: u64,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// The local lokinet daemon (`[lokinet]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LokinetConfig {
+    /// RPC port (OxenMQ), for status, paths and switching exits.
+    pub rpc: SocketAddr,
+    /// Exits to send clearnet traffic through, preferred first
+    /// (`[[lokinet.exits]]`).
+    pub exits: Vec<LokinetExitConfig>,
+}
+
+impl Default for LokinetConfig {
+    fn default() -> Self {
+        Self {
+            rpc: SocketAddr::from(([127, 0, 0, 1], 1190)),
+            exits: Vec::new(),
+        }
+    }
+}
+
+/// A lokinet exit node.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LokinetExitConfig {
+    /// `.loki` address or ONS name.
+    pub address: String,
+    /// Auth token, for exits that require one; may be a `keyring:` entry.
+    pub token: Option<String>,
+    /// Port connected to when probing; a refused connection still counts,
+    /// since it came back from the exit.
+    #[serde(default = "default_exit_probe_port")]
+    pub probe_port: u16,
+}
+
+fn default_exit_probe_port() -> u16 {
+    80
+}
+
+/// Oxen service node discovery from oxend (`[discovery]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// oxend's JSON-RPC endpoint (`http://127.0.0.1:22023/json_rpc`); unset:
+    /// no discovery.
+    pub oxend_rpc: Option<String>,
+    /// Keys an https `oxend_rpc` must present (`sha256//<base64>`); any
+    /// one matching is enough, and CA roots are then not consulted.
+    pub oxend_spki: Vec<SpkiPin>,
+    /// Nodes running an older version are skipped.
+    pub min_version: Option<Version>,
+    /// Versions with known bugs, skipped whatever `min_version` says.
+    pub bad_versions: Vec<Version>,
+    /// Discovered nodes `status --probe` measures, at most.
+    pub probe_limit: usize,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            oxend_rpc: None,
+            oxend_spki: Vec::new(),
+            min_version: None,
+            bad_versions: Vec::new(),
+            probe_limit: 8,
+        }
+    }
+}
+
+/// Tor's control port, for attaching pinned streams (`tor_exits` in
+/// `[[rules]]`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TorControlConfig {
+    pub address: SocketAddr,
+    /// Secret behind Tor's `HashedControlPassword`, or
+    /// `keyring:<service>/<account>` (`keyring` feature).
+    pub password: Option<String>,
+    /// Tor's `control_auth_cookie` (`CookieAuthentication 1`), read on every
+    /// connect. Without it or a password, no authentication.
+    pub cookie_file: Option<PathBuf>,
+}
+
+impl Default for TorControlConfig {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], 9051)),
+            password: None,
+            cookie_file: None,
+        }
+    }
 }
 
 /// Top-level Gold Dust config.
 ///
 /// For v0.2 this is very simple: just switches for Oxen/Tor.
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct GoldDustConfig {
     pub backends: BackendConfig,
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    /// Per-destination rules (`[[rules]]`).
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    /// Backend groups for rules to prefer (`[groups.oxen-eu]`, ...).
+    #[serde(default)]
+    pub groups: HashMap<String, GroupConfig>,
+    /// Per-backend limits (`[limits.tor]`, ...).
+    #[serde(default)]
+    pub limits: HashMap<String, LimitConfig>,
+    /// Per-application profiles by SOCKS username (`[apps.browser]`, ...).
+    #[serde(default)]
+    pub apps: HashMap<String, AppConfig>,
+    /// Per-user policies when running system-wide (`[users.alice]`, ...).
+    #[serde(default)]
+    pub users: HashMap<String, UserConfig>,
+    #[serde(default)]
+    pub rotation: RotationConfig,
+    /// Tor control port, for `tor_exits` pins.
+    #[serde(default)]
+    pub tor_control: TorControlConfig,
+    #[serde(default)]
+    pub lokinet: LokinetConfig,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    /// Per-egress keepalive (`[keepalive.tor]`, ...).
+    #[serde(default)]
+    pub keepalive: HashMap<String, KeepaliveConfig>,
+    /// Per-egress dial budgets (`[timeouts.tor]`, ...).
+    #[serde(default)]
+    pub timeouts: HashMap<String, TimeoutConfig>,
+    /// Per-backend capabilities (`[capabilities.tor]`, ...).
+    #[serde(default)]
+    pub capabilities: HashMap<String, CapabilityConfig>,
+    /// Probe reference targets per kind (`[probes.tor]`, `[probes.oxen]`).
+    #[serde(default)]
+    pub probes: HashMap<String, ProbeConfig>,
+    #[serde(default)]
+    pub chaos: ChaosConfig,
+    #[serde(default)]
+    pub dns: DnsConfig,
+    #[serde(default)]
+    pub lan: LanConfig,
+    #[serde(default)]
+    pub portal: PortalConfig,
+    #[serde(default)]
+    pub blocklist: BlocklistConfig,
+    #[serde(default)]
+    pub gossip: GossipConfig,
+    #[serde(default)]
+    pub health_feed: HealthFeedConfig,
+    #[serde(default)]
+    pub ha: HaConfig,
+    #[serde(default)]
+    pub failover: FailoverConfig,
+    #[serde(default)]
+    pub standby: StandbyConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub dispatcher: DispatcherConfig,
+    #[serde(default)]
+    pub power: PowerConfig,
+    #[serde(default)]
+    pub firewall: FirewallConfig,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    #[serde(default)]
+    pub dashboard: DashboardConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub metrics_push: MetricsPushConfig,
 }
 
 impl GoldDustConfig {
-    /// Load Gold Dust config from a TOML file.
+    /// Load Gold Dust config from a TOML file. Unknown keys are ignored.
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self::load_with_warnings(path)?.0)
+    }
+
+    /// [`GoldDustConfig::load`], with a warning for each unknown key it
+    /// ignored, pointing at its line.
+    pub fn load_with_warnings<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<(Self, Vec<Diagnostic>), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
         let text = fs::read_to_string(path)?;
-        let cfg: GoldDustConfig = toml::from_str(&text)?;
+        let (cfg, warnings) = Self::check(&text).map_err(|d| d.with_path(path))?;
+        Ok((
+            cfg,
+            warnings.into_iter().map(|w| w.with_path(path)).collect(),
+        ))
+    }
+
+    /// Parse and validate config TOML text. Unknown keys are ignored.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self::check(text)?.0)
+    }
+
+    /// Errors point at the offending line, with a hint where one helps.
+    /// Unknown keys are dropped with a warning each, as `config lint` does,
+    /// so a config from a newer release (or with a typo) still loads.
+    fn check(text: &str) -> Result<(Self, Vec<Diagnostic>), Diagnostic> {
+        let (cfg, warnings) = match toml::from_str::<GoldDustConfig>(text) {
+            Ok(cfg) => (cfg, Vec::new()),
+            // Still failing without them, a misspelled key is the likely cause
+            Err(e) if e.message().starts_with("unknown field") => {
+                lint::without_unknown_keys(text).map_err(|_| Diagnostic::from_toml(text, &e))?
+            }
+            Err(e) => return Err(Diagnostic::from_toml(text, &e)),
+        };
+        Ok((cfg.validate(text)?, warnings))
+    }
+
+    /// Look up the secrets given as `keyring:` references (the Tor control
+    /// password, lokinet exit tokens) in the platform keychain, in place.
+    pub fn resolve_secrets(&mut self) -> Result<(), String> {
+        if let Some(password) = &mut self.tor_control.password {
+            *password =
+                keychain::resolve(password).map_err(|e| format!("[tor_control] password: {e}"))?;
+        }
+        for exit in &mut self.lokinet.exits {
+            if let Some(token) = &mut exit.token {
+                *token = keychain::resolve(token)
+                    .map_err(|e| format!("[[lokinet.exits]] {} token: {e}", exit.address))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks beyond what deserializing does, on config parsed from `text`.
+    pub(crate) fn validate(self, text: &str) -> Result<Self, Diagnostic> {
+        let cfg = self;
+        let at_rule = |message: String, key: &str| {
+            let span = rule_index(&message)
+                .and_then(|i| diagnostic::array_table_span(text, "rules", i, key));
+            Diagnostic::new(text, message).with_span(span)
+        };
+        cfg.rule_matcher().map_err(|e| {
+            at_rule(e, "host")
+                .with_help("use `example.com`, `*.example.com`, `.example.com`, an IP or a CIDR")
+        })?;
+        cfg.rule_conditions().map_err(|e| at_rule(e, "when"))?;
+        for (i, rule) in cfg.rules.iter().enumerate() {
+            let Some(group) = rule.group.as_ref().filter(|g| !cfg.groups.contains_key(*g)) else {
+                continue;
+            };
+            let names: Vec<&str> = cfg.groups.keys().map(String::as_str).collect();
+            let mut d = at_rule(format!("rule #{i}: unknown group `{group}`"), "group");
+            if let Some(name) = diagnostic::did_you_mean(group, &names) {
+                d = d.with_help(format!("did you mean `{name}`?"));
+            }
+            return Err(d);
+        }
+        for (i, rule) in cfg.rules.iter().enumerate() {
+            let Some(exits) = &rule.tor_exits else {
+                continue;
+            };
+            let bad = exits
+                .iter()
+                .find(|fp| crate::torctl::fingerprint(fp).is_none());
+            let message = match bad {
+                _ if exits.is_empty() => "needs at least one fingerprint".to_string(),
+                Some(fp) => format!("`{fp}` is not a relay fingerprint"),
+                None => continue,
+            };
+            return Err(
+                at_rule(format!("rule #{i}: tor_exits: {message}"), "tor_exits")
+                    .with_help("40 hex digits, as on metrics.torproject.org, with or without `$`"),
+            );
+        }
+        if let Some(i) = cfg.rules.iter().position(|r| r.bandwidth_kbps == Some(0)) {
+            return Err(at_rule(
+                format!("rule #{i}: bandwidth_kbps: 0 would stall every connection"),
+                "bandwidth_kbps",
+            )
+            .with_help("leave it unset for no bandwidth limit"));
+        }
+        for (i, exit) in cfg.lokinet.exits.iter().enumerate() {
+            if !exit.address.ends_with(".loki") {
+                return Err(Diagnostic::new(
+                    text,
+                    format!(
+                        "lokinet exit #{i}: `{}` is not a .loki address or ONS name",
+                        exit.address
+                    ),
+                )
+                .with_span(diagnostic::array_table_span(
+                    text,
+                    "lokinet.exits",
+                    i,
+                    "address",
+                )));
+            }
+        }
+        for (egress, limits) in &cfg.limits {
+            if limits.bandwidth_kbps == Some(0) {
+                let header = format!("limits.{egress}");
+                return Err(Diagnostic::new(
+                    text,
+                    format!("[{header}] bandwidth_kbps: 0 would stall every session"),
+                )
+                .with_span(diagnostic::key_span(text, &header, "bandwidth_kbps"))
+                .with_help("leave it unset for no bandwidth limit"));
+            }
+            if limits.buffer_kib.is_some_and(|kib| kib < MIN_BUFFER_KIB) {
+                let header = format!("limits.{egress}");
+                return Err(Diagnostic::new(
+                    text,
+                    format!("[{header}] buffer_kib: below {MIN_BUFFER_KIB} KiB sessions stall"),
+                )
+                .with_span(diagnostic::key_span(text, &header, "buffer_kib"))
+                .with_help("leave it unset to let the OS size the buffers"));
+            }
+        }
+        if let Some(vantage) = &cfg.routing.vantage {
+            let bias = cfg.routing.vantage_bias();
+            let bad = if cfg.groups.is_empty() {
+                Some(("vantage", "needs [groups] to choose from".to_string()))
+            } else if vantage != "auto" && !cfg.groups.contains_key(vantage) {
+                Some(("vantage", format!("no group `{vantage}` (or use \"auto\")")))
+            } else if !(0.0..=1.0).contains(&bias) {
+                Some(("vantage_bias", format!("{bias} is not between 0 and 1")))
+            } else {
+                None
+            };
+            if let Some((key, message)) = bad {
+                return Err(Diagnostic::new(text, format!("[routing] {key}: {message}"))
+                    .with_span(diagnostic::key_span(text, "routing", key)));
+            }
+        }
+        for (name, group) in &cfg.groups {
+            if group.backends.is_empty() {
+                let header = format!("groups.{name}");
+                return Err(Diagnostic::new(
+                    text,
+                    format!("[{header}]: needs at least one backend"),
+                )
+                .with_span(diagnostic::key_span(text, &header, "backends"))
+                .with_help("e.g. backends = [\"oxen-node-1\", \"oxen-node-2\"]"));
+            }
+        }
+        for (i, chain) in cfg.backends.chains.iter().enumerate() {
+            let hops_ok = chain
+                .chain
+                .iter()
+                .all(|k| matches!(k, BackendKind::Tor | BackendKind::Oxen));
+            if chain.chain.len() < 2 || !hops_ok {
+                let span = diagnostic::array_table_span(text, "backends.chains", i, "chain");
+                return Err(Diagnostic::new(
+                    text,
+                    format!(
+                        "chain `{}`: needs two or more hops of `tor` / `oxen`",
+                        chain.name()
+                    ),
+                )
+                .with_span(span)
+                .with_help("e.g. chain = [\"tor\", \"oxen\"]"));
+            }
+        }
+        if let Some(budget) = cfg.failover.error_budget {
+            let window = cfg.failover.window_secs;
+            let bad = if !(budget > 0.0 && budget < 1.0) {
+                Some(("error_budget", format!("{budget} is not between 0 and 1")))
+            } else if window == 0 || window > 3_600 {
+                Some(("window_secs", format!("{window} is not between 1 and 3600")))
+            } else {
+                None
+            };
+            if let Some((key, message)) = bad {
+                return Err(
+                    Diagnostic::new(text, format!("[failover] {key}: {message}"))
+                        .with_span(diagnostic::key_span(text, "failover", key))
+                        .with_help("e.g. error_budget = 0.05 (a 95% SLO), window_secs = 600"),
+                );
+            }
+        }
+        if !cfg.dashboard.listen.ip().is_loopback() {
+            return Err(Diagnostic::new(
+                text,
+                format!(
+                    "[dashboard] listen: {} is not a loopback address",
+                    cfg.dashboard.listen
+                ),
+            )
+            .with_span(diagnostic::key_span(text, "dashboard", "listen"))
+            .with_help(
+                "the dashboard is for this machine; use 127.0.0.1 or [::1] \
+                 ([admin] serves it to others)",
+            ));
+        }
+        if let Some(Err(e)) = keychain::Entry::parse(&cfg.encryption.key) {
+            return Err(Diagnostic::new(text, format!("[encryption] key: {e}"))
+                .with_span(diagnostic::key_span(text, "encryption", "key"))
+                .with_help("e.g. keyring:gold-dust/state"));
+        }
+        let secrets = [
+            (
+                "tor_control",
+                "password",
+                cfg.tor_control.password.as_deref(),
+                "tor-control",
+            ),
+            ("api", "token_file", cfg.api.token_file.to_str(), "api"),
+            (
+                "api",
+                "read_token_file",
+                cfg.api.read_token_file.to_str(),
+                "api-read",
+            ),
+        ];
+        for (section, key, value, account) in secrets {
+            if let Some(Err(e)) = value.and_then(keychain::Entry::parse) {
+                return Err(Diagnostic::new(text, format!("[{section}] {key}: {e}"))
+                    .with_span(diagnostic::key_span(text, section, key))
+                    .with_help(format!("e.g. keyring:gold-dust/{account}")));
+            }
+        }
+        for (i, exit) in cfg.lokinet.exits.iter().enumerate() {
+            if let Some(Err(e)) = exit.token.as_deref().and_then(keychain::Entry::parse) {
+                return Err(Diagnostic::new(
+                    text,
+                    format!("[[lokinet.exits]] {} token: {e}", exit.address),
+                )
+                .with_span(diagnostic::array_table_span(
+                    text,
+                    "lokinet.exits",
+                    i,
+                    "token",
+                ))
+                .with_help("e.g. keyring:gold-dust/lokinet-exit"));
+            }
+        }
+        for (key, list) in [
+            ("operators", &cfg.api.operators),
+            ("viewers", &cfg.api.viewers),
+        ] {
+            if let Err(e) = Grants::new(list, &[]) {
+                return Err(Diagnostic::new(text, format!("[api] {key}: {e}"))
+                    .with_span(diagnostic::key_span(text, "api", key))
+                    .with_help("a login name, a uid, or @group"));
+            }
+        }
+        for (i, rule) in cfg.alerts.rules.iter().enumerate() {
+            if rule.metric != AlertMetric::KillSwitch && rule.above.is_none() {
+                return Err(Diagnostic::new(
+                    text,
+                    format!("alert rule #{i}: `{}` needs `above`", rule.metric.as_str()),
+                )
+                .with_span(diagnostic::array_table_span(
+                    text,
+                    "alerts.rules",
+                    i,
+                    "metric",
+                ))
+                .with_help("e.g. above = 500"));
+            }
+        }
+        for url in cfg.alerts.webhooks.iter().chain(&cfg.alerts.notify) {
+            if let Err(e) = Url::parse(url) {
+                return Err(Diagnostic::new(text, format!("[alerts]: {e}"))
+                    .with_span(text.find(url.as_str()).map(|at| at..at + url.len())));
+            }
+        }
+        if let Some(syslog) = &cfg.logging.syslog {
+            if let Err(e) = syslog.address.parse::<SyslogAddress>() {
+                return Err(
+                    Diagnostic::new(text, format!("[logging.syslog] address: {e}"))
+                        .with_span(diagnostic::key_span(text, "logging.syslog", "address")),
+                );
+            }
+            let bad = |c: char| !c.is_ascii_graphic();
+            if syslog.app_name.is_empty()
+                || syslog.app_name.len() > 48
+                || syslog.app_name.contains(bad)
+            {
+                return Err(Diagnostic::new(
+                    text,
+                    "[logging.syslog] app_name: 1 to 48 printable characters, no spaces"
+                        .to_string(),
+                )
+                .with_span(diagnostic::key_span(
+                    text,
+                    "logging.syslog",
+                    "app_name",
+                )));
+            }
+        }
+        if cfg.logging.event_log
+            && (cfg.logging.event_source.is_empty() || cfg.logging.event_source.contains('\\'))
+        {
+            return Err(Diagnostic::new(
+                text,
+                "[logging] event_source: needs a name without `\\`".to_string(),
+            )
+            .with_span(diagnostic::key_span(text, "logging", "event_source")));
+        }
+        if cfg.metrics_push.enabled() {
+            let push = &cfg.metrics_push;
+            let span = |key| diagnostic::key_span(text, "metrics_push", key);
+            if let Err(e) = Url::parse(&push.url) {
+                return Err(Diagnostic::new(text, format!("[metrics_push] url: {e}"))
+                    .with_span(span("url")));
+            }
+            if push.interval_secs == 0 {
+                return Err(Diagnostic::new(
+                    text,
+                    "[metrics_push] interval_secs: must be at least 1".to_string(),
+                )
+                .with_span(span("interval_secs")));
+            }
+            if push.job.is_empty() {
+                return Err(
+                    Diagnostic::new(text, "[metrics_push] job: empty".to_string())
+                        .with_span(span("job")),
+                );
+            }
+            if let Some(name) = push
+                .labels
+                .keys()
+                .find(|k| !metrics::valid_label(k) || *k == "job")
+            {
+                return Err(Diagnostic::new(
+                    text,
+                    format!("[metrics_push.labels] `{name}` is not a Prometheus label name"),
+                )
+                .with_span(
+                    diagnostic::key_span(text, "metrics_push.labels", name)
+                        .or_else(|| span("labels")),
+                )
+                .with_help(
+                    "letters, digits and `_`, not starting with a digit or `__`; `job` is set by `job`",
+                ));
+            }
+            if push.via == Some(EgressKind::Masque) && cfg.backends.masque.is_none() {
+                return Err(Diagnostic::new(
+                    text,
+                    "[metrics_push] via = \"masque\" needs [backends.masque]".to_string(),
+                )
+                .with_span(span("via")));
+            }
+        }
+        if let Some(name) = cfg
+            .dns
+            .hosts
+            .iter()
+            .find_map(|(name, addrs)| addrs.to_vec().is_empty().then_some(name))
+        {
+            return Err(Diagnostic::new(
+                text,
+                format!("[dns.hosts] {name}: needs at least one address"),
+            )
+            .with_span(diagnostic::key_span(text, "dns.hosts", name)));
+        }
+        if cfg.dns.mode == DnsMode::Dot && cfg.dns.dot_servers.is_empty() {
+            return Err(Diagnostic::new(
+                text,
+                "[dns] mode = \"dot\" needs dot_servers".to_string(),
+            )
+            .with_span(diagnostic::key_span(text, "dns", "mode"))
+            .with_help("e.g. dot_servers = [\"1.1.1.1#cloudflare-dns.com\"]"));
+        }
+        for (egress, resolver) in &cfg.dns.resolvers {
+            let header = format!("dns.resolvers.{}", egress.as_str());
+            let kinds = resolver.kinds();
+            if kinds.len() != 1 {
+                let span = match kinds.get(1) {
+                    Some(key) => diagnostic::key_span(text, &header, key),
+                    None => diagnostic::key_span(text, "dns.resolvers", egress.as_str()),
+                };
+                return Err(Diagnostic::new(
+                    text,
+                    format!("[{header}] needs exactly one of dns, doh_url, dot_servers or remote"),
+                )
+                .with_span(span));
+            }
+            if let Some(Err(e)) = resolver.doh_url.as_deref().map(Url::parse) {
+                return Err(Diagnostic::new(text, format!("[{header}] doh_url: {e}"))
+                    .with_span(diagnostic::key_span(text, &header, "doh_url")));
+            }
+        }
+        if let Err(e) = Lan::new(&cfg.lan) {
+            let key = match e.split_once(':') {
+                Some((key, _)) => key,
+                None => "action",
+            };
+            return Err(Diagnostic::new(text, format!("[lan] {e}"))
+                .with_span(diagnostic::key_span(text, "lan", key)));
+        }
+        let probe = Url::parse(&cfg.portal.probe_url).and_then(|u| {
+            if u.tls {
+                Err("must be an http:// URL: a portal can't redirect https".to_string())
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = probe {
+            return Err(Diagnostic::new(text, format!("[portal] probe_url: {e}"))
+                .with_span(diagnostic::key_span(text, "portal", "probe_url")));
+        }
+        for (key, value) in [
+            ("probe_stretch", u64::from(cfg.power.probe_stretch)),
+            ("batch_secs", cfg.power.batch_secs),
+        ] {
+            if value == 0 {
+                return Err(
+                    Diagnostic::new(text, format!("[power] {key}: must be at least 1"))
+                        .with_span(diagnostic::key_span(text, "power", key)),
+                );
+            }
+        }
+        if let Some(url) = &cfg.discovery.oxend_rpc {
+            if let Err(e) = Url::parse(url) {
+                return Err(Diagnostic::new(text, format!("[discovery] oxend_rpc: {e}"))
+                    .with_span(diagnostic::key_span(text, "discovery", "oxend_rpc")));
+            }
+        }
+        let pinned_rpc = cfg.discovery.oxend_rpc.as_deref().map(Url::parse);
+        if !cfg.discovery.oxend_spki.is_empty()
+            && !matches!(pinned_rpc, Some(Ok(Url { tls: true, .. })))
+        {
+            return Err(Diagnostic::new(
+                text,
+                "[discovery] oxend_spki: pins need an https:// oxend_rpc".to_string(),
+            )
+            .with_span(diagnostic::key_span(text, "discovery", "oxend_spki")));
+        }
+        for (kind, probes) in &cfg.probes {
+            let header = format!("probes.{kind}");
+            if kind != "tor" && kind != "oxen" {
+                let marker = format!("[{header}]");
+                return Err(Diagnostic::new(
+                    text,
+                    format!("[{header}]: only `tor` and `oxen` are probed"),
+                )
+                .with_span(text.find(&marker).map(|at| at..at + marker.len())));
+            }
+            if probes.timeout_secs == 0 {
+                return Err(Diagnostic::new(
+                    text,
+                    format!("[{header}] timeout_secs: must be at least 1"),
+                )
+                .with_span(diagnostic::key_span(text, &header, "timeout_secs")));
+            }
+        }
+        if cfg.ha.enabled {
+            let key_span = |key: &str| diagnostic::key_span(text, "ha", key);
+            if cfg.ha.peer.is_none() {
+                return Err(Diagnostic::new(text, "[ha] peer: must be set")
+                    .with_span(key_span("enabled"))
+                    .with_help("the other daemon's heartbeat address, e.g. `192.168.1.3:7947`"));
+            }
+            if cfg.ha.secret.is_empty() {
+                return Err(Diagnostic::new(text, "[ha] secret: must be set")
+                    .with_span(key_span("enabled"))
+                    .with_help("both daemons sign heartbeats with it"));
+            }
+            if cfg.ha.interval_ms < 100 {
+                return Err(
+                    Diagnostic::new(text, "[ha] interval_ms: must be at least 100")
+                        .with_span(key_span("interval_ms")),
+                );
+            }
+            match cfg.ha.virtual_ip() {
+                Err(e) => {
+                    return Err(Diagnostic::new(text, format!("[ha] virtual_ip: {e}"))
+                        .with_span(key_span("virtual_ip")))
+                }
+                Ok(Some(vip)) if vip.is_ipv6() || vip.prefix() == 0 => {
+                    return Err(Diagnostic::new(
+                        text,
+                        format!("[ha] virtual_ip: {vip} is not an IPv4 host on a network"),
+                    )
+                    .with_span(key_span("virtual_ip"))
+                    .with_help("an IPv4 address with the LAN's prefix, e.g. `192.168.1.1/24`"))
+                }
+                Ok(Some(_)) if cfg.ha.interface.is_none() => {
+                    return Err(Diagnostic::new(text, "[ha] interface: must be set")
+                        .with_span(key_span("virtual_ip"))
+                        .with_help("the interface the virtual address goes on, e.g. `eth0`"))
+                }
+                Ok(_) => {}
+            }
+        }
+        if cfg.standby.enabled {
+            if let Err(e) = Url::parse(&cfg.standby.canary) {
+                return Err(Diagnostic::new(text, format!("[standby] canary: {e}"))
+                    .with_span(diagnostic::key_span(text, "standby", "canary")));
+            }
+        }
+        if let Some(masque) = &cfg.backends.masque {
+            if let Err(e) = Url::parse(&masque.relay).and_then(|u| {
+                if u.tls {
+                    Ok(())
+                } else {
+                    Err("the relay must be an https:// URL".to_string())
+                }
+            }) {
+                return Err(
+                    Diagnostic::new(text, format!("[backends.masque] relay: {e}"))
+                        .with_span(diagnostic::key_span(text, "backends.masque", "relay")),
+                );
+            }
+        }
         Ok(cfg)
     }
 }
+
+/// The `N` of a `rule #N: ...` validation message.
+fn rule_index(message: &str) -> Option<usize> {
+    let rest = message.strip_prefix("rule #")?;
+    rest[..rest.find(':')?].parse().ok()
+}
+
 impl GoldDustConfig {
+    /// Compile `[[rules]]` host patterns for fast per-connection lookups.
+    pub fn rule_matcher(&self) -> Result<RuleMatcher, String> {
+        RuleMatcher::compile(self.rules.iter().map(|r| r.host.as_str()))
+            .map(|m| m.with_priorities(self.rules.iter().map(|r| r.priority).collect()))
+    }
+
+    /// Compile `[[rules]]` `when` conditions.
+    pub fn rule_conditions(&self) -> Result<Conditions, String> {
+        Conditions::compile(&self.rules)
+    }
+
+    /// Overlapping `[[rules]]` that disagree on a setting, and which wins.
+    /// Patterns overlap when one covers the other; rules whose host doesn't
+    /// parse are skipped.
+    pub fn rule_conflicts(&self) -> Vec<RuleConflict> {
+        let patterns: Vec<Option<Pattern>> =
+            self.rules.iter().map(|r| r.host.parse().ok()).collect();
+        let mut conflicts = Vec::new();
+        for (j, b) in self.rules.iter().enumerate() {
+            for (i, a) in self.rules[..j].iter().enumerate() {
+                let overlap = match (&patterns[i], &patterns[j]) {
+                    (Some(p), Some(q)) => p.covers(q) || q.covers(p),
+                    _ => false,
+                };
+                if !overlap {
+                    continue;
+                }
+                let ((winner, w), (loser, l)) = match b.priority > a.priority {
+                    true => ((j, b), (i, a)),
+                    false => ((i, a), (j, b)),
+                };
+                for ((setting, ours), (_, theirs)) in w.settings().into_iter().zip(l.settings()) {
+                    let (Some(ours), Some(theirs)) = (ours, theirs) else {
+                        continue;
+                    };
+                    if ours != theirs {
+                        conflicts.push(RuleConflict {
+                            setting,
+                            winner,
+                            winner_host: w.host.clone(),
+                            winner_value: ours,
+                            loser,
+                            loser_host: l.host.clone(),
+                            loser_value: theirs,
+                            by_priority: a.priority != b.priority,
+                            conditional: w.when.is_some(),
+                        });
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Limits for one egress, or none if not configured.
+    pub fn limits_for(&self, egress: &str) -> LimitConfig {
+        self.limits.get(egress).cloned().unwrap_or_default()
+    }
+
+    /// Probe targets for one backend kind, or the defaults (none).
+    pub fn probes_for(&self, kind: BackendKind) -> ProbeConfig {
+        self.probes.get(kind.as_str()).cloned().unwrap_or_default()
+    }
+
+    /// Keepalive for one egress, or the defaults (off).
+    pub fn keepalive_for(&self, egress: &str) -> KeepaliveConfig {
+        self.keepalive.get(egress).cloned().unwrap_or_default()
+    }
+
+    /// Dial budgets for one egress, over its defaults.
+    pub fn timeouts_for(&self, egress: &str) -> TimeoutConfig {
+        let defaults = TimeoutConfig::defaults(egress);
+        let set = self.timeouts.get(egress).copied().unwrap_or_default();
+        TimeoutConfig {
+            connect_secs: set.connect_secs.or(defaults.connect_secs),
+            handshake_secs: set.handshake_secs.or(defaults.handshake_secs),
+            first_byte_secs: set.first_byte_secs.or(defaults.first_byte_secs),
+        }
+    }
+
     /// Fallback config if gold-dust-vpn.toml is missing.
     pub fn default_for_demo() -> Self {
         Self {
             backends: BackendConfig {
                 oxen_enabled: true,
                 tor_enabled: true,
+                tor_socks: default_tor_socks(),
+                chains: Vec::new(),
+                masque: None,
             },
+            routing: RoutingConfig::default(),
+            rules: Vec::new(),
+            groups: HashMap::new(),
+            limits: HashMap::new(),
+            apps: HashMap::new(),
+            users: HashMap::new(),
+            rotation: RotationConfig::default(),
+            tor_control: TorControlConfig::default(),
+            lokinet: LokinetConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            keepalive: HashMap::new(),
+            timeouts: HashMap::new(),
+            capabilities: HashMap::new(),
+            probes: HashMap::new(),
+            chaos: ChaosConfig::default(),
+            dns: DnsConfig::default(),
+            lan: LanConfig::default(),
+            portal: PortalConfig::default(),
+            blocklist: BlocklistConfig::default(),
+            gossip: GossipConfig::default(),
+            health_feed: HealthFeedConfig::default(),
+            ha: HaConfig::default(),
+            failover: FailoverConfig::default(),
+            standby: StandbyConfig::default(),
+            retry: RetryConfig::default(),
+            dispatcher: DispatcherConfig::default(),
+            power: PowerConfig::default(),
+            firewall: FirewallConfig::default(),
+            sandbox: SandboxConfig::default(),
+            logging: LoggingConfig::default(),
+            encryption: EncryptionConfig::default(),
+            dashboard: DashboardConfig::default(),
+            api: ApiConfig::default(),
+            admin: AdminConfig::default(),
+            alerts: AlertsConfig::default(),
+            metrics_push: MetricsPushConfig::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = "[backends]\noxen_enabled = false\ntor_enabled = true\n";
+
+    #[test]
+    fn unknown_keys_are_warnings() {
+        let text = format!("{MINIMAL}tor_sox = 1\n\n[backends.foo]\nx = 1\n");
+        let (cfg, warnings) = GoldDustConfig::check(&text).expect("loads");
+        assert!(cfg.backends.tor_enabled);
+        let messages: Vec<&str> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(messages.contains(&"unknown key `backends.tor_sox` (ignored)"));
+        assert!(messages.contains(&"unknown key `backends.foo` (ignored)"));
+        assert!(warnings.iter().all(|w| w.line_col().is_some()));
+        assert!(GoldDustConfig::parse(&text).is_ok());
+    }
+
+    #[test]
+    fn known_keys_load_without_warnings() {
+        let (_, warnings) = GoldDustConfig::check(MINIMAL).expect("loads");
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn misspelled_required_key_is_the_error() {
+        let err = GoldDustConfig::check("[backends]\noxen_enabled = false\ntor_enabeld = true\n")
+            .expect_err("fails");
+        assert!(err.message.starts_with("unknown field `tor_enabeld`"));
+        assert_eq!(err.line_col(), Some((3, 1)));
+    }
+
+    #[test]
+    fn zero_bandwidth_is_rejected() {
+        let text = format!("{MINIMAL}\n[[rules]]\nhost = \"*\"\nbandwidth_kbps = 0\n");
+        let err = GoldDustConfig::check(&text).expect_err("fails");
+        assert!(
+            err.message.starts_with("rule #0: bandwidth_kbps"),
+            "{}",
+            err.message
+        );
+
+        let text = format!("{MINIMAL}\n[limits.tor]\nbandwidth_kbps = 0\n");
+        let err = GoldDustConfig::check(&text).expect_err("fails");
+        assert_eq!(err.line_col(), Some((6, 1)));
+    }
+}
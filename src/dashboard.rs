@@ -0,0 +1,100 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::gossip::Report;
+use crate::stats::{Canary, EgressUsage, TrafficEntry};
+
+/// The single-page UI served at `/`.
+pub const PAGE: &str = include_str!("dashboard.html");
+
+/// Finished sessions kept for "recent decisions".
+pub const DECISIONS_KEPT: usize = 50;
+
+/// Measurements kept per backend for the health graphs.
+pub const HISTORY_KEPT: usize = 120;
+
+/// One measurement of one backend, as plotted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthPoint {
+    pub unix: u64,
+    pub latency_ms: f64,
+    pub failure_rate: f64,
+    pub enabled: bool,
+}
+
+/// Rolling per-backend health, newest last.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HealthHistory {
+    pub backends: BTreeMap<String, VecDeque<HealthPoint>>,
+}
+
+impl HealthHistory {
+    pub fn record(&mut self, report: &Report) {
+        let points = self.backends.entry(report.health.name.clone()).or_default();
+        points.push_back(HealthPoint {
+            unix: report.observed_unix,
+            latency_ms: report.health.latency_ms,
+            failure_rate: report.health.failure_rate,
+            enabled: report.health.enabled,
+        });
+        while points.len() > HISTORY_KEPT {
+            points.pop_front();
+        }
+    }
+}
+
+/// Everything the page shows, as served by `/api/status` and pushed over
+/// `/api/ws`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    pub updated_unix: u64,
+    /// Flag-file mode: `tor`, `direct` or `masque`.
+    pub mode: String,
+    /// Modes this dispatcher can switch to.
+    pub modes: Vec<String>,
+    /// Deciding and logging sessions without forwarding them (`--dry-run`).
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Deciding and logging sessions, forwarding all of them direct
+    /// (`--audit`).
+    #[serde(default)]
+    pub audit: bool,
+    /// Last warm-standby canary through Tor (`[standby]`).
+    #[serde(default)]
+    pub standby: Option<Canary>,
+    /// Client connections being handled right now.
+    pub sessions: usize,
+    pub egress: BTreeMap<String, EgressUsage>,
+    /// Newest report per backend.
+    pub backends: Vec<Report>,
+    pub history: HealthHistory,
+    /// Newest first.
+    pub decisions: Vec<TrafficEntry>,
+}
+
+/// Body of `POST /api/mode`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModeRequest {
+    pub mode: String,
+}
+
+/// Whether a request is addressed to the dashboard itself on loopback, not
+/// to some other name that resolves there (DNS rebinding) or sent by another
+/// site's page (checked through `Origin`, which browsers always set on
+/// WebSocket handshakes and cross-site POSTs).
+pub fn local_request(host: Option<&str>, origin: Option<&str>) -> bool {
+    let local = |authority: &str| {
+        let host = match authority.rsplit_once(':') {
+            Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+            _ => authority,
+        };
+        matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+    };
+    let host_ok = host.is_some_and(local);
+    let origin_ok = origin.is_none_or(|o| {
+        o.strip_prefix("http://")
+            .is_some_and(|authority| Some(authority) == host)
+    });
+    host_ok && origin_ok
+}
@@ -0,0 +1,272 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A config error pointing at the offending spot in the TOML source:
+///
+/// ```text
+/// unknown field `tor_enabeld`, expected one of `oxen_enabled`, ...
+///  --> gold-dust-gateway.toml:3:1
+///   |
+/// 3 | tor_enabeld = true
+///   | ^^^^^^^^^^^
+///   = help: did you mean `tor_enabled`?
+/// ```
+#[derive(Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    /// File the source came from, if any.
+    pub path: Option<PathBuf>,
+    /// Byte range into the source.
+    pub span: Option<Range<usize>>,
+    pub help: Option<String>,
+    source: String,
+}
+
+impl Diagnostic {
+    pub fn new(source: &str, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            path: None,
+            span: None,
+            help: None,
+            source: source.to_string(),
+        }
+    }
+
+    /// Wrap a TOML syntax or deserialization error, adding a hint where
+    /// serde's message leaves one to guess.
+    pub fn from_toml(source: &str, err: &toml::de::Error) -> Self {
+        let message = err.message().trim_end().to_string();
+        let help = hint(&message);
+        Self {
+            span: err.span(),
+            help,
+            ..Self::new(source, message)
+        }
+    }
+
+    pub fn with_span(mut self, span: Option<Range<usize>>) -> Self {
+        self.span = span;
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+
+    /// 1-based line and column of the span start.
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        let start = self.span.as_ref()?.start.min(self.source.len());
+        let before = &self.source[..start];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        Some((line, col))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        let file = self
+            .path
+            .as_ref()
+            .map_or("<config>".to_string(), |p| p.display().to_string());
+        match (self.line_col(), &self.span) {
+            (Some((line, col)), Some(span)) => {
+                let text = self.source.lines().nth(line - 1).unwrap_or("");
+                let width = line.to_string().len();
+                let pad = " ".repeat(width);
+                // Underline within the first line of the span only
+                let end = span.end.clamp(span.start, self.source.len());
+                let len = self.source[span.start.min(end)..end]
+                    .split('\n')
+                    .next()
+                    .map_or(0, |s| s.chars().count())
+                    .max(1);
+                write!(f, "\n{pad}--> {file}:{line}:{col}")?;
+                write!(f, "\n{pad} |")?;
+                write!(f, "\n{line} | {text}")?;
+                write!(f, "\n{pad} | {}{}", " ".repeat(col - 1), "^".repeat(len))?;
+                if let Some(help) = &self.help {
+                    write!(f, "\n{pad} = help: {help}")?;
+                }
+            }
+            _ => {
+                if self.path.is_some() {
+                    write!(f, "\n --> {file}")?;
+                }
+                if let Some(help) = &self.help {
+                    write!(f, "\n  = help: {help}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// `main` returning `Err` prints Debug; show the report, not the struct
+impl fmt::Debug for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Hint for common serde messages: a close match among the expected
+/// names, or how TOML spells the expected type.
+pub(crate) fn hint(message: &str) -> Option<String> {
+    if let Some(rest) = message
+        .strip_prefix("unknown field ")
+        .or_else(|| message.strip_prefix("unknown variant "))
+    {
+        let (got, expected) = rest.split_once(", expected ")?;
+        let got = got.trim_matches('`');
+        let names = backticked(expected);
+        return match did_you_mean(got, &names) {
+            Some(name) => Some(format!("did you mean `{name}`?")),
+            None if expected.starts_with("one of") || names.len() > 1 => {
+                Some(format!("expected one of {}", quote_all(&names)))
+            }
+            None => None,
+        };
+    }
+    if let Some(field) = message.strip_prefix("missing field ") {
+        return Some(format!("add {} to this table", field));
+    }
+    let expected = message
+        .strip_prefix("invalid type: ")?
+        .split_once(", expected ")?
+        .1;
+    Some(
+        match expected {
+            "a boolean" => "write `true` or `false`, without quotes",
+            "a string" => "strings are quoted, e.g. \"tor\"",
+            "a sequence" => "lists are written in brackets, e.g. [\"tor\", \"oxen\"]",
+            "u8" | "u16" | "u32" | "u64" | "usize" | "i32" | "i64" | "f64" | "an integer" => {
+                "numbers are written without quotes"
+            }
+            _ => return None,
+        }
+        .to_string(),
+    )
+}
+
+fn backticked(s: &str) -> Vec<&str> {
+    s.split('`').skip(1).step_by(2).collect()
+}
+
+fn quote_all(names: &[&str]) -> String {
+    names
+        .iter()
+        .map(|n| format!("`{n}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The candidate closest to `word`, if it is plausibly a typo of it.
+pub fn did_you_mean<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let limit = (word.chars().count() / 3).max(1);
+    candidates
+        .iter()
+        .map(|c| (levenshtein(word, c), *c))
+        .filter(|(d, _)| *d <= limit)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(cur).min(row[j])
+            };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// Span of `key` inside the `index`-th (0-based) `[[header]]` table, or of
+/// the header line itself if the key is not written there.
+pub fn array_table_span(
+    source: &str,
+    header: &str,
+    index: usize,
+    key: &str,
+) -> Option<Range<usize>> {
+    span_in_table(source, &format!("[[{header}]]"), index, key)
+}
+
+/// Span of `key` inside the `[header]` table, or of its own
+/// `[header.key]` table, or of the header itself.
+pub fn key_span(source: &str, header: &str, key: &str) -> Option<Range<usize>> {
+    let span = span_in_table(source, &format!("[{header}]"), 0, key);
+    if span
+        .as_ref()
+        .is_some_and(|s| source.get(s.clone()) == Some(key))
+    {
+        return span;
+    }
+    header_span(source, &format!("[{header}.{key}]")).or(span)
+}
+
+/// Span of the first `marker` table header.
+fn header_span(source: &str, marker: &str) -> Option<Range<usize>> {
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let start = offset + (line.len() - trimmed.len());
+        offset += line.len();
+        if trimmed.starts_with(marker) {
+            return Some(start..start + marker.len());
+        }
+    }
+    None
+}
+
+fn span_in_table(source: &str, marker: &str, index: usize, key: &str) -> Option<Range<usize>> {
+    let mut offset = 0;
+    let mut seen = None;
+    let mut header_span = None;
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let start = offset + (line.len() - trimmed.len());
+        offset += line.len();
+        if trimmed.starts_with('[') {
+            if trimmed.starts_with(marker) {
+                seen = Some(seen.map_or(0, |n| n + 1));
+                if seen == Some(index) {
+                    header_span = Some(start..start + marker.len());
+                }
+            } else if header_span.is_some() {
+                break;
+            }
+            continue;
+        }
+        if seen == Some(index) {
+            if let Some(rest) = trimmed.strip_prefix(key) {
+                if rest.trim_start().starts_with('=') {
+                    return Some(start..start + key.len());
+                }
+            }
+        } else if seen.is_some_and(|n| n > index) {
+            break;
+        }
+    }
+    header_span
+}
@@ -0,0 +1,172 @@
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::DiscoveryConfig;
+use crate::http::{self, Url};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// A service node version: `10.4.0`; missing parts are 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Version(pub [u16; 3]);
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = [0u16; 3];
+        for (i, part) in s.trim().trim_start_matches('v').split('.').enumerate() {
+            let slot = parts
+                .get_mut(i)
+                .ok_or_else(|| format!("`{s}` has more than three parts"))?;
+            *slot = part
+                .parse()
+                .map_err(|_| format!("`{s}` is not a version like 10.4.0"))?;
+        }
+        Ok(Self(parts))
+    }
+}
+
+impl TryFrom<String> for Version {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Version> for String {
+    fn from(v: Version) -> String {
+        v.to_string()
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [major, minor, patch] = self.0;
+        write!(f, "{major}.{minor}.{patch}")
+    }
+}
+
+/// An active service node that passed the version checks.
+#[derive(Debug, Clone)]
+pub struct ServiceNode {
+    pub pubkey: String,
+    /// Its public IP and storage server port, what probes connect to.
+    pub address: SocketAddr,
+    pub version: Version,
+}
+
+impl ServiceNode {
+    /// Short name for status lines: `sn-` and the key's first 8 digits.
+    pub fn name(&self) -> String {
+        format!("sn-{}", &self.pubkey[..self.pubkey.len().min(8)])
+    }
+}
+
+/// What one discovery round found.
+#[derive(Debug, Clone, Default)]
+pub struct Discovered {
+    pub nodes: Vec<ServiceNode>,
+    /// Rejected for running a version below `min_version`.
+    pub too_old: usize,
+    /// Rejected for running one of `bad_versions`.
+    pub known_bad: usize,
+    /// Rejected for reporting no version (or no usable address) at all.
+    pub unusable: usize,
+}
+
+impl Discovered {
+    pub fn rejected(&self) -> usize {
+        self.too_old + self.known_bad + self.unusable
+    }
+}
+
+/// Ask oxend for the active service nodes and keep those whose version
+/// `cfg` accepts.
+pub async fn discover(cfg: &DiscoveryConfig, rpc: &Url) -> Result<Discovered, BoxError> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": "0",
+        "method": "get_service_nodes",
+        "params": {
+            "active_only": true,
+            "fields": {
+                "service_node_pubkey": true,
+                "public_ip": true,
+                "storage_port": true,
+                "service_node_version": true,
+            },
+        },
+    });
+    let resp = http::request_pinned(
+        "POST",
+        rpc,
+        &cfg.oxend_spki,
+        &[("Content-Type", "application/json")],
+        request.to_string().as_bytes(),
+    )
+    .await?;
+    if resp.status != 200 {
+        return Err(format!("oxend answered HTTP {}", resp.status).into());
+    }
+    let body: Value = serde_json::from_slice(&resp.body)?;
+    if let Some(error) = body.get("error") {
+        return Err(format!("oxend: {}", error["message"].as_str().unwrap_or("error")).into());
+    }
+    let states = body["result"]["service_node_states"]
+        .as_array()
+        .ok_or("oxend returned no service_node_states")?;
+    Ok(sort(cfg, states))
+}
+
+/// Split `states` into accepted nodes and rejection counts.
+fn sort(cfg: &DiscoveryConfig, states: &[Value]) -> Discovered {
+    let mut found = Discovered::default();
+    for state in states {
+        // [major, minor, patch]; all zeroes until the node sends an uptime proof
+        let version = state["service_node_version"]
+            .as_array()
+            .filter(|v| v.len() == 3)
+            .and_then(|v| {
+                let mut parts = [0u16; 3];
+                for (part, n) in parts.iter_mut().zip(v) {
+                    *part = u16::try_from(n.as_u64()?).ok()?;
+                }
+                Some(Version(parts))
+            })
+            .filter(|v| *v != Version([0, 0, 0]));
+        let ip = state["public_ip"]
+            .as_str()
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .filter(|ip| !ip.is_unspecified());
+        let port = state["storage_port"]
+            .as_u64()
+            .and_then(|p| u16::try_from(p).ok())
+            .filter(|p| *p != 0);
+        let (Some(version), Some(ip), Some(port), Some(pubkey)) =
+            (version, ip, port, state["service_node_pubkey"].as_str())
+        else {
+            found.unusable += 1;
+            continue;
+        };
+        if cfg.min_version.is_some_and(|min| version < min) {
+            found.too_old += 1;
+        } else if cfg.bad_versions.contains(&version) {
+            found.known_bad += 1;
+        } else {
+            found.nodes.push(ServiceNode {
+                pubkey: pubkey.to_string(),
+                address: SocketAddr::new(ip, port),
+                version,
+            });
+        }
+    }
+    found
+}
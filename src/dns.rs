@@ -0,0 +1,718 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rustls::pki_types::ServerName;
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UdpSocket;
+use tokio_rustls::TlsConnector;
+
+use crate::cache::{BoundedCache, CacheStats, Limits};
+use crate::config::{DnsConfig, DotProfile, ResolverConfig};
+use crate::http::{self, Url};
+use crate::pinning;
+use crate::relay::Upstream;
+use crate::target::Target;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_AAAA: u16 = 28;
+const TYPE_SOA: u16 = 6;
+
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// Longest we keep a positive answer, whatever its TTL says.
+const MAX_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// Longest we remember that a name has no addresses (RFC 2308 suggests
+/// up to three hours; names tend to appear sooner than that).
+const MAX_NEGATIVE_TTL: Duration = Duration::from_secs(300);
+
+/// Names cached unless `[dns] cache_size` says otherwise.
+pub const DEFAULT_CACHE_SIZE: usize = 1024;
+
+/// Build a recursive query for `name` (`qtype` = [`TYPE_A`] / [`TYPE_AAAA`]).
+pub fn encode_query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(32 + name.len());
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&[0x01, 0x00]); // RD
+    msg.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // QD=1
+    for label in name.trim_end_matches('.').split('.') {
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&1u16.to_be_bytes()); // IN
+    msg
+}
+
+/// One resource record from the answer section.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub rtype: u16,
+    pub ttl: u32,
+    pub data: Vec<u8>,
+}
+
+impl Record {
+    /// Address carried by an A/AAAA record.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match (self.rtype, self.data.len()) {
+            (TYPE_A, 4) => {
+                let b: [u8; 4] = self.data[..].try_into().ok()?;
+                Some(IpAddr::V4(Ipv4Addr::from(b)))
+            }
+            (TYPE_AAAA, 16) => {
+                let b: [u8; 16] = self.data[..].try_into().ok()?;
+                Some(IpAddr::V6(Ipv6Addr::from(b)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Decoded DNS response (header fields we care about, answers and
+/// authority records).
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub id: u16,
+    pub rcode: u8,
+    pub answers: Vec<Record>,
+    /// Where a negative answer's SOA is.
+    pub authority: Vec<Record>,
+}
+
+impl Message {
+    /// How long this answer's absence of records may be cached (RFC 2308
+    /// §5): the SOA's TTL or its MINIMUM field, whichever is lower. `None`
+    /// without an SOA, when it must not be cached at all.
+    pub fn negative_ttl(&self) -> Option<u32> {
+        self.authority
+            .iter()
+            .filter(|r| r.rtype == TYPE_SOA)
+            .find_map(|soa| {
+                // MINIMUM is the last of the SOA's five trailing 32-bit fields
+                let minimum = soa.data.get(soa.data.len().checked_sub(4)?..)?;
+                let minimum = u32::from_be_bytes(minimum.try_into().ok()?);
+                Some(soa.ttl.min(minimum))
+            })
+    }
+}
+
+fn skip_name(msg: &[u8], mut pos: usize) -> Result<usize, BoxError> {
+    loop {
+        let len = *msg.get(pos).ok_or("truncated name")? as usize;
+        if len == 0 {
+            return Ok(pos + 1);
+        }
+        if len & 0xC0 == 0xC0 {
+            return Ok(pos + 2); // compression pointer ends the name
+        }
+        pos += 1 + len;
+    }
+}
+
+fn u16_at(msg: &[u8], pos: usize) -> Result<u16, BoxError> {
+    let b = msg.get(pos..pos + 2).ok_or("truncated message")?;
+    Ok(u16::from_be_bytes([b[0], b[1]]))
+}
+
+/// Decode a response message.
+pub fn decode(msg: &[u8]) -> Result<Message, BoxError> {
+    let id = u16_at(msg, 0)?;
+    let rcode = (u16_at(msg, 2)? & 0x000F) as u8;
+    let qdcount = u16_at(msg, 4)?;
+    let ancount = u16_at(msg, 6)?;
+    let nscount = u16_at(msg, 8)?;
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = skip_name(msg, pos)? + 4;
+    }
+
+    let (answers, pos) = records(msg, pos, ancount)?;
+    let (authority, _) = records(msg, pos, nscount)?;
+    Ok(Message {
+        id,
+        rcode,
+        answers,
+        authority,
+    })
+}
+
+/// `count` resource records from `pos` on, and where they end.
+fn records(msg: &[u8], mut pos: usize, count: u16) -> Result<(Vec<Record>, usize), BoxError> {
+    let mut records = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        pos = skip_name(msg, pos)?;
+        let rtype = u16_at(msg, pos)?;
+        let ttl_bytes = msg.get(pos + 4..pos + 8).ok_or("truncated record")?;
+        let ttl = u32::from_be_bytes([ttl_bytes[0], ttl_bytes[1], ttl_bytes[2], ttl_bytes[3]]);
+        let rdlen = u16_at(msg, pos + 8)? as usize;
+        pos += 10;
+        let data = msg.get(pos..pos + rdlen).ok_or("truncated rdata")?.to_vec();
+        pos += rdlen;
+        records.push(Record { rtype, ttl, data });
+    }
+    Ok((records, pos))
+}
+
+/// Outcome of resolving one name.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub ips: Vec<IpAddr>,
+    /// Served from the cache rather than a fresh query.
+    pub cached: bool,
+    /// Seconds until the answer expires.
+    pub ttl: u64,
+}
+
+/// Answers by name, kept for their TTL; a name without addresses is kept
+/// too, for as long as its zone allows. At most `[dns] cache_size` names:
+/// when full, expired ones go first, then the least recently (or, with
+/// `cache_eviction = "lfu"`, least often) used.
+#[derive(Debug)]
+struct Cache {
+    entries: Mutex<BoundedCache<String, (Vec<IpAddr>, Instant)>>,
+}
+
+impl Cache {
+    fn new(limits: Limits) -> Self {
+        Self {
+            entries: Mutex::new(BoundedCache::new(limits)),
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.entries.lock().expect("dns cache poisoned").stats()
+    }
+
+    fn clear(&self) {
+        self.entries.lock().expect("dns cache poisoned").clear();
+    }
+
+    /// A fresh entry for `key`; an `Err` if the name is known to have no
+    /// addresses.
+    fn get(&self, key: &str) -> Option<Result<Resolution, BoxError>> {
+        let mut entries = self.entries.lock().expect("dns cache poisoned");
+        let now = Instant::now();
+        let (ips, expires) = entries.get_if(key, |(_, expires)| *expires > now)?;
+        if ips.is_empty() {
+            return Some(Err(format!("{} has no A/AAAA records (cached)", key).into()));
+        }
+        Some(Ok(Resolution {
+            ips: ips.clone(),
+            cached: true,
+            ttl: (*expires - now).as_secs(),
+        }))
+    }
+
+    /// Keep the addresses in `answers` (A then AAAA) for their shortest TTL,
+    /// or their absence for the shortest negative TTL (if every answer has
+    /// one).
+    fn insert(&self, key: String, answers: &[Message]) -> Result<Resolution, BoxError> {
+        if let Some(failed) = answers
+            .iter()
+            .find(|m| m.rcode != 0 && m.rcode != RCODE_NXDOMAIN)
+        {
+            return Err(format!("lookup of {} failed (rcode {})", key, failed.rcode).into());
+        }
+        let mut ips = Vec::new();
+        let mut ttl = MAX_CACHE_TTL.as_secs() as u32;
+        for record in answers.iter().flat_map(|m| &m.answers) {
+            if let Some(ip) = record.ip() {
+                ips.push(ip);
+                ttl = ttl.min(record.ttl);
+            }
+        }
+        if ips.is_empty() {
+            let negative = answers
+                .iter()
+                .map(Message::negative_ttl)
+                .try_fold(MAX_NEGATIVE_TTL.as_secs() as u32, |ttl, n| {
+                    Some(ttl.min(n?))
+                });
+            if let Some(ttl) = negative.filter(|ttl| *ttl > 0) {
+                self.store(key.clone(), Vec::new(), ttl);
+            }
+            return Err(format!("{} has no A/AAAA records", key).into());
+        }
+
+        self.store(key, ips.clone(), ttl);
+        Ok(Resolution {
+            ips,
+            cached: false,
+            ttl: u64::from(ttl),
+        })
+    }
+
+    fn store(&self, key: String, ips: Vec<IpAddr>, ttl: u32) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("dns cache poisoned");
+        if entries.is_full() {
+            entries.retain(|_, (_, expires)| *expires > now);
+        }
+        entries.insert(key, (ips, now + Duration::from_secs(u64::from(ttl))));
+    }
+}
+
+/// DNS-over-HTTPS (RFC 8484) resolver with a TTL-respecting cache.
+#[derive(Debug)]
+pub struct DohResolver {
+    url: Url,
+    cache: Cache,
+}
+
+impl DohResolver {
+    /// Resolve via `url`, caching names within `cache`.
+    pub fn new(url: &str, cache: Limits) -> Result<Self, String> {
+        Ok(Self {
+            url: Url::parse(url)?,
+            cache: Cache::new(cache),
+        })
+    }
+
+    /// Forget every cached answer. TTLs run on the monotonic clock, which
+    /// stops while the machine sleeps, so after a wake-up they would run long.
+    pub fn reset(&self) {
+        self.cache.clear();
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    async fn query(
+        &self,
+        stream: Box<dyn Upstream>,
+        name: &str,
+        qtype: u16,
+    ) -> Result<Message, BoxError> {
+        let resp = http::request_over(
+            stream,
+            "POST",
+            &self.url,
+            &[
+                ("Content-Type", "application/dns-message"),
+                ("Accept", "application/dns-message"),
+            ],
+            &encode_query(0, name, qtype),
+        )
+        .await?;
+        if resp.status != 200 {
+            return Err(format!("DoH server answered HTTP {}", resp.status).into());
+        }
+        decode(&resp.body)
+    }
+
+    /// Resolve A and AAAA records for `name`.
+    pub async fn resolve(&self, name: &str) -> Result<Resolution, BoxError> {
+        self.resolve_via(name, |server: Target| async move {
+            let tcp = tokio::net::TcpStream::connect((server.host_str(), server.port)).await?;
+            Ok(Box::new(tcp) as Box<dyn Upstream>)
+        })
+        .await
+    }
+
+    /// Like [`resolve`](Self::resolve), reaching the server with `dial`
+    /// (e.g. through an egress).
+    pub async fn resolve_via<F, Fut>(&self, name: &str, dial: F) -> Result<Resolution, BoxError>
+    where
+        F: Fn(Target) -> Fut,
+        Fut: Future<Output = Result<Box<dyn Upstream>, BoxError>>,
+    {
+        let key = name.to_ascii_lowercase();
+        if let Some(hit) = self.cache.get(&key) {
+            return hit;
+        }
+        let mut answers = Vec::with_capacity(2);
+        for qtype in [TYPE_A, TYPE_AAAA] {
+            let stream = dial(self.url.target()).await?;
+            answers.push(self.query(stream, &key, qtype).await?);
+        }
+        self.cache.insert(key, &answers)
+    }
+}
+
+/// Longest a plain DNS server gets to answer one query.
+const PLAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Plain DNS over UDP to one local server, such as Tor's `DNSPort` or
+/// lokinet's resolver, with a TTL-respecting cache.
+#[derive(Debug)]
+pub struct PlainResolver {
+    server: SocketAddr,
+    cache: Cache,
+    next_id: AtomicU16,
+}
+
+impl PlainResolver {
+    pub fn new(server: SocketAddr, cache: Limits) -> Self {
+        Self {
+            server,
+            cache: Cache::new(cache),
+            next_id: AtomicU16::new(1),
+        }
+    }
+
+    /// Forget every cached answer (see `DohResolver::reset`).
+    pub fn reset(&self) {
+        self.cache.clear();
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    async fn query(&self, socket: &UdpSocket, name: &str, qtype: u16) -> Result<Message, BoxError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        socket.send(&encode_query(id, name, qtype)).await?;
+        let mut reply = vec![0u8; 4096];
+        loop {
+            let len = tokio::time::timeout(PLAIN_TIMEOUT, socket.recv(&mut reply))
+                .await
+                .map_err(|_| format!("{} did not answer", self.server))??;
+            let msg = decode(&reply[..len])?;
+            // A late answer to an earlier query
+            if msg.id == id {
+                return Ok(msg);
+            }
+        }
+    }
+
+    /// Resolve A and AAAA records for `name`.
+    pub async fn resolve(&self, name: &str) -> Result<Resolution, BoxError> {
+        let key = name.to_ascii_lowercase();
+        if let Some(hit) = self.cache.get(&key) {
+            return hit;
+        }
+        let local: SocketAddr = match self.server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(self.server).await?;
+        let answers = [
+            self.query(&socket, &key, TYPE_A).await?,
+            self.query(&socket, &key, TYPE_AAAA).await?,
+        ];
+        self.cache.insert(key, &answers)
+    }
+}
+
+/// The resolver `[dns.resolvers]` gives one egress.
+#[derive(Debug)]
+pub enum EgressResolver {
+    Plain(PlainResolver),
+    /// Reached through the egress.
+    Doh(DohResolver),
+    /// Reached through the egress.
+    Dot(DotResolver),
+    /// No lookup: the backend resolves.
+    Remote,
+}
+
+impl EgressResolver {
+    pub fn new(cfg: &ResolverConfig, cache: Limits) -> Result<Self, String> {
+        Ok(match (&cfg.dns, &cfg.doh_url) {
+            (Some(server), _) => EgressResolver::Plain(PlainResolver::new(*server, cache)),
+            (_, Some(url)) => EgressResolver::Doh(DohResolver::new(url, cache)?),
+            _ if !cfg.dot_servers.is_empty() => EgressResolver::Dot(DotResolver::new(
+                cfg.dot_servers.clone(),
+                cfg.dot_profile,
+                cache,
+            )),
+            _ => EgressResolver::Remote,
+        })
+    }
+
+    /// Forget cached answers and idle connections (see `DotResolver::reset`).
+    pub async fn reset(&self) {
+        match self {
+            EgressResolver::Plain(r) => r.reset(),
+            EgressResolver::Doh(r) => r.reset(),
+            EgressResolver::Dot(r) => r.reset().await,
+            EgressResolver::Remote => {}
+        }
+    }
+
+    /// How its cache is doing; `None` for `remote`, which keeps none.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        match self {
+            EgressResolver::Plain(r) => Some(r.cache_stats()),
+            EgressResolver::Doh(r) => Some(r.cache_stats()),
+            EgressResolver::Dot(r) => Some(r.cache_stats()),
+            EgressResolver::Remote => None,
+        }
+    }
+}
+
+/// Fixed addresses for names (`[dns.hosts]`, `hosts_file`), used instead of
+/// any lookup.
+#[derive(Debug, Clone, Default)]
+pub struct Hosts {
+    names: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Hosts {
+    /// The hosts file (if any) with `[dns.hosts]` on top: a name in both
+    /// takes the config's addresses.
+    pub fn load(cfg: &DnsConfig) -> Result<Self, BoxError> {
+        let mut hosts = Self::default();
+        if let Some(path) = &cfg.hosts_file {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| format!("hosts file {}: {}", path.display(), e))?;
+            hosts.parse_file(&text);
+        }
+        for (name, addrs) in &cfg.hosts {
+            hosts.names.insert(normalize(name), addrs.to_vec());
+        }
+        Ok(hosts)
+    }
+
+    /// `/etc/hosts` format: an address, then its names; `#` comments. Lines
+    /// not starting with an address are skipped.
+    fn parse_file(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut fields = line.split_whitespace();
+            let Some(Ok(ip)) = fields.next().map(str::parse::<IpAddr>) else {
+                continue;
+            };
+            for name in fields {
+                let addrs = self.names.entry(normalize(name)).or_default();
+                if !addrs.contains(&ip) {
+                    addrs.push(ip);
+                }
+            }
+        }
+    }
+
+    /// Addresses fixed for `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&[IpAddr]> {
+        self.names.get(&normalize(name)).map(Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Port DNS-over-TLS servers listen on (RFC 7858).
+pub const DOT_PORT: u16 = 853;
+
+/// A DNS-over-TLS server: `1.1.1.1`, `9.9.9.9:853#dns.quad9.net`. The name
+/// after `#` is what its certificate must be issued for; without one, the
+/// address itself.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DotServer {
+    pub target: Target,
+    pub auth_name: Option<String>,
+}
+
+impl FromStr for DotServer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, auth_name) = match s.split_once('#') {
+            Some((addr, name)) if !name.is_empty() => (addr, Some(name.to_ascii_lowercase())),
+            Some(_) => return Err(format!("`{s}`: nothing after `#`")),
+            None => (s, None),
+        };
+        let target: Target = addr.parse().map_err(|e| format!("`{s}`: {e}"))?;
+        let has_port = match addr.rsplit_once(']') {
+            Some((_, after)) => after.starts_with(':'),
+            None => addr.matches(':').count() == 1,
+        };
+        Ok(Self {
+            target: if has_port {
+                target
+            } else {
+                Target::new(target.host, DOT_PORT)
+            },
+            auth_name,
+        })
+    }
+}
+
+impl TryFrom<String> for DotServer {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for DotServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.auth_name {
+            Some(name) => write!(f, "{}#{}", self.target, name),
+            None => self.target.fmt(f),
+        }
+    }
+}
+
+/// DNS-over-TLS (RFC 7858) resolver, reaching its servers through whichever
+/// egress the caller dials, with a TTL-respecting cache. One connection per
+/// egress is kept open between lookups.
+pub struct DotResolver {
+    servers: Vec<DotServer>,
+    profile: DotProfile,
+    cache: Cache,
+    /// Idle connections by egress name.
+    idle: tokio::sync::Mutex<HashMap<String, Box<dyn Upstream>>>,
+    next_id: AtomicU16,
+}
+
+impl fmt::Debug for DotResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DotResolver")
+            .field("servers", &self.servers)
+            .field("profile", &self.profile)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DotResolver {
+    /// Resolve via `servers`, caching names within `cache`.
+    pub fn new(servers: Vec<DotServer>, profile: DotProfile, cache: Limits) -> Self {
+        Self {
+            servers,
+            profile,
+            cache: Cache::new(cache),
+            idle: tokio::sync::Mutex::new(HashMap::new()),
+            next_id: AtomicU16::new(1),
+        }
+    }
+
+    /// Forget every cached answer and idle connection, as after a wake-up
+    /// (see `DohResolver::reset`): a connection left over from before would
+    /// hang rather than fail.
+    pub async fn reset(&self) {
+        self.cache.clear();
+        self.idle.lock().await.clear();
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Resolve A and AAAA records for `name`, connecting to the servers (in
+    /// order, until one answers) with `dial`, which reaches them via
+    /// `egress`.
+    pub async fn resolve<F, Fut>(
+        &self,
+        name: &str,
+        egress: &str,
+        dial: F,
+    ) -> Result<Resolution, BoxError>
+    where
+        F: Fn(Target) -> Fut,
+        Fut: Future<Output = Result<Box<dyn Upstream>, BoxError>>,
+    {
+        let key = name.to_ascii_lowercase();
+        if let Some(hit) = self.cache.get(&key) {
+            return hit;
+        }
+
+        // A reused connection may have been closed by the server meanwhile
+        let reused = self.idle.lock().await.remove(egress);
+        if let Some(mut conn) = reused {
+            if let Ok(answers) = self.exchange(&mut conn, &key).await {
+                self.idle.lock().await.insert(egress.to_string(), conn);
+                return self.cache.insert(key, &answers);
+            }
+        }
+
+        let mut failures = Vec::new();
+        for server in &self.servers {
+            let attempt = async {
+                let mut conn = self.connect(server, &dial).await?;
+                let answers = self.exchange(&mut conn, &key).await?;
+                Ok::<_, BoxError>((conn, answers))
+            };
+            match attempt.await {
+                Ok((conn, answers)) => {
+                    self.idle.lock().await.insert(egress.to_string(), conn);
+                    return self.cache.insert(key, &answers);
+                }
+                Err(e) => failures.push(format!("{}: {}", server, e)),
+            }
+        }
+        Err(failures.join("; ").into())
+    }
+
+    /// A connection to `server`: verified TLS when strict; when
+    /// opportunistic, TLS whatever the certificate, else plain DNS over TCP
+    /// on port 53.
+    async fn connect<F, Fut>(
+        &self,
+        server: &DotServer,
+        dial: &F,
+    ) -> Result<Box<dyn Upstream>, BoxError>
+    where
+        F: Fn(Target) -> Fut,
+        Fut: Future<Output = Result<Box<dyn Upstream>, BoxError>>,
+    {
+        let name = server
+            .auth_name
+            .clone()
+            .unwrap_or_else(|| server.target.host_str());
+        let name = ServerName::try_from(name)?;
+        let tls = match self.profile {
+            DotProfile::Strict => TlsConnector::from(http::tls_config()),
+            DotProfile::Opportunistic => TlsConnector::from(pinning::unauthenticated_tls_config()),
+        };
+        let connected = match dial(server.target.clone()).await {
+            Ok(stream) => tls.connect(name, stream).await.map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        match (connected, self.profile) {
+            (Ok(stream), _) => Ok(Box::new(stream)),
+            (Err(e), DotProfile::Strict) => Err(e),
+            (Err(_), DotProfile::Opportunistic) => {
+                dial(Target::new(server.target.host.clone(), 53)).await
+            }
+        }
+    }
+
+    /// Ask for A then AAAA over `conn` (RFC 1035 §4.2.2 framing).
+    async fn exchange(
+        &self,
+        conn: &mut Box<dyn Upstream>,
+        name: &str,
+    ) -> Result<[Message; 2], BoxError> {
+        let mut answers = Vec::with_capacity(2);
+        for qtype in [TYPE_A, TYPE_AAAA] {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let query = encode_query(id, name, qtype);
+            let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+            framed.extend(query);
+            conn.write_all(&framed).await?;
+            conn.flush().await?;
+            let len = conn.read_u16().await?;
+            let mut reply = vec![0u8; len as usize];
+            conn.read_exact(&mut reply).await?;
+            let msg = decode(&reply)?;
+            if msg.id != id {
+                return Err("DNS reply for another query".into());
+            }
+            answers.push(msg);
+        }
+        Ok(answers.try_into().expect("two queries"))
+    }
+}
@@ -0,0 +1,193 @@
+/// Whether this build can write to the Windows Event Log.
+pub const AVAILABLE: bool = cfg!(windows);
+
+/// Event IDs, so Windows tooling can filter on them.
+pub const STARTED: u32 = 1;
+pub const STOPPED: u32 = 2;
+pub const FAILOVER: u32 = 100;
+pub const KILL_SWITCH: u32 = 200;
+
+/// Message file registered for the source: the .NET Framework's, whose
+/// messages are all just `%1`, so any event ID shows the text as written.
+pub const MESSAGE_FILE: &str =
+    r"%SystemRoot%\Microsoft.NET\Framework64\v4.0.30319\EventLogMessages.dll";
+
+/// Event Log entry types used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Information,
+    Warning,
+    Error,
+}
+
+/// An event source in the Application log.
+#[derive(Debug)]
+pub struct EventLog {
+    #[cfg(windows)]
+    handle: windows::Handle,
+}
+
+impl EventLog {
+    /// Register `source` if it isn't yet (needs administrator rights the
+    /// first time; without them, entries still get written, but Event
+    /// Viewer can't find their message text), then open it.
+    #[cfg(windows)]
+    pub fn open(source: &str) -> Result<Self, String> {
+        // Best effort: an unregistered source still logs
+        let _ = windows::register(source, MESSAGE_FILE);
+        Ok(Self {
+            handle: windows::open(source)?,
+        })
+    }
+
+    #[cfg(not(windows))]
+    pub fn open(_source: &str) -> Result<Self, String> {
+        Err("the Windows Event Log only exists on Windows".to_string())
+    }
+
+    #[cfg(windows)]
+    pub fn report(&self, kind: EventKind, id: u32, message: &str) -> Result<(), String> {
+        windows::report(&self.handle, kind, id, message)
+    }
+
+    #[cfg(not(windows))]
+    pub fn report(&self, _kind: EventKind, _id: u32, _message: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::OsStr;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+
+    use windows_sys::Win32::Foundation::{ERROR_SUCCESS, HANDLE};
+    use windows_sys::Win32::System::EventLog::{
+        DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE,
+        EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+    };
+    use windows_sys::Win32::System::Registry::{
+        RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_WRITE,
+        REG_DWORD, REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE,
+    };
+
+    use super::EventKind;
+
+    /// Application log sources live here.
+    const SOURCES_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application";
+
+    /// The source handle; Event Log handles may be used from any thread.
+    #[derive(Debug)]
+    pub(super) struct Handle(HANDLE);
+
+    unsafe impl Send for Handle {}
+    unsafe impl Sync for Handle {}
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            unsafe { DeregisterEventSource(self.0) };
+        }
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        OsStr::new(s).encode_wide().chain(Some(0)).collect()
+    }
+
+    pub(super) fn register(source: &str, message_file: &str) -> Result<(), String> {
+        let path = wide(&format!(r"{SOURCES_KEY}\{source}"));
+        let mut key: HKEY = ptr::null_mut();
+        let status = unsafe {
+            RegCreateKeyExW(
+                HKEY_LOCAL_MACHINE,
+                path.as_ptr(),
+                0,
+                ptr::null(),
+                REG_OPTION_NON_VOLATILE,
+                KEY_WRITE,
+                ptr::null(),
+                &mut key,
+                ptr::null_mut(),
+            )
+        };
+        if status != ERROR_SUCCESS {
+            return Err(format!(
+                "registering event source `{source}`: {}",
+                io::Error::from_raw_os_error(status as i32)
+            ));
+        }
+        let file = wide(message_file);
+        let types: u32 = 7; // error, warning, information
+        let written = unsafe {
+            RegSetValueExW(
+                key,
+                wide("EventMessageFile").as_ptr(),
+                0,
+                REG_EXPAND_SZ,
+                file.as_ptr().cast(),
+                (file.len() * 2) as u32,
+            ) == ERROR_SUCCESS
+                && RegSetValueExW(
+                    key,
+                    wide("TypesSupported").as_ptr(),
+                    0,
+                    REG_DWORD,
+                    (&types as *const u32).cast(),
+                    4,
+                ) == ERROR_SUCCESS
+        };
+        unsafe { RegCloseKey(key) };
+        match written {
+            true => Ok(()),
+            false => Err(format!(
+                "registering event source `{source}`: {}",
+                io::Error::last_os_error()
+            )),
+        }
+    }
+
+    pub(super) fn open(source: &str) -> Result<Handle, String> {
+        let name = wide(source);
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), name.as_ptr()) };
+        if handle.is_null() {
+            return Err(format!(
+                "event source `{source}`: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        Ok(Handle(handle))
+    }
+
+    pub(super) fn report(
+        handle: &Handle,
+        kind: EventKind,
+        id: u32,
+        message: &str,
+    ) -> Result<(), String> {
+        let kind = match kind {
+            EventKind::Information => EVENTLOG_INFORMATION_TYPE,
+            EventKind::Warning => EVENTLOG_WARNING_TYPE,
+            EventKind::Error => EVENTLOG_ERROR_TYPE,
+        };
+        let text = wide(message);
+        let strings = [text.as_ptr()];
+        let ok = unsafe {
+            ReportEventW(
+                handle.0,
+                kind,
+                0,
+                id,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                ptr::null(),
+            )
+        };
+        match ok {
+            0 => Err(io::Error::last_os_error().to_string()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::error::Error;
+use std::net::SocketAddr;
+
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+
+use crate::http::{self, Url};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Default IP-echo endpoint (JSON with `ip` and `country`).
+pub const DEFAULT_ECHO_URL: &str = "https://ipinfo.io/json";
+
+/// How traffic leaves for one check.
+#[derive(Debug, Clone, Copy)]
+pub enum Path {
+    Direct,
+    Socks(SocketAddr),
+}
+
+/// Apparent exit as seen by the echo service.
+#[derive(Debug, Clone)]
+pub struct ExitInfo {
+    pub ip: String,
+    pub country: Option<String>,
+}
+
+/// Fetch `url` over `path` and report what address the far end saw.
+///
+/// Understands JSON bodies with `ip` (and optionally `country`) fields as
+/// well as plain-text bodies holding just the address.
+pub async fn check(url: &Url, path: Path) -> Result<ExitInfo, BoxError> {
+    let tcp = match path {
+        Path::Direct => TcpStream::connect((url.host.as_str(), url.port)).await?,
+        Path::Socks(proxy) => Socks5Stream::connect(proxy, (url.host.as_str(), url.port))
+            .await?
+            .into_inner(),
+    };
+    let resp = http::request_over(tcp, "GET", url, &[("Accept", "application/json")], &[]).await?;
+    if resp.status != 200 {
+        return Err(format!("echo service answered HTTP {}", resp.status).into());
+    }
+
+    let body = String::from_utf8_lossy(&resp.body);
+    match serde_json::from_str::<serde_json::Value>(&body) {
+        Ok(json) => Ok(ExitInfo {
+            ip: json["ip"]
+                .as_str()
+                .ok_or("echo response has no `ip` field")?
+                .to_string(),
+            country: json["country"].as_str().map(str::to_string),
+        }),
+        Err(_) => Ok(ExitInfo {
+            ip: body.trim().to_string(),
+            country: None,
+        }),
+    }
+}
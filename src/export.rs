@@ -0,0 +1,300 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::gossip::Report;
+use crate::recording::Recorded;
+use crate::stats::{now_unix, TrafficEntry};
+
+/// Output format of `gold-dust-gateway export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    /// Needs the `parquet` feature.
+    Parquet,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "parquet" => Ok(Format::Parquet),
+            other => Err(format!("unknown format `{other}` (csv, parquet)")),
+        }
+    }
+}
+
+/// Which rows to export.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Inclusive lower bound (unix seconds).
+    pub since: Option<u64>,
+    /// Exclusive upper bound (unix seconds).
+    pub until: Option<u64>,
+    /// Egress (traffic) or backend name / kind (health).
+    pub backend: Option<String>,
+}
+
+impl Filter {
+    fn in_range(&self, unix: u64) -> bool {
+        self.since.is_none_or(|s| unix >= s) && self.until.is_none_or(|u| unix < u)
+    }
+
+    pub fn keep_traffic(&self, e: &TrafficEntry) -> bool {
+        self.in_range(e.unix)
+            && self
+                .backend
+                .as_ref()
+                .is_none_or(|b| e.egress.as_ref() == Some(b))
+    }
+
+    pub fn keep_report(&self, r: &Report) -> bool {
+        self.in_range(r.observed_unix)
+            && self
+                .backend
+                .as_ref()
+                .is_none_or(|b| &r.health.name == b || r.health.kind.as_str() == b)
+    }
+
+    pub fn keep_decision(&self, r: &Recorded) -> bool {
+        self.in_range(r.unix)
+            && self
+                .backend
+                .as_ref()
+                .is_none_or(|b| &r.chosen == b || r.kind.as_str() == b)
+    }
+}
+
+/// A point in time on the command line: unix seconds, or an age such as
+/// `90m`, `24h`, `7d` (that long before now).
+pub fn parse_time(s: &str) -> Result<u64, String> {
+    if let Ok(unix) = s.parse() {
+        return Ok(unix);
+    }
+    let split = s.len().saturating_sub(1);
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n
+        .parse()
+        .map_err(|_| format!("`{s}` is neither unix seconds nor an age like 24h"))?;
+    let secs = match unit {
+        "s" => n,
+        "m" => n * 60,
+        "h" => n * 3600,
+        "d" => n * 86_400,
+        _ => return Err(format!("`{s}`: age unit must be s, m, h or d")),
+    };
+    Ok(now_unix().saturating_sub(secs))
+}
+
+/// Column types, so Parquet gets a typed schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Int,
+    Float,
+    Bool,
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+}
+
+/// Rows ready to write in either format.
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub columns: Vec<(&'static str, Kind)>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    pub fn traffic(entries: &[TrafficEntry]) -> Self {
+        Self {
+            columns: vec![
+                ("unix", Kind::Int),
+                ("client", Kind::Text),
+                ("target", Kind::Text),
+                ("egress", Kind::Text),
+                ("app", Kind::Text),
+                ("user", Kind::Text),
+                ("outcome", Kind::Text),
+                ("audit", Kind::Text),
+                ("bytes_up", Kind::Int),
+                ("bytes_down", Kind::Int),
+                ("duration_ms", Kind::Int),
+            ],
+            rows: entries
+                .iter()
+                .map(|e| {
+                    vec![
+                        Value::Int(e.unix as i64),
+                        Value::Text(e.client.clone()),
+                        Value::Text(e.target.clone()),
+                        Value::Text(e.egress.clone().unwrap_or_default()),
+                        Value::Text(e.app.clone().unwrap_or_default()),
+                        Value::Text(e.user.clone().unwrap_or_default()),
+                        Value::Text(e.outcome.clone()),
+                        Value::Text(e.audit.clone().unwrap_or_default()),
+                        Value::Int(e.bytes_up as i64),
+                        Value::Int(e.bytes_down as i64),
+                        Value::Int(e.duration_ms as i64),
+                    ]
+                })
+                .collect(),
+        }
+    }
+
+    pub fn health(reports: &[Report]) -> Self {
+        Self {
+            columns: vec![
+                ("observed_unix", Kind::Int),
+                ("node", Kind::Text),
+                ("backend", Kind::Text),
+                ("kind", Kind::Text),
+                ("latency_ms", Kind::Float),
+                ("failure_rate", Kind::Float),
+                ("enabled", Kind::Bool),
+                ("ipv6", Kind::Bool),
+            ],
+            rows: reports
+                .iter()
+                .map(|r| {
+                    vec![
+                        Value::Int(r.observed_unix as i64),
+                        Value::Text(r.node.clone()),
+                        Value::Text(r.health.name.clone()),
+                        Value::Text(r.health.kind.as_str().to_string()),
+                        Value::Float(r.health.latency_ms),
+                        Value::Float(r.health.failure_rate),
+                        Value::Bool(r.health.enabled),
+                        Value::Bool(r.health.ipv6),
+                    ]
+                })
+                .collect(),
+        }
+    }
+
+    /// RFC 4180 CSV with a header row.
+    pub fn write_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let header: Vec<&str> = self.columns.iter().map(|(name, _)| *name).collect();
+        writeln!(out, "{}", header.join(","))?;
+        for row in &self.rows {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|v| match v {
+                    Value::Int(n) => n.to_string(),
+                    Value::Float(f) => f.to_string(),
+                    Value::Bool(b) => b.to_string(),
+                    Value::Text(s) if s.contains([',', '"', '\n', '\r']) => {
+                        format!("\"{}\"", s.replace('"', "\"\""))
+                    }
+                    Value::Text(s) => s.clone(),
+                })
+                .collect();
+            writeln!(out, "{}", cells.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// One row group, plain required columns.
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet(&self, path: &Path) -> Result<(), String> {
+        use std::sync::Arc;
+
+        use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type};
+        use parquet::file::properties::WriterProperties;
+        use parquet::file::writer::SerializedFileWriter;
+        use parquet::schema::parser::parse_message_type;
+
+        let fields: Vec<String> = self
+            .columns
+            .iter()
+            .map(|(name, kind)| match kind {
+                Kind::Int => format!("REQUIRED INT64 {name};"),
+                Kind::Float => format!("REQUIRED DOUBLE {name};"),
+                Kind::Bool => format!("REQUIRED BOOLEAN {name};"),
+                Kind::Text => format!("REQUIRED BYTE_ARRAY {name} (UTF8);"),
+            })
+            .collect();
+        let schema = parse_message_type(&format!("message export {{ {} }}", fields.join(" ")))
+            .map_err(|e| e.to_string())?;
+
+        let file = File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer =
+            SerializedFileWriter::new(file, Arc::new(schema), props).map_err(|e| e.to_string())?;
+        let mut group = writer.next_row_group().map_err(|e| e.to_string())?;
+        let mut index = 0;
+        while let Some(mut column) = group.next_column().map_err(|e| e.to_string())? {
+            let cells = self.rows.iter().map(|row| &row[index]);
+            let written = match self.columns[index].1 {
+                Kind::Int => {
+                    let values: Vec<i64> = cells
+                        .map(|v| match v {
+                            Value::Int(n) => *n,
+                            _ => 0,
+                        })
+                        .collect();
+                    column.typed::<Int64Type>().write_batch(&values, None, None)
+                }
+                Kind::Float => {
+                    let values: Vec<f64> = cells
+                        .map(|v| match v {
+                            Value::Float(f) => *f,
+                            _ => 0.0,
+                        })
+                        .collect();
+                    column
+                        .typed::<DoubleType>()
+                        .write_batch(&values, None, None)
+                }
+                Kind::Bool => {
+                    let values: Vec<bool> = cells.map(|v| v == &Value::Bool(true)).collect();
+                    column.typed::<BoolType>().write_batch(&values, None, None)
+                }
+                Kind::Text => {
+                    let values: Vec<ByteArray> = cells
+                        .map(|v| match v {
+                            Value::Text(s) => ByteArray::from(s.as_str()),
+                            _ => ByteArray::from(""),
+                        })
+                        .collect();
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&values, None, None)
+                }
+            };
+            written.map_err(|e| e.to_string())?;
+            column.close().map_err(|e| e.to_string())?;
+            index += 1;
+        }
+        group.close().map_err(|e| e.to_string())?;
+        writer.close().map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    pub fn write_parquet(&self, _path: &Path) -> Result<(), String> {
+        Err("Parquet export needs the `parquet` feature".to_string())
+    }
+
+    /// Write to `path`, or CSV to stdout without one.
+    pub fn write(&self, format: Format, path: Option<&Path>) -> Result<(), String> {
+        match (format, path) {
+            (Format::Csv, None) => self
+                .write_csv(io::stdout().lock())
+                .map_err(|e| e.to_string()),
+            (Format::Csv, Some(path)) => File::create(path)
+                .and_then(|f| self.write_csv(io::BufWriter::new(f)))
+                .map_err(|e| format!("{}: {e}", path.display())),
+            (Format::Parquet, Some(path)) => self.write_parquet(path),
+            (Format::Parquet, None) => Err("Parquet export needs --output".to_string()),
+        }
+    }
+}
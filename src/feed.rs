@@ -0,0 +1,56 @@
+use serde::Deserialize;
+
+use crate::router::{BackendHealth, BackendKind};
+
+/// Node name external feed reports are filed under on the health board.
+pub const FEED_NODE: &str = "feed";
+
+/// One backend as reported by an external monitoring system.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FeedRecord {
+    pub name: String,
+    pub kind: BackendKind,
+    pub latency_ms: f64,
+    pub failure_rate: f64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub ipv6: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl From<FeedRecord> for BackendHealth {
+    fn from(r: FeedRecord) -> Self {
+        Self {
+            name: r.name,
+            kind: r.kind,
+            latency_ms: r.latency_ms,
+            failure_rate: r.failure_rate,
+            enabled: r.enabled,
+            ipv6: r.ipv6,
+        }
+    }
+}
+
+/// Parse a feed body: a JSON array of records, or NDJSON (one per line).
+pub fn parse(body: &str) -> Result<Vec<BackendHealth>, String> {
+    if body.trim_start().starts_with('[') {
+        let records: Vec<FeedRecord> = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        return Ok(records.into_iter().map(Into::into).collect());
+    }
+    body.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| parse_line(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+/// Parse one NDJSON record.
+pub fn parse_line(line: &str) -> Result<BackendHealth, String> {
+    serde_json::from_str::<FeedRecord>(line)
+        .map(Into::into)
+        .map_err(|e| e.to_string())
+}
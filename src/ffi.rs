@@ -0,0 +1,184 @@
+//! C ABI over the routing core (`ffi` feature). See `include/gold_dust.h`.
+//!
+//! Strings returned to C are owned by the caller and must be released with
+//! `gd_string_free`. On failure functions return NULL and `gd_last_error`
+//! says why.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use crate::config::GoldDustConfig;
+use crate::policy;
+use crate::router::{Router, NO_BACKENDS};
+use crate::target::Target;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Opaque router handle for C callers.
+pub struct GdRouter {
+    router: Router,
+}
+
+fn set_error(msg: impl ToString) {
+    let msg = CString::new(msg.to_string().replace('\0', " ")).expect("NULs replaced");
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    match CString::new(s) {
+        Ok(c) => c.into_raw(),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `s` must be NULL or a valid NUL-terminated string.
+unsafe fn str_arg<'a>(s: *const c_char, what: &str) -> Option<&'a str> {
+    if s.is_null() {
+        set_error(format!("{what} is NULL"));
+        return None;
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(e) => {
+            set_error(format!("{what}: {e}"));
+            None
+        }
+    }
+}
+
+/// Build a router from a config TOML path, or the demo config if NULL.
+/// NULL if the config doesn't load or configures no backends.
+///
+/// # Safety
+/// `config_path` must be NULL or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn gd_router_new(config_path: *const c_char) -> *mut GdRouter {
+    let cfg = if config_path.is_null() {
+        GoldDustConfig::default_for_demo()
+    } else {
+        let Some(path) = str_arg(config_path, "config_path") else {
+            return ptr::null_mut();
+        };
+        match GoldDustConfig::load(path) {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        }
+    };
+
+    let mut router = Router::from_config(&cfg);
+    if router.is_empty() {
+        set_error(NO_BACKENDS);
+        return ptr::null_mut();
+    }
+    if let Some(path) = &cfg.routing.policy {
+        match policy::load(path) {
+            Ok(p) => router.set_policy(p),
+            Err(e) => {
+                set_error(e);
+                return ptr::null_mut();
+            }
+        }
+    }
+    Box::into_raw(Box::new(GdRouter { router }))
+}
+
+/// Release a router from `gd_router_new`. NULL is ignored.
+///
+/// # Safety
+/// `router` must be NULL or a pointer from `gd_router_new`, not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn gd_router_free(router: *mut GdRouter) {
+    if !router.is_null() {
+        drop(Box::from_raw(router));
+    }
+}
+
+/// Choose a backend for `target` (same syntax as `gold-dust-gateway route`).
+///
+/// Returns JSON: `{"backend", "kind", "latency_ms", "failure_rate", "ipv6"}`.
+///
+/// # Safety
+/// `router` must come from `gd_router_new`; `target` must be a valid
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn gd_router_route(
+    router: *mut GdRouter,
+    target: *const c_char,
+) -> *mut c_char {
+    let Some(router) = router.as_mut() else {
+        set_error("router is NULL");
+        return ptr::null_mut();
+    };
+    let Some(target) = str_arg(target, "target") else {
+        return ptr::null_mut();
+    };
+    let target: Target = match target.parse() {
+        Ok(t) => t,
+        Err(e) => {
+            set_error(e);
+            return ptr::null_mut();
+        }
+    };
+
+    let Some(choice) = router.router.choose_backend_for(&target) else {
+        set_error(NO_BACKENDS);
+        return ptr::null_mut();
+    };
+    into_c_string(
+        serde_json::json!({
+            "backend": choice.name,
+            "kind": choice.kind.as_str(),
+            "latency_ms": choice.latency_ms,
+            "failure_rate": choice.failure_rate,
+            "ipv6": choice.ipv6,
+        })
+        .to_string(),
+    )
+}
+
+/// Current backend health as a JSON array.
+///
+/// # Safety
+/// `router` must come from `gd_router_new`.
+#[no_mangle]
+pub unsafe extern "C" fn gd_router_status_json(router: *const GdRouter) -> *mut c_char {
+    let Some(router) = router.as_ref() else {
+        set_error("router is NULL");
+        return ptr::null_mut();
+    };
+    match serde_json::to_string(&router.router.backend_health()) {
+        Ok(json) => into_c_string(json),
+        Err(e) => {
+            set_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Message for the last failed call on this thread, or NULL. Owned by the
+/// library: valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn gd_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Release a string returned by this library. NULL is ignored.
+///
+/// # Safety
+/// `s` must be NULL or a string returned by a `gd_*` function, not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn gd_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
@@ -0,0 +1,334 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Command, Stdio};
+
+use crate::config::{FirewallBackend, GoldDustConfig};
+use crate::target::Cidr;
+
+/// nftables table owning every rule we add.
+pub const NFT_TABLE: &str = "gold_dust";
+/// iptables/ip6tables chain owning every rule we add.
+pub const IPT_CHAIN: &str = "GOLD_DUST_OUT";
+
+const LAN_V4: [&str; 4] = [
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "169.254.0.0/16",
+];
+const LAN_V6: [&str; 2] = ["fc00::/7", "fe80::/10"];
+
+/// An allowed destination: a network, optionally one TCP/UDP port.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub net: Cidr,
+    pub port: Option<u16>,
+}
+
+impl Endpoint {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.contains('/') {
+            return Ok(Self {
+                net: s.parse()?,
+                port: None,
+            });
+        }
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self {
+                net: host_net(addr.ip()),
+                port: Some(addr.port()),
+            });
+        }
+        match s.trim_start_matches('[').trim_end_matches(']').parse() {
+            Ok(ip) => Ok(Self {
+                net: host_net(ip),
+                port: None,
+            }),
+            Err(_) => Err(format!(
+                "`{s}` is not an IP, ip:port or CIDR (firewall rules can't use host names)"
+            )),
+        }
+    }
+}
+
+fn host_net(ip: IpAddr) -> Cidr {
+    let prefix = if ip.is_ipv4() { 32 } else { 128 };
+    format!("{ip}/{prefix}")
+        .parse()
+        .expect("host prefix is always valid")
+}
+
+/// Kill-switch rules derived from `[firewall]`: outbound traffic is dropped
+/// unless it is loopback (the dispatcher and local Tor/lokinet ports), part
+/// of an established flow, from an allowed user, or to an allowed endpoint.
+#[derive(Debug, Clone)]
+pub struct Ruleset {
+    pub allow_uids: Vec<u32>,
+    pub endpoints: Vec<Endpoint>,
+    pub allow_lan: bool,
+}
+
+impl Ruleset {
+    /// A Tor SOCKS port off this host is allowed automatically.
+    pub fn from_config(cfg: &GoldDustConfig) -> Result<Self, String> {
+        let mut endpoints: Vec<Endpoint> = cfg
+            .firewall
+            .allow
+            .iter()
+            .map(|s| Endpoint::parse(s))
+            .collect::<Result<_, _>>()?;
+        let socks = cfg.backends.tor_socks;
+        if cfg.backends.tor_enabled && !socks.ip().is_loopback() {
+            endpoints.push(Endpoint {
+                net: host_net(socks.ip()),
+                port: Some(socks.port()),
+            });
+        }
+        Ok(Self {
+            allow_uids: cfg.firewall.allow_uids.clone(),
+            endpoints,
+            allow_lan: cfg.firewall.allow_lan,
+        })
+    }
+
+    /// `nft -f` script replacing our table in one transaction.
+    pub fn nft_script(&self) -> String {
+        let mut out = String::new();
+        // Declare then delete, so the script works whether or not it exists
+        let _ = writeln!(out, "table inet {NFT_TABLE}\ndelete table inet {NFT_TABLE}");
+        let _ = writeln!(out, "table inet {NFT_TABLE} {{");
+        let _ = writeln!(out, "  chain output {{");
+        let _ = writeln!(out, "    type filter hook output priority 0; policy drop;");
+        let _ = writeln!(out, "    oif \"lo\" accept");
+        let _ = writeln!(out, "    ct state established,related accept");
+        for uid in &self.allow_uids {
+            let _ = writeln!(out, "    meta skuid {uid} accept");
+        }
+        if self.allow_lan {
+            for net in LAN_V4 {
+                let _ = writeln!(out, "    ip daddr {net} accept");
+            }
+            for net in LAN_V6 {
+                let _ = writeln!(out, "    ip6 daddr {net} accept");
+            }
+        }
+        for ep in &self.endpoints {
+            let family = if ep.net.is_ipv6() { "ip6" } else { "ip" };
+            match ep.port {
+                Some(port) => {
+                    let _ = writeln!(
+                        out,
+                        "    {family} daddr {} meta l4proto {{ tcp, udp }} th dport {port} accept",
+                        ep.net
+                    );
+                }
+                None => {
+                    let _ = writeln!(out, "    {family} daddr {} accept", ep.net);
+                }
+            }
+        }
+        let _ = writeln!(out, "  }}\n}}");
+        out
+    }
+
+    /// `iptables-restore --noflush` input for one family.
+    pub fn iptables_script(&self, v6: bool) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "*filter");
+        let _ = writeln!(out, ":{IPT_CHAIN} - [0:0]");
+        let _ = writeln!(out, "-F {IPT_CHAIN}");
+        let _ = writeln!(out, "-A {IPT_CHAIN} -o lo -j ACCEPT");
+        let _ = writeln!(
+            out,
+            "-A {IPT_CHAIN} -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT"
+        );
+        for uid in &self.allow_uids {
+            let _ = writeln!(out, "-A {IPT_CHAIN} -m owner --uid-owner {uid} -j ACCEPT");
+        }
+        if self.allow_lan {
+            let lan: &[&str] = if v6 { &LAN_V6 } else { &LAN_V4 };
+            for net in lan {
+                let _ = writeln!(out, "-A {IPT_CHAIN} -d {net} -j ACCEPT");
+            }
+        }
+        for ep in self.endpoints.iter().filter(|ep| ep.net.is_ipv6() == v6) {
+            match ep.port {
+                Some(port) => {
+                    for proto in ["tcp", "udp"] {
+                        let _ = writeln!(
+                            out,
+                            "-A {IPT_CHAIN} -d {} -p {proto} --dport {port} -j ACCEPT",
+                            ep.net
+                        );
+                    }
+                }
+                None => {
+                    let _ = writeln!(out, "-A {IPT_CHAIN} -d {} -j ACCEPT", ep.net);
+                }
+            }
+        }
+        let _ = writeln!(out, "-A {IPT_CHAIN} -j REJECT");
+        let _ = writeln!(out, "COMMIT");
+        out
+    }
+}
+
+/// Everything `apply` would load, for review (`firewall apply --dry-run`).
+pub fn render(ruleset: &Ruleset, backend: FirewallBackend) -> String {
+    match backend {
+        FirewallBackend::Nftables => format!("# nft -f -\n{}", ruleset.nft_script()),
+        FirewallBackend::Iptables => format!(
+            "# iptables-restore --noflush\n{}\n# ip6tables-restore --noflush\n{}",
+            ruleset.iptables_script(false),
+            ruleset.iptables_script(true)
+        ),
+    }
+}
+
+/// What was loaded before `apply`, to restore on rollback.
+#[derive(Debug, Clone)]
+pub enum Previous {
+    /// Our nft table's previous contents (`None`: it didn't exist).
+    Nft(Option<String>),
+    /// Previous iptables/ip6tables `filter` tables (`iptables-save` output).
+    Iptables { v4: String, v6: String },
+}
+
+/// Load the rules. Each family is replaced in one atomic transaction; if any
+/// step fails, whatever was already applied is rolled back.
+pub fn apply(ruleset: &Ruleset, backend: FirewallBackend) -> Result<Previous, String> {
+    match backend {
+        FirewallBackend::Nftables => {
+            let previous = Command::new("nft")
+                .args(["list", "table", "inet", NFT_TABLE])
+                .stderr(Stdio::null())
+                .output()
+                .ok()
+                .filter(|o| o.status.success())
+                .map(|o| String::from_utf8_lossy(&o.stdout).into_owned());
+            run_stdin("nft", &["-f", "-"], &ruleset.nft_script())?;
+            Ok(Previous::Nft(previous))
+        }
+        FirewallBackend::Iptables => {
+            let previous = Previous::Iptables {
+                v4: save("iptables-save")?,
+                v6: save("ip6tables-save")?,
+            };
+            let steps = [
+                ("iptables-restore", ruleset.iptables_script(false)),
+                ("ip6tables-restore", ruleset.iptables_script(true)),
+            ];
+            for (tool, script) in steps {
+                let jump = ensure_jump(&tool.replace("-restore", ""));
+                if let Err(e) = run_stdin(tool, &["--noflush"], &script).and(jump) {
+                    let _ = rollback(&previous);
+                    return Err(e);
+                }
+            }
+            Ok(previous)
+        }
+    }
+}
+
+/// Put the previous rules back.
+pub fn rollback(previous: &Previous) -> Result<(), String> {
+    match previous {
+        Previous::Nft(None) => remove(FirewallBackend::Nftables),
+        Previous::Nft(Some(table)) => run_stdin(
+            "nft",
+            &["-f", "-"],
+            &format!("table inet {NFT_TABLE}\ndelete table inet {NFT_TABLE}\n{table}"),
+        ),
+        Previous::Iptables { v4, v6 } => {
+            run_stdin("iptables-restore", &["-T", "filter"], v4)?;
+            run_stdin("ip6tables-restore", &["-T", "filter"], v6)
+        }
+    }
+}
+
+/// Remove every rule we own.
+pub fn remove(backend: FirewallBackend) -> Result<(), String> {
+    match backend {
+        FirewallBackend::Nftables => run_stdin(
+            "nft",
+            &["-f", "-"],
+            &format!("table inet {NFT_TABLE}\ndelete table inet {NFT_TABLE}\n"),
+        ),
+        FirewallBackend::Iptables => {
+            for tool in ["iptables", "ip6tables"] {
+                // Drop every jump to our chain, then the chain itself
+                while run(tool, &["-D", "OUTPUT", "-j", IPT_CHAIN]).is_ok() {}
+                let _ = run(tool, &["-F", IPT_CHAIN]);
+                let _ = run(tool, &["-X", IPT_CHAIN]);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Make OUTPUT jump to our chain (once).
+fn ensure_jump(tool: &str) -> Result<(), String> {
+    if run(tool, &["-C", "OUTPUT", "-j", IPT_CHAIN]).is_ok() {
+        return Ok(());
+    }
+    run(tool, &["-I", "OUTPUT", "1", "-j", IPT_CHAIN])
+}
+
+fn save(tool: &str) -> Result<String, String> {
+    let out = Command::new(tool)
+        .args(["-t", "filter"])
+        .output()
+        .map_err(|e| format!("{tool}: {e}"))?;
+    if !out.status.success() {
+        return Err(format!(
+            "{tool}: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+fn run(tool: &str, args: &[&str]) -> Result<(), String> {
+    let out = Command::new(tool)
+        .args(args)
+        .output()
+        .map_err(|e| format!("{tool}: {e}"))?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{tool} {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))
+    }
+}
+
+fn run_stdin(tool: &str, args: &[&str], input: &str) -> Result<(), String> {
+    let mut child = Command::new(tool)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{tool}: {e}"))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input.as_bytes())
+        .map_err(|e| format!("{tool}: {e}"))?;
+    let out = child
+        .wait_with_output()
+        .map_err(|e| format!("{tool}: {e}"))?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{tool}: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))
+    }
+}
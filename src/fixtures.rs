@@ -0,0 +1,118 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::router::{BackendChoice, Requirements, Router, NO_BACKENDS};
+use crate::target::Target;
+
+/// Expected routing decisions, loaded from TOML or JSON (`policy test`).
+///
+/// ```toml
+/// seed = 7
+///
+/// [[cases]]
+/// target = "example.com:443"
+/// expect = "tor"                             # backend name or kind
+///
+/// [[cases]]
+/// target = "10.1.2.3:53"
+/// udp = true
+/// expect = ["oxen-node-1", "oxen-node-2"]    # any of these
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixtures {
+    /// RNG seed for this run; overrides the config seed.
+    pub seed: Option<u64>,
+    pub cases: Vec<Case>,
+}
+
+/// One target and the backends it may be routed to.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    pub target: Target,
+    /// The traffic is UDP (`route --udp`).
+    #[serde(default)]
+    pub udp: bool,
+    pub expect: Expect,
+    pub note: Option<String>,
+}
+
+/// Backend names or kinds (`tor`, `oxen`, ...), any of which passes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Expect {
+    One(String),
+    Any(Vec<String>),
+}
+
+impl Expect {
+    pub fn allowed(&self) -> &[String] {
+        match self {
+            Expect::One(one) => std::slice::from_ref(one),
+            Expect::Any(any) => any,
+        }
+    }
+
+    pub fn accepts(&self, choice: &BackendChoice) -> bool {
+        self.allowed()
+            .iter()
+            .any(|e| e.eq_ignore_ascii_case(&choice.name) || e == choice.kind.as_str())
+    }
+}
+
+/// Result of routing one case.
+#[derive(Debug, Clone)]
+pub struct CaseOutcome {
+    pub case: Case,
+    pub got: BackendChoice,
+    /// The routing policy failed, so the built-in order decided.
+    pub policy_error: Option<String>,
+}
+
+impl CaseOutcome {
+    /// Routed as expected, by the configured policy.
+    pub fn passed(&self) -> bool {
+        self.policy_error.is_none() && self.case.expect.accepts(&self.got)
+    }
+}
+
+impl Fixtures {
+    /// Load fixtures; `.json` files are parsed as JSON, anything else as TOML.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let fixtures = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&text)?,
+            _ => toml::from_str(&text)?,
+        };
+        Ok(fixtures)
+    }
+
+    /// Route every case through `router`, in order. Fails if the router has
+    /// no backends to decide between.
+    pub fn run(&self, router: &mut Router) -> Result<Vec<CaseOutcome>, String> {
+        if let Some(seed) = self.seed {
+            router.reseed(seed);
+        }
+        self.cases
+            .iter()
+            .map(|case| {
+                let needs = Requirements {
+                    udp: case.udp,
+                    ..Requirements::of(&case.target)
+                };
+                let got = router
+                    .choose_backend_with(&case.target, &needs)
+                    .ok_or(NO_BACKENDS)?;
+                Ok(CaseOutcome {
+                    case: case.clone(),
+                    got,
+                    policy_error: router.take_policy_error(),
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+
+use crate::health::HealthSource;
+use crate::router::BackendHealth;
+use crate::stats::{now_unix, write_atomic};
+
+/// Where the dispatcher publishes the fleet's merged health for `status`.
+pub const HEALTH_PATH: &str = "gold-dust-health.json";
+
+/// Datagrams older than this are dropped.
+const MAX_SKEW_SECS: u64 = 60;
+
+/// Datagrams sent further than this in the future are dropped, so a peer's
+/// reports can't outrank everything measured until its clock comes round.
+const MAX_FUTURE_SECS: u64 = 5;
+
+/// Length of the HMAC-SHA256 tag at the front of every datagram.
+const TAG_LEN: usize = 32;
+
+/// One backend measurement and the instance that made it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub node: String,
+    pub observed_unix: u64,
+    pub health: BackendHealth,
+    /// Carried over from a previous run and not re-measured since.
+    #[serde(default)]
+    pub stale: bool,
+}
+
+/// Upper bounds (ms) of the latency histogram buckets; one more bucket
+/// holds everything slower.
+pub const LATENCY_BUCKETS_MS: [f64; 6] = [50.0, 100.0, 250.0, 500.0, 1000.0, 2000.0];
+
+/// Latency distribution of the reports seen for one backend.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyHistogram {
+    pub counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency_ms: f64) {
+        let i = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&b| latency_ms < b)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[i] += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Bucket bound under which a `q` share of samples fall (`None` if the
+    /// histogram is empty, infinity for the overflow bucket).
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+        let wanted = (q * total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= wanted {
+                return Some(LATENCY_BUCKETS_MS.get(i).copied().unwrap_or(f64::INFINITY));
+            }
+        }
+        Some(f64::INFINITY)
+    }
+}
+
+/// Fleet-wide view: the newest report per backend.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HealthBoard {
+    pub updated_unix: u64,
+    pub reports: BTreeMap<String, Report>,
+    /// Latency histograms per backend, kept across restarts.
+    #[serde(default)]
+    pub histograms: BTreeMap<String, LatencyHistogram>,
+}
+
+impl HealthBoard {
+    /// Read a board, or `None` if missing/unreadable.
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let text = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        write_atomic(path.as_ref(), &serde_json::to_string_pretty(self)?)
+    }
+
+    /// The board a previous run left at `path`, every report marked stale,
+    /// so a restarted daemon has something to go on before its first probe.
+    pub fn warm_start<P: AsRef<Path>>(path: P) -> Self {
+        let mut board = Self::load(path).unwrap_or_default();
+        for report in board.reports.values_mut() {
+            report.stale = true;
+        }
+        board.updated_unix = now_unix();
+        board
+    }
+
+    /// Keep `report` if it is newer than the live one we hold. Returns
+    /// whether it was kept.
+    pub fn merge(&mut self, report: Report) -> bool {
+        let newer = self
+            .reports
+            .get(&report.health.name)
+            .is_none_or(|r| r.stale || r.observed_unix < report.observed_unix);
+        if newer {
+            self.updated_unix = now_unix();
+            if report.health.enabled {
+                self.histograms
+                    .entry(report.health.name.clone())
+                    .or_default()
+                    .record(report.health.latency_ms);
+            }
+            self.reports.insert(report.health.name.clone(), report);
+        }
+        newer
+    }
+
+    /// Live report on `backend` no older than `max_age_secs`, if any.
+    pub fn fresh(&self, backend: &str, max_age_secs: u64) -> Option<&Report> {
+        self.reports
+            .get(backend)
+            .filter(|r| !r.stale && now_unix().saturating_sub(r.observed_unix) <= max_age_secs)
+    }
+
+    /// Drop live reports older than `max_age_secs`. Stale (warm-start)
+    /// reports stay until a live one replaces them.
+    pub fn retain_fresh(&mut self, max_age_secs: u64) {
+        let now = now_unix();
+        self.reports
+            .retain(|_, r| r.stale || now.saturating_sub(r.observed_unix) <= max_age_secs);
+    }
+}
+
+impl HealthSource for HealthBoard {
+    fn snapshot(&mut self) -> Vec<BackendHealth> {
+        self.reports.values().map(|r| r.health.clone()).collect()
+    }
+}
+
+/// What travels in one datagram, after the tag.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    pub node: String,
+    /// Counts up with every datagram from `node`; a repeat is a replay.
+    pub seq: u64,
+    pub sent_unix: u64,
+    pub reports: Vec<Report>,
+}
+
+/// Signs and checks gossip datagrams with the fleet's shared secret.
+pub struct Gossip {
+    key: hmac::Key,
+    node: String,
+    /// Last sequence number we sent.
+    seq: AtomicU64,
+    /// Highest sequence number and send time accepted per peer node.
+    seen: Mutex<HashMap<String, (u64, u64)>>,
+}
+
+impl Gossip {
+    pub fn new(secret: &str, node: String) -> Result<Self, String> {
+        if secret.is_empty() {
+            return Err("[gossip] secret must be set to authenticate peers".to_string());
+        }
+        Ok(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            node,
+            seq: AtomicU64::new(0),
+            seen: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// This instance's id in reports.
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Datagram carrying `reports`: HMAC tag, then the JSON envelope.
+    pub fn seal(&self, reports: Vec<Report>) -> Vec<u8> {
+        let body = serde_json::to_vec(&Envelope {
+            node: self.node.clone(),
+            seq: self.seq.fetch_add(1, Ordering::Relaxed) + 1,
+            sent_unix: now_unix(),
+            reports,
+        })
+        .expect("gossip envelope serializes");
+        let tag = hmac::sign(&self.key, &body);
+        let mut datagram = tag.as_ref().to_vec();
+        datagram.extend(body);
+        datagram
+    }
+
+    /// Check and decode a peer's datagram. Forged, stale, future-dated or
+    /// replayed datagrams are rejected; our own broadcasts, echoed back,
+    /// give `None`.
+    ///
+    /// Node ids are fresh every run, so a peer's sequence numbers only have
+    /// to count up for as long as its datagrams pass the age check.
+    pub fn open(&self, datagram: &[u8]) -> Result<Option<Envelope>, String> {
+        if datagram.len() < TAG_LEN {
+            return Err("short datagram".to_string());
+        }
+        let (tag, body) = datagram.split_at(TAG_LEN);
+        hmac::verify(&self.key, body, tag).map_err(|_| "bad signature".to_string())?;
+
+        let envelope: Envelope = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+        if envelope.node == self.node {
+            return Ok(None);
+        }
+        let now = now_unix();
+        if now.saturating_sub(envelope.sent_unix) > MAX_SKEW_SECS {
+            return Err(format!("stale datagram from {}", envelope.node));
+        }
+        if envelope.sent_unix.saturating_sub(now) > MAX_FUTURE_SECS {
+            return Err(format!(
+                "datagram from {} is dated in the future",
+                envelope.node
+            ));
+        }
+        if envelope
+            .reports
+            .iter()
+            .any(|r| r.observed_unix > envelope.sent_unix)
+        {
+            return Err(format!(
+                "report from {} observed after it was sent",
+                envelope.node
+            ));
+        }
+
+        let mut seen = self.seen.lock().expect("gossip peers poisoned");
+        if seen
+            .get(&envelope.node)
+            .is_some_and(|&(seq, _)| envelope.seq <= seq)
+        {
+            return Err(format!("replayed datagram from {}", envelope.node));
+        }
+        seen.retain(|_, &mut (_, sent)| now.saturating_sub(sent) <= MAX_SKEW_SECS);
+        seen.insert(envelope.node.clone(), (envelope.seq, envelope.sent_unix));
+        Ok(Some(envelope))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::BackendKind;
+
+    fn report(node: &str, observed_unix: u64) -> Report {
+        Report {
+            node: node.to_string(),
+            observed_unix,
+            health: BackendHealth {
+                name: "tor-1".to_string(),
+                kind: BackendKind::Tor,
+                latency_ms: 80.0,
+                failure_rate: 0.0,
+                enabled: true,
+                ipv6: false,
+            },
+            stale: false,
+        }
+    }
+
+    fn signed(gossip: &Gossip, envelope: &Envelope) -> Vec<u8> {
+        let body = serde_json::to_vec(envelope).unwrap();
+        let mut datagram = hmac::sign(&gossip.key, &body).as_ref().to_vec();
+        datagram.extend(body);
+        datagram
+    }
+
+    fn pair() -> (Gossip, Gossip) {
+        let a = Gossip::new("secret", "a".to_string()).unwrap();
+        let b = Gossip::new("secret", "b".to_string()).unwrap();
+        (a, b)
+    }
+
+    #[test]
+    fn replayed_datagrams_are_rejected() {
+        let (a, b) = pair();
+        let first = a.seal(vec![report("a", now_unix())]);
+        let second = a.seal(vec![report("a", now_unix())]);
+        assert!(b.open(&first).unwrap().is_some());
+        assert!(b.open(&second).unwrap().is_some());
+        assert!(b.open(&first).unwrap_err().starts_with("replayed"));
+        assert!(b.open(&second).unwrap_err().starts_with("replayed"));
+        assert!(a.open(&first).unwrap().is_none());
+    }
+
+    #[test]
+    fn forged_old_and_future_datagrams_are_rejected() {
+        let (a, b) = pair();
+        let mut datagram = a.seal(vec![]);
+        *datagram.last_mut().unwrap() ^= 1;
+        assert_eq!(b.open(&datagram).unwrap_err(), "bad signature");
+
+        let now = now_unix();
+        let envelope = |seq, sent_unix, observed_unix| Envelope {
+            node: "a".to_string(),
+            seq,
+            sent_unix,
+            reports: vec![report("a", observed_unix)],
+        };
+        let old = signed(&a, &envelope(1, now - MAX_SKEW_SECS - 1, now - 100));
+        assert!(b.open(&old).unwrap_err().starts_with("stale"));
+        let future = signed(&a, &envelope(2, now + 60, now));
+        assert!(b.open(&future).unwrap_err().contains("future"));
+        let ahead = signed(&a, &envelope(3, now, now + 60));
+        assert!(b.open(&ahead).unwrap_err().contains("observed after"));
+        assert!(b.open(&signed(&a, &envelope(4, now, now))).is_ok());
+    }
+
+    #[test]
+    fn merge_needs_strictly_newer_reports() {
+        let mut board = HealthBoard::default();
+        assert!(board.merge(report("a", 100)));
+        assert!(!board.merge(report("b", 100)));
+        assert!(!board.merge(report("b", 99)));
+        assert!(board.merge(report("b", 101)));
+        assert_eq!(board.reports["tor-1"].node, "b");
+
+        // A warm-start report gives way to any live one
+        board.reports.get_mut("tor-1").unwrap().stale = true;
+        assert!(board.merge(report("a", 50)));
+    }
+}
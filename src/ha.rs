@@ -0,0 +1,519 @@
+//! Active/standby pairs (`[ha]`): two dispatchers trade signed heartbeats
+//! over UDP, and the active one holds the pair's virtual address. When it
+//! goes quiet (or says it is leaving), the standby claims the address and
+//! serves on it with the same egress mode.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Socket, Type};
+
+use crate::config::HaConfig;
+use crate::stats::now_unix;
+
+/// Whether this build can move the virtual address (`ha` feature, Linux
+/// only). Heartbeats and roles work either way.
+pub const AVAILABLE: bool = cfg!(all(target_os = "linux", feature = "ha"));
+
+/// Heartbeats older than this (or from further in the future) are dropped.
+const MAX_SKEW_SECS: u64 = 60;
+
+/// Length of the HMAC-SHA256 tag at the front of every datagram.
+const TAG_LEN: usize = 32;
+
+/// Which half of the pair this daemon is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Standby,
+    Active,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Standby => "standby",
+            Role::Active => "active",
+        }
+    }
+}
+
+/// One daemon telling the other it is alive, and what it is doing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub node: String,
+    /// Counts up per sender; replays are dropped.
+    pub seq: u64,
+    pub sent_unix: u64,
+    pub priority: u8,
+    pub active: bool,
+    /// Shutting down: the standby need not wait out `dead_after`.
+    pub leaving: bool,
+    /// The sender's egress mode (`gold-dust-tor.flag`), for the standby to
+    /// follow.
+    pub mode: Option<String>,
+    /// Digest of the sender's policy: its config, less `[ha]`.
+    pub policy: String,
+}
+
+/// What `status` shows of the pair.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HaStatus {
+    pub role: Role,
+    pub priority: u8,
+    pub since_unix: u64,
+    pub peer: Option<PeerStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStatus {
+    pub priority: u8,
+    pub active: bool,
+    pub seen_unix: u64,
+}
+
+/// The last heartbeat from the peer.
+#[derive(Debug)]
+struct Peer {
+    node: String,
+    seq: u64,
+    priority: u8,
+    active: bool,
+    at: Instant,
+    seen_unix: u64,
+}
+
+/// This daemon's side of the pair: signs and checks heartbeats, and
+/// decides the role from what the peer says (or doesn't).
+#[derive(Debug)]
+pub struct Pair {
+    key: hmac::Key,
+    node: String,
+    priority: u8,
+    preempt: bool,
+    dead_after: Duration,
+    role: Role,
+    since_unix: u64,
+    seq: u64,
+    started: Instant,
+    peer: Option<Peer>,
+}
+
+impl Pair {
+    /// Start out active if we already hold the virtual address (after a
+    /// restart or upgrade), otherwise standby until the peer is heard or
+    /// has had `dead_after` to be.
+    pub fn new(cfg: &HaConfig, node: String, holding: bool, now: Instant) -> Result<Self, String> {
+        if cfg.secret.is_empty() {
+            return Err("[ha] secret must be set to authenticate the peer".to_string());
+        }
+        Ok(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, cfg.secret.as_bytes()),
+            node,
+            priority: cfg.priority,
+            preempt: cfg.preempt,
+            dead_after: cfg.dead_after(),
+            role: if holding { Role::Active } else { Role::Standby },
+            since_unix: now_unix(),
+            seq: 0,
+            started: now,
+            peer: None,
+        })
+    }
+
+    /// This daemon's id in heartbeats.
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Datagram announcing us: HMAC tag, then the JSON heartbeat.
+    pub fn seal(&mut self, mode: Option<String>, policy: &str, leaving: bool) -> Vec<u8> {
+        self.seq += 1;
+        let body = serde_json::to_vec(&Heartbeat {
+            node: self.node.clone(),
+            seq: self.seq,
+            sent_unix: now_unix(),
+            priority: if leaving { 0 } else { self.priority },
+            active: self.role == Role::Active && !leaving,
+            leaving,
+            mode,
+            policy: policy.to_string(),
+        })
+        .expect("heartbeat serializes");
+        let tag = hmac::sign(&self.key, &body);
+        let mut datagram = tag.as_ref().to_vec();
+        datagram.extend(body);
+        datagram
+    }
+
+    /// Check and decode a datagram. Forged, stale and replayed ones are
+    /// rejected; our own, echoed back, give `None`.
+    pub fn open(&self, datagram: &[u8]) -> Result<Option<Heartbeat>, String> {
+        if datagram.len() < TAG_LEN {
+            return Err("short datagram".to_string());
+        }
+        let (tag, body) = datagram.split_at(TAG_LEN);
+        hmac::verify(&self.key, body, tag).map_err(|_| "bad signature".to_string())?;
+
+        let heartbeat: Heartbeat = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+        if heartbeat.node == self.node {
+            return Ok(None);
+        }
+        if now_unix().abs_diff(heartbeat.sent_unix) > MAX_SKEW_SECS {
+            return Err(format!("stale heartbeat from {}", heartbeat.node));
+        }
+        if let Some(peer) = &self.peer {
+            if peer.node == heartbeat.node && heartbeat.seq <= peer.seq {
+                return Err(format!("replayed heartbeat from {}", heartbeat.node));
+            }
+        }
+        Ok(Some(heartbeat))
+    }
+
+    /// Take in the peer's heartbeat. Returns why the role changed, if it
+    /// did.
+    pub fn heard(&mut self, heartbeat: &Heartbeat, now: Instant) -> Option<String> {
+        self.peer = Some(Peer {
+            node: heartbeat.node.clone(),
+            seq: heartbeat.seq,
+            priority: heartbeat.priority,
+            active: heartbeat.active,
+            at: now,
+            seen_unix: now_unix(),
+        });
+        // Ties go to the higher node id, so exactly one side wins
+        let outranked = (heartbeat.priority, heartbeat.node.as_str()) > (self.priority, &self.node);
+        match self.role {
+            Role::Standby if heartbeat.leaving => self.become_(Role::Active, "the peer is leaving"),
+            Role::Standby if !heartbeat.active && !outranked => self.become_(
+                Role::Active,
+                "neither side is active and we outrank the peer",
+            ),
+            Role::Standby if heartbeat.active && !outranked && self.preempt => self.become_(
+                Role::Active,
+                &format!(
+                    "preempting the peer (priority {} over {})",
+                    self.priority, heartbeat.priority
+                ),
+            ),
+            Role::Active if heartbeat.active && outranked => self.become_(
+                Role::Standby,
+                &format!(
+                    "the peer is active with priority {} over our {}",
+                    heartbeat.priority, self.priority
+                ),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Called every interval: a standby whose peer has been silent for
+    /// `dead_after` takes over.
+    pub fn tick(&mut self, now: Instant) -> Option<String> {
+        if self.role == Role::Active {
+            return None;
+        }
+        let last = self.peer.as_ref().map_or(self.started, |p| p.at);
+        let silent = now.saturating_duration_since(last);
+        if silent < self.dead_after {
+            return None;
+        }
+        let reason = match &self.peer {
+            Some(_) => format!("the peer has been silent for {}ms", silent.as_millis()),
+            None => format!("no peer heard in {}ms", silent.as_millis()),
+        };
+        self.become_(Role::Active, &reason)
+    }
+
+    pub fn status(&self) -> HaStatus {
+        HaStatus {
+            role: self.role,
+            priority: self.priority,
+            since_unix: self.since_unix,
+            peer: self.peer.as_ref().map(|p| PeerStatus {
+                priority: p.priority,
+                active: p.active,
+                seen_unix: p.seen_unix,
+            }),
+        }
+    }
+
+    fn become_(&mut self, role: Role, reason: &str) -> Option<String> {
+        self.role = role;
+        self.since_unix = now_unix();
+        Some(reason.to_string())
+    }
+}
+
+/// Digest of a config's text less its `[ha]` table, which differs between
+/// the two halves of a pair by design. Equal digests mean equal policy.
+pub fn policy_digest(config_text: &str) -> String {
+    let mut table: toml::Table = match toml::from_str(config_text) {
+        Ok(table) => table,
+        Err(_) => return String::new(),
+    };
+    table.remove("ha");
+    let canonical = toml::to_string(&table).unwrap_or_default();
+    let digest = ring::digest::digest(&ring::digest::SHA256, canonical.as_bytes());
+    digest.as_ref()[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Is `ip` assigned to one of this host's interfaces? Binding to it only
+/// works then.
+pub fn holds(ip: Ipv4Addr) -> bool {
+    std::net::UdpSocket::bind((ip, 0)).is_ok()
+}
+
+/// Listen on `addr` whether or not this host holds it yet (`IP_FREEBIND`
+/// on Linux), so a standby serves the moment the address moves to it.
+pub fn listen(addr: SocketAddr) -> io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    #[cfg(target_os = "linux")]
+    match addr {
+        SocketAddr::V4(_) => socket.set_freebind_v4(true)?,
+        SocketAddr::V6(_) => socket.set_freebind_v6(true)?,
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+#[cfg(all(target_os = "linux", feature = "ha"))]
+pub use imp::VirtualAddress;
+
+/// Stand-in so callers need no `cfg`: never constructed.
+#[cfg(not(all(target_os = "linux", feature = "ha")))]
+pub struct VirtualAddress(());
+
+#[cfg(not(all(target_os = "linux", feature = "ha")))]
+impl VirtualAddress {
+    pub fn new(_ip: Ipv4Addr, _prefix: u8, _interface: &str) -> Result<Self, String> {
+        Err("moving the virtual address needs the `ha` feature (Linux only)".to_string())
+    }
+
+    pub fn ip(&self) -> Ipv4Addr {
+        Ipv4Addr::UNSPECIFIED
+    }
+
+    pub fn claim(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn release(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "ha"))]
+mod imp {
+    use std::ffi::CString;
+    use std::io;
+    use std::mem;
+    use std::net::Ipv4Addr;
+    use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+    use std::os::raw::c_void;
+
+    /// Gratuitous ARPs sent on claiming, for caches that miss the first.
+    const ANNOUNCEMENTS: usize = 3;
+
+    /// The pair's address on one interface, added and removed over
+    /// rtnetlink. Needs `CAP_NET_ADMIN` (and `CAP_NET_RAW` for the
+    /// announcements): root, with the sandbox's `user` unset.
+    #[derive(Debug)]
+    pub struct VirtualAddress {
+        ip: Ipv4Addr,
+        prefix: u8,
+        interface: String,
+        index: u32,
+        mac: [u8; 6],
+    }
+
+    impl VirtualAddress {
+        /// Look `interface` up: its index, and the Ethernet address the
+        /// announcements come from.
+        pub fn new(ip: Ipv4Addr, prefix: u8, interface: &str) -> Result<Self, String> {
+            let name = CString::new(interface).map_err(|e| format!("{interface}: {e}"))?;
+            // SAFETY: `name` is a valid C string
+            let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+            if index == 0 {
+                return Err(format!("{interface}: {}", io::Error::last_os_error()));
+            }
+            let socket = socket(libc::AF_INET, libc::SOCK_DGRAM, 0)
+                .map_err(|e| format!("{interface}: {e}"))?;
+            // SAFETY: a zeroed ifreq with the name filled in is what
+            // SIOCGIFHWADDR reads, and it writes the address back into it
+            let hwaddr = unsafe {
+                let mut req: libc::ifreq = mem::zeroed();
+                for (to, from) in req.ifr_name.iter_mut().zip(name.as_bytes_with_nul()) {
+                    *to = *from as libc::c_char;
+                }
+                let fd = std::os::fd::AsRawFd::as_raw_fd(&socket);
+                check(libc::ioctl(fd, libc::SIOCGIFHWADDR, &mut req) as isize)
+                    .map_err(|e| format!("{interface}: {e}"))?;
+                req.ifr_ifru.ifru_hwaddr
+            };
+            if hwaddr.sa_family != libc::ARPHRD_ETHER {
+                return Err(format!("{interface} is not an Ethernet interface"));
+            }
+            let mut mac = [0u8; 6];
+            for (to, from) in mac.iter_mut().zip(hwaddr.sa_data) {
+                *to = from as u8;
+            }
+            Ok(Self {
+                ip,
+                prefix,
+                interface: interface.to_string(),
+                index,
+                mac,
+            })
+        }
+
+        pub fn ip(&self) -> Ipv4Addr {
+            self.ip
+        }
+
+        /// Add the address (if it isn't there yet) and announce it, so the
+        /// LAN sends its traffic here from now on.
+        pub fn claim(&self) -> Result<(), String> {
+            match self.netlink(libc::RTM_NEWADDR, libc::NLM_F_CREATE | libc::NLM_F_EXCL) {
+                Ok(()) => {}
+                Err(e) if e.raw_os_error() == Some(libc::EEXIST) => {}
+                Err(e) => return Err(format!("adding {}: {e}", self.describe())),
+            }
+            for _ in 0..ANNOUNCEMENTS {
+                self.announce()
+                    .map_err(|e| format!("announcing {}: {e}", self.describe()))?;
+            }
+            Ok(())
+        }
+
+        /// Remove the address, if it is there.
+        pub fn release(&self) -> Result<(), String> {
+            match self.netlink(libc::RTM_DELADDR, 0) {
+                Ok(()) => Ok(()),
+                Err(e) if e.raw_os_error() == Some(libc::EADDRNOTAVAIL) => Ok(()),
+                Err(e) => Err(format!("removing {}: {e}", self.describe())),
+            }
+        }
+
+        fn describe(&self) -> String {
+            format!("{}/{} on {}", self.ip, self.prefix, self.interface)
+        }
+
+        /// Send one address request and wait for the kernel's answer.
+        fn netlink(&self, kind: u16, flags: i32) -> io::Result<()> {
+            let socket = socket(libc::AF_NETLINK, libc::SOCK_RAW, libc::NETLINK_ROUTE)?;
+            let fd = std::os::fd::AsRawFd::as_raw_fd(&socket);
+
+            // nlmsghdr, ifaddrmsg, then IFA_LOCAL and IFA_ADDRESS
+            let attr = |kind: u16, ip: Ipv4Addr| {
+                let mut attr = Vec::with_capacity(8);
+                attr.extend(8u16.to_ne_bytes());
+                attr.extend(kind.to_ne_bytes());
+                attr.extend(ip.octets());
+                attr
+            };
+            let mut body = vec![libc::AF_INET as u8, self.prefix, 0, libc::RT_SCOPE_UNIVERSE];
+            body.extend(self.index.to_ne_bytes());
+            body.extend(attr(libc::IFA_LOCAL, self.ip));
+            body.extend(attr(libc::IFA_ADDRESS, self.ip));
+            let mut msg = Vec::with_capacity(16 + body.len());
+            msg.extend((16 + body.len() as u32).to_ne_bytes());
+            msg.extend(kind.to_ne_bytes());
+            msg.extend(((libc::NLM_F_REQUEST | libc::NLM_F_ACK | flags) as u16).to_ne_bytes());
+            msg.extend(1u32.to_ne_bytes());
+            msg.extend(0u32.to_ne_bytes());
+            msg.extend(body);
+
+            // SAFETY: a zeroed sockaddr_nl addresses the kernel
+            let mut kernel: libc::sockaddr_nl = unsafe { mem::zeroed() };
+            kernel.nl_family = libc::AF_NETLINK as u16;
+            check(unsafe {
+                libc::sendto(
+                    fd,
+                    msg.as_ptr() as *const c_void,
+                    msg.len(),
+                    0,
+                    &kernel as *const _ as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_nl>() as u32,
+                )
+            })?;
+
+            // The ack: nlmsghdr, then the errno (0 for success), negated
+            let mut ack = [0u8; 1024];
+            // SAFETY: `ack` is writable for its length
+            let n =
+                check(unsafe { libc::recv(fd, ack.as_mut_ptr() as *mut c_void, ack.len(), 0) })?;
+            if n < 20 || u16::from_ne_bytes([ack[4], ack[5]]) != libc::NLMSG_ERROR as u16 {
+                return Err(io::Error::other("unexpected rtnetlink reply"));
+            }
+            match i32::from_ne_bytes([ack[16], ack[17], ack[18], ack[19]]) {
+                0 => Ok(()),
+                errno => Err(io::Error::from_raw_os_error(-errno)),
+            }
+        }
+
+        /// Broadcast a gratuitous ARP request for the address.
+        fn announce(&self) -> io::Result<()> {
+            let arp = (libc::ETH_P_ARP as u16).to_be();
+            let socket = socket(libc::AF_PACKET, libc::SOCK_DGRAM, i32::from(arp))?;
+            let fd = std::os::fd::AsRawFd::as_raw_fd(&socket);
+
+            let mut frame = Vec::with_capacity(28);
+            frame.extend(1u16.to_be_bytes()); // Ethernet
+            frame.extend(0x0800u16.to_be_bytes()); // IPv4
+            frame.extend([6, 4]);
+            frame.extend(1u16.to_be_bytes()); // request
+            frame.extend(self.mac);
+            frame.extend(self.ip.octets());
+            frame.extend([0u8; 6]);
+            frame.extend(self.ip.octets());
+
+            // SAFETY: zeroed, then filled in as packet(7) describes
+            let mut to: libc::sockaddr_ll = unsafe { mem::zeroed() };
+            to.sll_family = libc::AF_PACKET as u16;
+            to.sll_protocol = arp;
+            to.sll_ifindex = self.index as i32;
+            to.sll_halen = 6;
+            to.sll_addr[..6].copy_from_slice(&[0xff; 6]);
+            check(unsafe {
+                libc::sendto(
+                    fd,
+                    frame.as_ptr() as *const c_void,
+                    frame.len(),
+                    0,
+                    &to as *const _ as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_ll>() as u32,
+                )
+            })
+            .map(drop)
+        }
+    }
+
+    fn socket(domain: i32, kind: i32, protocol: i32) -> io::Result<OwnedFd> {
+        // SAFETY: a fresh descriptor, owned from here
+        let fd =
+            check(unsafe { libc::socket(domain, kind | libc::SOCK_CLOEXEC, protocol) } as isize)?
+                as RawFd;
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// A syscall's result, or the error it set.
+    fn check(ret: isize) -> io::Result<usize> {
+        match ret {
+            n if n < 0 => Err(io::Error::last_os_error()),
+            n => Ok(n as usize),
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! Zero-downtime upgrades: a new dispatcher takes the listening sockets of
+//! the one running in its directory over a unix socket (`SCM_RIGHTS`), and
+//! the old one drains its sessions and exits. Clients never see a refused
+//! connection, since the sockets never close.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Unix socket a running dispatcher offers its listeners on.
+pub const HANDOVER_PATH: &str = "gold-dust-handover.sock";
+
+/// Whether this build can hand sockets over (`handover` feature, Linux
+/// only).
+pub const AVAILABLE: bool = cfg!(all(target_os = "linux", feature = "handover"));
+
+/// First line a successor sends, naming the protocol version.
+pub const REQUEST: &str = "gold-dust-handover 1";
+
+/// Most sockets one handover carries.
+pub const MAX_SOCKETS: usize = 16;
+
+/// What a handed-over socket is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Tcp,
+    Udp,
+    Unix,
+}
+
+/// A socket taken over from the previous instance, still bound and
+/// listening.
+#[derive(Debug)]
+pub enum Inherited {
+    Tcp(std::net::TcpListener),
+    Udp(std::net::UdpSocket),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+/// What a successor is offered sockets on: a unix socket.
+#[cfg(unix)]
+pub use std::os::unix::net::UnixListener as Listener;
+
+/// Stand-in so callers need no `cfg`: there are no unix sockets here.
+#[cfg(not(unix))]
+#[derive(Debug)]
+pub enum Listener {}
+
+/// A copy of a serving socket, to hand over.
+#[cfg(unix)]
+pub type Socket = std::os::fd::OwnedFd;
+
+/// A copy of a serving socket, to hand over.
+#[cfg(windows)]
+pub type Socket = std::os::windows::io::OwnedSocket;
+
+/// Bytes the old instance relayed while draining, after the new one had
+/// loaded the ledgers: by egress, and by `[users]` key.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tail {
+    pub egress: BTreeMap<String, u64>,
+    pub users: BTreeMap<String, u64>,
+}
+
+impl Tail {
+    /// What `self` counts beyond `baseline`.
+    pub fn since(&self, baseline: &Tail) -> Tail {
+        let delta = |now: &BTreeMap<String, u64>, then: &BTreeMap<String, u64>| {
+            now.iter()
+                .map(|(k, &v)| {
+                    (
+                        k.clone(),
+                        v.saturating_sub(then.get(k).copied().unwrap_or(0)),
+                    )
+                })
+                .filter(|&(_, v)| v > 0)
+                .collect()
+        };
+        Tail {
+            egress: delta(&self.egress, &baseline.egress),
+            users: delta(&self.users, &baseline.users),
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "handover"))]
+pub use imp::{listen, request, Offer, Predecessor, Successor};
+
+#[cfg(not(all(target_os = "linux", feature = "handover")))]
+const UNSUPPORTED: &str = "built without the `handover` feature (Linux only)";
+
+/// Stand-in so callers need no `cfg`: never constructed.
+#[cfg(not(all(target_os = "linux", feature = "handover")))]
+pub struct Offer(());
+
+/// Stand-in so callers need no `cfg`: never constructed.
+#[cfg(not(all(target_os = "linux", feature = "handover")))]
+pub struct Successor(());
+
+/// Stand-in so callers need no `cfg`: never constructed.
+#[cfg(not(all(target_os = "linux", feature = "handover")))]
+pub struct Predecessor(());
+
+#[cfg(not(all(target_os = "linux", feature = "handover")))]
+pub fn listen(_path: &str) -> std::io::Result<Listener> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        UNSUPPORTED,
+    ))
+}
+
+#[cfg(not(all(target_os = "linux", feature = "handover")))]
+pub fn request(_path: &str) -> Result<(Vec<Inherited>, Predecessor), String> {
+    Err(UNSUPPORTED.to_string())
+}
+
+#[cfg(not(all(target_os = "linux", feature = "handover")))]
+impl Offer {
+    pub fn from_std(_listener: Listener) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            UNSUPPORTED,
+        ))
+    }
+
+    pub async fn next(&self) -> std::io::Result<Successor> {
+        std::future::pending().await
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "handover")))]
+impl Successor {
+    pub async fn hand_over(&mut self, _sockets: &[(Kind, &Socket)]) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            UNSUPPORTED,
+        ))
+    }
+
+    pub async fn finish(self, _tail: &Tail) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "handover")))]
+impl Predecessor {
+    pub async fn tail(self) -> std::io::Result<Tail> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            UNSUPPORTED,
+        ))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "handover"))]
+mod imp {
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::mem;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::ptr;
+
+    use serde::{Deserialize, Serialize};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, Interest};
+
+    use super::{Inherited, Kind, Tail, MAX_SOCKETS, REQUEST};
+
+    /// Sent alongside the descriptors, in their order.
+    #[derive(Serialize, Deserialize)]
+    struct Header {
+        sockets: Vec<Kind>,
+    }
+
+    /// Bytes of header a handover carries at most.
+    const HEADER_MAX: usize = 1024;
+
+    /// Listen on `path` for a successor. Only the owner (and root) may
+    /// connect: whoever does gets every listener.
+    pub fn listen(path: &str) -> io::Result<UnixListener> {
+        // Ours from an earlier run, or our predecessor's, already used
+        if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    }
+
+    /// Where successors knock.
+    pub struct Offer(tokio::net::UnixListener);
+
+    impl Offer {
+        pub fn from_std(listener: UnixListener) -> io::Result<Self> {
+            tokio::net::UnixListener::from_std(listener).map(Self)
+        }
+
+        /// The next one to connect, if it asks properly; anyone else is
+        /// hung up on, with the reason returned.
+        pub async fn next(&self) -> io::Result<Successor> {
+            let (stream, _) = self.0.accept().await?;
+            check(stream).await
+        }
+    }
+
+    /// Is the peer us (or root), and does it speak our protocol?
+    async fn check(stream: tokio::net::UnixStream) -> io::Result<Successor> {
+        let uid = stream.peer_cred()?.uid();
+        // SAFETY: getuid cannot fail
+        let own = unsafe { libc::getuid() };
+        if uid != own && uid != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("uid {uid} is neither us ({own}) nor root"),
+            ));
+        }
+        let mut stream = tokio::io::BufReader::new(stream);
+        let mut line = String::new();
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            stream.read_line(&mut line),
+        )
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if line.trim_end() != REQUEST {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected {REQUEST:?}, got {:?}", line.trim_end()),
+            ));
+        }
+        Ok(Successor(stream.into_inner()))
+    }
+
+    /// A new instance taking over from this one.
+    pub struct Successor(tokio::net::UnixStream);
+
+    impl Successor {
+        /// Send `sockets` across. They stay open here too until dropped.
+        pub async fn hand_over(&mut self, sockets: &[(Kind, &OwnedFd)]) -> io::Result<()> {
+            let header = Header {
+                sockets: sockets.iter().map(|&(kind, _)| kind).collect(),
+            };
+            let mut header = serde_json::to_vec(&header)?;
+            header.push(b'\n');
+            let fds: Vec<RawFd> = sockets.iter().map(|(_, fd)| fd.as_raw_fd()).collect();
+            self.0
+                .async_io(Interest::WRITABLE, || {
+                    send_fds(self.0.as_raw_fd(), &header, &fds)
+                })
+                .await
+        }
+
+        /// Report what was relayed since the handover, once drained.
+        pub async fn finish(mut self, tail: &Tail) -> io::Result<()> {
+            let mut line = serde_json::to_vec(tail)?;
+            line.push(b'\n');
+            self.0.write_all(&line).await?;
+            self.0.shutdown().await
+        }
+    }
+
+    /// The instance we took over from, draining until it reports its tail.
+    pub struct Predecessor(UnixStream);
+
+    impl Predecessor {
+        /// Wait for the old instance to finish draining and report what it
+        /// relayed meanwhile. Fails if it exits without saying.
+        pub async fn tail(self) -> io::Result<Tail> {
+            self.0.set_nonblocking(true)?;
+            let stream = tokio::net::UnixStream::from_std(self.0)?;
+            let mut line = String::new();
+            tokio::io::BufReader::new(stream)
+                .read_line(&mut line)
+                .await?;
+            if line.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(serde_json::from_str(&line)?)
+        }
+    }
+
+    /// Ask the instance listening on `path` for its sockets.
+    pub fn request(path: &str) -> Result<(Vec<Inherited>, Predecessor), String> {
+        let mut stream = UnixStream::connect(path)
+            .map_err(|e| format!("no running instance to take over from ({path}: {e})"))?;
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(30)))
+            .and_then(|()| writeln!(stream, "{REQUEST}"))
+            .map_err(|e| format!("{path}: {e}"))?;
+        let (header, fds) =
+            recv_fds(stream.as_raw_fd()).map_err(|e| format!("{path}: receiving sockets: {e}"))?;
+        let header: Header = BufReader::new(header.as_slice())
+            .lines()
+            .next()
+            .and_then(|line| line.ok())
+            .and_then(|line| serde_json::from_str(&line).ok())
+            .ok_or_else(|| format!("{path}: malformed handover header"))?;
+        if header.sockets.len() != fds.len() {
+            return Err(format!(
+                "{path}: {} socket(s) announced, {} received",
+                header.sockets.len(),
+                fds.len()
+            ));
+        }
+        stream
+            .set_read_timeout(None)
+            .map_err(|e| format!("{path}: {e}"))?;
+        let sockets = header
+            .sockets
+            .into_iter()
+            .zip(fds)
+            .map(|(kind, fd)| match kind {
+                Kind::Tcp => Inherited::Tcp(fd.into()),
+                Kind::Udp => Inherited::Udp(fd.into()),
+                Kind::Unix => Inherited::Unix(fd.into()),
+            })
+            .collect();
+        Ok((sockets, Predecessor(stream)))
+    }
+
+    /// Room for `MAX_SOCKETS` descriptors in one control message.
+    fn cmsg_space() -> usize {
+        // SAFETY: pure arithmetic
+        unsafe { libc::CMSG_SPACE((MAX_SOCKETS * mem::size_of::<RawFd>()) as u32) as usize }
+    }
+
+    fn send_fds(socket: RawFd, data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+        if fds.len() > MAX_SOCKETS || data.len() > HEADER_MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many sockets to hand over",
+            ));
+        }
+        let mut control = vec![0u8; cmsg_space()];
+        let mut iov = libc::iovec {
+            iov_base: data.as_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        // SAFETY: every pointer in `msg` outlives the call, and the control
+        // message is written within the buffer CMSG_SPACE sized
+        unsafe {
+            let mut msg: libc::msghdr = mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            if !fds.is_empty() {
+                let len = mem::size_of_val(fds);
+                msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+                msg.msg_controllen = libc::CMSG_SPACE(len as u32) as usize;
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(len as u32) as usize;
+                ptr::copy_nonoverlapping(
+                    fds.as_ptr(),
+                    libc::CMSG_DATA(cmsg) as *mut RawFd,
+                    fds.len(),
+                );
+            }
+            match libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL) {
+                n if n < 0 => Err(io::Error::last_os_error()),
+                n if (n as usize) < data.len() => Err(io::ErrorKind::WriteZero.into()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    fn recv_fds(socket: RawFd) -> io::Result<(Vec<u8>, Vec<OwnedFd>)> {
+        let mut data = vec![0u8; HEADER_MAX];
+        let mut control = vec![0u8; cmsg_space()];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let mut fds = Vec::new();
+        // SAFETY: as in `send_fds`; descriptors the kernel installed are
+        // owned from here on, whatever else goes wrong
+        let n = unsafe {
+            let mut msg: libc::msghdr = mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = control.len();
+            let n = libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC);
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let bytes = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                    let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                    for i in 0..bytes / mem::size_of::<RawFd>() {
+                        fds.push(OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(i))));
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+            if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "more sockets than one handover carries",
+                ));
+            }
+            n as usize
+        };
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        data.truncate(n);
+        Ok((data, fds))
+    }
+}
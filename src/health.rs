@@ -0,0 +1,314 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::config::GoldDustConfig;
+use crate::discovery::ServiceNode;
+use crate::router::{BackendHealth, BackendKind, HealthUpdate};
+use crate::socks;
+use crate::target::Target;
+
+/// Where the router gets backend health from.
+///
+/// The router only ever sees snapshots, so policies can be exercised against
+/// [`InMemoryHealth`] without touching the network.
+pub trait HealthSource {
+    /// Current health of every backend this source knows about.
+    fn snapshot(&mut self) -> Vec<BackendHealth>;
+}
+
+/// Simulated Oxen/Tor (and the HTTP/3 relay, if configured) backends driven by the
+/// config enable flags.
+#[derive(Debug, Clone)]
+pub struct StaticHealth {
+    backends: Vec<BackendHealth>,
+}
+
+impl StaticHealth {
+    /// Build the demo backend set from config flags (oxen_enabled / tor_enabled).
+    pub fn from_config(config: &GoldDustConfig) -> Self {
+        let mut backends = Vec::new();
+
+        if config.backends.oxen_enabled {
+            backends.push(BackendHealth {
+                name: "oxen-node-1".to_string(),
+                kind: BackendKind::Oxen,
+                latency_ms: 60.0,
+                failure_rate: 0.02,
+                enabled: true,
+                ipv6: false,
+            });
+            backends.push(BackendHealth {
+                name: "oxen-node-2".to_string(),
+                kind: BackendKind::Oxen,
+                latency_ms: 70.0,
+                failure_rate: 0.03,
+                enabled: true,
+                ipv6: true,
+            });
+        }
+
+        if config.backends.tor_enabled {
+            backends.push(BackendHealth {
+                name: "tor-exit-1".to_string(),
+                kind: BackendKind::Tor,
+                latency_ms: 250.0,
+                failure_rate: 0.01,
+                enabled: true,
+                ipv6: true,
+            });
+        }
+
+        if config.backends.masque.is_some() {
+            backends.push(BackendHealth {
+                name: "masque-relay".to_string(),
+                kind: BackendKind::Masque,
+                latency_ms: 120.0,
+                failure_rate: 0.02,
+                enabled: true,
+                ipv6: true,
+            });
+        }
+
+        Self { backends }
+    }
+}
+
+impl HealthSource for StaticHealth {
+    fn snapshot(&mut self) -> Vec<BackendHealth> {
+        self.backends.clone()
+    }
+}
+
+/// Fixed, hand-editable health for tests and embedders.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryHealth {
+    backends: Vec<BackendHealth>,
+}
+
+impl InMemoryHealth {
+    pub fn new(backends: Vec<BackendHealth>) -> Self {
+        Self { backends }
+    }
+
+    /// Apply a partial update. Returns `false` if no backend has that name.
+    pub fn apply(&mut self, update: &HealthUpdate) -> bool {
+        match self.backends.iter_mut().find(|b| b.name == update.backend) {
+            Some(b) => {
+                update.apply_to(b);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl HealthSource for InMemoryHealth {
+    fn snapshot(&mut self) -> Vec<BackendHealth> {
+        self.backends.clone()
+    }
+}
+
+/// One endpoint for [`TcpProber`] to measure.
+#[derive(Debug, Clone)]
+pub struct ProbeTarget {
+    pub name: String,
+    pub kind: BackendKind,
+    pub addr: SocketAddr,
+    /// Whether traffic through this backend can reach IPv6 destinations.
+    pub ipv6: bool,
+    /// Endpoints reached through the backend (`[probes]`): over SOCKS at
+    /// `addr` for Tor, over the system's routes for lokinet. Empty: just
+    /// connect to `addr`.
+    pub references: Vec<Target>,
+    pub timeout: Duration,
+    /// A refused connection counts as reached: the refusal came back from
+    /// the far end (a lokinet exit answering for itself).
+    pub refused_is_up: bool,
+}
+
+/// Real prober: measures TCP connect latency and failure rate per backend.
+#[derive(Debug, Clone)]
+pub struct TcpProber {
+    targets: Vec<ProbeTarget>,
+    attempts: u32,
+}
+
+/// Connect timeout for a local daemon's port.
+const DAEMON_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl TcpProber {
+    pub fn new(targets: Vec<ProbeTarget>) -> Self {
+        Self {
+            targets,
+            attempts: 3,
+        }
+    }
+
+    /// Probe the local Tor SOCKS port and lokinet RPC port, per enable flags,
+    /// or the `[probes]` targets through them, plus each lokinet exit.
+    /// (The HTTP/3 relay speaks QUIC, so a TCP connect says nothing about it.)
+    pub fn local_daemons(config: &GoldDustConfig) -> Self {
+        let target = |name: &str, kind: BackendKind, addr: SocketAddr, ipv6: bool| {
+            let probes = config.probes_for(kind);
+            ProbeTarget {
+                name: name.to_string(),
+                kind,
+                addr,
+                ipv6,
+                timeout: if probes.targets.is_empty() {
+                    DAEMON_TIMEOUT
+                } else {
+                    Duration::from_secs(probes.timeout_secs)
+                },
+                references: probes.targets,
+                refused_is_up: false,
+            }
+        };
+        let mut targets = Vec::new();
+        if config.backends.oxen_enabled {
+            targets.push(target(
+                "lokinet-local",
+                BackendKind::Oxen,
+                config.lokinet.rpc,
+                false,
+            ));
+            // Each exit on its own: a dead exit isn't a dead lokinet
+            for exit in &config.lokinet.exits {
+                let Ok(reference) = format!("{}:{}", exit.address, exit.probe_port).parse() else {
+                    continue;
+                };
+                targets.push(ProbeTarget {
+                    name: exit.address.clone(),
+                    kind: BackendKind::Oxen,
+                    addr: config.lokinet.rpc,
+                    ipv6: false,
+                    references: vec![reference],
+                    timeout: Duration::from_secs(config.probes_for(BackendKind::Oxen).timeout_secs),
+                    refused_is_up: true,
+                });
+            }
+        }
+        if config.backends.tor_enabled {
+            targets.push(target(
+                "tor-local",
+                BackendKind::Tor,
+                config.backends.tor_socks,
+                true,
+            ));
+        }
+        Self::new(targets)
+    }
+
+    /// Also measure discovered service nodes, by connecting to each one's
+    /// storage server port.
+    pub fn with_service_nodes(mut self, nodes: &[ServiceNode]) -> Self {
+        self.targets.extend(nodes.iter().map(|node| ProbeTarget {
+            name: node.name(),
+            kind: BackendKind::Oxen,
+            addr: node.address,
+            ipv6: false,
+            references: Vec::new(),
+            timeout: DAEMON_TIMEOUT,
+            refused_is_up: false,
+        }));
+        self
+    }
+
+    /// What this prober measures.
+    pub fn targets(&self) -> &[ProbeTarget] {
+        &self.targets
+    }
+
+    /// Probe one target (blocking: up to `attempts` connects, or one per
+    /// reference if there are more, taking turns).
+    pub fn probe(&self, target: &ProbeTarget) -> BackendHealth {
+        let attempts = self.attempts.max(target.references.len() as u32);
+        let mut failures = 0;
+        let mut total_ms = 0.0;
+
+        for i in 0..attempts {
+            let started = Instant::now();
+            let connected = match target.references.as_slice() {
+                [] => TcpStream::connect_timeout(&target.addr, target.timeout).map(drop),
+                references => {
+                    let reference = &references[i as usize % references.len()];
+                    match target.kind {
+                        BackendKind::Tor => socks_connect(target.addr, reference, target.timeout),
+                        _ => direct_connect(reference, target.timeout),
+                    }
+                }
+            };
+            let connected = match connected {
+                Err(e) if target.refused_is_up && e.kind() == io::ErrorKind::ConnectionRefused => {
+                    Ok(())
+                }
+                connected => connected,
+            };
+            match connected {
+                Ok(()) => total_ms += started.elapsed().as_secs_f64() * 1000.0,
+                Err(_) => failures += 1,
+            }
+        }
+
+        let successes = attempts - failures;
+        BackendHealth {
+            name: target.name.clone(),
+            kind: target.kind,
+            latency_ms: if successes > 0 {
+                total_ms / successes as f64
+            } else {
+                target.timeout.as_secs_f64() * 1000.0
+            },
+            failure_rate: failures as f64 / attempts as f64,
+            enabled: successes > 0,
+            ipv6: target.ipv6,
+        }
+    }
+}
+
+/// Open a stream to `to` through the SOCKS5 proxy at `proxy` (no auth). The
+/// proxy resolves names, so `.onion` works.
+fn socks_connect(proxy: SocketAddr, to: &Target, timeout: Duration) -> io::Result<()> {
+    let host = to.host_str();
+    let len = u8::try_from(host.len()).map_err(|_| io::Error::other("host name too long"))?;
+    let mut stream = TcpStream::connect_timeout(&proxy, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    stream.write_all(&[socks::VERSION, 1, 0])?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method)?;
+    if method != [socks::VERSION, 0] {
+        return Err(io::Error::other("proxy wants authentication"));
+    }
+    let mut request = vec![socks::VERSION, socks::CMD_CONNECT, 0, 3, len];
+    request.extend(host.as_bytes());
+    request.extend(to.port.to_be_bytes());
+    stream.write_all(&request)?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    match reply[1] {
+        socks::SUCCEEDED => Ok(()),
+        code => Err(io::Error::other(format!("SOCKS reply {code}"))),
+    }
+}
+
+/// Open a TCP connection to `to` the way the system routes it (lokinet
+/// resolves and carries `.loki` names).
+fn direct_connect(to: &Target, timeout: Duration) -> io::Result<()> {
+    let mut last = io::Error::other("no address");
+    for addr in (to.host_str().as_str(), to.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(()),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+impl HealthSource for TcpProber {
+    fn snapshot(&mut self) -> Vec<BackendHealth> {
+        self.targets.iter().map(|t| self.probe(t)).collect()
+    }
+}
@@ -0,0 +1,265 @@
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::{Arc, OnceLock};
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+use crate::pinning::{self, SpkiPin};
+use crate::target::{Host, Target};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Parsed `http(s)://host[:port]/path` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("`{url}` is not an http:// or https:// URL"));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let target: Target = format!("{}://{}", if tls { "https" } else { "http" }, authority)
+            .parse()
+            .map_err(|e| format!("bad URL `{url}`: {e}"))?;
+        Ok(Self {
+            tls,
+            host: target.host_str(),
+            port: target.port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Host and port as a dial target.
+    pub fn target(&self) -> Target {
+        let host = match self.host.parse::<IpAddr>() {
+            Ok(ip) => Host::from(ip),
+            Err(_) => Host::Domain(self.host.clone()),
+        };
+        Target::new(host, self.port)
+    }
+
+    /// `Host:` header value.
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match (self.tls, self.port) {
+            (true, 443) | (false, 80) => host,
+            _ => format!("{}:{}", host, self.port),
+        }
+    }
+}
+
+/// Minimal HTTP/1.1 response.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Shared rustls client config with the Mozilla root set.
+pub fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            Arc::new(
+                ClientConfig::builder_with_provider(Arc::new(
+                    rustls::crypto::ring::default_provider(),
+                ))
+                .with_safe_default_protocol_versions()
+                .expect("ring supports the default TLS versions")
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+            )
+        })
+        .clone()
+}
+
+/// Wrap an established stream in TLS, verifying `host`.
+pub async fn tls_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    host: &str,
+) -> Result<TlsStream<S>, BoxError> {
+    let name = ServerName::try_from(host.to_string())?;
+    Ok(TlsConnector::from(tls_config())
+        .connect(name, stream)
+        .await?)
+}
+
+/// Send one request over `stream` (with `Connection: close`) and read the reply.
+pub async fn send<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response, BoxError> {
+    let mut req = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        url.path,
+        url.authority(),
+        body.len()
+    );
+    for (k, v) in headers {
+        req.push_str(&format!("{k}: {v}\r\n"));
+    }
+    req.push_str("\r\n");
+
+    stream.write_all(req.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await?;
+
+    let mut raw = Vec::new();
+    // Servers often skip TLS close_notify; keep whatever arrived.
+    if let Err(e) = stream.read_to_end(&mut raw).await {
+        if raw.is_empty() {
+            return Err(e.into());
+        }
+    }
+    parse_response(&raw)
+}
+
+/// Connect to `url` directly (TLS if https) and send one request.
+pub async fn request(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response, BoxError> {
+    let tcp = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    request_over(tcp, method, url, headers, body).await
+}
+
+/// Like [`request`], but an https server must hold one of `pins`' keys
+/// instead of a CA-issued certificate. No pins: the usual CA checks.
+pub async fn request_pinned(
+    method: &str,
+    url: &Url,
+    pins: &[SpkiPin],
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response, BoxError> {
+    if pins.is_empty() || !url.tls {
+        return request(method, url, headers, body).await;
+    }
+    let tcp = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    let name = ServerName::try_from(url.host.clone())?;
+    let tls = TlsConnector::from(pinning::tls_config(pins))
+        .connect(name, tcp)
+        .await?;
+    send(tls, method, url, headers, body).await
+}
+
+/// Send one request over an already-connected stream (e.g. a SOCKS tunnel),
+/// adding TLS if the URL is https.
+pub async fn request_over<S: AsyncRead + AsyncWrite + Unpin>(
+    tcp: S,
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response, BoxError> {
+    if url.tls {
+        send(
+            tls_connect(tcp, &url.host).await?,
+            method,
+            url,
+            headers,
+            body,
+        )
+        .await
+    } else {
+        send(tcp, method, url, headers, body).await
+    }
+}
+
+fn parse_response(raw: &[u8]) -> Result<Response, BoxError> {
+    let split = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("truncated HTTP response")?;
+    let head = String::from_utf8_lossy(&raw[..split]);
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse().ok())
+        .ok_or("bad HTTP status line")?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect();
+
+    let mut response = Response {
+        status,
+        headers,
+        body: raw[split + 4..].to_vec(),
+    };
+    if response
+        .header("transfer-encoding")
+        .is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+    {
+        response.body = dechunk(&response.body)?;
+    } else if let Some(len) = response
+        .header("content-length")
+        .and_then(|v| v.parse().ok())
+    {
+        response.body.truncate(len);
+    }
+    Ok(response)
+}
+
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, BoxError> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or("truncated chunk")?;
+        let size_str = String::from_utf8_lossy(&body[..line_end]);
+        let size = usize::from_str_radix(size_str.split(';').next().unwrap_or("").trim(), 16)?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        if body.len() < size {
+            return Err("truncated chunk".into());
+        }
+        out.extend_from_slice(&body[..size]);
+        body = body.get(size + 2..).unwrap_or(&[]);
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use crate::matcher::Pattern;
+
+/// Rule-set format `rules import` reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Clash rules (`DOMAIN-SUFFIX,example.com,PROXY`), as a plain list or a
+    /// rule provider's `payload:`, including `domain` and `ipcidr` providers.
+    Clash,
+    /// v2ray domain lists (`domain:example.com`, `full:`, bare names).
+    V2ray,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "clash" => Ok(Format::Clash),
+            "v2ray" => Ok(Format::V2ray),
+            other => Err(format!("unknown format `{other}` (clash, v2ray)")),
+        }
+    }
+}
+
+/// One converted entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Imported {
+    /// 1-based line in the source, and its text.
+    pub line: usize,
+    pub text: String,
+    /// Host pattern, as `[[rules]] host` takes it.
+    pub host: String,
+    /// Clash policy (`PROXY`, `DIRECT`, ...), when the line names one.
+    pub policy: Option<String>,
+}
+
+/// A line with no gold-dust equivalent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    pub line: usize,
+    pub text: String,
+    pub reason: String,
+}
+
+/// What a rule set converts to, in source order.
+#[derive(Debug, Clone, Default)]
+pub struct Import {
+    pub rules: Vec<Imported>,
+    pub skipped: Vec<Skipped>,
+}
+
+/// Convert `text` line by line. Blank lines and comments are dropped, later
+/// duplicates of a host are skipped, and every pattern is checked as the
+/// config would check it.
+pub fn import(text: &str, format: Format) -> Import {
+    let mut out = Import::default();
+    let mut seen = HashSet::new();
+    for (i, raw) in text.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+        let converted = match format {
+            Format::Clash => clash(line),
+            Format::V2ray => v2ray(line),
+        };
+        let skip = |reason: String| Skipped {
+            line: i + 1,
+            text: line.to_string(),
+            reason,
+        };
+        let (host, policy) = match converted {
+            Ok(Some(found)) => found,
+            Ok(None) => continue,
+            Err(reason) => {
+                out.skipped.push(skip(reason));
+                continue;
+            }
+        };
+        let host = match host.parse::<Pattern>() {
+            Ok(pattern) => pattern.to_string(),
+            Err(e) => {
+                out.skipped.push(skip(e));
+                continue;
+            }
+        };
+        if !seen.insert(host.clone()) {
+            out.skipped.push(skip(format!("`{host}` already imported")));
+            continue;
+        }
+        out.rules.push(Imported {
+            line: i + 1,
+            text: line.to_string(),
+            host,
+            policy,
+        });
+    }
+    out
+}
+
+type Converted = Result<Option<(String, Option<String>)>, String>;
+
+/// A Clash rule, or a rule-provider payload entry.
+fn clash(line: &str) -> Converted {
+    if line == "payload:" {
+        return Ok(None);
+    }
+    // Payload entries: `- DOMAIN,example.com`, `- '+.example.com'`
+    let line = match line.strip_prefix('-') {
+        Some(entry) => entry.trim().trim_matches(|c| c == '\'' || c == '"'),
+        None => line,
+    };
+    let mut fields = line.split(',').map(str::trim);
+    let kind = fields.next().unwrap_or_default();
+    let mut value = || {
+        fields
+            .next()
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| format!("{kind} without a value"))
+    };
+    let host = match kind {
+        "DOMAIN" => value()?.to_string(),
+        "DOMAIN-SUFFIX" => format!(".{}", value()?),
+        "IP-CIDR" | "IP-CIDR6" => value()?.to_string(),
+        "MATCH" | "FINAL" => {
+            return Ok(Some(("*".to_string(), fields.next().map(str::to_string))));
+        }
+        _ if line.contains(',') => return Err(format!("{kind} rules have no equivalent")),
+        // `domain` provider: `+.` is the domain and its subdomains, `.` and
+        // `*.` its subdomains
+        _ => {
+            let host = if let Some(domain) = line.strip_prefix("+.") {
+                format!(".{domain}")
+            } else if let Some(domain) = line.strip_prefix('.') {
+                format!("*.{domain}")
+            } else {
+                line.to_string()
+            };
+            return Ok(Some((host, None)));
+        }
+    };
+    let policy = fields
+        .next()
+        .filter(|p| !p.is_empty() && *p != "no-resolve")
+        .map(str::to_string);
+    Ok(Some((host, policy)))
+}
+
+/// A v2ray domain-list entry. Attributes (`@cn`) and trailing comments are
+/// dropped.
+fn v2ray(line: &str) -> Converted {
+    let line = line.split('#').next().unwrap_or_default();
+    let entry = line.split_whitespace().next().unwrap_or_default();
+    if entry.is_empty() {
+        return Ok(None);
+    }
+    let host = match entry.split_once(':') {
+        None => format!(".{entry}"),
+        Some(("domain", domain)) => format!(".{domain}"),
+        Some(("full", host)) => host.to_string(),
+        Some((kind @ ("keyword" | "regexp"), _)) => {
+            return Err(format!("{kind}: entries have no equivalent"));
+        }
+        Some(("include", list)) => {
+            return Err(format!("include:{list}: import that list as well"));
+        }
+        Some((kind, _)) => return Err(format!("unknown entry type `{kind}:`")),
+    };
+    Ok(Some((host, None)))
+}
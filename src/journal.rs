@@ -0,0 +1,187 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::sync::OnceLock;
+
+use ring::digest;
+
+use crate::config::JournaldMode;
+
+/// journald's native protocol socket.
+const SOCKET: &str = "/run/systemd/journal/socket";
+
+static JOURNAL: OnceLock<Journal> = OnceLock::new();
+
+/// syslog(3) priorities, as journald's `PRIORITY` field takes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Err = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+}
+
+/// A connection to journald's socket.
+#[derive(Debug)]
+pub struct Journal {
+    socket: imp::Socket,
+    identifier: String,
+}
+
+impl Journal {
+    pub fn connect(identifier: &str) -> io::Result<Self> {
+        let socket = imp::Socket::connect(SOCKET)?;
+        Ok(Self {
+            socket,
+            identifier: identifier.to_string(),
+        })
+    }
+
+    /// One entry: `message`, `priority` and `fields` (upper-case names).
+    pub fn send(
+        &self,
+        priority: Priority,
+        message: &str,
+        fields: &[(&str, &str)],
+    ) -> io::Result<()> {
+        let mut entry = Vec::new();
+        let priority = (priority as u8).to_string();
+        let standard = [
+            ("MESSAGE", message),
+            ("PRIORITY", priority.as_str()),
+            ("SYSLOG_IDENTIFIER", self.identifier.as_str()),
+        ];
+        for (name, value) in standard.iter().chain(fields) {
+            entry.extend_from_slice(name.as_bytes());
+            if value.contains('\n') {
+                // Binary-safe form: the length, little-endian, then the value
+                entry.push(b'\n');
+                entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            } else {
+                entry.push(b'=');
+            }
+            entry.extend_from_slice(value.as_bytes());
+            entry.push(b'\n');
+        }
+        self.socket.send(&entry)
+    }
+}
+
+/// Did systemd connect stderr to the journal? It says so in
+/// `JOURNAL_STREAM` (`<device>:<inode>` of the stream).
+pub fn stderr_is_journal() -> bool {
+    let Some((dev, ino)) = env::var("JOURNAL_STREAM").ok().and_then(|s| {
+        s.split_once(':')
+            .map(|(d, i)| (d.to_string(), i.to_string()))
+    }) else {
+        return false;
+    };
+    imp::file_id("/proc/self/fd/2")
+        .is_some_and(|(d, i)| d.to_string() == dev && i.to_string() == ino)
+}
+
+/// Route [`log`] to the journal per `mode`, as `identifier`. Returns whether
+/// it does; `on` without a reachable journal is an error.
+pub fn init(mode: JournaldMode, identifier: &str) -> Result<bool, String> {
+    let wanted = match mode {
+        JournaldMode::Off => return Ok(false),
+        JournaldMode::Auto => stderr_is_journal(),
+        JournaldMode::On => true,
+    };
+    if !wanted {
+        return Ok(false);
+    }
+    match Journal::connect(identifier) {
+        Ok(journal) => Ok(JOURNAL.set(journal).is_ok()),
+        Err(e) if mode == JournaldMode::On => Err(format!("journald socket {SOCKET}: {e}")),
+        Err(_) => Ok(false),
+    }
+}
+
+/// The journal, once [`init`] connected it.
+pub fn get() -> Option<&'static Journal> {
+    JOURNAL.get()
+}
+
+/// Log a line: to the journal once [`init`] connected it, else to stdout
+/// (info, notice) or stderr. The `[identifier] ` prefix the line carries for
+/// the terminal is dropped in the journal, which has a field for it.
+pub fn log(priority: Priority, args: fmt::Arguments) {
+    let line = args.to_string();
+    if let Some(journal) = JOURNAL.get() {
+        let prefix = format!("[{}] ", journal.identifier);
+        let message = line.strip_prefix(&prefix).unwrap_or(&line);
+        if journal.send(priority, message, &[]).is_ok() {
+            return;
+        }
+    }
+    match priority {
+        Priority::Info | Priority::Notice => println!("{line}"),
+        Priority::Warning | Priority::Err => eprintln!("{line}"),
+    }
+}
+
+/// First 16 hex digits of the target's SHA-256, so entries about one
+/// destination can be found without the journal naming it.
+pub fn target_hash(target: &str) -> String {
+    digest::digest(&digest::SHA256, target.as_bytes())
+        .as_ref()
+        .iter()
+        .take(8)
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::net::UnixDatagram;
+
+    #[derive(Debug)]
+    pub struct Socket(UnixDatagram);
+
+    impl Socket {
+        pub fn connect(path: &str) -> io::Result<Self> {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(path)?;
+            Ok(Self(socket))
+        }
+
+        pub fn send(&self, entry: &[u8]) -> io::Result<()> {
+            self.0.send(entry).map(drop)
+        }
+    }
+
+    /// Device and inode of `path`.
+    pub fn file_id(path: &str) -> Option<(u64, u64)> {
+        fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+    }
+}
+
+/// journald is Linux's alone: there is never one to connect to.
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::io;
+
+    #[derive(Debug)]
+    pub struct Socket;
+
+    impl Socket {
+        pub fn connect(_path: &str) -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "journald only runs on Linux",
+            ))
+        }
+
+        pub fn send(&self, _entry: &[u8]) -> io::Result<()> {
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+    }
+
+    pub fn file_id(_path: &str) -> Option<(u64, u64)> {
+        None
+    }
+}
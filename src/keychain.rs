@@ -0,0 +1,96 @@
+//! Secrets kept in the platform keychain (Secret Service, macOS Keychain,
+//! Windows Credential Manager), named in config as
+//! `keyring:<service>/<account>`. Needs the `keyring` feature.
+//!
+//! The keychain is reached through a blocking API that may start its own
+//! runtime, so look secrets up before the dispatcher's starts.
+
+/// Whether this build can reach the platform keychain (`keyring` feature).
+pub const AVAILABLE: bool = cfg!(feature = "keyring");
+
+/// What marks a config value as a keychain reference.
+pub const SCHEME: &str = "keyring:";
+
+/// A keychain entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub service: String,
+    pub account: String,
+}
+
+impl Entry {
+    /// The entry `value` names, if it is a `keyring:` reference.
+    pub fn parse(value: &str) -> Option<Result<Self, String>> {
+        let rest = value.strip_prefix(SCHEME)?;
+        Some(match rest.split_once('/') {
+            Some((service, account)) if !service.is_empty() && !account.is_empty() => Ok(Self {
+                service: service.to_string(),
+                account: account.to_string(),
+            }),
+            _ => Err(format!("`{value}`: expected {SCHEME}<service>/<account>")),
+        })
+    }
+
+    /// The secret stored under this entry; `None` if there is none.
+    pub fn get(&self) -> Result<Option<String>, String> {
+        imp::get(self).map_err(|e| format!("{self}: {e}"))
+    }
+
+    /// Store `secret` under this entry, replacing any there.
+    pub fn set(&self, secret: &str) -> Result<(), String> {
+        imp::set(self, secret).map_err(|e| format!("{self}: {e}"))
+    }
+}
+
+/// `value` itself, or the secret it names if it is a `keyring:` reference.
+pub fn resolve(value: &str) -> Result<String, String> {
+    match Entry::parse(value) {
+        None => Ok(value.to_string()),
+        Some(entry) => {
+            let entry = entry?;
+            entry
+                .get()?
+                .ok_or_else(|| format!("{entry}: no secret stored"))
+        }
+    }
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}/{}", SCHEME, self.service, self.account)
+    }
+}
+
+#[cfg(feature = "keyring")]
+mod imp {
+    use super::Entry;
+
+    pub fn get(entry: &Entry) -> Result<Option<String>, String> {
+        let found =
+            keyring::Entry::new(&entry.service, &entry.account).and_then(|e| e.get_password());
+        match found {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn set(entry: &Entry, secret: &str) -> Result<(), String> {
+        keyring::Entry::new(&entry.service, &entry.account)
+            .and_then(|e| e.set_password(secret))
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+mod imp {
+    use super::Entry;
+
+    pub fn get(_entry: &Entry) -> Result<Option<String>, String> {
+        Err("built without the `keyring` feature".to_string())
+    }
+
+    pub fn set(_entry: &Entry, _secret: &str) -> Result<(), String> {
+        Err("built without the `keyring` feature".to_string())
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::config::{LanAction, LanConfig};
+use crate::matcher::RuleMatcher;
+use crate::target::{Cidr, Host};
+
+/// Private, link-local, unique-local and loopback networks.
+pub const DEFAULT_NETWORKS: &[&str] = &[
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "169.254.0.0/16",
+    "127.0.0.0/8",
+    "fc00::/7",
+    "fe80::/10",
+    "::1/128",
+];
+
+/// mDNS (RFC 6762), home networks (RFC 8375) and the loopback name.
+pub const DEFAULT_DOMAINS: &[&str] = &["local", "home.arpa", "localhost"];
+
+/// Why a destination counts as local.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LanMatch {
+    /// The name ends in one of the LAN domains.
+    Name(String),
+    /// The address (given or resolved) is in one of the LAN networks.
+    Address(IpAddr, Cidr),
+}
+
+impl fmt::Display for LanMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LanMatch::Name(domain) => write!(f, "name under .{domain}"),
+            LanMatch::Address(ip, net) => write!(f, "{ip} in {net}"),
+        }
+    }
+}
+
+/// `[lan]`, compiled.
+#[derive(Debug, Clone)]
+pub struct Lan {
+    action: LanAction,
+    networks: Vec<Cidr>,
+    domains: Vec<String>,
+    exempt: RuleMatcher,
+}
+
+impl Lan {
+    /// Errors start with the offending key (`networks: ...`).
+    pub fn new(cfg: &LanConfig) -> Result<Self, String> {
+        let networks: Vec<&str> = match &cfg.networks {
+            Some(list) => list.iter().map(String::as_str).collect(),
+            None => DEFAULT_NETWORKS.to_vec(),
+        };
+        let networks = networks
+            .iter()
+            .map(|n| n.parse::<Cidr>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("networks: {e}"))?;
+        let domains: Vec<String> = match &cfg.domains {
+            Some(list) => list
+                .iter()
+                .map(|d| d.trim().trim_matches('.').to_ascii_lowercase())
+                .collect(),
+            None => DEFAULT_DOMAINS.iter().map(|d| d.to_string()).collect(),
+        };
+        if domains.iter().any(String::is_empty) {
+            return Err("domains: empty name".to_string());
+        }
+        let exempt = RuleMatcher::compile(cfg.exempt.iter().map(String::as_str))
+            .map_err(|e| format!("exempt: {}", e.replacen("rule #", "entry #", 1)))?;
+        Ok(Self {
+            action: cfg.action,
+            networks,
+            domains,
+            exempt,
+        })
+    }
+
+    pub fn action(&self) -> LanAction {
+        self.action
+    }
+
+    /// Why `host` (or one of its `resolved` addresses) is on the LAN;
+    /// `None` if it isn't, is exempt, or `[lan]` is off.
+    pub fn check(&self, host: &Host, resolved: &[IpAddr]) -> Option<LanMatch> {
+        if self.action == LanAction::Off || self.exempt.first_match(host, resolved).is_some() {
+            return None;
+        }
+        if let Host::Domain(name) = host {
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            let domain = self.domains.iter().find(|d| {
+                name == **d
+                    || name
+                        .strip_suffix(d.as_str())
+                        .is_some_and(|rest| rest.ends_with('.'))
+            });
+            if let Some(domain) = domain {
+                return Some(LanMatch::Name(domain.clone()));
+            }
+        }
+        host.ip()
+            .into_iter()
+            .chain(resolved.iter().copied())
+            .find_map(|ip| {
+                self.networks
+                    .iter()
+                    .find(|net| net.contains(&ip))
+                    .map(|net| LanMatch::Address(ip, *net))
+            })
+    }
+}
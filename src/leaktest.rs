@@ -0,0 +1,137 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use rand::Rng;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::http::{self, Url};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Default tagged-lookup service (bash.ws DNS leak test API).
+pub const DEFAULT_LEAK_SERVICE: &str = "https://bash.ws";
+
+/// How long one tagged lookup may take through the gateway.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One party the test service saw.
+#[derive(Debug, Clone)]
+pub struct Observed {
+    pub ip: String,
+    pub country: Option<String>,
+    pub asn: Option<String>,
+}
+
+/// Outcome of a DNS leak test.
+#[derive(Debug, Clone)]
+pub struct DnsLeakReport {
+    /// Test id the lookups were tagged with.
+    pub id: String,
+    /// Lookups the gateway accepted (a refusal still may have resolved).
+    pub lookups_sent: usize,
+    /// Our own address, as seen when fetching the results directly.
+    pub local: Option<Observed>,
+    /// Resolvers that asked the service's authoritative server for our tags.
+    pub resolvers: Vec<Observed>,
+}
+
+impl DnsLeakReport {
+    /// Resolvers on our own network: same address or same ASN as `local`.
+    pub fn leaks(&self) -> Vec<&Observed> {
+        let Some(local) = &self.local else {
+            return Vec::new();
+        };
+        self.resolvers
+            .iter()
+            .filter(|r| r.ip == local.ip || (r.asn.is_some() && r.asn == local.asn))
+            .collect()
+    }
+}
+
+/// Run a DNS leak test against the gateway's CONNECT proxy at `proxy`.
+///
+/// Asks `service` for a fresh test id, makes `lookups` CONNECT requests for
+/// uniquely tagged names under it through the gateway, then fetches the list
+/// of resolvers the service saw. The id and results are fetched directly, so
+/// the report also says which network the test machine itself is on.
+pub async fn dns(
+    service: &str,
+    proxy: SocketAddr,
+    lookups: usize,
+) -> Result<DnsLeakReport, BoxError> {
+    let base = Url::parse(service)?;
+    let id_url = Url::parse(&format!("{}/id", service.trim_end_matches('/')))?;
+    let resp = http::request("GET", &id_url, &[], &[]).await?;
+    if resp.status != 200 {
+        return Err(format!("leak service answered HTTP {} for a test id", resp.status).into());
+    }
+    let id = String::from_utf8_lossy(&resp.body).trim().to_string();
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("leak service returned an odd test id {:?}", id).into());
+    }
+
+    let nonce: u32 = rand::thread_rng().gen();
+    let mut lookups_sent = 0;
+    for i in 0..lookups {
+        let name = format!("{}-{:x}.{}.{}", i, nonce, id, base.host);
+        match tokio::time::timeout(LOOKUP_TIMEOUT, connect_through(proxy, &name)).await {
+            Ok(Ok(())) => lookups_sent += 1,
+            Ok(Err(e)) => return Err(format!("gateway at {}: {}", proxy, e).into()),
+            Err(_) => {} // slow resolution: the query went out regardless
+        }
+    }
+
+    // Give resolvers a moment to reach the authoritative server
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let results_url = Url::parse(&format!(
+        "{}/dnsleak/test/{}?json",
+        service.trim_end_matches('/'),
+        id
+    ))?;
+    let resp = http::request("GET", &results_url, &[], &[]).await?;
+    if resp.status != 200 {
+        return Err(format!("leak service answered HTTP {} for results", resp.status).into());
+    }
+    let entries: Vec<Value> = serde_json::from_slice(&resp.body)?;
+
+    let mut report = DnsLeakReport {
+        id,
+        lookups_sent,
+        local: None,
+        resolvers: Vec::new(),
+    };
+    for entry in &entries {
+        let observed = Observed {
+            ip: entry["ip"].as_str().unwrap_or_default().to_string(),
+            country: entry["country_name"]
+                .as_str()
+                .or(entry["country"].as_str())
+                .map(str::to_string),
+            asn: entry["asn"].as_str().map(str::to_string),
+        };
+        match entry["type"].as_str() {
+            Some("ip") => report.local = Some(observed),
+            Some("dns") => report.resolvers.push(observed),
+            _ => {}
+        }
+    }
+    Ok(report)
+}
+
+/// Ask the CONNECT proxy for `name:80` and wait for its status line.
+///
+/// The answer itself does not matter: by the time the gateway replies it
+/// has had to resolve the name somewhere.
+async fn connect_through(proxy: SocketAddr, name: &str) -> Result<(), BoxError> {
+    let mut stream = TcpStream::connect(proxy).await?;
+    stream
+        .write_all(format!("CONNECT {name}:80 HTTP/1.1\r\nHost: {name}:80\r\n\r\n").as_bytes())
+        .await?;
+    let mut buf = [0u8; 64];
+    let _ = stream.read(&mut buf).await;
+    Ok(())
+}
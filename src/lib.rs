@@ -1,2 +1,75 @@
+pub mod access;
+pub mod adblock;
+pub mod admin;
+pub mod alerts;
+pub mod audit;
+pub mod availability;
+pub mod blocklist;
+pub mod cache;
+pub mod chaos;
 pub mod config;
+pub mod dashboard;
+pub mod diagnostic;
+pub mod discovery;
+pub mod dns;
+pub mod eventlog;
+pub mod exitip;
+pub mod export;
+pub mod feed;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod firewall;
+pub mod fixtures;
+pub mod gossip;
+pub mod ha;
+pub mod handover;
+pub mod health;
+pub mod http;
+pub mod import;
+pub mod journal;
+pub mod keychain;
+pub mod lan;
+pub mod leaktest;
+pub mod lint;
+pub mod logfile;
+pub mod lokinet;
+pub mod masque;
+pub mod matcher;
+pub mod metrics;
+#[cfg(feature = "uniffi")]
+pub mod mobile;
+pub mod monitor;
+pub mod paths;
+pub mod peer;
+pub mod pidfile;
+pub mod pinning;
+pub mod policy;
+pub mod portal;
+pub mod power;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod quota;
+pub mod ratelimit;
+pub mod recording;
+pub mod relay;
+pub mod replay;
+pub mod retry;
+pub mod rotation;
 pub mod router;
+pub mod sandbox;
+pub mod script;
+pub mod seal;
+pub mod simulate;
+pub mod socks;
+#[cfg(all(target_os = "linux", feature = "splice"))]
+mod splice;
+pub mod stats;
+pub mod suspend;
+pub mod syslog;
+pub mod target;
+pub mod token;
+pub mod torctl;
+pub mod uring;
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
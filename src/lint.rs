@@ -0,0 +1,384 @@
+use std::ops::Range;
+
+use serde_path_to_error::Segment;
+use toml::{Table, Value};
+
+use crate::config::{GoldDustConfig, LanAction, RuleConfig};
+use crate::diagnostic::{self, Diagnostic};
+use crate::matcher::Pattern;
+use crate::target::Cidr;
+
+/// Addresses a CONNECT never reaches: "this network", multicast, reserved.
+const UNROUTABLE: &[&str] = &[
+    "0.0.0.0/8",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "ff00::/8",
+    "::/128",
+];
+
+/// Private, shared and local networks; anything else is public.
+const NON_PUBLIC: &[&str] = &[
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "::1/128",
+    "fc00::/7",
+    "fe80::/10",
+];
+
+/// `[firewall] allow` networks at least this wide (v4, v6) are more than a
+/// backend's endpoints.
+const WIDE_ALLOW: (u8, u8) = (16, 32);
+
+/// `config lint`: what a valid config probably didn't mean: the unknown
+/// keys loading ignores, and more. Anything that stops the config loading
+/// is the error.
+pub fn lint(text: &str) -> Result<Vec<Diagnostic>, Diagnostic> {
+    let (cfg, mut warnings) = without_unknown_keys(text)?;
+    let cfg = cfg.validate(text)?;
+    warnings.extend(shadowed_rules(text, &cfg));
+    warnings.extend(rule_conflicts(text, &cfg));
+    warnings.extend(disabled_backends(text, &cfg));
+    warnings.extend(unreachable_networks(text, &cfg));
+    warnings.extend(permissive_direct(text, &cfg));
+    Ok(warnings)
+}
+
+/// Parse `text`, dropping (and warning about) every key the config doesn't
+/// know, one at a time until the rest deserializes.
+pub(crate) fn without_unknown_keys(
+    text: &str,
+) -> Result<(GoldDustConfig, Vec<Diagnostic>), Diagnostic> {
+    let mut doc: Table = text.parse().map_err(|e| Diagnostic::from_toml(text, &e))?;
+    let mut warnings = Vec::new();
+    loop {
+        let err = match serde_path_to_error::deserialize(Value::Table(doc.clone())) {
+            Ok(cfg) => return Ok((cfg, warnings)),
+            Err(e) => e,
+        };
+        let message = err.inner().to_string();
+        let segments: Vec<&Segment> = err.path().iter().collect();
+        let span = path_span(text, &segments);
+        if !message.starts_with("unknown field") || !remove(&mut doc, &segments) {
+            return Err(
+                Diagnostic::new(text, format!("{}: {}", err.path(), message)).with_span(span),
+            );
+        }
+        let mut warning = Diagnostic::new(text, format!("unknown key `{}` (ignored)", err.path()))
+            .with_span(span);
+        if let Some(help) = diagnostic::hint(&message) {
+            warning = warning.with_help(help);
+        }
+        warnings.push(warning);
+    }
+}
+
+/// Remove the key at `path` from `table`; false if it isn't there.
+fn remove(table: &mut Table, path: &[&Segment]) -> bool {
+    match path {
+        [Segment::Map { key }] => table.remove(key).is_some(),
+        [Segment::Map { key }, rest @ ..] => table.get_mut(key).is_some_and(|v| remove_in(v, rest)),
+        _ => false,
+    }
+}
+
+fn remove_in(value: &mut Value, path: &[&Segment]) -> bool {
+    match (value, path) {
+        (Value::Table(table), _) => remove(table, path),
+        (Value::Array(items), [Segment::Seq { index }, rest @ ..]) => {
+            items.get_mut(*index).is_some_and(|v| remove_in(v, rest))
+        }
+        _ => false,
+    }
+}
+
+/// Where the key at `path` is written: `[a.b] key`, `[[a]] key` (the n-th)
+/// or a top-level key.
+fn path_span(text: &str, path: &[&Segment]) -> Option<Range<usize>> {
+    let names: Vec<String> = path
+        .iter()
+        .map(|s| match s {
+            Segment::Map { key } => key.clone(),
+            Segment::Seq { index } => format!("#{index}"),
+            _ => String::new(),
+        })
+        .collect();
+    match names.as_slice() {
+        [key] => top_level_span(text, key),
+        [table, index, key] if index.starts_with('#') => {
+            let index = index[1..].parse().ok()?;
+            diagnostic::array_table_span(text, table, index, key)
+        }
+        [tables @ .., key] if tables.iter().all(|t| !t.starts_with('#')) => {
+            diagnostic::key_span(text, &tables.join("."), key)
+        }
+        _ => None,
+    }
+}
+
+fn top_level_span(text: &str, key: &str) -> Option<Range<usize>> {
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let start = offset + (line.len() - trimmed.len());
+        offset += line.len();
+        if trimmed.starts_with('[') {
+            return None;
+        }
+        let rest = trimmed.strip_prefix(key)?;
+        if rest.trim_start().starts_with('=') {
+            return Some(start..start + key.len());
+        }
+    }
+    None
+}
+
+fn rule_warning(text: &str, index: usize, key: &str, message: String) -> Diagnostic {
+    Diagnostic::new(text, format!("rule #{index}: {message}"))
+        .with_span(diagnostic::array_table_span(text, "rules", index, key))
+}
+
+/// Rules whose settings never apply: a rule ranked above them (higher
+/// `priority`, or earlier at the same one) matches every host they do. Rate
+/// limits go to the top-ranked matching rule of all (`when` permitting);
+/// `balance`, `group`, `tor_exits` and `idle_timeout_secs` to the top-ranked
+/// one that sets them (`when` not consulted).
+fn shadowed_rules(text: &str, cfg: &GoldDustConfig) -> Vec<Diagnostic> {
+    let patterns: Vec<Option<Pattern>> = cfg.rules.iter().map(|r| r.host.parse().ok()).collect();
+    let settings = |r: &RuleConfig| {
+        [
+            (
+                "rate limits",
+                r.connections_per_minute.is_some() || r.bandwidth_kbps.is_some(),
+            ),
+            ("balance", r.balance.is_some()),
+            ("group", r.group.is_some()),
+            ("tor_exits", r.tor_exits.is_some()),
+            ("idle_timeout", r.idle_timeout_secs.is_some()),
+        ]
+    };
+    let mut warnings = Vec::new();
+    for (j, later) in cfg.rules.iter().enumerate() {
+        let Some(pattern) = &patterns[j] else {
+            continue;
+        };
+        let covering = |i: usize| patterns[i].as_ref().is_some_and(|p| p.covers(pattern));
+        let mut dead = Vec::new();
+        let mut by = None;
+        for (k, (setting, set)) in settings(later).into_iter().enumerate() {
+            if !set {
+                continue;
+            }
+            let ranks_above = |i: usize| {
+                let p = cfg.rules[i].priority;
+                p > later.priority || (p == later.priority && i < j)
+            };
+            let earlier = (0..cfg.rules.len()).filter(|&i| ranks_above(i)).find(|&i| {
+                let rule = &cfg.rules[i];
+                match k {
+                    0 => rule.when.is_none() && covering(i),
+                    _ => settings(rule)[k].1 && covering(i),
+                }
+            });
+            if let Some(i) = earlier {
+                dead.push(setting);
+                by.get_or_insert(i);
+            }
+        }
+        if let Some(i) = by {
+            warnings.push(
+                rule_warning(
+                    text,
+                    j,
+                    "host",
+                    format!(
+                        "`{}` is shadowed by rule #{} (`{}`), which is checked first; its {} never apply",
+                        later.host,
+                        i,
+                        cfg.rules[i].host,
+                        dead.join(", ")
+                    ),
+                )
+                .with_help("give it a higher priority, or narrow the broader rule"),
+            );
+        }
+    }
+    warnings
+}
+
+/// Overlapping rules that disagree and are ranked only by file order. Those
+/// where the winner covers the loser are `shadowed_rules`.
+fn rule_conflicts(text: &str, cfg: &GoldDustConfig) -> Vec<Diagnostic> {
+    let covers = |i: usize, j: usize| {
+        let pattern = |k: usize| cfg.rules[k].host.parse::<Pattern>().ok();
+        pattern(i)
+            .zip(pattern(j))
+            .is_some_and(|(p, q)| p.covers(&q))
+    };
+    cfg.rule_conflicts()
+        .into_iter()
+        .filter(|c| !c.by_priority)
+        .filter(|c| {
+            let shadowed = c.setting != "rate limits" || !c.conditional;
+            !(shadowed && covers(c.winner, c.loser))
+        })
+        .map(|c| {
+            rule_warning(text, c.loser, "host", c.to_string())
+                .with_help("set `priority` on the rule meant to win")
+        })
+        .collect()
+}
+
+/// Rules that prefer or pin a backend the config turns off.
+fn disabled_backends(text: &str, cfg: &GoldDustConfig) -> Vec<Diagnostic> {
+    let backends = &cfg.backends;
+    let off = |kind: &str| match kind {
+        "oxen" => !backends.oxen_enabled,
+        "tor" => !backends.tor_enabled,
+        "masque" => backends.masque.is_none(),
+        _ => false,
+    };
+    // Backends go by kind (`tor-exit-1`, `oxen-node-2`), chains by name
+    let disabled = |name: &str| {
+        let kind = name.split('-').next().unwrap_or(name);
+        let chain = backends.chains.iter().find(|c| c.name() == name);
+        match chain {
+            Some(chain) => chain.chain.iter().any(|hop| off(hop.as_str())),
+            None => off(kind),
+        }
+    };
+    let mut warnings = Vec::new();
+    for (i, rule) in cfg.rules.iter().enumerate() {
+        if rule.tor_exits.is_some() && !backends.tor_enabled {
+            warnings.push(rule_warning(
+                text,
+                i,
+                "tor_exits",
+                "pins Tor exits, but [backends] tor_enabled = false".to_string(),
+            ));
+        }
+        let Some((name, group)) = rule
+            .group
+            .as_ref()
+            .and_then(|g| cfg.groups.get(g).map(|group| (g, group)))
+        else {
+            continue;
+        };
+        let off: Vec<&str> = group
+            .backends
+            .iter()
+            .map(String::as_str)
+            .filter(|b| disabled(b))
+            .collect();
+        let message = match off.len() {
+            0 => continue,
+            n if n == group.backends.len() => {
+                format!("every backend of group `{name}` is disabled")
+            }
+            _ => format!("group `{name}` includes disabled backends"),
+        };
+        warnings.push(rule_warning(
+            text,
+            i,
+            "group",
+            format!("{}: {}", message, off.join(", ")),
+        ));
+    }
+    warnings
+}
+
+fn networks(list: &[&str]) -> Vec<Cidr> {
+    list.iter().filter_map(|n| n.parse().ok()).collect()
+}
+
+/// IP and CIDR rules that can't match a session the dispatcher lets through.
+fn unreachable_networks(text: &str, cfg: &GoldDustConfig) -> Vec<Diagnostic> {
+    let unroutable = networks(UNROUTABLE);
+    let lan: Vec<Cidr> = match (&cfg.lan.networks, cfg.lan.action) {
+        (_, LanAction::Direct | LanAction::Off) => Vec::new(),
+        (Some(list), LanAction::Reject) => list.iter().filter_map(|n| n.parse().ok()).collect(),
+        (None, LanAction::Reject) => networks(crate::lan::DEFAULT_NETWORKS),
+    };
+    let mut warnings = Vec::new();
+    for (i, rule) in cfg.rules.iter().enumerate() {
+        let net = match rule.host.parse::<Pattern>() {
+            Ok(Pattern::Cidr(net)) => net,
+            Ok(Pattern::Ip(ip)) => format!("{}/{}", ip, if ip.is_ipv4() { 32 } else { 128 })
+                .parse()
+                .expect("an address is a CIDR"),
+            _ => continue,
+        };
+        let message = if let Some(r) = unroutable.iter().find(|r| r.covers(&net)) {
+            format!("`{}` is in {}, which no session connects to", rule.host, r)
+        } else if let Some(l) = lan.iter().find(|l| l.covers(&net)) {
+            format!(
+                "`{}` is in {}, and [lan] action = \"reject\" refuses it before rules apply",
+                rule.host, l
+            )
+        } else if net.has_host_bits() {
+            format!(
+                "`{}` has host bits set; it matches the whole /{} network",
+                rule.host,
+                net.prefix()
+            )
+        } else {
+            continue;
+        };
+        warnings.push(rule_warning(text, i, "host", message));
+    }
+    warnings
+}
+
+/// Settings that send (or let) public traffic out directly, around Tor.
+fn permissive_direct(text: &str, cfg: &GoldDustConfig) -> Vec<Diagnostic> {
+    let local = networks(NON_PUBLIC);
+    let public = |net: &Cidr| !local.iter().any(|l| l.covers(net));
+    let mut warnings = Vec::new();
+    if cfg.lan.action == LanAction::Direct {
+        for net in cfg.lan.networks.iter().flatten() {
+            if net.parse::<Cidr>().is_ok_and(|n| public(&n)) {
+                warnings.push(
+                    Diagnostic::new(
+                        text,
+                        format!("[lan] networks: `{net}` includes public addresses, which go direct, outside Tor"),
+                    )
+                    .with_span(diagnostic::key_span(text, "lan", "networks"))
+                    .with_help("list only private networks, or use `exempt` for the public part"),
+                );
+            }
+        }
+    }
+    for entry in &cfg.firewall.allow {
+        let Ok(net) = entry.parse::<Cidr>() else {
+            continue;
+        };
+        let wide = match net.is_ipv6() {
+            false => WIDE_ALLOW.0,
+            true => WIDE_ALLOW.1,
+        };
+        if net.prefix() < wide && public(&net) {
+            warnings.push(
+                Diagnostic::new(
+                    text,
+                    format!("[firewall] allow: `{entry}` lets anyone reach a wide public range past the kill switch"),
+                )
+                .with_span(diagnostic::key_span(text, "firewall", "allow"))
+                .with_help("allow the backends' own addresses"),
+            );
+        }
+    }
+    if cfg.firewall.allow_uids.contains(&0) {
+        warnings.push(
+            Diagnostic::new(
+                text,
+                "[firewall] allow_uids: uid 0 lets everything running as root out directly",
+            )
+            .with_span(diagnostic::key_span(text, "firewall", "allow_uids")),
+        );
+    }
+    warnings
+}
@@ -0,0 +1,190 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::LoggingConfig;
+use crate::seal::Sealer;
+use crate::stats::now_unix;
+
+/// Append-only log file that rotates itself, for hosts without logrotate.
+///
+/// `traffic.log` rotates to `traffic.log.1` (`.1.gz` when compressing),
+/// shifting older files up to `keep`; anything beyond that is deleted.
+#[derive(Debug)]
+pub struct RotatingLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened_unix: u64,
+    max_bytes: u64,
+    max_age_secs: u64,
+    keep: usize,
+    compress: bool,
+    /// Seals each line (`[encryption]`).
+    sealer: Option<Arc<Sealer>>,
+}
+
+impl RotatingLog {
+    /// Open (appending to) `path` with the limits from `[logging]`.
+    pub fn open<P: AsRef<Path>>(path: P, cfg: &LoggingConfig) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (file, size, opened_unix) = open_append(&path)?;
+        Ok(Self {
+            path,
+            file,
+            size,
+            opened_unix,
+            max_bytes: cfg.max_size_mb.max(1) * 1024 * 1024,
+            max_age_secs: cfg.max_age_hours * 3600,
+            keep: cfg.keep,
+            compress: cfg.compress,
+            sealer: None,
+        })
+    }
+
+    /// Seal every line written from now on.
+    pub fn sealed(mut self, sealer: Option<Arc<Sealer>>) -> Self {
+        self.sealer = sealer;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one line, rotating first if the file is due.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let sealed;
+        let line = match &self.sealer {
+            Some(sealer) => {
+                sealed = sealer.seal(line);
+                &sealed
+            }
+            None => line,
+        };
+        let too_old = self.max_age_secs > 0
+            && self.size > 0
+            && now_unix().saturating_sub(self.opened_unix) >= self.max_age_secs;
+        if self.size + line.len() as u64 + 1 > self.max_bytes || too_old {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Move the current file aside and start a new one.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // Drop the oldest, then shift .N to .N+1
+            for n in (1..=self.keep).rev() {
+                for gz in [false, true] {
+                    let from = self.rotated(n, gz);
+                    if !from.exists() {
+                        continue;
+                    }
+                    if n == self.keep {
+                        fs::remove_file(&from)?;
+                    } else {
+                        fs::rename(&from, self.rotated(n + 1, gz))?;
+                    }
+                }
+            }
+            let first = self.rotated(1, false);
+            fs::rename(&self.path, &first)?;
+            if self.compress {
+                gzip(&first, &self.rotated(1, true))?;
+            }
+        }
+        (self.file, self.size, self.opened_unix) = open_append(&self.path)?;
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize, gz: bool) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        if gz {
+            name.push(".gz");
+        }
+        PathBuf::from(name)
+    }
+}
+
+/// Every line of `path` and its rotated files, oldest first.
+pub fn read_lines<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
+    let path = path.as_ref();
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let base = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "log path has no file name"))?
+        .to_string_lossy()
+        .into_owned();
+
+    // `base.N` / `base.N.gz`, highest N (oldest) first
+    let mut rotated = Vec::new();
+    for dirent in fs::read_dir(dir)? {
+        let name = dirent?.file_name().to_string_lossy().into_owned();
+        let Some(suffix) = name.strip_prefix(&base).and_then(|s| s.strip_prefix('.')) else {
+            continue;
+        };
+        if let Ok(n) = suffix.trim_end_matches(".gz").parse::<usize>() {
+            rotated.push((n, dir.join(&name)));
+        }
+    }
+    rotated.sort_by_key(|(n, _)| std::cmp::Reverse(*n));
+
+    let mut lines = Vec::new();
+    for file in rotated
+        .into_iter()
+        .map(|(_, p)| p)
+        .chain([path.to_path_buf()])
+    {
+        let reader: Box<dyn Read> = match File::open(&file) {
+            Ok(f) if file.extension().is_some_and(|e| e == "gz") => Box::new(GzDecoder::new(f)),
+            Ok(f) => Box::new(f),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                lines.push(line);
+            }
+        }
+    }
+    Ok(lines)
+}
+
+fn open_append(path: &Path) -> io::Result<(File, u64, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let meta = file.metadata()?;
+    // Age counts from creation where the filesystem records it, else from now
+    let opened = meta
+        .created()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_else(now_unix);
+    Ok((file, meta.len(), opened))
+}
+
+/// Compress `from` into `to` and remove `from`.
+fn gzip(from: &Path, to: &Path) -> io::Result<()> {
+    let mut input = File::open(from)?;
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(from)
+}
@@ -0,0 +1,167 @@
+use std::error::Error;
+use std::net::SocketAddr;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::config::LokinetExitConfig;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Every IPv4 destination, the range an exit is mapped for.
+const ALL_TRAFFIC: &str = "0.0.0.0/0";
+
+/// The daemon's status (`llarp.status`).
+pub async fn status(rpc: SocketAddr) -> Result<Value, BoxError> {
+    request(rpc, "llarp.status", None).await
+}
+
+/// Send all traffic through `exit`, with its auth token if it has one
+/// (`llarp.exit`, as `lokinet-vpn --exit` does).
+pub async fn set_exit(rpc: SocketAddr, exit: &LokinetExitConfig) -> Result<(), BoxError> {
+    let mut args = json!({ "exit": exit.address, "range": ALL_TRAFFIC });
+    if let Some(token) = &exit.token {
+        args["token"] = json!(token);
+    }
+    request(rpc, "llarp.exit", Some(&args)).await?;
+    Ok(())
+}
+
+/// Stop using any exit: traffic to non-`.loki` addresses leaves directly.
+pub async fn clear_exit(rpc: SocketAddr) -> Result<(), BoxError> {
+    let args = json!({ "unmap": true, "range": ALL_TRAFFIC });
+    request(rpc, "llarp.exit", Some(&args)).await?;
+    Ok(())
+}
+
+/// Exits mapped in a status, by range: any `exitMap` object (per endpoint).
+pub fn active_exits(status: &Value) -> Vec<(String, String)> {
+    let mut exits = Vec::new();
+    let mut stack = vec![status];
+    while let Some(value) = stack.pop() {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::Object(ranges)) = map.get("exitMap") {
+                    for (range, exit) in ranges {
+                        let exit = exit
+                            .as_str()
+                            .map_or_else(|| exit.to_string(), str::to_string);
+                        exits.push((range.clone(), exit));
+                    }
+                }
+                stack.extend(map.values());
+            }
+            Value::Array(items) => stack.extend(items),
+            _ => {}
+        }
+    }
+    exits
+}
+
+/// One request to the RPC port, which speaks OxenMQ: ZMTP 3 with the NULL
+/// mechanism, a request being the endpoint, a reply tag and JSON `args`.
+/// Fails on an `error` in the reply.
+pub async fn request(
+    rpc: SocketAddr,
+    endpoint: &str,
+    args: Option<&Value>,
+) -> Result<Value, BoxError> {
+    let mut stream = TcpStream::connect(rpc).await?;
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    stream.write_all(&greeting).await?;
+    stream.read_exact(&mut greeting).await?;
+    if greeting[0] != 0xff || greeting[9] != 0x7f || greeting[10] < 3 {
+        return Err("lokinet RPC: not a ZMTP 3 peer".into());
+    }
+
+    let mut ready = vec![5];
+    ready.extend_from_slice(b"READY");
+    ready.push(11);
+    ready.extend_from_slice(b"Socket-Type");
+    ready.extend_from_slice(&6u32.to_be_bytes());
+    ready.extend_from_slice(b"DEALER");
+    write_frame(&mut stream, COMMAND, &ready).await?;
+    loop {
+        let (flags, body) = read_frame(&mut stream).await?;
+        if flags & COMMAND != 0 && body.get(1..6) == Some(b"READY") {
+            break;
+        }
+        if flags & COMMAND != 0 && body.get(1..6) == Some(b"ERROR") {
+            return Err("lokinet RPC refused the connection".into());
+        }
+    }
+
+    // OxenMQ request: [endpoint, reply tag, data]; reply: [REPLY, tag, data]
+    write_frame(&mut stream, MORE, endpoint.as_bytes()).await?;
+    match args {
+        Some(args) => {
+            write_frame(&mut stream, MORE, b"gold-dust").await?;
+            write_frame(&mut stream, 0, args.to_string().as_bytes()).await?;
+        }
+        None => write_frame(&mut stream, 0, b"gold-dust").await?,
+    }
+    let reply = loop {
+        let mut message = Vec::new();
+        loop {
+            let (flags, body) = read_frame(&mut stream).await?;
+            if flags & COMMAND != 0 {
+                continue;
+            }
+            message.push(body);
+            if flags & MORE == 0 {
+                break;
+            }
+        }
+        if message.first().map(Vec::as_slice) == Some(b"REPLY") {
+            break message;
+        }
+    };
+    let data = reply.last().ok_or("lokinet RPC: empty reply")?;
+    let reply: Value = serde_json::from_slice(data)
+        .map_err(|e| format!("lokinet RPC: {} reply is not JSON: {}", endpoint, e))?;
+    if let Some(error) = reply.get("error").filter(|e| !e.is_null()) {
+        return Err(format!("lokinet RPC: {}: {}", endpoint, error).into());
+    }
+    Ok(reply)
+}
+
+/// ZMTP frame flags.
+const MORE: u8 = 0x01;
+const LONG: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+
+async fn write_frame(stream: &mut TcpStream, flags: u8, body: &[u8]) -> Result<(), BoxError> {
+    let mut frame = Vec::with_capacity(body.len() + 9);
+    match u8::try_from(body.len()) {
+        Ok(len) => frame.extend_from_slice(&[flags, len]),
+        Err(_) => {
+            frame.push(flags | LONG);
+            frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(body);
+    stream.write_all(&frame).await?;
+    Ok(())
+}
+
+/// A status fits in a few MiB; refuse anything claiming more.
+const FRAME_MAX: u64 = 16 * 1024 * 1024;
+
+async fn read_frame(stream: &mut TcpStream) -> Result<(u8, Vec<u8>), BoxError> {
+    let flags = stream.read_u8().await?;
+    let len = match flags & LONG {
+        0 => u64::from(stream.read_u8().await?),
+        _ => stream.read_u64().await?,
+    };
+    if len > FRAME_MAX {
+        return Err(format!("lokinet RPC: {} byte frame", len).into());
+    }
+    let mut body = vec![0u8; len as usize];
+    stream.read_exact(&mut body).await?;
+    Ok((flags, body))
+}
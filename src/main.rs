@@ -1,10 +1,65 @@
+use std::cmp::Reverse;
 use std::error::Error;
-use std::path::PathBuf;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 
 use clap::{Parser, Subcommand};
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
 
-use gold_dust_gateway::config::GoldDustConfig;
-use gold_dust_gateway::router::{BackendChoice, BackendHealth, BackendKind, Router};
+use gold_dust_gateway::admin::{self, Authority, Usage};
+use gold_dust_gateway::alerts::{AlertSnapshot, AlertState, ALERTS_PATH};
+use gold_dust_gateway::audit::{self, Action};
+use gold_dust_gateway::availability::{AvailabilityLedger, AVAILABILITY_PATH, WINDOWS};
+use gold_dust_gateway::blocklist::{Blocklist, BLOCKLIST_PATH};
+use gold_dust_gateway::config::{
+    Balance, DnsMode, DotProfile, EgressKind, GoldDustConfig, LanAction, LokinetExitConfig,
+    RuleConfig,
+};
+use gold_dust_gateway::discovery::{self, Discovered, Version};
+use gold_dust_gateway::dns::{DohResolver, DotResolver, Hosts};
+use gold_dust_gateway::exitip::{self, DEFAULT_ECHO_URL};
+use gold_dust_gateway::export::{self, Filter, Format, Table};
+use gold_dust_gateway::firewall::{self, Ruleset};
+use gold_dust_gateway::fixtures::Fixtures;
+use gold_dust_gateway::gossip::{HealthBoard, Report, HEALTH_PATH};
+use gold_dust_gateway::health::TcpProber;
+use gold_dust_gateway::http::Url;
+use gold_dust_gateway::import;
+use gold_dust_gateway::lan::{Lan, LanMatch};
+use gold_dust_gateway::leaktest::{self, DEFAULT_LEAK_SERVICE};
+use gold_dust_gateway::lint;
+use gold_dust_gateway::logfile;
+use gold_dust_gateway::lokinet;
+use gold_dust_gateway::matcher::Pattern;
+use gold_dust_gateway::monitor::{self, Streamed};
+use gold_dust_gateway::paths;
+use gold_dust_gateway::policy;
+use gold_dust_gateway::portal::{self, PortalMode, PORTAL_PATH};
+use gold_dust_gateway::quota::{UsageLedger, USAGE_PATH, USER_USAGE_PATH};
+use gold_dust_gateway::ratelimit::RateLimiter;
+use gold_dust_gateway::recording::{self, Recorded, Verdict};
+use gold_dust_gateway::relay::Upstream;
+use gold_dust_gateway::replay::Replay;
+use gold_dust_gateway::router::{
+    BackendChoice, BackendKind, Load, Rejection, Requirements, Router, RouterSnapshot, Trend,
+    NO_BACKENDS,
+};
+use gold_dust_gateway::seal::{self, Sealer};
+use gold_dust_gateway::simulate::Scenario;
+use gold_dust_gateway::stats::{now_unix, Canary, TrafficEntry, TrafficSnapshot, STATS_PATH};
+use gold_dust_gateway::target::Target;
+use gold_dust_gateway::token;
+use gold_dust_gateway::torctl::{self, ExitPins};
+
+const FLAG_PATH: &str = "gold-dust-tor.flag";
+
+/// How long `status` waits for oxend's service node list.
+const DISCOVERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long `portal` waits for the connectivity probe.
+const PORTAL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
 /// Gold Dust Gateway: Oxen-first, Tor-fallback routing brain.
 ///
@@ -16,6 +71,15 @@ struct Cli {
     #[arg(long, short)]
     config: Option<PathBuf>,
 
+    /// Seed randomized backend selection (overrides `[routing] seed`)
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    /// Decide from router state saved by `status --json` instead of the
+    /// live backends
+    #[arg(long, global = true)]
+    snapshot: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -23,66 +87,2883 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Show backend health snapshot.
-    Status,
+    Status {
+        /// Probe the local Tor/lokinet daemons instead of the simulated backends
+        #[arg(long)]
+        probe: bool,
+        /// Print the backend snapshot as JSON (e.g. to diff against later)
+        #[arg(long)]
+        json: bool,
+        #[command(subcommand)]
+        action: Option<StatusAction>,
+    },
     /// Ask the gateway which backend it would use for this target.
     Route {
-        /// Host:port you want to reach (e.g. example.com:80)
-        target: String,
+        /// Host:port you want to reach (e.g. example.com:80, [::1]:22, https://example.com)
+        target: Target,
+        /// Show each step of the decision (resolution, rules, backend)
+        #[arg(long)]
+        explain: bool,
+        /// The traffic is UDP: only backends that carry datagrams qualify
+        #[arg(long)]
+        udp: bool,
+    },
+    /// Explain why a backend is not the one chosen for this target.
+    WhyNot {
+        /// Backend name (as in `status`)
+        backend: String,
+        /// Host:port, as for `route`
+        target: Target,
+        /// The traffic is UDP
+        #[arg(long)]
+        udp: bool,
+    },
+    /// Replay a scripted scenario of health events and print the decisions.
+    Simulate {
+        /// Scenario file (.toml or .json)
+        scenario: PathBuf,
+    },
+    /// Fetch an IP-echo endpoint through each backend and show the exit IP.
+    CheckExitIp {
+        /// Echo endpoint returning JSON `ip`/`country` (or a bare address)
+        #[arg(long, default_value = DEFAULT_ECHO_URL)]
+        url: String,
+    },
+    /// Check the running gateway for leaks.
+    Leaktest {
+        #[command(subcommand)]
+        test: LeakTest,
+    },
+    /// Dump the dispatcher's traffic or health log for offline analysis.
+    Export {
+        /// Which log to export
+        #[arg(value_enum)]
+        data: ExportData,
+        /// csv or parquet
+        #[arg(long, default_value = "csv")]
+        format: Format,
+        /// Only rows at or after this time (unix seconds, or an age like 24h)
+        #[arg(long, value_parser = export::parse_time)]
+        since: Option<u64>,
+        /// Only rows before this time (unix seconds, or an age like 1h)
+        #[arg(long, value_parser = export::parse_time)]
+        until: Option<u64>,
+        /// Only this egress (traffic) or backend name / kind (health)
+        #[arg(long)]
+        backend: Option<String>,
+        /// Log to read instead of the one in `[logging]`
+        #[arg(long)]
+        log: Option<PathBuf>,
+        /// Output file (CSV goes to stdout without one)
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Re-decide the sessions in a traffic log under the current config and
+    /// report which decisions would change; or, with `--verify`, make
+    /// recorded routing decisions again from their inputs.
+    Replay {
+        /// Traffic log (e.g. from `dispatcher --audit`) instead of the one in
+        /// `[logging]`; with `--verify`, decisions instead of `[routing] record`
+        log: Option<PathBuf>,
+        /// Flag-file mode to assume (tor, direct, masque); defaults to the
+        /// current one
+        #[arg(long, value_parser = parse_egress, conflicts_with = "verify")]
+        mode: Option<EgressKind>,
+        /// Check that recorded decisions come out the same (fails if not)
+        #[arg(long)]
+        verify: bool,
+        /// With `--verify`, only decisions for this backend name or kind
+        #[arg(long, requires = "verify")]
+        backend: Option<String>,
+        /// Only sessions at or after this time (unix seconds, or an age like 24h)
+        #[arg(long, value_parser = export::parse_time)]
+        since: Option<u64>,
+        /// Only sessions before this time (unix seconds, or an age like 1h)
+        #[arg(long, value_parser = export::parse_time)]
+        until: Option<u64>,
+    },
+    /// Kill-switch firewall rules matching the config (needs root).
+    Firewall {
+        #[command(subcommand)]
+        action: FirewallAction,
+    },
+    /// Alert rules evaluated by the dispatcher (`[[alerts.rules]]`).
+    Alerts {
+        #[command(subcommand)]
+        action: AlertsAction,
+    },
+    /// Check the config file.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Routing regression tests against the current config.
+    Policy {
+        #[command(subcommand)]
+        action: PolicyAction,
+    },
+    /// Compiled `[[rules]]`.
+    Rules {
+        #[command(subcommand)]
+        action: RulesAction,
+    },
+    /// Print the dispatcher's decisions, probes and failovers as they happen
+    /// (needs `[dashboard]`).
+    Monitor {
+        /// One JSON object per line
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the live Tor circuits and lokinet paths, hop by hop.
+    Paths,
+    /// Lokinet exits (`[[lokinet.exits]]`), through the daemon's RPC port.
+    Lokinet {
+        #[command(subcommand)]
+        action: LokinetAction,
+    },
+    /// Captive portals: detect one, and let it through to log in.
+    Portal {
+        #[command(subcommand)]
+        action: PortalAction,
+    },
+    /// Print the local API's bearer token (`[api]`), for scripts and the
+    /// dashboard.
+    Token {
+        /// Print a dashboard link that hands the token to the browser
+        #[arg(long)]
+        url: bool,
+        /// Print the token that may only read
+        #[arg(long)]
+        read: bool,
+    },
+    /// Remote admin over mutual TLS (`[admin]`).
+    Admin {
+        #[command(subcommand)]
+        action: AdminAction,
+    },
+    /// Administrative actions recorded in `[logging] audit_log`.
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ExportData {
+    /// Per-session routing decisions (`[logging] traffic_log`)
+    Traffic,
+    /// Accepted health reports (`[logging] health_log`)
+    Health,
+}
+
+#[derive(Subcommand, Debug)]
+enum FirewallAction {
+    /// Load the rules, replacing any previous gold-dust rules atomically.
+    Apply {
+        /// Print the rules instead of loading them
+        #[arg(long)]
+        dry_run: bool,
+        /// Roll back unless confirmed within this many seconds (0: no prompt)
+        #[arg(long, default_value_t = 30)]
+        rollback_secs: u64,
+    },
+    /// Remove every gold-dust rule.
+    Remove,
+}
+
+#[derive(Subcommand, Debug)]
+enum StatusAction {
+    /// Compare current backend health with a `status --json` snapshot.
+    Diff {
+        /// Earlier snapshot
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AlertsAction {
+    /// List each rule and whether it is firing.
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Warn about what loads but probably isn't meant: unknown keys,
+    /// shadowed rules, disabled backends, unreachable networks, wide direct
+    /// paths
+    Lint {
+        /// Exit non-zero on warnings too
+        #[arg(long)]
+        strict: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum PolicyAction {
+    /// Route each fixture target and fail if any lands on an unexpected
+    /// backend.
+    Test {
+        /// Fixtures file (.toml or .json)
+        fixtures: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum LokinetAction {
+    /// Probe each configured exit and show which one is active.
+    Exits,
+    /// Send clearnet traffic through an exit: the one given, else the first
+    /// configured exit that answers a probe.
+    Use {
+        /// `.loki` address; a configured exit's token is sent along
+        exit: Option<String>,
+    },
+    /// Stop using an exit (clearnet traffic leaves lokinet directly).
+    Off,
+}
+
+#[derive(Subcommand, Debug)]
+enum PortalAction {
+    /// Fetch the probe URL directly and report any portal in the way.
+    Check,
+    /// Send the portal's hosts directly for a while; everything else stays
+    /// on its backend. Ends early once a backend gets through again.
+    Login {
+        /// Portal host; default: the one the probe is redirected to
+        host: Option<String>,
+        /// How long (default: `[portal] login_mins`)
+        #[arg(long)]
+        mins: Option<u64>,
+    },
+    /// End portal mode now.
+    Done,
+}
+
+#[derive(Subcommand, Debug)]
+enum AdminAction {
+    /// Issue a certificate from the admin CA (`[admin] ca_cert`, created
+    /// on first use): a client's, or with --server a dispatcher's.
+    Cert {
+        /// Client name, as logged and as `operators`, `viewers` and
+        /// `revoked` list it; with --server, the host name or address
+        /// clients reach the dispatcher at
+        name: String,
+        /// Issue the dispatcher's certificate (`[admin] cert` and `key`)
+        #[arg(long)]
+        server: bool,
+        /// More names or addresses the dispatcher answers on (repeatable)
+        #[arg(long = "san", value_name = "NAME", requires = "server")]
+        sans: Vec<String>,
+        /// Days the certificate is good for
+        #[arg(long, default_value_t = 365)]
+        days: u32,
+        /// Directory to write to, for another machine: `<name>.pem` and
+        /// `<name>.key`, or the default cert and key names with --server
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AuditAction {
+    /// List who did what, oldest first.
+    List {
+        /// Only actions at or after this time (unix seconds, or an age like 24h)
+        #[arg(long, value_parser = export::parse_time)]
+        since: Option<u64>,
+        /// Only actions by this user or admin client (`admin <name>`)
+        #[arg(long)]
+        who: Option<String>,
+        /// One JSON object per line, as recorded
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum RulesAction {
+    /// Print the compiled rule sets in evaluation order, with indices.
+    Dump,
+    /// Walk the rules for one target in evaluation order: what each tested,
+    /// matched, skipped or decided. No resolution, health or probes.
+    Eval {
+        /// Host:port, as for `route`
+        target: Target,
+        /// Address the host resolves to, for IP and CIDR rules (repeatable;
+        /// default: its `[dns.hosts]` entry)
+        #[arg(long = "resolved", value_name = "IP")]
+        resolved: Vec<IpAddr>,
+    },
+    /// Convert a Clash or v2ray rule set into `[[rules]]` entries, printed
+    /// as TOML to append to the config.
+    Import {
+        /// Rule set file
+        file: PathBuf,
+        /// clash or v2ray
+        #[arg(long)]
+        from: import::Format,
+        /// `[groups]` entry every imported rule prefers
+        #[arg(long)]
+        group: Option<String>,
+        /// Clash policy to `[groups]` entry, e.g. PROXY=oxen-eu (repeatable);
+        /// rules with an unmapped policy fall back to --group, or are skipped
+        #[arg(long = "policy", value_name = "POLICY=GROUP", value_parser = parse_policy)]
+        policies: Vec<(String, String)>,
+        /// connections_per_minute for every imported rule
+        #[arg(long)]
+        connections_per_minute: Option<u32>,
+        /// bandwidth_kbps for every imported rule
+        #[arg(long)]
+        bandwidth_kbps: Option<u64>,
+        /// priority for every imported rule
+        #[arg(long, default_value_t = 0)]
+        priority: i32,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum LeakTest {
+    /// Send tagged lookups through the dispatcher and list who resolved them.
+    Dns {
+        /// Tagged-lookup service (bash.ws DNS leak test API)
+        #[arg(long, default_value = DEFAULT_LEAK_SERVICE)]
+        service: String,
+        /// Dispatcher CONNECT proxy to test
+        #[arg(long, default_value = "127.0.0.1:7777")]
+        proxy: SocketAddr,
+        /// Number of tagged lookups
+        #[arg(long, default_value_t = 6)]
+        lookups: usize,
     },
 }
 
 fn load_config(path: Option<PathBuf>) -> Result<GoldDustConfig, Box<dyn Error>> {
     let cfg_path = path.unwrap_or_else(|| PathBuf::from("gold-dust-gateway.toml"));
-    GoldDustConfig::load(cfg_path)
+    let (cfg, warnings) = GoldDustConfig::load_with_warnings(cfg_path)?;
+    for warning in &warnings {
+        eprintln!("warning: {}\n", warning);
+    }
+    Ok(cfg)
+}
+
+/// `config lint`, before the config is loaded, so its warnings show once.
+fn run_lint(path: Option<PathBuf>, strict: bool) -> Result<(), Box<dyn Error>> {
+    let path = path.unwrap_or_else(|| PathBuf::from("gold-dust-gateway.toml"));
+    let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let warnings = lint::lint(&text).map_err(|d| d.with_path(&path))?;
+    for warning in &warnings {
+        println!("warning: {}\n", warning.clone().with_path(&path));
+    }
+    println!("{}: {} warning(s)", path.display(), warnings.len());
+    if strict && !warnings.is_empty() {
+        return Err(format!("{} warning(s) with --strict", warnings.len()).into());
+    }
+    Ok(())
 }
 
 fn backend_label(kind: BackendKind) -> &'static str {
     match kind {
         BackendKind::Oxen => "Oxen-first, Tor-fallback policy",
         BackendKind::Tor => "Tor fallback",
+        BackendKind::Chain => "chained hops, defense in depth",
+        BackendKind::Masque => "HTTP/3 CONNECT relay, last resort",
     }
 }
 
-fn print_status(router: &mut Router) {
+fn print_status(router: &mut Router, cfg: &GoldDustConfig, usage: &UsageLedger) {
     let health_list = router.backend_health();
 
     println!("=== Gold Dust Gateway backend status ===");
     for h in health_list {
         println!(
-            "- {:<12} [{:?}]  latency={:6.1} ms  failure_rate={:.3}  enabled={}",
-            h.name, h.kind, h.latency_ms, h.failure_rate, h.enabled
+            "- {:<12} [{:?}]  latency={:6.1} ms  failure_rate={:.3}  enabled={}  ipv6={}{}",
+            h.name,
+            h.kind,
+            h.latency_ms,
+            h.failure_rate,
+            h.enabled,
+            h.ipv6,
+            if router.saturated(&h) {
+                "  [SATURATED]"
+            } else {
+                ""
+            }
+        );
+    }
+
+    let overrides = cfg.rules.iter().filter(|r| r.balance.is_some()).count();
+    println!(
+        "Selection: {}{}",
+        cfg.routing.balance.as_str(),
+        match overrides {
+            0 => String::new(),
+            n => format!(" ({} per-target override(s) in [[rules]])", n),
+        }
+    );
+
+    let groups = router.group_health();
+    if !groups.is_empty() {
+        println!();
+        println!("=== Backend groups ===");
+    }
+    match (&cfg.routing.vantage, router.nearest_group()) {
+        (Some(vantage), Some(group)) => println!(
+            "Vantage: {} (nearest: {}, tried first for {:.0}% of decisions)",
+            vantage,
+            group,
+            router.vantage_bias() * 100.0
+        ),
+        (Some(vantage), None) => println!("Vantage: {} (no group has a usable member)", vantage),
+        (None, _) => {}
+    }
+    for g in groups {
+        let health = match g.best_latency_ms {
+            Some(latency) => format!(
+                "best latency={:6.1} ms  failure_rate={:.3}",
+                latency, g.failure_rate
+            ),
+            None => "[DOWN]".to_string(),
+        };
+        println!(
+            "- {:<12} {}/{} up  {}{}",
+            g.name,
+            g.up,
+            g.members,
+            health,
+            if g.missing.is_empty() {
+                String::new()
+            } else {
+                format!("  (not reported: {})", g.missing.join(", "))
+            }
+        );
+    }
+
+    println!();
+    println!("=== Dispatcher egress utilization ===");
+    match TrafficSnapshot::load(STATS_PATH).filter(|s| s.is_fresh()) {
+        Some(snapshot) => {
+            for (name, usage) in &snapshot.egress {
+                let cap = match (usage.limit_kbps, usage.utilization()) {
+                    (Some(limit), Some(util)) => {
+                        format!("cap={} kbit/s ({:.0}%)", limit, util * 100.0)
+                    }
+                    _ => "cap=none".to_string(),
+                };
+                let sessions = match usage.max_sessions {
+                    Some(max) if max > 0 => format!(
+                        "{}/{} ({:.0}%)",
+                        usage.sessions,
+                        max,
+                        usage.sessions as f64 * 100.0 / max as f64
+                    ),
+                    _ => usage.sessions.to_string(),
+                };
+                let mut extra = String::new();
+                if usage.idle_closed > 0 {
+                    extra += &format!("  idle_closed={}", usage.idle_closed);
+                }
+                if usage.overruns > 0 {
+                    extra += &format!("  overruns={}/{} dials", usage.overruns, usage.dials);
+                }
+                println!(
+                    "- {:<12} rate={:8.1} kbit/s  {}  sessions={}  total={} bytes{}",
+                    name, usage.rate_kbps, cap, sessions, usage.bytes_total, extra
+                );
+            }
+            match &snapshot.standby {
+                Some(Canary {
+                    error: None,
+                    unix,
+                    latency_ms,
+                }) => println!(
+                    "Standby: tor warm (canary {:.0} ms, {}s ago)",
+                    latency_ms,
+                    now_unix().saturating_sub(*unix)
+                ),
+                Some(Canary {
+                    error: Some(e),
+                    unix,
+                    ..
+                }) => println!(
+                    "Standby: tor COLD (canary failed {}s ago: {})",
+                    now_unix().saturating_sub(*unix),
+                    e
+                ),
+                None => {}
+            }
+            if let Some(ha) = &snapshot.ha {
+                let peer = match &ha.peer {
+                    Some(peer) => format!(
+                        "peer {} (priority {}) heard {}s ago",
+                        if peer.active { "active" } else { "standby" },
+                        peer.priority,
+                        now_unix().saturating_sub(peer.seen_unix)
+                    ),
+                    None => "peer never heard".to_string(),
+                };
+                println!(
+                    "HA: {} (priority {}) for {}s; {}",
+                    ha.role.as_str(),
+                    ha.priority,
+                    now_unix().saturating_sub(ha.since_unix),
+                    peer
+                );
+            }
+            if snapshot.low_power {
+                println!(
+                    "Power: low (stats every {}s, probes stretched, no standby canary)",
+                    snapshot.interval_secs
+                );
+            }
+        }
+        None => println!("(dispatcher not running: no fresh {})", STATS_PATH),
+    }
+
+    if cfg.gossip.enabled || cfg.health_feed.enabled {
+        println!();
+        println!("=== Shared health (gossip / feed) ===");
+        let max_age = cfg.gossip.interval_secs.max(1) * 3;
+        match HealthBoard::load(HEALTH_PATH) {
+            Some(board) if now_unix().saturating_sub(board.updated_unix) <= max_age => {
+                for report in board.reports.values() {
+                    let h = &report.health;
+                    println!(
+                        "- {:<12} [{:?}]  latency={:6.1} ms  failure_rate={:.3}  enabled={}  from {} {}s ago{}",
+                        h.name,
+                        h.kind,
+                        h.latency_ms,
+                        h.failure_rate,
+                        h.enabled,
+                        report.node,
+                        now_unix().saturating_sub(report.observed_unix),
+                        if report.stale { "  [stale: previous run]" } else { "" }
+                    );
+                    if let Some(hist) = board.histograms.get(&h.name) {
+                        if let (Some(p50), Some(p90)) = (hist.quantile(0.5), hist.quantile(0.9)) {
+                            println!(
+                                "  {:<12} latency p50 < {} ms, p90 < {} ms ({} samples)",
+                                "",
+                                p50,
+                                p90,
+                                hist.total()
+                            );
+                        }
+                    }
+                }
+            }
+            _ => println!("(no fresh {}: is the dispatcher running?)", HEALTH_PATH),
+        }
+        print_availability(cfg);
+    }
+
+    let mut quotas: Vec<_> = cfg
+        .limits
+        .iter()
+        .filter_map(|(name, l)| l.monthly_quota_mb.map(|mb| (name, l, mb)))
+        .collect();
+    if !quotas.is_empty() {
+        quotas.sort_by_key(|(name, _, _)| name.as_str());
+        println!();
+        println!("=== Monthly quotas ===");
+        for (name, limits, mb) in quotas {
+            let used_mb = usage.used(name, limits) as f64 / (1024.0 * 1024.0);
+            println!(
+                "- {:<12} used={:.1} / {} MiB  resets on day {}{}",
+                name,
+                used_mb,
+                mb,
+                limits.reset_day(),
+                if usage.exhausted(name, limits) {
+                    "  [DRAINED]"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+
+    let mut users: Vec<_> = cfg
+        .users
+        .iter()
+        .filter_map(|(name, u)| u.monthly_quota_mb.map(|mb| (name, u.limits(), mb)))
+        .collect();
+    if !users.is_empty() {
+        users.sort_by_key(|(name, _, _)| name.as_str());
+        // Without the key, sealed usage reads as none
+        let sealer = open_sealer(cfg, false);
+        let usage = UsageLedger::load(
+            USER_USAGE_PATH,
+            sealer.as_ref().ok().and_then(Option::as_ref),
+        );
+        println!();
+        println!("=== User quotas ===");
+        if let Err(e) = &sealer {
+            println!("(usage unreadable: {})", e);
+        }
+        for (name, limits, mb) in users {
+            let used_mb = usage.used(name, &limits) as f64 / (1024.0 * 1024.0);
+            println!(
+                "- {:<12} used={:.1} / {} MiB  resets on day {}{}",
+                name,
+                used_mb,
+                mb,
+                limits.reset_day(),
+                if usage.exhausted(name, &limits) {
+                    "  [REFUSED]"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+}
+
+/// Uptime per backend over the rolling windows, from the dispatcher's
+/// report counts, and how much of the `[failover]` error budget is spent.
+fn print_availability(cfg: &GoldDustConfig) {
+    let ledger = AvailabilityLedger::load(AVAILABILITY_PATH);
+    if ledger.backends.is_empty() {
+        return;
+    }
+    println!();
+    println!("=== Availability (share of reports up) ===");
+    let now = now_unix();
+    for name in ledger.backends.keys() {
+        let windows: Vec<String> = WINDOWS
+            .iter()
+            .map(|(label, secs)| match ledger.uptime(name, now, *secs) {
+                Some(up) => format!("{}={:6.2}%", label, up * 100.0),
+                None => format!("{}={:>7}", label, "-"),
+            })
+            .collect();
+        let budget = match ledger.budget_spent(name, now, &cfg.failover) {
+            Some(spent) if spent > 1.0 => format!("  budget {:.0}% spent, BURNT", spent * 100.0),
+            Some(spent) => format!("  budget {:.0}% spent", spent * 100.0),
+            None => String::new(),
+        };
+        println!(
+            "- {:<12} {}  ({} reports){}",
+            name,
+            windows.join("  "),
+            ledger.reports(name),
+            budget
         );
     }
 }
 
-fn print_route_decision(target: &str, choice: &BackendChoice) {
+/// Service nodes oxend knows of that pass the version checks, or why
+/// asking failed; `None` without `[discovery] oxend_rpc`.
+fn discover_nodes(cfg: &GoldDustConfig) -> Option<Result<Discovered, String>> {
+    let rpc = Url::parse(cfg.discovery.oxend_rpc.as_ref()?).ok()?;
+    let rt = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(e) => return Some(Err(e.to_string())),
+    };
+    Some(
+        rt.block_on(async {
+            tokio::time::timeout(DISCOVERY_TIMEOUT, discovery::discover(&cfg.discovery, &rpc)).await
+        })
+        .map_err(|_| "oxend did not answer in time".to_string())
+        .and_then(|found| found.map_err(|e| e.to_string())),
+    )
+}
+
+fn print_discovery(cfg: &GoldDustConfig, discovered: &Result<Discovered, String>) {
+    println!();
+    println!("=== Oxen service node discovery ===");
+    let found = match discovered {
+        Ok(found) => found,
+        Err(e) => {
+            println!("(failed: {})", e);
+            return;
+        }
+    };
+    let total = found.nodes.len() + found.rejected();
+    println!("Accepted: {} of {} active nodes", found.nodes.len(), total);
+    if found.rejected() > 0 {
+        let mut reasons = Vec::new();
+        if found.too_old > 0 {
+            let min = cfg.discovery.min_version.unwrap_or(Version([0, 0, 0]));
+            reasons.push(format!("{} below {}", found.too_old, min));
+        }
+        if found.known_bad > 0 {
+            let bad: Vec<String> = cfg
+                .discovery
+                .bad_versions
+                .iter()
+                .map(Version::to_string)
+                .collect();
+            reasons.push(format!(
+                "{} known-bad ({})",
+                found.known_bad,
+                bad.join(", ")
+            ));
+        }
+        if found.unusable > 0 {
+            reasons.push(format!("{} without a version or address", found.unusable));
+        }
+        println!("Rejected: {}", reasons.join(", "));
+    }
+    let mut versions: Vec<Version> = found.nodes.iter().map(|n| n.version).collect();
+    versions.sort_unstable();
+    versions.dedup();
+    if let (Some(oldest), Some(newest)) = (versions.first(), versions.last()) {
+        println!("Versions: {} to {}", oldest, newest);
+    }
+}
+
+fn print_status_diff(router: &Router, file: &Path) -> Result<(), Box<dyn Error>> {
+    let earlier = load_snapshot(file)?;
+    let now = router.snapshot();
+
+    println!(
+        "=== Backend changes since {} ({}s ago) ===",
+        file.display(),
+        now.taken_unix.saturating_sub(earlier.taken_unix)
+    );
+    let mut counts = [0usize; 5];
+    for d in now.diff(&earlier) {
+        let (label, slot) = match d.trend {
+            Trend::Better => ("[BETTER]", 0),
+            Trend::Worse => ("[WORSE]", 1),
+            Trend::Unchanged => ("", 2),
+            Trend::Added => ("[NEW]", 3),
+            Trend::Removed => ("[GONE]", 4),
+        };
+        counts[slot] += 1;
+        let detail = match (&d.before, &d.after) {
+            (Some(b), Some(a)) => {
+                let mut detail = format!(
+                    "latency={:6.1} -> {:6.1} ms  failure_rate={:.3} -> {:.3}",
+                    b.latency_ms, a.latency_ms, b.failure_rate, a.failure_rate
+                );
+                if b.enabled != a.enabled {
+                    detail.push_str(if a.enabled { "  (back up)" } else { "  (down)" });
+                }
+                detail
+            }
+            (None, Some(h)) | (Some(h), None) => format!(
+                "latency={:6.1} ms  failure_rate={:.3}  enabled={}",
+                h.latency_ms, h.failure_rate, h.enabled
+            ),
+            (None, None) => String::new(),
+        };
+        println!("- {:<12} {:<8}  {}", d.name, label, detail);
+    }
+    println!(
+        "{} better, {} worse, {} unchanged, {} new, {} gone",
+        counts[0], counts[1], counts[2], counts[3], counts[4]
+    );
+    Ok(())
+}
+
+fn print_blocklist(cfg: &GoldDustConfig, blocklist: &Blocklist, blocked: &[String]) {
+    if cfg.blocklist.sources.is_empty() && cfg.blocklist.entries.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("=== Blocklist ===");
+    if blocklist.updated_unix == 0 && !cfg.blocklist.sources.is_empty() {
+        println!("(sources not fetched yet: the dispatcher refreshes them)");
+    }
+    for source in &blocklist.sources {
+        match &source.error {
+            Some(e) => println!("- {:<40} error: {}", source.source, e),
+            None => println!("- {:<40} {} entries", source.source, source.entries),
+        }
+    }
+    println!(
+        "{} entries total, {} candidate(s) filtered{}",
+        blocklist.entries.len(),
+        blocked.len(),
+        if blocked.is_empty() {
+            String::new()
+        } else {
+            format!(": {}", blocked.join(", "))
+        }
+    );
+}
+
+fn print_route_decision(target: &Target, choice: &BackendChoice) {
     println!("=== Gold Dust Gateway route decision ===");
     println!("Target:   {}", target);
     println!("Backend:  {} [{:?}]", choice.name, choice.kind);
     println!("Latency:  {:.1} ms", choice.latency_ms);
     println!("Failure:  {:.3}", choice.failure_rate);
+    if target.is_ipv6() {
+        println!(
+            "IPv6:     target is IPv6, backend {}",
+            if choice.ipv6 {
+                "reaches IPv6"
+            } else {
+                "is IPv4-only (no IPv6-capable backend available)"
+            }
+        );
+    }
     println!(
         "Decision: use {} ({})",
         choice.name,
-        backend_label(choice.kind.clone())
+        backend_label(choice.kind)
+    );
+}
+
+/// What the dispatcher does with a `[lan]` destination instead of routing it.
+fn lan_effect(action: LanAction) -> &'static str {
+    match action {
+        LanAction::Direct => "the dispatcher connects directly, not through the backend",
+        LanAction::Reject => "the dispatcher refuses it",
+        LanAction::Off => "routed as usual",
+    }
+}
+
+/// Resolve a named target from `[dns.hosts]`, else via DoH or DoT when
+/// `[dns] mode` asks for it.
+///
+/// Returns the resolved addresses and a one-line description of the step.
+fn pre_resolve(cfg: &GoldDustConfig, target: &Target) -> (Vec<IpAddr>, String) {
+    if target.host.ip().is_some() {
+        return (Vec::new(), "not needed (IP literal)".to_string());
+    }
+    let host = target.host_str();
+    match Hosts::load(&cfg.dns) {
+        Ok(hosts) => {
+            if let Some(ips) = hosts.get(&host) {
+                let list: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+                let line = format!(
+                    "hosts {} -> {} (fixed, dialed as such)",
+                    host,
+                    list.join(", ")
+                );
+                return (ips.to_vec(), line);
+            }
+        }
+        Err(e) => println!("Hosts:    {} (ignored)", e),
+    }
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build();
+    let (how, resolved) = match cfg.dns.mode {
+        DnsMode::System => {
+            return (
+                Vec::new(),
+                "skipped (dns.mode = system: rules see the host name only)".to_string(),
+            );
+        }
+        DnsMode::Remote => {
+            return (
+                Vec::new(),
+                "none (dns.mode = remote: name passed unresolved to the backend)".to_string(),
+            );
+        }
+        DnsMode::Doh => (
+            format!("DoH {}", cfg.dns.doh_url),
+            DohResolver::new(&cfg.dns.doh_url, cfg.dns.cache())
+                .map_err(Into::into)
+                .and_then(|r| rt?.block_on(r.resolve(&host))),
+        ),
+        DnsMode::Dot => {
+            // Through the flag's egress, as the dispatcher would
+            let socks = fs::read_to_string(FLAG_PATH)
+                .map(|s| s.trim() == "on")
+                .unwrap_or(true)
+                .then_some(cfg.backends.tor_socks);
+            let resolver = DotResolver::new(
+                cfg.dns.dot_servers.clone(),
+                cfg.dns.dot_profile,
+                cfg.dns.cache(),
+            );
+            let via = |server: Target| async move {
+                let stream: Box<dyn Upstream> = match socks {
+                    Some(proxy) => Box::new(
+                        Socks5Stream::connect(proxy, server.socks_addr())
+                            .await?
+                            .into_inner(),
+                    ),
+                    None => Box::new(TcpStream::connect((server.host_str(), server.port)).await?),
+                };
+                Ok(stream)
+            };
+            let servers: Vec<String> = cfg.dns.dot_servers.iter().map(|s| s.to_string()).collect();
+            (
+                format!(
+                    "DoT {} ({}, via {})",
+                    servers.join(", "),
+                    match cfg.dns.dot_profile {
+                        DotProfile::Strict => "strict",
+                        DotProfile::Opportunistic => "opportunistic",
+                    },
+                    if socks.is_some() { "tor" } else { "direct" }
+                ),
+                rt.map_err(Into::into)
+                    .and_then(|rt| rt.block_on(resolver.resolve(&host, "cli", via))),
+            )
+        }
+    };
+    match resolved {
+        Ok(res) => {
+            let ips: Vec<String> = res.ips.iter().map(|ip| ip.to_string()).collect();
+            let line = format!(
+                "{} -> {} (ttl {}s, {})",
+                how,
+                ips.join(", "),
+                res.ttl,
+                if res.cached { "cached" } else { "fresh" }
+            );
+            (res.ips, line)
+        }
+        Err(e) => (
+            Vec::new(),
+            format!("{} failed: {} (rules see the host name only)", how, e),
+        ),
+    }
+}
+
+fn print_route_explanation(
+    cfg: &GoldDustConfig,
+    router: &mut Router,
+    target: &Target,
+    needs: &Requirements,
+) -> Result<BackendChoice, Box<dyn Error>> {
+    println!("=== Gold Dust Gateway route explanation ===");
+    println!("1) Target:   {}", target);
+
+    // LAN names are never looked up, as in the dispatcher
+    let lan = Lan::new(&cfg.lan)?;
+    let (resolved, resolve_line) = match lan.check(&target.host, &[]) {
+        Some(LanMatch::Name(_)) => (
+            Vec::new(),
+            "skipped (LAN name, never sent to a resolver)".to_string(),
+        ),
+        _ => pre_resolve(cfg, target),
+    };
+    println!("2) Resolve:  {}", resolve_line);
+    if let Some(why) = lan.check(&target.host, &resolved) {
+        println!("   LAN:      {} ({})", why, lan_effect(lan.action()));
+    }
+
+    // Rules whose pattern matched but whose `when` did not hold
+    let conditions = cfg.rule_conditions()?;
+    let mut first = None;
+    for i in cfg.rule_matcher()?.matches(&target.host, &resolved) {
+        match conditions.check(i, target, &resolved) {
+            Ok(true) => {
+                first = Some(i);
+                break;
+            }
+            Ok(false) => println!(
+                "   Rules:    rule #{} `{}` skipped (when `{}` is false)",
+                i,
+                cfg.rules[i].host,
+                cfg.rules[i].when.as_deref().unwrap_or("")
+            ),
+            Err(e) => println!("   Rules:    rule #{} skipped ({})", i, e),
+        }
+    }
+
+    match first.map(|i| (i, &cfg.rules[i])) {
+        Some((i, rule)) => {
+            let mut effects = Vec::new();
+            if let Some(n) = rule.connections_per_minute {
+                effects.push(format!("connections_per_minute={}", n));
+            }
+            if let Some(n) = rule.bandwidth_kbps {
+                effects.push(format!("bandwidth_kbps={}", n));
+            }
+            println!(
+                "3) Rules:    rule #{} `{}` matched ({})",
+                i,
+                rule.host,
+                if effects.is_empty() {
+                    "no limits".to_string()
+                } else {
+                    effects.join(", ")
+                }
+            );
+        }
+        None => println!("3) Rules:    no rule matched ({} checked)", cfg.rules.len()),
+    }
+
+    match (router.group_for(target), router.nearest_group()) {
+        (Some(group), _) => println!(
+            "   Group:    prefers {} ({})",
+            group,
+            cfg.groups[group].backends.join(", ")
+        ),
+        (None, Some(group)) => println!(
+            "   Group:    leans toward {}, the nearest, for {:.0}% of decisions",
+            group,
+            router.vantage_bias() * 100.0
+        ),
+        (None, None) => {}
+    }
+    let ineligible = router.ineligible(needs);
+    for (name, what) in &ineligible {
+        println!("   Capable:  {} skipped (no {})", name, what);
+    }
+    let choice = router
+        .choose_backend_with(target, needs)
+        .ok_or(NO_BACKENDS)?;
+    let source = match (&cfg.routing.policy, router.take_policy_error()) {
+        _ if ineligible.iter().any(|(name, _)| *name == choice.name) => {
+            "no capable backend, absolute fallback".to_string()
+        }
+        (Some(path), None) => format!("ranked by policy {}", path.display()),
+        (Some(_), Some(e)) => format!("policy failed: {}; {}", e, backend_label(choice.kind)),
+        (None, _) => backend_label(choice.kind).to_string(),
+    };
+    println!(
+        "4) Backend:  {} [{:?}] ({})",
+        choice.name, choice.kind, source
+    );
+    let pins = ExitPins::from_rules(&cfg.rules)?;
+    if let Some(exits) = pins
+        .as_ref()
+        .and_then(|p| p.exits_for(&target.host, &resolved))
+    {
+        println!(
+            "   Exit:     pinned to {} in the dispatcher (through Tor only, via {})",
+            exits.join(" or "),
+            cfg.tor_control.address
+        );
+    }
+    Ok(choice)
+}
+
+/// The decision for `target`, seen from a backend that didn't get it.
+fn print_why_not(
+    cfg: &GoldDustConfig,
+    router: &mut Router,
+    blocked: &[String],
+    drained: impl Fn(BackendKind) -> bool,
+    name: &str,
+    target: &Target,
+    needs: &Requirements,
+) -> Result<(), Box<dyn Error>> {
+    let health = router.backend_health();
+    let Some(backend) = health.iter().find(|b| b.name.eq_ignore_ascii_case(name)) else {
+        if let Some(name) = blocked.iter().find(|b| b.eq_ignore_ascii_case(name)) {
+            println!("=== Why not {} for {} ===", name, target);
+            println!("- blocklisted: never a candidate (see `status`)");
+            return Ok(());
+        }
+        let known: Vec<&str> = health.iter().map(|b| b.name.as_str()).collect();
+        return Err(format!("no backend named {} (known: {})", name, known.join(", ")).into());
+    };
+
+    let choice = router
+        .choose_backend_with(target, needs)
+        .ok_or(NO_BACKENDS)?;
+    let policy_error = router.take_policy_error();
+    let by_policy = cfg.routing.policy.is_some() && policy_error.is_none();
+    let reasons = router
+        .why_not(&backend.name, target, needs, &choice, by_policy)
+        .expect("backend is known to the router");
+
+    println!("=== Why not {} for {} ===", backend.name, target);
+    println!(
+        "Chosen:   {} [{:?}] ({})",
+        choice.name,
+        choice.kind,
+        backend_label(choice.kind)
+    );
+    if let Some(e) = &policy_error {
+        println!("Policy:   failed, built-in order used: {}", e);
+    }
+    if reasons.is_empty() {
+        let capable = !router
+            .ineligible(needs)
+            .iter()
+            .any(|(n, _)| *n == backend.name);
+        if backend.enabled && capable && !router.saturated(backend) {
+            println!("{} is the backend chosen.", backend.name);
+        } else {
+            println!(
+                "{} is chosen, but only as the absolute fallback: no backend is usable",
+                backend.name
+            );
+        }
+        return Ok(());
+    }
+    for reason in reasons {
+        let line = match reason {
+            Rejection::Disabled if backend.kind == BackendKind::Chain => {
+                "down: a hop has no enabled backend".to_string()
+            }
+            Rejection::Disabled if drained(backend.kind) => format!(
+                "down: the {} monthly quota is used up",
+                backend.kind.as_str()
+            ),
+            Rejection::Disabled => {
+                "down: reported disabled (taken again as soon as it reports up)".to_string()
+            }
+            Rejection::Saturated => "at its max_sessions cap in the dispatcher".to_string(),
+            Rejection::Lacks(what) => format!("cannot carry {} (see [capabilities])", what),
+            Rejection::Policy => format!("the routing policy ranked {} higher", choice.name),
+            Rejection::Group(group) => format!(
+                "a rule prefers group {} for this target, and {} is in it",
+                group, choice.name
+            ),
+            Rejection::Nearest(group) => format!(
+                "{} is in {}, the nearest group, tried first for {:.0}% of decisions",
+                choice.name,
+                group,
+                router.vantage_bias() * 100.0
+            ),
+            Rejection::NoIpv6 => format!(
+                "IPv6 target: {} reaches IPv6, {} does not",
+                choice.name, backend.name
+            ),
+            Rejection::Order(kind) => format!(
+                "a usable {} backend comes first (chain, oxen, tor, masque)",
+                kind.as_str()
+            ),
+            Rejection::Balance {
+                balance: balance @ (Balance::Random | Balance::RoundRobin),
+                ..
+            } => format!(
+                "same pool, {} balancing picked {} this time",
+                balance.as_str(),
+                choice.name
+            ),
+            Rejection::Balance {
+                balance,
+                score,
+                chosen_score,
+            } => format!(
+                "same pool, {} balancing picked {} this time (score {:.2} vs {:.2})",
+                balance.as_str(),
+                choice.name,
+                score,
+                chosen_score
+            ),
+        };
+        println!("- {}", line);
+    }
+    Ok(())
+}
+
+fn run_simulation(router: &mut Router, path: &PathBuf) -> Result<(), Box<dyn Error>> {
+    let scenario = Scenario::load(path)?;
+
+    println!("=== Gold Dust Gateway simulation: {} ===", path.display());
+    for step in scenario.run(router)? {
+        match &step.note {
+            Some(note) => println!("[t={}s] {}", step.t, note),
+            None => println!("[t={}s]", step.t),
+        }
+        for name in &step.unknown_backends {
+            println!("  ! unknown backend in event: {}", name);
+        }
+        for e in &step.policy_errors {
+            println!("  ! policy failed, built-in order used: {}", e);
+        }
+        for (target, choice) in &step.decisions {
+            println!(
+                "  {:<24} -> {} [{:?}]  latency={:.1} ms  failure={:.3}",
+                target.to_string(),
+                choice.name,
+                choice.kind,
+                choice.latency_ms,
+                choice.failure_rate
+            );
+        }
+    }
+
+    let total: u64 = router.picks().values().sum();
+    if total > 0 {
+        println!();
+        println!("=== Distribution ({} decisions) ===", total);
+        for (name, n) in router.picks() {
+            println!(
+                "- {:<12} {:>6}  {:5.1}%",
+                name,
+                n,
+                *n as f64 * 100.0 / total as f64
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn check_exit_ips(cfg: &GoldDustConfig, url: &str) -> Result<(), Box<dyn Error>> {
+    let url = Url::parse(url)?;
+    let mut paths = vec![("direct", exitip::Path::Direct)];
+    if cfg.backends.tor_enabled {
+        paths.push(("tor", exitip::Path::Socks(cfg.backends.tor_socks)));
+    }
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    println!("=== Gold Dust Gateway exit IPs ({}) ===", url.authority());
+    let mut direct_ip = None;
+    for (name, path) in paths {
+        match rt.block_on(exitip::check(&url, path)) {
+            Ok(exit) => {
+                let note = match (&direct_ip, name) {
+                    (Some(ip), "tor") if ip == &exit.ip => "  !! same as direct: not anonymized",
+                    _ => "",
+                };
+                println!(
+                    "- {:<8} ip={:<40} country={}{}",
+                    name,
+                    exit.ip,
+                    exit.country.as_deref().unwrap_or("?"),
+                    note
+                );
+                if name == "direct" {
+                    direct_ip = Some(exit.ip);
+                }
+            }
+            Err(e) => println!("- {:<8} error: {}", name, e),
+        }
+    }
+    if cfg.backends.oxen_enabled {
+        println!(
+            "- {:<8} skipped: lokinet exits are system-routed, no local proxy to dial",
+            "oxen"
+        );
+    }
+
+    Ok(())
+}
+
+fn run_dns_leaktest(
+    service: &str,
+    proxy: SocketAddr,
+    lookups: usize,
+) -> Result<(), Box<dyn Error>> {
+    // Same rule as the dispatcher: Tor unless the flag file says "off"
+    let via_tor = fs::read_to_string(FLAG_PATH)
+        .map(|s| s.trim() == "on")
+        .unwrap_or(true);
+
+    println!("=== Gold Dust Gateway DNS leak test ===");
+    println!(
+        "Gateway:   {} (egress: {})",
+        proxy,
+        if via_tor { "tor" } else { "direct" }
+    );
+
+    let report = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(leaktest::dns(service, proxy, lookups))
+        .map_err(|e| e.to_string())?;
+
+    println!(
+        "Test id:   {} ({} lookups sent)",
+        report.id, report.lookups_sent
+    );
+    if let Some(local) = &report.local {
+        println!(
+            "Local:     {}  {}  {}",
+            local.ip,
+            local.asn.as_deref().unwrap_or("?"),
+            local.country.as_deref().unwrap_or("?")
+        );
+    }
+    println!("Resolvers seen: {}", report.resolvers.len());
+    for r in &report.resolvers {
+        println!(
+            "- {:<40} {}  {}",
+            r.ip,
+            r.asn.as_deref().unwrap_or("?"),
+            r.country.as_deref().unwrap_or("?")
+        );
+    }
+
+    let leaks = report.leaks();
+    let verdict = if report.resolvers.is_empty() {
+        "INCONCLUSIVE: no resolver reached the test service".to_string()
+    } else if !via_tor {
+        "n/a: egress is direct, so the local resolver is expected".to_string()
+    } else if leaks.is_empty() {
+        "OK: every lookup was resolved outside the local network".to_string()
+    } else {
+        format!(
+            "LEAK: {} resolver(s) on the local network saw the lookups",
+            leaks.len()
+        )
+    };
+    println!("Verdict:   {}", verdict);
+    Ok(())
+}
+
+fn run_export(
+    cfg: &GoldDustConfig,
+    data: ExportData,
+    log: Option<PathBuf>,
+    filter: &Filter,
+    format: Format,
+    output: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let (configured, setting) = match data {
+        ExportData::Traffic => (&cfg.logging.traffic_log, "traffic_log"),
+        ExportData::Health => (&cfg.logging.health_log, "health_log"),
+    };
+    let path = log
+        .or_else(|| configured.clone())
+        .ok_or_else(|| format!("no log to read: set [logging] {} or pass --log", setting))?;
+    let lines = read_log(cfg, &path)?;
+
+    // A torn last line (dispatcher killed mid-write) is skipped, not fatal
+    let table = match data {
+        ExportData::Traffic => Table::traffic(
+            &lines
+                .iter()
+                .filter_map(|l| serde_json::from_str::<TrafficEntry>(l).ok())
+                .filter(|e| filter.keep_traffic(e))
+                .collect::<Vec<_>>(),
+        ),
+        ExportData::Health => Table::health(
+            &lines
+                .iter()
+                .filter_map(|l| serde_json::from_str::<Report>(l).ok())
+                .filter(|r| filter.keep_report(r))
+                .collect::<Vec<_>>(),
+        ),
+    };
+    table.write(format, output.as_deref())?;
+    if let Some(output) = &output {
+        eprintln!(
+            "Exported {} row(s) to {}",
+            table.rows.len(),
+            output.display()
+        );
+    }
+    Ok(())
+}
+
+fn parse_egress(s: &str) -> Result<EgressKind, String> {
+    match s {
+        "tor" => Ok(EgressKind::Tor),
+        "direct" => Ok(EgressKind::Direct),
+        "masque" => Ok(EgressKind::Masque),
+        other => Err(format!("unknown mode `{other}` (tor, direct, masque)")),
+    }
+}
+
+/// The flag file's egress, read as the dispatcher reads it.
+fn flag_egress() -> EgressKind {
+    match fs::read_to_string(FLAG_PATH) {
+        Ok(s) if s.trim() == "masque" => EgressKind::Masque,
+        Ok(s) if s.trim() != "on" => EgressKind::Direct,
+        _ => EgressKind::Tor,
+    }
+}
+
+fn run_replay(
+    cfg: &GoldDustConfig,
+    log: Option<PathBuf>,
+    mode: Option<EgressKind>,
+    filter: &Filter,
+) -> Result<(), Box<dyn Error>> {
+    let path = log
+        .or_else(|| cfg.logging.traffic_log.clone())
+        .ok_or("no log to read: set [logging] traffic_log or pass a path")?;
+    let lines = read_log(cfg, &path)?;
+    let entries: Vec<TrafficEntry> = lines
+        .iter()
+        .filter_map(|l| serde_json::from_str::<TrafficEntry>(l).ok())
+        .filter(|e| filter.keep_traffic(e))
+        .collect();
+    let mode = mode.unwrap_or_else(flag_egress);
+    let report = Replay::new(cfg, mode)
+        .map_err(|e| e.to_string())?
+        .run(&entries);
+
+    println!(
+        "=== Replay: {} ({} session(s), mode {}) ===",
+        path.display(),
+        entries.len(),
+        mode.as_str()
+    );
+    for change in &report.changes {
+        let who: Vec<&str> = [&change.user, &change.app]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        let label = match who.as_slice() {
+            [] => change.target.clone(),
+            who => format!("{} ({})", change.target, who.join(", ")),
+        };
+        println!(
+            "{:>5}x  {:<40} {} -> {}",
+            change.sessions, label, change.before, change.after
+        );
+    }
+    if !report.changes.is_empty() {
+        println!();
+    }
+    println!(
+        "{} of {} session(s) would be decided differently, {} target(s)",
+        report.changed_sessions(),
+        report.replayed,
+        report.changed_targets()
     );
+    if !report.skipped.is_empty() {
+        let skipped: Vec<String> = report
+            .skipped
+            .iter()
+            .map(|(outcome, n)| format!("{} {}", outcome, n))
+            .collect();
+        println!(
+            "Not replayed (live state or no decision): {}",
+            skipped.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// `replay --verify`: make each recorded decision again from its inputs.
+fn run_verify(
+    cfg: &GoldDustConfig,
+    router: &mut Router,
+    log: Option<PathBuf>,
+    filter: &Filter,
+) -> Result<(), Box<dyn Error>> {
+    let path = log
+        .or_else(|| cfg.routing.record.clone())
+        .ok_or("no decisions to verify: set [routing] record or pass a path")?;
+    let records: Vec<Recorded> = recording::read(&path)?
+        .into_iter()
+        .filter(|r| filter.keep_decision(r))
+        .collect();
+
+    println!(
+        "=== Verify: {} ({} decision(s)) ===",
+        path.display(),
+        records.len()
+    );
+    let (mut changed, mut unverifiable) = (0, 0);
+    for recorded in &records {
+        let before = format!("{} [{}]", recorded.chosen, recorded.kind.as_str());
+        match recording::verify(router, recorded).ok_or(NO_BACKENDS)? {
+            Verdict::Identical => {}
+            Verdict::Changed {
+                chosen,
+                kind,
+                policy_error,
+            } => {
+                changed += 1;
+                println!(
+                    "{}  {:<32} {} -> {} [{}]",
+                    recorded.time(),
+                    recorded.target.to_string(),
+                    before,
+                    chosen,
+                    kind.as_str()
+                );
+                match (&recorded.policy_error, &policy_error) {
+                    (None, Some(e)) => println!("      policy failed this time: {}", e),
+                    (Some(e), None) => println!("      policy failed then: {}", e),
+                    _ => {}
+                }
+            }
+            Verdict::RulesChanged(now) => {
+                unverifiable += 1;
+                let then = &recorded.rules;
+                let name = |group: &Option<String>| group.clone().unwrap_or("-".to_string());
+                let mut differences = Vec::new();
+                if then.balance != now.balance {
+                    differences.push(format!(
+                        "balance {} -> {}",
+                        then.balance.as_str(),
+                        now.balance.as_str()
+                    ));
+                }
+                if then.group != now.group {
+                    differences.push(format!(
+                        "group {} -> {}",
+                        name(&then.group),
+                        name(&now.group)
+                    ));
+                }
+                if then.nearest != now.nearest {
+                    differences.push(format!(
+                        "nearest group {} -> {}",
+                        name(&then.nearest),
+                        name(&now.nearest)
+                    ));
+                }
+                println!(
+                    "{}  {:<32} {}, rules changed: {}",
+                    recorded.time(),
+                    recorded.target.to_string(),
+                    before,
+                    differences.join(", ")
+                );
+            }
+        }
+    }
+    if changed + unverifiable > 0 {
+        println!();
+    }
+    println!(
+        "{} identical, {} different, {} under changed rules",
+        records.len() - changed - unverifiable,
+        changed,
+        unverifiable
+    );
+    if changed > 0 {
+        return Err(format!(
+            "{} of {} decision(s) came out differently",
+            changed,
+            records.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn run_firewall(cfg: &GoldDustConfig, action: FirewallAction) -> Result<(), Box<dyn Error>> {
+    let backend = cfg.firewall.backend;
+    let (dry_run, rollback_secs) = match action {
+        FirewallAction::Remove => {
+            firewall::remove(backend)?;
+            println!("Firewall: gold-dust rules removed");
+            record(cfg, Action::new(audit::invoker(), "cli", "firewall remove"));
+            return Ok(());
+        }
+        FirewallAction::Apply {
+            dry_run,
+            rollback_secs,
+        } => (dry_run, rollback_secs),
+    };
+
+    let ruleset = Ruleset::from_config(cfg)?;
+    if dry_run {
+        print!("{}", firewall::render(&ruleset, backend));
+        return Ok(());
+    }
+
+    let previous = firewall::apply(&ruleset, backend)?;
+    println!("Firewall: kill-switch rules applied ({:?})", backend);
+    let applied =
+        Action::new(audit::invoker(), "cli", "firewall apply").to(format!("{:?}", backend));
+    if rollback_secs == 0 {
+        record(cfg, applied);
+        return Ok(());
+    }
+
+    // A rule set that cuts off this session can't be confirmed, so it reverts
+    println!(
+        "Type `yes` within {}s to keep them, anything else rolls back.",
+        rollback_secs
+    );
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        let _ = std::io::stdin().read_line(&mut line);
+        let _ = tx.send(line);
+    });
+    match rx.recv_timeout(std::time::Duration::from_secs(rollback_secs)) {
+        Ok(line) if line.trim() == "yes" => {
+            println!("Firewall: kept");
+            record(cfg, applied);
+        }
+        _ => {
+            firewall::rollback(&previous)?;
+            println!("Firewall: rolled back to the previous rules");
+        }
+    }
+    Ok(())
+}
+
+/// Tor circuits (control port) and lokinet paths (RPC port), as trees.
+fn print_paths(cfg: &GoldDustConfig) -> Result<(), Box<dyn Error>> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let patience = std::time::Duration::from_secs(10);
+    let branch = |last: bool| if last { "└─" } else { "├─" };
+
+    if cfg.backends.tor_enabled {
+        println!(
+            "=== Tor circuits (control port {}) ===",
+            cfg.tor_control.address
+        );
+        let circuits = paths::tor_circuits(&cfg.tor_control);
+        match rt.block_on(async { tokio::time::timeout(patience, circuits).await }) {
+            Ok(Ok(circuits)) if circuits.is_empty() => println!("(no built circuits)"),
+            Ok(Ok(circuits)) => {
+                for circuit in &circuits {
+                    println!(
+                        "circuit {} ({}, {} stream(s))",
+                        circuit.id, circuit.purpose, circuit.streams
+                    );
+                    for (i, relay) in circuit.hops.iter().enumerate() {
+                        println!(
+                            "{} {:<6}  {:<19}  {}…  {:<15}  {}",
+                            branch(i + 1 == circuit.hops.len()),
+                            circuit.role(i),
+                            relay.nickname.as_deref().unwrap_or("?"),
+                            &relay.fingerprint[..17],
+                            relay.address.as_deref().unwrap_or("?"),
+                            relay.country.as_deref().unwrap_or("??")
+                        );
+                    }
+                }
+            }
+            Ok(Err(e)) => println!("unavailable: {}", e),
+            Err(_) => println!("unavailable: no answer in {}s", patience.as_secs()),
+        }
+    }
+
+    if cfg.backends.oxen_enabled {
+        if cfg.backends.tor_enabled {
+            println!();
+        }
+        println!("=== Lokinet paths (RPC {}) ===", cfg.lokinet.rpc);
+        let found = paths::lokinet_paths(cfg.lokinet.rpc);
+        match rt.block_on(async { tokio::time::timeout(patience, found).await }) {
+            Ok(Ok(found)) if found.is_empty() => println!("(no paths)"),
+            Ok(Ok(found)) => {
+                for path in &found {
+                    let state = match path.ready {
+                        Some(true) => " (ready)",
+                        Some(false) => " (building)",
+                        None => "",
+                    };
+                    println!("{}{}", path.owner, state);
+                    for (i, router) in path.hops.iter().enumerate() {
+                        println!(
+                            "{} hop {}  {}",
+                            branch(i + 1 == path.hops.len()),
+                            i + 1,
+                            router
+                        );
+                    }
+                }
+            }
+            Ok(Err(e)) => println!("unavailable: {}", e),
+            Err(_) => println!("unavailable: no answer in {}s", patience.as_secs()),
+        }
+    }
+    Ok(())
+}
+
+/// List, probe and switch lokinet exits.
+fn run_portal(cfg: &GoldDustConfig, action: PortalAction) -> Result<(), Box<dyn Error>> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let detect = || {
+        rt.block_on(async {
+            tokio::time::timeout(PORTAL_TIMEOUT, portal::detect(&cfg.portal)).await
+        })
+        .map_err(|_| "probe timed out".to_string())?
+        .map_err(|e| e.to_string())
+    };
+
+    match action {
+        PortalAction::Check => {
+            println!("=== Captive portal ({}) ===", cfg.portal.probe_url);
+            match detect() {
+                Ok(None) => println!("Probe:    answered as expected, no portal"),
+                Ok(Some(found)) => {
+                    println!("Probe:    redirected to {}", found.login_url);
+                    println!("Portal:   {} (log in with `portal login`)", found.host);
+                }
+                Err(e) => println!("Probe:    {}", e),
+            }
+            match PortalMode::load(PORTAL_PATH) {
+                Some(mode) => println!(
+                    "Mode:     {} direct for {}s more",
+                    mode.hosts.join(", "),
+                    mode.remaining_secs()
+                ),
+                None => println!("Mode:     off"),
+            }
+        }
+        PortalAction::Login { host, mins } => {
+            let (host, login_url) =
+                match host {
+                    Some(host) => (host.to_ascii_lowercase(), None),
+                    None => match detect()? {
+                        Some(found) => (found.host, Some(found.login_url)),
+                        None => return Err(
+                            "no captive portal detected; give its host to let it through anyway"
+                                .into(),
+                        ),
+                    },
+                };
+            let mins = mins.unwrap_or(cfg.portal.login_mins);
+            let before =
+                PortalMode::load(PORTAL_PATH).map_or("off".to_string(), |m| m.hosts.join(", "));
+            let mode = PortalMode {
+                hosts: vec![host],
+                login_url,
+                until_unix: now_unix() + mins * 60,
+            };
+            mode.save(PORTAL_PATH)?;
+            record(
+                cfg,
+                Action::new(audit::invoker(), "cli", "portal login")
+                    .from(before)
+                    .to(format!("{} for {} min", mode.hosts[0], mins)),
+            );
+            println!(
+                "Portal mode: {} goes direct for {} min; everything else stays on its backend.",
+                mode.hosts[0], mins
+            );
+            if let Some(url) = &mode.login_url {
+                println!("Log in at {} (browser pointed at the dispatcher).", url);
+            }
+            println!("It ends once a backend gets through again, or with `portal done`.");
+        }
+        PortalAction::Done => {
+            let before = PortalMode::load(PORTAL_PATH);
+            if PortalMode::end(PORTAL_PATH)? {
+                println!("Portal mode ended.");
+                let mut action = Action::new(audit::invoker(), "cli", "portal done").to("off");
+                if let Some(mode) = before {
+                    action = action.from(mode.hosts.join(", "));
+                }
+                record(cfg, action);
+            } else {
+                println!("Portal mode was off.");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_admin(cfg: &GoldDustConfig, action: AdminAction) -> Result<(), Box<dyn Error>> {
+    let AdminAction::Cert {
+        name,
+        server,
+        sans,
+        days,
+        out,
+    } = action;
+    let ca_cert = &cfg.admin.ca_cert;
+    let (ca, created) = Authority::open_or_create(ca_cert)?;
+    if created {
+        println!(
+            "Created the admin CA: {} (key: {}; keep it here)",
+            ca_cert.display(),
+            ca_cert.with_extension("key").display()
+        );
+    }
+    let usage = if server { Usage::Server } else { Usage::Client };
+    let issued = ca.issue(&name, usage, &sans, days)?;
+    let (cert, key) = match (server, out) {
+        (true, None) => (cfg.admin.cert.clone(), cfg.admin.key.clone()),
+        (true, Some(dir)) => (dir.join(admin::CERT_PATH), dir.join(admin::KEY_PATH)),
+        (false, dir) => {
+            let dir = dir.unwrap_or_default();
+            (
+                dir.join(format!("{name}.pem")),
+                dir.join(format!("{name}.key")),
+            )
+        }
+    };
+    admin::write_new(&key, &issued.key)?;
+    admin::write_new(&cert, &issued.cert)?;
+    let kind = if server { "server" } else { "client" };
+    record(
+        cfg,
+        Action::new(audit::invoker(), "cli", "admin cert")
+            .to(format!("{} `{}` for {} days", kind, name, days)),
+    );
+    println!(
+        "Issued {} for `{}`, good for {} days: {} and {}",
+        if server {
+            "a server certificate"
+        } else {
+            "a client certificate"
+        },
+        name,
+        days,
+        cert.display(),
+        key.display()
+    );
+    if server {
+        println!(
+            "The dispatcher there also needs {} as its [admin] ca_cert.",
+            ca_cert.display()
+        );
+    } else {
+        println!(
+            "List `{}` in the routers' [admin] operators or viewers to let it in.",
+            name
+        );
+        println!(
+            "curl --cacert {} --cert {} --key {} https://<router>:{}/api/status",
+            ca_cert.display(),
+            cert.display(),
+            key.display(),
+            cfg.admin.listen.port()
+        );
+    }
+    Ok(())
+}
+
+/// The `[encryption]` key, if encryption is on. Only writers `create` it.
+fn open_sealer(cfg: &GoldDustConfig, create: bool) -> Result<Option<Sealer>, String> {
+    if !cfg.encryption.enabled {
+        return Ok(None);
+    }
+    Sealer::load(&cfg.encryption.key, create).map(|(sealer, _)| Some(sealer))
+}
+
+/// The plain lines of a log the dispatcher wrote, opened with the
+/// `[encryption]` key where sealed.
+fn read_log(cfg: &GoldDustConfig, path: &Path) -> Result<Vec<String>, String> {
+    let lines = logfile::read_lines(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    seal::open_lines(lines, open_sealer(cfg, false)?.as_ref())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Add an action by whoever runs us to the audit trail. It has already
+/// happened, so failing to record it only warns.
+fn record(cfg: &GoldDustConfig, action: Action) {
+    let recorded = open_sealer(cfg, true).and_then(|sealer| {
+        audit::record(&cfg.logging.audit_log, &action, sealer.as_ref()).map_err(|e| e.to_string())
+    });
+    if let Err(e) = recorded {
+        eprintln!(
+            "warning: could not record to {}: {}",
+            cfg.logging.audit_log.display(),
+            e
+        );
+    }
+}
+
+/// `audit list`: the trail, oldest first.
+fn list_audit(
+    cfg: &GoldDustConfig,
+    since: Option<u64>,
+    who: Option<&str>,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let path = &cfg.logging.audit_log;
+    let actions = audit::read(path, open_sealer(cfg, false)?.as_ref())?;
+    let shown = actions
+        .iter()
+        .filter(|a| since.is_none_or(|since| a.unix >= since))
+        .filter(|a| who.is_none_or(|who| a.who == who || a.who.starts_with(&format!("{who} ("))));
+    let mut any = false;
+    for action in shown {
+        any = true;
+        if json {
+            println!("{}", serde_json::to_string(action)?);
+            continue;
+        }
+        let change = match (&action.from, &action.to) {
+            (Some(from), Some(to)) => format!("{} -> {}", from, to),
+            (None, Some(to)) => to.clone(),
+            (Some(from), None) => format!("{} -> (gone)", from),
+            (None, None) => String::new(),
+        };
+        println!(
+            "{}  {:<24} {:<9} {:<15} {}",
+            action.time(),
+            action.who,
+            action.via,
+            action.action,
+            change
+        );
+    }
+    if !any && !json {
+        match actions.is_empty() {
+            true => println!("No actions recorded in {}.", path.display()),
+            false => println!("No matching actions."),
+        }
+    }
+    Ok(())
+}
+
+fn run_lokinet(cfg: &GoldDustConfig, action: LokinetAction) -> Result<(), Box<dyn Error>> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let rpc = cfg.lokinet.rpc;
+    let prober = TcpProber::local_daemons(cfg);
+    let probe = |address: &str| {
+        let target = prober.targets().iter().find(|t| t.name == address)?;
+        Some(prober.probe(target))
+    };
+    // What was active before a switch, for the audit trail
+    let active_exit = || {
+        let status = rt.block_on(lokinet::status(rpc)).ok()?;
+        let active: Vec<String> = lokinet::active_exits(&status)
+            .into_iter()
+            .map(|(_, exit)| exit)
+            .collect();
+        Some(match active.is_empty() {
+            true => "none".to_string(),
+            false => active.join(", "),
+        })
+    };
+    let record_exit = |before: Option<String>, after: &str| {
+        let mut action = Action::new(audit::invoker(), "cli", "lokinet exit").to(after);
+        if let Some(before) = before {
+            action = action.from(before);
+        }
+        record(cfg, action);
+    };
+
+    match action {
+        LokinetAction::Exits => {
+            println!("=== Lokinet exits (RPC {}) ===", rpc);
+            let active = match rt.block_on(lokinet::status(rpc)) {
+                Ok(status) => {
+                    let active = lokinet::active_exits(&status);
+                    match active.as_slice() {
+                        [] => println!("Active: none (clearnet traffic leaves directly)"),
+                        mapped => {
+                            for (range, exit) in mapped {
+                                println!("Active: {} for {}", exit, range);
+                            }
+                        }
+                    }
+                    active.into_iter().map(|(_, exit)| exit).collect()
+                }
+                Err(e) => {
+                    println!("Active: unknown ({})", e);
+                    Vec::new()
+                }
+            };
+            if cfg.lokinet.exits.is_empty() {
+                println!("No exits configured: add [[lokinet.exits]] to the config.");
+            }
+            for (i, exit) in cfg.lokinet.exits.iter().enumerate() {
+                let reach = match probe(&exit.address) {
+                    Some(h) if h.enabled => format!("reachable  {:6.1} ms", h.latency_ms),
+                    _ => "UNREACHABLE".to_string(),
+                };
+                println!(
+                    "#{:<3} {:<40} {:<8} {}{}",
+                    i,
+                    exit.address,
+                    if exit.token.is_some() { "token" } else { "-" },
+                    reach,
+                    if active.contains(&exit.address) {
+                        "  [ACTIVE]"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        LokinetAction::Use {
+            exit: Some(address),
+        } => {
+            let exit = match cfg.lokinet.exits.iter().find(|e| e.address == address) {
+                Some(exit) => exit.clone(),
+                None => {
+                    println!(
+                        "{} is not in [[lokinet.exits]]: no auth token sent",
+                        address
+                    );
+                    LokinetExitConfig {
+                        address,
+                        token: None,
+                        probe_port: 80,
+                    }
+                }
+            };
+            let before = active_exit();
+            rt.block_on(lokinet::set_exit(rpc, &exit))
+                .map_err(|e| e.to_string())?;
+            println!("Lokinet exit: {}", exit.address);
+            record_exit(before, &exit.address);
+        }
+        LokinetAction::Use { exit: None } => {
+            for exit in &cfg.lokinet.exits {
+                match probe(&exit.address) {
+                    Some(h) if h.enabled => {
+                        let before = active_exit();
+                        rt.block_on(lokinet::set_exit(rpc, exit))
+                            .map_err(|e| e.to_string())?;
+                        record_exit(before, &exit.address);
+                        println!(
+                            "Lokinet exit: {} (answered in {:.1} ms)",
+                            exit.address, h.latency_ms
+                        );
+                        return Ok(());
+                    }
+                    _ => println!("- {} unreachable, skipped", exit.address),
+                }
+            }
+            return Err("no configured lokinet exit is reachable".into());
+        }
+        LokinetAction::Off => {
+            let before = active_exit();
+            rt.block_on(lokinet::clear_exit(rpc))
+                .map_err(|e| e.to_string())?;
+            println!("Lokinet exit: none");
+            record_exit(before, "none");
+        }
+    }
+    Ok(())
+}
+
+/// Follow the dispatcher's event stream until it goes away.
+fn run_monitor(cfg: &GoldDustConfig, json: bool) -> Result<(), Box<dyn Error>> {
+    if !cfg.dashboard.enabled {
+        return Err("the event stream is served on the dashboard listener: \
+                    set [dashboard] enabled = true"
+            .into());
+    }
+    let addr = cfg.dashboard.listen;
+    // Watching needs only read access; without either token we may still
+    // be a granted user (`[api] viewers`)
+    let token = match cfg.api.auth {
+        true => token::read(&cfg.api.token_file)
+            .or_else(|_| token::read(&cfg.api.read_token_file))
+            .ok(),
+        false => None,
+    };
+    eprintln!("Following dispatcher events on {} (Ctrl-C to stop)", addr);
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(monitor::follow(
+            addr,
+            token.as_deref(),
+            |streamed| match streamed {
+                Streamed::Event(event) if json => {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&event).expect("events serialize")
+                    )
+                }
+                Streamed::Event(event) => println!("{}", event.line()),
+                Streamed::Skipped(n) => eprintln!("(fell behind: {} event(s) skipped)", n),
+            },
+        ))
+        .map_err(|e| format!("{}: {}", addr, e))?;
+    eprintln!("Dispatcher closed the stream");
+    Ok(())
+}
+
+/// Route the fixtures and print a diff of the cases that missed.
+fn run_policy_test(router: &mut Router, path: &Path) -> Result<(), Box<dyn Error>> {
+    let fixtures = Fixtures::load(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let outcomes = fixtures.run(router)?;
+
+    println!("=== Policy test: {} ===", path.display());
+    let mut failed = 0;
+    for (i, outcome) in outcomes.iter().enumerate() {
+        let case = &outcome.case;
+        let label = format!(
+            "#{} {}{}",
+            i,
+            case.target,
+            if case.udp { " (udp)" } else { "" }
+        );
+        let got = format!("{} [{}]", outcome.got.name, outcome.got.kind.as_str());
+        if outcome.passed() {
+            println!("ok    {:<36} -> {}", label, got);
+            continue;
+        }
+        failed += 1;
+        println!("FAIL  {}", label);
+        if let Some(note) = &case.note {
+            println!("      ({})", note);
+        }
+        println!("-     expected: {}", case.expect.allowed().join(" | "));
+        println!("+     got:      {}", got);
+        if let Some(e) = &outcome.policy_error {
+            println!("      policy failed, built-in order used: {}", e);
+        }
+    }
+    println!();
+    println!("{} passed, {} failed", outcomes.len() - failed, failed);
+    if failed > 0 {
+        return Err(format!("{} of {} case(s) failed", failed, outcomes.len()).into());
+    }
+    Ok(())
+}
+
+/// Every rule set as the engines consult it: compiled patterns, in
+/// evaluation order, indexed as in the config.
+fn dump_rules(cfg: &GoldDustConfig) -> Result<(), Box<dyn Error>> {
+    // Fails the same way the dispatcher would on a bad pattern or `when`
+    RateLimiter::from_rules(&cfg.rules)?;
+    println!("=== Rules ===");
+    println!(
+        "Limits (dispatcher): highest-priority, then first, rule whose host and `when` match; \
+         none: unlimited"
+    );
+    print_rules(&cfg.rules)?;
+
+    println!();
+    let overrides: Vec<_> = cfg
+        .rules
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| r.balance.map(|b| (i, r, b)))
+        .collect();
+    println!(
+        "Balance (router): highest-priority, then first, rule with `balance` whose host \
+         matches (`when` not checked); none: {}",
+        cfg.routing.balance.as_str()
+    );
+    if overrides.is_empty() {
+        println!("  (no overrides)");
+    }
+    for (i, rule, balance) in overrides {
+        let pattern: Pattern = rule.host.parse()?;
+        println!(
+            "  #{:<3} {:<28} {}",
+            i,
+            pattern.to_string(),
+            balance.as_str()
+        );
+    }
+
+    println!();
+    println!(
+        "Groups (router): highest-priority, then first, rule with `group` whose host matches \
+         (`when` not checked); none: all backends"
+    );
+    let preferred: Vec<_> = cfg
+        .rules
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| r.group.as_ref().map(|g| (i, r, g)))
+        .collect();
+    if preferred.is_empty() {
+        println!("  (no group preferences)");
+    }
+    for (i, rule, group) in preferred {
+        let pattern: Pattern = rule.host.parse()?;
+        println!(
+            "  #{:<3} {:<28} {} ({})",
+            i,
+            pattern.to_string(),
+            group,
+            cfg.groups[group].backends.join(", ")
+        );
+    }
+
+    println!();
+    println!("Conflicts: overlapping rules that set something differently");
+    let conflicts = cfg.rule_conflicts();
+    if conflicts.is_empty() {
+        println!("  (none)");
+    }
+    for conflict in conflicts {
+        println!("  {}", conflict);
+    }
+
+    let mut users: Vec<_> = cfg
+        .users
+        .iter()
+        .filter(|(_, u)| !u.rules.is_empty())
+        .collect();
+    users.sort_by_key(|(name, _)| name.as_str());
+    for (name, user) in users {
+        RateLimiter::from_rules(&user.rules).map_err(|e| format!("[users.{}]: {}", name, e))?;
+        println!();
+        println!(
+            "Limits for user {}: checked after the global limits, same order",
+            name
+        );
+        print_rules(&user.rules)?;
+    }
+    Ok(())
+}
+
+/// A rule's limits as `rules dump` shows them; `None` if it sets none.
+fn rule_limits(rule: &RuleConfig) -> Option<String> {
+    let mut limits = Vec::new();
+    if let Some(n) = rule.connections_per_minute {
+        limits.push(format!("{}/min", n));
+    }
+    if let Some(kbps) = rule.bandwidth_kbps {
+        limits.push(format!("{} kbit/s shared", kbps));
+    }
+    (!limits.is_empty()).then(|| limits.join(", "))
+}
+
+fn rule_exits(exits: &[String]) -> String {
+    let exits: Vec<String> = exits
+        .iter()
+        .filter_map(|fp| torctl::fingerprint(fp))
+        .collect();
+    exits.join("|")
+}
+
+/// An idle limit as `rules` shows it.
+fn rule_idle(secs: u64) -> String {
+    match secs {
+        0 => "never".to_string(),
+        secs => format!("{}s", secs),
+    }
+}
+
+fn print_rules(rules: &[RuleConfig]) -> Result<(), Box<dyn Error>> {
+    if rules.is_empty() {
+        println!("  (no rules)");
+    }
+    for (i, rule) in rules.iter().enumerate() {
+        let pattern: Pattern = rule.host.parse()?;
+        let mut effects =
+            vec![rule_limits(rule).unwrap_or_else(|| "no limits (stops the search)".to_string())];
+        if let Some(balance) = rule.balance {
+            effects.push(format!("balance={}", balance.as_str()));
+        }
+        if let Some(group) = &rule.group {
+            effects.push(format!("group={}", group));
+        }
+        if let Some(exits) = &rule.tor_exits {
+            effects.push(format!("tor_exits={}", rule_exits(exits)));
+        }
+        if let Some(secs) = rule.idle_timeout_secs {
+            effects.push(format!("idle={}", rule_idle(secs)));
+        }
+        if rule.priority != 0 {
+            effects.push(format!("priority={}", rule.priority));
+        }
+        println!(
+            "  #{:<3} {:<28} {:<18} {}",
+            i,
+            pattern.to_string(),
+            pattern.kind(),
+            effects.join(", ")
+        );
+        if let Some(when) = &rule.when {
+            println!("        when {}", when);
+        }
+    }
+    Ok(())
+}
+
+/// `rules eval`: the rules for `target` in the order the engines try them,
+/// and which one settles each of limits, `balance`, `group`, `tor_exits` and
+/// `idle_timeout_secs`. Only the dispatcher's limits consult `when`.
+fn eval_rules(
+    cfg: &GoldDustConfig,
+    target: &Target,
+    mut resolved: Vec<IpAddr>,
+) -> Result<(), Box<dyn Error>> {
+    // Fails the same way the dispatcher would on a bad pattern or `when`
+    RateLimiter::from_rules(&cfg.rules)?;
+    let conditions = cfg.rule_conditions()?;
+    println!("=== Rules eval ===");
+    println!("Target:   {}", target);
+    let mut from = "--resolved";
+    if resolved.is_empty() && target.host.ip().is_none() {
+        from = "[dns.hosts]";
+        match Hosts::load(&cfg.dns) {
+            Ok(hosts) => resolved = hosts.get(&target.host_str()).unwrap_or(&[]).to_vec(),
+            Err(e) => println!("Hosts:    {} (ignored)", e),
+        }
+    }
+    match from {
+        _ if resolved.is_empty() => {
+            if target.host.ip().is_none() {
+                println!("Resolved: none (name only; --resolved to test IP rules)");
+            }
+        }
+        from => {
+            let ips: Vec<String> = resolved.iter().map(|ip| ip.to_string()).collect();
+            println!("Resolved: {} ({})", ips.join(", "), from);
+        }
+    }
+    println!();
+
+    let mut order: Vec<usize> = (0..cfg.rules.len()).collect();
+    order.sort_by_key(|&i| Reverse(cfg.rules[i].priority));
+    if order.is_empty() {
+        println!("  (no rules)");
+    }
+    const SETTINGS: [&str; 5] = ["limits", "balance", "group", "tor_exits", "idle"];
+    // Rule that settled each of SETTINGS, with its value
+    let mut decided: [Option<(usize, String)>; 5] = Default::default();
+    for i in order {
+        let rule = &cfg.rules[i];
+        let pattern: Pattern = rule.host.parse()?;
+        let mut notes = Vec::new();
+        if !pattern.matches(&target.host, &resolved) {
+            notes.push("no match".to_string());
+        } else {
+            let values = [
+                Some(rule_limits(rule).unwrap_or_else(|| "no limits".to_string())),
+                rule.balance.map(|b| b.as_str().to_string()),
+                rule.group.clone(),
+                rule.tor_exits.as_deref().map(rule_exits),
+                rule.idle_timeout_secs.map(rule_idle),
+            ];
+            for (k, value) in values.into_iter().enumerate() {
+                let Some(value) = value else {
+                    continue;
+                };
+                let setting = SETTINGS[k];
+                if let Some((by, _)) = &decided[k] {
+                    notes.push(format!(
+                        "{}: {}, not reached (#{} decided)",
+                        setting, value, by
+                    ));
+                    continue;
+                }
+                if k == 0 {
+                    match conditions.check(i, target, &resolved) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            let when = rule.when.as_deref().unwrap_or("");
+                            notes.push(format!(
+                                "limits: {}, skipped (when `{}` is false)",
+                                value, when
+                            ));
+                            continue;
+                        }
+                        Err(e) => {
+                            notes.push(format!("limits: {}, skipped ({})", value, e));
+                            continue;
+                        }
+                    }
+                }
+                notes.push(format!("decides {}: {}", setting, value));
+                decided[k] = Some((i, value));
+            }
+        }
+        println!(
+            "  #{:<3} {:<28} {:<18} {}",
+            i,
+            pattern.to_string(),
+            pattern.kind(),
+            notes.join("; ")
+        );
+    }
+
+    println!();
+    let defaults = [
+        "unlimited".to_string(),
+        format!("[routing] balance = {}", cfg.routing.balance.as_str()),
+        "all backends".to_string(),
+        "any Tor exit".to_string(),
+        format!(
+            "[dispatcher] idle_timeout_secs = {}",
+            rule_idle(cfg.dispatcher.idle_timeout_secs)
+        ),
+    ];
+    for (k, default) in defaults.iter().enumerate() {
+        let label = format!("{}:", SETTINGS[k]);
+        match &decided[k] {
+            Some((i, value)) => println!("{:<10} {} (rule #{})", label, value, i),
+            None => println!("{:<10} {} (no rule)", label, default),
+        }
+    }
+    Ok(())
+}
+
+fn parse_policy(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((policy, group)) if !policy.is_empty() && !group.is_empty() => {
+            Ok((policy.to_string(), group.to_string()))
+        }
+        _ => Err(format!("`{s}`: expected POLICY=GROUP")),
+    }
+}
+
+/// `rules import`: `file` converted to `[[rules]]` with `shared`'s settings,
+/// on stdout; what couldn't be converted goes to stderr.
+fn import_rules(
+    cfg: &GoldDustConfig,
+    file: &Path,
+    from: import::Format,
+    policies: &[(String, String)],
+    shared: &RuleConfig,
+) -> Result<(), Box<dyn Error>> {
+    if !policies.is_empty() && from != import::Format::Clash {
+        return Err("--policy needs --from clash".into());
+    }
+    let groups = shared.group.iter().chain(policies.iter().map(|(_, g)| g));
+    for group in groups {
+        if !cfg.groups.contains_key(group) {
+            return Err(format!("no [groups.{}] in the config", group).into());
+        }
+    }
+    let text = fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+    let mut imported = import::import(&text, from);
+
+    let mut rules = Vec::new();
+    for rule in imported.rules {
+        let mapped = rule
+            .policy
+            .as_ref()
+            .and_then(|p| policies.iter().find(|(policy, _)| policy == p))
+            .map(|(_, group)| group.clone());
+        let group = mapped.or_else(|| shared.group.clone());
+        if !policies.is_empty() && group.is_none() {
+            imported.skipped.push(import::Skipped {
+                line: rule.line,
+                text: rule.text,
+                reason: format!(
+                    "policy {} not mapped",
+                    rule.policy.as_deref().unwrap_or("(none)")
+                ),
+            });
+            continue;
+        }
+        rules.push(RuleConfig {
+            host: rule.host,
+            group,
+            ..shared.clone()
+        });
+    }
+
+    println!(
+        "# Imported from {} ({} rule(s))",
+        file.display(),
+        rules.len()
+    );
+    for rule in &rules {
+        println!();
+        println!("[[rules]]");
+        println!("host = {}", toml::Value::from(rule.host.as_str()));
+        if let Some(n) = rule.connections_per_minute {
+            println!("connections_per_minute = {}", n);
+        }
+        if let Some(kbps) = rule.bandwidth_kbps {
+            println!("bandwidth_kbps = {}", kbps);
+        }
+        if let Some(group) = &rule.group {
+            println!("group = {}", toml::Value::from(group.as_str()));
+        }
+        if rule.priority != 0 {
+            println!("priority = {}", rule.priority);
+        }
+    }
+
+    imported.skipped.sort_by_key(|s| s.line);
+    for skipped in &imported.skipped {
+        eprintln!(
+            "skipped line {} `{}`: {}",
+            skipped.line, skipped.text, skipped.reason
+        );
+    }
+    eprintln!(
+        "{}: {} rule(s) imported, {} line(s) skipped",
+        file.display(),
+        rules.len(),
+        imported.skipped.len()
+    );
+    Ok(())
+}
+
+fn print_alerts(cfg: &GoldDustConfig) {
+    println!("=== Alerts ===");
+    if cfg.alerts.rules.is_empty() {
+        println!("No rules configured: add [[alerts.rules]] to the config.");
+        return;
+    }
+    let Some(snapshot) = AlertSnapshot::load(ALERTS_PATH).filter(|s| s.is_fresh()) else {
+        println!(
+            "Dispatcher not evaluating (no fresh {}). Rules:",
+            ALERTS_PATH
+        );
+        for rule in &cfg.alerts.rules {
+            match &rule.name {
+                Some(name) => println!("- {}: {}", name, rule.condition()),
+                None => println!("- {}", rule.condition()),
+            }
+        }
+        return;
+    };
+    let now = now_unix();
+    for alert in &snapshot.alerts {
+        let state = match alert.state {
+            AlertState::Ok => "ok",
+            AlertState::Pending => "PENDING",
+            AlertState::Firing => "FIRING",
+        };
+        print!("- {:<8} {}", state, alert.rule);
+        if alert.rule != alert.condition {
+            print!(" ({})", alert.condition);
+        }
+        if let (Some(subject), Some(value)) = (&alert.subject, alert.value) {
+            print!(" | {} at {:.3}", subject, value);
+        }
+        if let Some(since) = alert.since {
+            print!(" for {}s", now.saturating_sub(since));
+        }
+        println!();
+    }
+    let targets = cfg.alerts.webhooks.len() + cfg.alerts.notify.len();
+    println!(
+        "Delivery: {} webhook(s), {} notify URL(s){}",
+        cfg.alerts.webhooks.len(),
+        cfg.alerts.notify.len(),
+        if targets == 0 { " (log only)" } else { "" }
+    );
+}
+
+fn load_snapshot(path: &Path) -> Result<RouterSnapshot, Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+}
+
+/// Quotas and session caps as the running dispatcher sees them.
+fn apply_live_state(router: &mut Router, cfg: &GoldDustConfig, usage: &UsageLedger) {
+    // Backends that used up their monthly quota take no new traffic
+    for kind in [BackendKind::Oxen, BackendKind::Tor, BackendKind::Masque] {
+        if usage.backend_exhausted(cfg, kind) {
+            router.drain(kind);
+        }
+    }
+
+    // Backends at max_sessions in the running dispatcher overflow to the next
+    if let Some(snapshot) = TrafficSnapshot::load(STATS_PATH).filter(|s| s.is_fresh()) {
+        for (name, egress) in &snapshot.egress {
+            router.set_load(
+                name,
+                Load {
+                    active: egress.sessions,
+                    max: egress.max_sessions,
+                },
+            );
+            // Dials that overran a `[timeouts]` budget failed too
+            if let Some(rate) = egress.overrun_rate() {
+                router.add_failures(name, rate);
+            }
+        }
+    }
+}
+
+/// Router over the externally fed health the dispatcher published, or the
+/// simulated backends if there is none.
+fn health_from_feed(cfg: &GoldDustConfig) -> Router {
+    let mut board = HealthBoard::load(HEALTH_PATH).unwrap_or_default();
+    board.retain_fresh(cfg.health_feed.max_age_secs);
+    if board.reports.is_empty() {
+        eprintln!(
+            "no fresh health in {} (is the dispatcher's feed running?); using simulated backends",
+            HEALTH_PATH
+        );
+        return Router::from_config(cfg);
+    }
+    let stale: Vec<&str> = board
+        .reports
+        .values()
+        .filter(|r| r.stale)
+        .map(|r| r.health.name.as_str())
+        .collect();
+    if !stale.is_empty() {
+        eprintln!(
+            "using stale health from a previous run for: {}",
+            stale.join(", ")
+        );
+    }
+    Router::from_source(&mut board, &cfg.routing)
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
+    if let Commands::Config {
+        action: ConfigAction::Lint { strict },
+    } = cli.command
+    {
+        return run_lint(cli.config, strict);
+    }
 
     // Load config and build router
-    let cfg = load_config(cli.config)?;
-    let mut router = Router::from_config(&cfg);
+    let mut cfg = load_config(cli.config)?;
+    let mut snapshot = cli.snapshot.as_deref().map(load_snapshot).transpose()?;
+    if let Some(seed) = snapshot.as_ref().and_then(|s| s.seed) {
+        cfg.routing.seed = Some(seed);
+    }
+    if cli.seed.is_some() {
+        cfg.routing.seed = cli.seed;
+    }
+    // Service nodes from oxend, for status (probed too with --probe)
+    let discovered = match (&snapshot, &cli.command) {
+        (
+            None,
+            Commands::Status {
+                json: false,
+                action: None,
+                ..
+            },
+        ) => discover_nodes(&cfg),
+        _ => None,
+    };
+    let mut router = match (&mut snapshot, &cli.command) {
+        (Some(snapshot), _) => {
+            let mut router = Router::from_source(snapshot, &cfg.routing);
+            router.set_balance_rules(&cfg.rules);
+            router.set_groups(&cfg.groups, &cfg.rules);
+            router.restore(snapshot);
+            router
+        }
+        (None, Commands::Status { probe: true, .. }) => {
+            let nodes = match &discovered {
+                Some(Ok(found)) => &found.nodes[..found.nodes.len().min(cfg.discovery.probe_limit)],
+                _ => &[],
+            };
+            Router::from_source(
+                &mut TcpProber::local_daemons(&cfg).with_service_nodes(nodes),
+                &cfg.routing,
+            )
+        }
+        _ if cfg.health_feed.enabled => health_from_feed(&cfg),
+        _ => Router::from_config(&cfg),
+    };
+    // Chains are evaluated over whichever health source was picked
+    router.set_chains(cfg.backends.chains.clone());
+    router.set_capabilities(cfg.capabilities.clone());
+    if let Some(path) = &cfg.routing.policy {
+        router.set_policy(policy::load(path)?);
+    }
+
+    // Known-bad relays/nodes never become candidates
+    let mut blocklist = Blocklist::load(BLOCKLIST_PATH).unwrap_or_default();
+    blocklist
+        .entries
+        .extend(cfg.blocklist.entries.iter().map(|e| e.to_ascii_lowercase()));
+    let usage = UsageLedger::load(USAGE_PATH, None);
+
+    // A snapshot already reflects blocklist, quotas and load when it was taken
+    let mut blocked = Vec::new();
+    if snapshot.is_none() {
+        blocked = router.exclude(|b| blocklist.contains(&b.name));
+        apply_live_state(&mut router, &cfg, &usage);
+    }
+    // Blocklist sources are remote, so they may name every backend there is;
+    // `status` still shows what was filtered
+    let decides = matches!(
+        cli.command,
+        Commands::Route { .. }
+            | Commands::WhyNot { .. }
+            | Commands::Simulate { .. }
+            | Commands::Policy { .. }
+    );
+    if decides && router.is_empty() {
+        return Err(match blocked.len() {
+            0 => NO_BACKENDS.to_string(),
+            n => format!("every backend is blocklisted ({} filtered)", n),
+        }
+        .into());
+    }
 
     match cli.command {
-        Commands::Status => {
-            print_status(&mut router);
+        Commands::Status {
+            action: Some(StatusAction::Diff { file }),
+            ..
+        } => {
+            print_status_diff(&router, &file)?;
+        }
+        Commands::Status { json: true, .. } => {
+            let mut state = router.snapshot();
+            state.histograms = match snapshot {
+                Some(earlier) => earlier.histograms,
+                None => {
+                    HealthBoard::load(HEALTH_PATH)
+                        .unwrap_or_default()
+                        .histograms
+                }
+            };
+            println!("{}", serde_json::to_string_pretty(&state)?);
+        }
+        Commands::Status { .. } => {
+            print_status(&mut router, &cfg, &usage);
+            if let Some(discovered) = &discovered {
+                print_discovery(&cfg, discovered);
+            }
+            print_blocklist(&cfg, &blocklist, &blocked);
+        }
+        Commands::Route {
+            target,
+            explain,
+            udp,
+        } => {
+            let needs = Requirements {
+                udp,
+                ..Requirements::of(&target)
+            };
+            if explain {
+                print_route_explanation(&cfg, &mut router, &target, &needs)?;
+            } else {
+                let choice = router
+                    .choose_backend_with(&target, &needs)
+                    .ok_or(NO_BACKENDS)?;
+                print_route_decision(&target, &choice);
+                if let Some(why) = Lan::new(&cfg.lan)?.check(&target.host, &[]) {
+                    println!("LAN:      {} ({})", why, lan_effect(cfg.lan.action));
+                }
+                if let Some(e) = router.take_policy_error() {
+                    println!("Policy:   failed, built-in order used: {}", e);
+                }
+            }
+        }
+        Commands::WhyNot {
+            backend,
+            target,
+            udp,
+        } => {
+            let needs = Requirements {
+                udp,
+                ..Requirements::of(&target)
+            };
+            // Quotas are only known live; a snapshot just says disabled
+            let drained =
+                |kind: BackendKind| snapshot.is_none() && usage.backend_exhausted(&cfg, kind);
+            print_why_not(
+                &cfg,
+                &mut router,
+                &blocked,
+                drained,
+                &backend,
+                &target,
+                &needs,
+            )?;
+        }
+        Commands::Simulate { scenario } => {
+            run_simulation(&mut router, &scenario)?;
+        }
+        Commands::CheckExitIp { url } => {
+            check_exit_ips(&cfg, &url)?;
+        }
+        Commands::Leaktest {
+            test:
+                LeakTest::Dns {
+                    service,
+                    proxy,
+                    lookups,
+                },
+        } => {
+            run_dns_leaktest(&service, proxy, lookups)?;
         }
-        Commands::Route { target } => {
-            let choice = router.choose_backend_for(&target);
-            print_route_decision(&target, &choice);
+        Commands::Export {
+            data,
+            format,
+            since,
+            until,
+            backend,
+            log,
+            output,
+        } => {
+            let filter = Filter {
+                since,
+                until,
+                backend,
+            };
+            run_export(&cfg, data, log, &filter, format, output)?;
         }
+        Commands::Replay {
+            log,
+            mode,
+            verify,
+            backend,
+            since,
+            until,
+        } => {
+            let filter = Filter {
+                since,
+                until,
+                backend,
+            };
+            if verify {
+                // Not into the file being read
+                router.record_to(None);
+                run_verify(&cfg, &mut router, log, &filter)?;
+            } else {
+                run_replay(&cfg, log, mode, &filter)?;
+            }
+        }
+        Commands::Firewall { action } => {
+            run_firewall(&cfg, action)?;
+        }
+        Commands::Alerts {
+            action: AlertsAction::Status,
+        } => {
+            print_alerts(&cfg);
+        }
+        Commands::Config { .. } => unreachable!("linted before loading"),
+        Commands::Policy {
+            action: PolicyAction::Test { fixtures },
+        } => {
+            // Reproducible without a seed anywhere, so CI runs agree
+            router.reseed(cfg.routing.seed.unwrap_or(0));
+            run_policy_test(&mut router, &fixtures)?;
+        }
+        Commands::Rules {
+            action: RulesAction::Dump,
+        } => {
+            dump_rules(&cfg)?;
+        }
+        Commands::Rules {
+            action: RulesAction::Eval { target, resolved },
+        } => {
+            eval_rules(&cfg, &target, resolved)?;
+        }
+        Commands::Rules {
+            action:
+                RulesAction::Import {
+                    file,
+                    from,
+                    group,
+                    policies,
+                    connections_per_minute,
+                    bandwidth_kbps,
+                    priority,
+                },
+        } => {
+            let shared = RuleConfig {
+                host: String::new(),
+                connections_per_minute,
+                bandwidth_kbps,
+                when: None,
+                balance: None,
+                group,
+                tor_exits: None,
+                idle_timeout_secs: None,
+                priority,
+            };
+            import_rules(&cfg, &file, from, &policies, &shared)?;
+        }
+        Commands::Monitor { json } => {
+            run_monitor(&cfg, json)?;
+        }
+        Commands::Paths => {
+            cfg.resolve_secrets()?;
+            print_paths(&cfg)?;
+        }
+        Commands::Lokinet { action } => {
+            cfg.resolve_secrets()?;
+            run_lokinet(&cfg, action)?;
+        }
+        Commands::Portal { action } => {
+            run_portal(&cfg, action)?;
+        }
+        Commands::Admin { action } => {
+            run_admin(&cfg, action)?;
+        }
+        Commands::Audit {
+            action: AuditAction::List { since, who, json },
+        } => {
+            list_audit(&cfg, since, who.as_deref(), json)?;
+        }
+        Commands::Token { url, read } => {
+            let path = match read {
+                true => &cfg.api.read_token_file,
+                false => &cfg.api.token_file,
+            };
+            let token = token::read(path).map_err(|e| {
+                format!("{e} (created on the dispatcher's first run, readable by its user only)")
+            })?;
+            match url {
+                true => println!("http://{}/#token={}", cfg.dashboard.listen, token),
+                false => println!("{}", token),
+            }
+        }
+    }
+    if let Some(e) = router.take_record_error() {
+        eprintln!("warning: could not record a decision to {}", e);
     }
 
     Ok(())
@@ -0,0 +1,214 @@
+use std::error::Error;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Whether this build can use the HTTP/3 CONNECT relay (`masque` feature).
+pub const AVAILABLE: bool = cfg!(feature = "masque");
+
+#[cfg(feature = "masque")]
+pub use imp::MasqueClient;
+
+/// Stand-in so callers need no `cfg`: never constructed.
+#[cfg(not(feature = "masque"))]
+pub struct MasqueClient(());
+
+#[cfg(not(feature = "masque"))]
+impl MasqueClient {
+    pub fn new(
+        _cfg: &crate::config::MasqueConfig,
+        _keep_alive: Option<std::time::Duration>,
+        _buffer_kib: Option<u64>,
+    ) -> Result<Self, String> {
+        Err("built without the `masque` feature".to_string())
+    }
+
+    pub async fn connect(
+        &self,
+        _target: &crate::target::Target,
+    ) -> Result<tokio::io::DuplexStream, BoxError> {
+        Err("built without the `masque` feature".into())
+    }
+
+    pub async fn reset(&self) {}
+}
+
+#[cfg(feature = "masque")]
+mod imp {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::{Buf, Bytes};
+    use h3::client::SendRequest;
+    use quinn::crypto::rustls::QuicClientConfig;
+    use quinn::{Endpoint, VarInt};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::sync::Mutex;
+
+    use super::BoxError;
+    use crate::config::MasqueConfig;
+    use crate::http::Url;
+    use crate::target::Target;
+
+    /// Buffered bytes per tunnel direction.
+    const WINDOW: usize = 64 * 1024;
+
+    /// One HTTP/3 connection to the relay, shared by every tunnel.
+    struct Session {
+        // Kept alive with the connection that came from it
+        _endpoint: Endpoint,
+        requests: SendRequest<h3_quinn::OpenStreams, Bytes>,
+    }
+
+    /// HTTP/3 client that opens `CONNECT host:port` tunnels through a relay.
+    ///
+    /// Plain RFC 9114 CONNECT, so TCP only: no extended CONNECT with
+    /// `:protocol` connect-udp (RFC 9298) or connect-ip.
+    pub struct MasqueClient {
+        relay: Url,
+        quic: quinn::ClientConfig,
+        session: Mutex<Option<Session>>,
+    }
+
+    impl MasqueClient {
+        /// `keep_alive` pings the relay when idle, so NATs and the relay's
+        /// idle timeout don't drop the connection under long sessions;
+        /// `buffer_kib` caps what the relay may send ahead on each tunnel.
+        pub fn new(
+            cfg: &MasqueConfig,
+            keep_alive: Option<Duration>,
+            buffer_kib: Option<u64>,
+        ) -> Result<Self, String> {
+            let relay = Url::parse(&cfg.relay)?;
+            let mut roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            if let Some(path) = &cfg.ca_cert {
+                let certs = CertificateDer::pem_file_iter(path)
+                    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                    .map_err(|e| format!("{}: {e}", path.display()))?;
+                roots = RootCertStore::empty();
+                for cert in certs {
+                    roots
+                        .add(cert)
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                }
+            }
+            let mut tls = ClientConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|e| e.to_string())?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+            tls.alpn_protocols = vec![b"h3".to_vec()];
+            let quic = QuicClientConfig::try_from(tls).map_err(|e| e.to_string())?;
+            let mut quic = quinn::ClientConfig::new(Arc::new(quic));
+            let mut transport = quinn::TransportConfig::default();
+            transport.keep_alive_interval(keep_alive);
+            if let Some(kib) = buffer_kib {
+                let window =
+                    VarInt::from_u64(kib.saturating_mul(1024)).map_err(|e| e.to_string())?;
+                transport.stream_receive_window(window);
+            }
+            quic.transport_config(Arc::new(transport));
+            Ok(Self {
+                relay,
+                quic,
+                session: Mutex::new(None),
+            })
+        }
+
+        /// Tunnel to `target`. Bytes written to the returned stream go to the
+        /// target, and its replies can be read back.
+        pub async fn connect(&self, target: &Target) -> Result<DuplexStream, BoxError> {
+            let request = http::Request::builder()
+                .method(http::Method::CONNECT)
+                .uri(target.to_string())
+                .body(())?;
+            let mut requests = self.requests().await?;
+            let mut stream = match requests.send_request(request.clone()).await {
+                Ok(stream) => stream,
+                Err(_) => {
+                    // The relay went away (idle timeout, network change): redial once
+                    self.session.lock().await.take();
+                    self.requests().await?.send_request(request).await?
+                }
+            };
+            let response = stream.recv_response().await?;
+            if !response.status().is_success() {
+                return Err(
+                    format!("relay refused CONNECT {target}: {}", response.status()).into(),
+                );
+            }
+
+            let (mut send, mut recv) = stream.split();
+            let (local, remote) = tokio::io::duplex(WINDOW);
+            let (mut from_client, mut to_client) = tokio::io::split(remote);
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; WINDOW];
+                while let Ok(n) = from_client.read(&mut buf).await {
+                    if n == 0 {
+                        let _ = send.finish().await;
+                        return;
+                    }
+                    if send
+                        .send_data(Bytes::copy_from_slice(&buf[..n]))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            });
+            tokio::spawn(async move {
+                while let Ok(Some(mut chunk)) = recv.recv_data().await {
+                    let mut bytes = chunk.copy_to_bytes(chunk.remaining());
+                    if to_client.write_all_buf(&mut bytes).await.is_err() {
+                        return;
+                    }
+                }
+                let _ = to_client.shutdown().await;
+            });
+            Ok(local)
+        }
+
+        /// Drop the shared connection, so the next tunnel dials afresh (after
+        /// a wake-up, the old one is dead but may not know it yet).
+        pub async fn reset(&self) {
+            self.session.lock().await.take();
+        }
+
+        /// The shared connection's request handle, dialing if there is none.
+        async fn requests(&self) -> Result<SendRequest<h3_quinn::OpenStreams, Bytes>, BoxError> {
+            let mut session = self.session.lock().await;
+            if let Some(s) = session.as_ref() {
+                return Ok(s.requests.clone());
+            }
+            let addr = tokio::net::lookup_host((self.relay.host.as_str(), self.relay.port))
+                .await?
+                .next()
+                .ok_or_else(|| format!("{}: no address", self.relay.host))?;
+            let bind: SocketAddr = if addr.is_ipv6() {
+                "[::]:0".parse()?
+            } else {
+                "0.0.0.0:0".parse()?
+            };
+            let mut endpoint = Endpoint::client(bind)?;
+            endpoint.set_default_client_config(self.quic.clone());
+            let conn = endpoint.connect(addr, &self.relay.host)?.await?;
+            let (mut driver, requests) = h3::client::new(h3_quinn::Connection::new(conn)).await?;
+            tokio::spawn(async move {
+                let _ = driver.wait_idle().await;
+            });
+            *session = Some(Session {
+                _endpoint: endpoint,
+                requests: requests.clone(),
+            });
+            Ok(requests)
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::target::{Cidr, Host};
+
+/// Multi-label public suffixes we know about. Every single-label TLD is
+/// treated as a public suffix too, bar [`SPECIAL_USE`]. Not the full PSL,
+/// but covers the registries people actually write rules against.
+const PUBLIC_SUFFIXES: &[&str] = &[
+    "ac.uk",
+    "co.uk",
+    "gov.uk",
+    "ltd.uk",
+    "me.uk",
+    "net.uk",
+    "org.uk",
+    "plc.uk",
+    "com.au",
+    "edu.au",
+    "gov.au",
+    "net.au",
+    "org.au",
+    "co.nz",
+    "net.nz",
+    "org.nz",
+    "co.jp",
+    "ne.jp",
+    "or.jp",
+    "ac.jp",
+    "go.jp",
+    "co.kr",
+    "or.kr",
+    "com.br",
+    "net.br",
+    "org.br",
+    "com.cn",
+    "net.cn",
+    "org.cn",
+    "gov.cn",
+    "com.hk",
+    "com.tw",
+    "com.sg",
+    "co.in",
+    "net.in",
+    "org.in",
+    "co.za",
+    "org.za",
+    "com.mx",
+    "com.ar",
+    "com.tr",
+    "com.ua",
+    "co.il",
+    "com.pl",
+    "co.id",
+    "com.my",
+    "com.ph",
+    "com.vn",
+    "eu.org",
+    "github.io",
+    "gitlab.io",
+    "herokuapp.com",
+    "netlify.app",
+    "pages.dev",
+    "vercel.app",
+    "workers.dev",
+    "blogspot.com",
+    "cloudfront.net",
+    "azurewebsites.net",
+    "appspot.com",
+];
+
+/// Special-use names (RFC 6761, 6762, 7686, 8375), overlay networks' own
+/// TLDs and names ICANN won't delegate: nobody registers under them, so a
+/// rule may cover one whole (`*.onion`, `.loki`).
+const SPECIAL_USE: &[&str] = &[
+    "onion",
+    "loki",
+    "i2p",
+    "local",
+    "localhost",
+    "test",
+    "invalid",
+    "internal",
+    "lan",
+    "home",
+    "corp",
+    "home.arpa",
+];
+
+/// Is `name` (lowercase, no trailing dot) a public suffix?
+pub fn is_public_suffix(name: &str) -> bool {
+    !SPECIAL_USE.contains(&name) && is_registry(name)
+}
+
+/// Is `name` one that names are registered under? Special-use TLDs count:
+/// `a.example.onion` belongs to `example.onion`.
+fn is_registry(name: &str) -> bool {
+    !name.contains('.') || PUBLIC_SUFFIXES.contains(&name)
+}
+
+/// Registrable domain ("eTLD+1") of `name`, e.g. `a.b.example.co.uk` →
+/// `example.co.uk`. `None` if `name` is itself a public suffix.
+pub fn registrable_domain(name: &str) -> Option<&str> {
+    // Walk suffixes from longest to shortest; the registrable domain is the
+    // one just above the longest public suffix.
+    let mut prev = None;
+    let mut rest = name;
+    loop {
+        if is_registry(rest) {
+            return prev;
+        }
+        prev = Some(rest);
+        rest = rest.split_once('.')?.1;
+    }
+}
+
+/// One compiled host pattern from a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// `*`: any host (for rules decided by their `when` condition).
+    Any,
+    /// `example.com`: that host only.
+    Exact(String),
+    /// `*.example.com`: any subdomain, not the apex.
+    Subdomains(String),
+    /// `.example.com`: the apex and any subdomain.
+    Domain(String),
+    /// `192.0.2.1`, `[2001:db8::1]`
+    Ip(IpAddr),
+    /// `10.0.0.0/8`, `2001:db8::/32`
+    Cidr(Cidr),
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().trim_end_matches('.').to_ascii_lowercase();
+        if s.is_empty() {
+            return Err("empty host pattern".to_string());
+        }
+        if s == "*" {
+            return Ok(Pattern::Any);
+        }
+        if s.contains('/') {
+            return s.parse().map(Pattern::Cidr);
+        }
+        if let Ok(ip) = s.trim_start_matches('[').trim_end_matches(']').parse() {
+            return Ok(Pattern::Ip(ip));
+        }
+
+        let (pattern, suffix) = if let Some(suffix) = s.strip_prefix("*.") {
+            (Pattern::Subdomains(suffix.to_string()), suffix)
+        } else if let Some(suffix) = s.strip_prefix('.') {
+            (Pattern::Domain(suffix.to_string()), suffix)
+        } else if s.contains('*') {
+            return Err(format!(
+                "`{s}`: `*` is only allowed as a leading `*.` label"
+            ));
+        } else {
+            return Ok(Pattern::Exact(s));
+        };
+
+        if suffix.contains('*') {
+            return Err(format!(
+                "`{s}`: `*` is only allowed as a leading `*.` label"
+            ));
+        }
+        if is_public_suffix(suffix) {
+            return Err(format!(
+                "`{s}` covers the whole public suffix `{suffix}`; use a registrable domain like `*.example.{suffix}`"
+            ));
+        }
+        Ok(pattern)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Any => f.write_str("*"),
+            Pattern::Exact(h) => f.write_str(h),
+            Pattern::Subdomains(s) => write!(f, "*.{s}"),
+            Pattern::Domain(s) => write!(f, ".{s}"),
+            Pattern::Ip(IpAddr::V6(ip)) => write!(f, "[{ip}]"),
+            Pattern::Ip(ip) => ip.fmt(f),
+            Pattern::Cidr(c) => c.fmt(f),
+        }
+    }
+}
+
+impl Pattern {
+    /// What the pattern covers, in words.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Pattern::Any => "any host",
+            Pattern::Exact(_) => "host",
+            Pattern::Subdomains(_) => "subdomains",
+            Pattern::Domain(_) => "domain+subdomains",
+            Pattern::Ip(_) => "address",
+            Pattern::Cidr(_) => "network",
+        }
+    }
+
+    /// Does this pattern match every host `other` matches?
+    pub fn covers(&self, other: &Pattern) -> bool {
+        let under = |name: &str, domain: &str| name.ends_with(&format!(".{domain}"));
+        match (self, other) {
+            (Pattern::Any, _) => true,
+            (Pattern::Exact(a), Pattern::Exact(b)) => a == b,
+            (Pattern::Subdomains(s), Pattern::Exact(n) | Pattern::Domain(n)) => under(n, s),
+            (Pattern::Subdomains(s), Pattern::Subdomains(n)) => s == n || under(n, s),
+            (
+                Pattern::Domain(s),
+                Pattern::Exact(n) | Pattern::Subdomains(n) | Pattern::Domain(n),
+            ) => s == n || under(n, s),
+            (Pattern::Ip(a), Pattern::Ip(b)) => a == b,
+            (Pattern::Cidr(c), Pattern::Ip(ip)) => c.contains(ip),
+            (Pattern::Cidr(c), Pattern::Cidr(d)) => c.covers(d),
+            _ => false,
+        }
+    }
+
+    /// Does this pattern match `host` (or, for IP patterns, any `resolved` address)?
+    pub fn matches(&self, host: &Host, resolved: &[IpAddr]) -> bool {
+        let mut ips = host.ip().into_iter().chain(resolved.iter().copied());
+        let name = match host {
+            Host::Domain(d) => d.as_str(),
+            _ => "",
+        };
+        match self {
+            Pattern::Any => true,
+            Pattern::Exact(h) => name == h,
+            Pattern::Subdomains(s) => name.len() > s.len() && name.ends_with(&format!(".{s}")),
+            Pattern::Domain(s) => name == s || name.ends_with(&format!(".{s}")),
+            Pattern::Ip(ip) => ips.any(|c| c == *ip),
+            Pattern::Cidr(c) => ips.any(|ip| c.contains(&ip)),
+        }
+    }
+}
+
+/// Pre-compiled rule set: host lookups cost one hash probe per label, no
+/// matter how many rules there are.
+#[derive(Debug, Clone, Default)]
+pub struct RuleMatcher {
+    exact: HashMap<String, Vec<usize>>,
+    subdomains: HashMap<String, Vec<usize>>,
+    domains: HashMap<String, Vec<usize>>,
+    /// IP, CIDR and `*` patterns, scanned in rule order.
+    networks: Vec<(Pattern, usize)>,
+    len: usize,
+    /// Per-rule priority; empty when every rule has the same one.
+    priorities: Vec<i32>,
+}
+
+impl RuleMatcher {
+    /// Compile patterns in rule order. Fails on the first invalid one.
+    pub fn compile<'a, I>(patterns: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut m = RuleMatcher::default();
+        for (i, raw) in patterns.into_iter().enumerate() {
+            let pattern = raw
+                .parse::<Pattern>()
+                .map_err(|e| format!("rule #{i}: {e}"))?;
+            // Indices are pushed in rule order, so each list stays sorted.
+            match pattern {
+                Pattern::Exact(h) => m.exact.entry(h).or_default().push(i),
+                Pattern::Subdomains(s) => m.subdomains.entry(s).or_default().push(i),
+                Pattern::Domain(s) => m.domains.entry(s).or_default().push(i),
+                p @ (Pattern::Any | Pattern::Ip(_) | Pattern::Cidr(_)) => m.networks.push((p, i)),
+            }
+            m.len = i + 1;
+        }
+        Ok(m)
+    }
+
+    /// Order matches by `priorities` (one per rule, highest first) before
+    /// rule order.
+    pub fn with_priorities(mut self, priorities: Vec<i32>) -> Self {
+        if priorities.windows(2).any(|w| w[0] != w[1]) {
+            self.priorities = priorities;
+        }
+        self
+    }
+
+    fn priority(&self, i: usize) -> i32 {
+        self.priorities.get(i).copied().unwrap_or(0)
+    }
+
+    /// Number of compiled rules.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Indices of rules whose host-name pattern matches, unordered.
+    fn host_hits(&self, host: &Host) -> Vec<usize> {
+        let mut hits: Vec<usize> = Vec::new();
+
+        if let Host::Domain(name) = host {
+            hits.extend(self.exact.get(name).into_iter().flatten());
+            hits.extend(self.domains.get(name).into_iter().flatten());
+            let mut rest = name.as_str();
+            while let Some((_, parent)) = rest.split_once('.') {
+                hits.extend(self.subdomains.get(parent).into_iter().flatten());
+                hits.extend(self.domains.get(parent).into_iter().flatten());
+                rest = parent;
+            }
+        }
+        hits
+    }
+
+    /// Indices of every rule matching `host`, highest priority first, then
+    /// in file order.
+    pub fn matches(&self, host: &Host, resolved: &[IpAddr]) -> Vec<usize> {
+        let mut hits = self.host_hits(host);
+        hits.extend(
+            self.networks
+                .iter()
+                .filter(|(p, _)| p.matches(host, resolved))
+                .map(|(_, i)| *i),
+        );
+        hits.sort_unstable();
+        hits.dedup();
+        if !self.priorities.is_empty() {
+            hits.sort_by_key(|&i| Reverse(self.priority(i)));
+        }
+        hits
+    }
+
+    /// Index of the rule matching `host` that wins: highest priority, then
+    /// first in file order.
+    pub fn first_match(&self, host: &Host, resolved: &[IpAddr]) -> Option<usize> {
+        if !self.priorities.is_empty() {
+            return self.matches(host, resolved).first().copied();
+        }
+        let best = self.host_hits(host).into_iter().min();
+
+        // Network rules are few and kept in order: stop at the first hit or
+        // once they can no longer beat a host match.
+        let network = self
+            .networks
+            .iter()
+            .take_while(|(_, i)| best.is_none_or(|b| *i < b))
+            .find(|(p, _)| p.matches(host, resolved))
+            .map(|(_, i)| *i);
+
+        network.or(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> Pattern {
+        s.parse().unwrap_or_else(|e| panic!("{s}: {e}"))
+    }
+
+    fn domain(name: &str) -> Host {
+        Host::Domain(name.to_string())
+    }
+
+    #[test]
+    fn wildcard_matches_subdomains_only() {
+        let p = pattern("*.Example.COM.");
+        assert_eq!(p, Pattern::Subdomains("example.com".to_string()));
+        assert!(p.matches(&domain("a.example.com"), &[]));
+        assert!(p.matches(&domain("a.b.example.com"), &[]));
+        assert!(!p.matches(&domain("example.com"), &[]));
+        assert!(!p.matches(&domain("badexample.com"), &[]));
+    }
+
+    #[test]
+    fn dot_prefix_matches_apex_and_subdomains() {
+        let p = pattern(".example.co.uk");
+        assert_eq!(p, Pattern::Domain("example.co.uk".to_string()));
+        assert!(p.matches(&domain("example.co.uk"), &[]));
+        assert!(p.matches(&domain("www.example.co.uk"), &[]));
+        assert!(!p.matches(&domain("other.co.uk"), &[]));
+    }
+
+    #[test]
+    fn exact_matches_that_host_only() {
+        let p = pattern("example.com");
+        assert_eq!(p, Pattern::Exact("example.com".to_string()));
+        assert!(p.matches(&domain("example.com"), &[]));
+        assert!(!p.matches(&domain("www.example.com"), &[]));
+        assert_eq!(pattern("*"), Pattern::Any);
+        assert_eq!(
+            pattern("localhost"),
+            Pattern::Exact("localhost".to_string())
+        );
+    }
+
+    #[test]
+    fn special_use_tlds_may_be_covered_whole() {
+        for s in [
+            "*.onion",
+            ".loki",
+            "*.local",
+            ".localhost",
+            "*.home.arpa",
+            "*.i2p",
+        ] {
+            pattern(s);
+        }
+        assert!(pattern("*.onion").matches(&domain("duckduckgo.onion"), &[]));
+        assert!(pattern(".loki").matches(&domain("exit.loki"), &[]));
+    }
+
+    #[test]
+    fn public_suffixes_and_malformed_patterns_are_rejected() {
+        for s in [
+            "*.com",
+            ".com",
+            "*.co.uk",
+            ".github.io",
+            ".",
+            "",
+            "a.*.example.com",
+            "ex*ample.com",
+            "*.*.example.com",
+        ] {
+            assert!(s.parse::<Pattern>().is_err(), "`{s}` parsed");
+        }
+    }
+
+    #[test]
+    fn registrable_domains() {
+        assert_eq!(
+            registrable_domain("a.b.example.co.uk"),
+            Some("example.co.uk")
+        );
+        assert_eq!(registrable_domain("www.example.com"), Some("example.com"));
+        assert_eq!(registrable_domain("a.example.onion"), Some("example.onion"));
+        assert_eq!(registrable_domain("co.uk"), None);
+        assert_eq!(registrable_domain("onion"), None);
+    }
+
+    #[test]
+    fn first_match_prefers_rule_order() {
+        let m = RuleMatcher::compile([".example.com", "www.example.com", "*.onion"]).unwrap();
+        assert_eq!(m.first_match(&domain("www.example.com"), &[]), Some(0));
+        assert_eq!(m.first_match(&domain("x.onion"), &[]), Some(2));
+        assert_eq!(m.first_match(&domain("example.org"), &[]), None);
+        assert!(RuleMatcher::compile(["example.com", "*.com"]).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::config::{MetricsPushConfig, PushFormat};
+use crate::http::Url;
+
+/// One value of a series. Names ending in `_total` are counters, the rest
+/// gauges.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+}
+
+impl Sample {
+    pub fn new(name: &'static str, help: &'static str, value: f64) -> Self {
+        Self {
+            name,
+            help,
+            labels: Vec::new(),
+            value,
+        }
+    }
+
+    pub fn label(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.labels.push((name, value.into()));
+        self
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.name.ends_with("_total") {
+            true => "counter",
+            false => "gauge",
+        }
+    }
+}
+
+/// `[a-zA-Z_][a-zA-Z0-9_]*`, and not `__`-prefixed (reserved).
+pub fn valid_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
+
+/// Prometheus text exposition (version 0.0.4). Samples of one metric must be
+/// adjacent.
+pub fn render_text(samples: &[Sample]) -> String {
+    let mut out = String::new();
+    let mut last = "";
+    for sample in samples {
+        if sample.name != last {
+            let _ = writeln!(out, "# HELP {} {}", sample.name, sample.help);
+            let _ = writeln!(out, "# TYPE {} {}", sample.name, sample.kind());
+            last = sample.name;
+        }
+        out.push_str(sample.name);
+        if !sample.labels.is_empty() {
+            let labels: Vec<String> = sample
+                .labels
+                .iter()
+                .map(|(k, v)| format!("{k}=\"{}\"", escape_label(v)))
+                .collect();
+            let _ = write!(out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(out, " {}", sample.value);
+    }
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// One push, ready to send.
+#[derive(Debug, Clone)]
+pub struct Push {
+    pub method: &'static str,
+    pub url: Url,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Push {
+    /// Encode `samples` for `cfg.format`, taken at `now_ms` (Unix, ms).
+    pub fn new(cfg: &MetricsPushConfig, samples: &[Sample], now_ms: i64) -> Result<Self, String> {
+        let mut url = Url::parse(&cfg.url)?;
+        let (method, content_type, body) = match cfg.format {
+            PushFormat::Pushgateway => {
+                let mut path = format!(
+                    "{}/metrics/{}",
+                    url.path.trim_end_matches('/'),
+                    label_segment("job", &cfg.job)
+                );
+                for (k, v) in &cfg.labels {
+                    path.push_str(&format!("/{}", label_segment(k, v)));
+                }
+                url.path = path;
+                // PUT: replaces the group, so series that went away do too
+                let body = render_text(samples).into_bytes();
+                ("PUT", "text/plain; version=0.0.4", body)
+            }
+            PushFormat::RemoteWrite => {
+                let body = snappy_literal(&write_request(cfg, samples, now_ms));
+                ("POST", "application/x-protobuf", body)
+            }
+        };
+        let mut headers = vec![("Content-Type".to_string(), content_type.to_string())];
+        if cfg.format == PushFormat::RemoteWrite {
+            headers.push(("Content-Encoding".to_string(), "snappy".to_string()));
+            headers.push((
+                "X-Prometheus-Remote-Write-Version".to_string(),
+                "0.1.0".to_string(),
+            ));
+        }
+        headers.extend(cfg.headers.iter().map(|(k, v)| (k.clone(), v.clone())));
+        Ok(Self {
+            method,
+            url,
+            headers,
+            body,
+        })
+    }
+}
+
+/// A Pushgateway grouping key pair; values beyond URL-safe characters go
+/// base64url-encoded, the `@base64` way.
+fn label_segment(name: &str, value: &str) -> String {
+    match safe(value) {
+        true => format!("{name}/{value}"),
+        false => format!("{name}@base64/{}", base64url(value.as_bytes())),
+    }
+}
+
+fn safe(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b))
+}
+
+fn base64url(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+        for _ in chunk.len()..3 {
+            out.push('=');
+        }
+    }
+    out
+}
+
+/// A remote-write `WriteRequest`: one series per sample, its labels sorted
+/// by name with `__name__` and `job` among them.
+fn write_request(cfg: &MetricsPushConfig, samples: &[Sample], now_ms: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for sample in samples {
+        let mut labels: BTreeMap<&str, &str> = cfg
+            .labels
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        labels.extend(sample.labels.iter().map(|(k, v)| (*k, v.as_str())));
+        labels.insert("__name__", sample.name);
+        labels.insert("job", &cfg.job);
+
+        let mut series = Vec::new();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            proto_bytes(&mut label, 1, name.as_bytes());
+            proto_bytes(&mut label, 2, value.as_bytes());
+            proto_bytes(&mut series, 1, &label);
+        }
+        let mut point = Vec::new();
+        point.push(1 << 3 | 1);
+        point.extend_from_slice(&sample.value.to_le_bytes());
+        point.push(2 << 3);
+        varint(&mut point, now_ms as u64);
+        proto_bytes(&mut series, 2, &point);
+        proto_bytes(&mut request, 1, &series);
+    }
+    request
+}
+
+/// A length-delimited protobuf field.
+fn proto_bytes(out: &mut Vec<u8>, field: u8, data: &[u8]) {
+    out.push(field << 3 | 2);
+    varint(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+fn varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Snappy block format without compression: the length, then the data as
+/// literals. Any snappy decoder reads it; pushes are small enough that
+/// compressing isn't worth a dependency.
+fn snappy_literal(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 16);
+    varint(&mut out, data.len() as u64);
+    for chunk in data.chunks(1 << 16) {
+        let n = chunk.len() - 1;
+        match n {
+            0..=59 => out.push((n as u8) << 2),
+            60..=0xff => out.extend_from_slice(&[60 << 2, n as u8]),
+            _ => {
+                out.push(61 << 2);
+                out.extend_from_slice(&(n as u16).to_le_bytes());
+            }
+        }
+        out.extend_from_slice(chunk);
+    }
+    out
+}
@@ -0,0 +1,195 @@
+//! Kotlin/Swift bindings (`uniffi` feature) for embedding the routing brain
+//! in Android/iOS VPN apps.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::config::GoldDustConfig;
+use crate::policy;
+use crate::router::{BackendChoice, BackendHealth, Router, NO_BACKENDS};
+use crate::target::Target;
+
+#[derive(Debug, uniffi::Error)]
+pub enum GoldDustError {
+    /// The config could not be loaded (or its policy module not compiled).
+    Config { message: String },
+    /// The target is not `host:port`, `[v6]:port`, a bare host or a URL.
+    Target { message: String },
+    /// The connect hook refused the connection.
+    Refused { target: String },
+}
+
+impl fmt::Display for GoldDustError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldDustError::Config { message } => write!(f, "config: {message}"),
+            GoldDustError::Target { message } => write!(f, "target: {message}"),
+            GoldDustError::Refused { target } => write!(f, "connect hook refused {target}"),
+        }
+    }
+}
+
+impl std::error::Error for GoldDustError {}
+
+/// One backend in a status snapshot.
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct Backend {
+    pub name: String,
+    /// `oxen` or `tor`.
+    pub kind: String,
+    pub latency_ms: f64,
+    pub failure_rate: f64,
+    pub enabled: bool,
+    pub ipv6: bool,
+    /// Decisions this instance has sent to the backend.
+    pub picks: u64,
+}
+
+impl From<BackendHealth> for Backend {
+    fn from(b: BackendHealth) -> Self {
+        Self {
+            name: b.name,
+            kind: b.kind.as_str().to_string(),
+            latency_ms: b.latency_ms,
+            failure_rate: b.failure_rate,
+            enabled: b.enabled,
+            ipv6: b.ipv6,
+            picks: 0,
+        }
+    }
+}
+
+/// The router's choice for one target.
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct RouteDecision {
+    /// Normalized target (`host:port`, v6 bracketed).
+    pub target: String,
+    pub backend: String,
+    /// `oxen` or `tor`.
+    pub kind: String,
+    pub latency_ms: f64,
+    pub failure_rate: f64,
+    pub ipv6: bool,
+}
+
+impl RouteDecision {
+    fn new(target: &Target, choice: BackendChoice) -> Self {
+        Self {
+            target: target.to_string(),
+            backend: choice.name,
+            kind: choice.kind.as_str().to_string(),
+            latency_ms: choice.latency_ms,
+            failure_rate: choice.failure_rate,
+            ipv6: choice.ipv6,
+        }
+    }
+}
+
+/// Called by `connect` once a backend is chosen, before the app dials it
+/// (e.g. to protect the socket from the VPN's own tunnel). Returning `false`
+/// refuses the connection.
+#[uniffi::export(with_foreign)]
+pub trait ConnectHook: Send + Sync {
+    fn before_connect(&self, decision: RouteDecision) -> bool;
+}
+
+/// Routing brain handle for mobile apps.
+#[derive(uniffi::Object)]
+pub struct GoldDust {
+    router: Mutex<Router>,
+    hook: Mutex<Option<Arc<dyn ConnectHook>>>,
+}
+
+impl GoldDust {
+    fn with_config(cfg: &GoldDustConfig) -> Result<Arc<Self>, GoldDustError> {
+        let mut router = Router::from_config(cfg);
+        if router.is_empty() {
+            return Err(GoldDustError::Config {
+                message: NO_BACKENDS.to_string(),
+            });
+        }
+        if let Some(path) = &cfg.routing.policy {
+            let policy = policy::load(path).map_err(|message| GoldDustError::Config { message })?;
+            router.set_policy(policy);
+        }
+        Ok(Arc::new(Self {
+            router: Mutex::new(router),
+            hook: Mutex::new(None),
+        }))
+    }
+
+    fn choose(&self, target: &str) -> Result<RouteDecision, GoldDustError> {
+        let target: Target =
+            target
+                .parse()
+                .map_err(|e: crate::target::TargetError| GoldDustError::Target {
+                    message: e.to_string(),
+                })?;
+        let choice = self
+            .router
+            .lock()
+            .expect("router poisoned")
+            .choose_backend_for(&target)
+            .ok_or_else(|| GoldDustError::Config {
+                message: NO_BACKENDS.to_string(),
+            })?;
+        Ok(RouteDecision::new(&target, choice))
+    }
+}
+
+#[uniffi::export]
+impl GoldDust {
+    /// Load a config TOML (the app's bundled or user-edited copy).
+    #[uniffi::constructor]
+    pub fn from_config_path(path: String) -> Result<Arc<Self>, GoldDustError> {
+        let cfg = GoldDustConfig::load(&path).map_err(|e| GoldDustError::Config {
+            message: e.to_string(),
+        })?;
+        Self::with_config(&cfg)
+    }
+
+    /// Parse config TOML text directly (no file access needed).
+    #[uniffi::constructor]
+    pub fn from_config_toml(toml: String) -> Result<Arc<Self>, GoldDustError> {
+        let cfg = GoldDustConfig::parse(&toml).map_err(|e| GoldDustError::Config {
+            message: e.to_string(),
+        })?;
+        Self::with_config(&cfg)
+    }
+
+    /// Current backend health.
+    pub fn status(&self) -> Vec<Backend> {
+        let router = self.router.lock().expect("router poisoned");
+        router
+            .backend_health()
+            .into_iter()
+            .map(|b| Backend {
+                picks: router.picks().get(&b.name).copied().unwrap_or(0),
+                ..Backend::from(b)
+            })
+            .collect()
+    }
+
+    /// Which backend would carry `target`, without calling the hook.
+    pub fn route(&self, target: String) -> Result<RouteDecision, GoldDustError> {
+        self.choose(&target)
+    }
+
+    /// Choose a backend for a connection the app is about to open and run
+    /// the connect hook on it.
+    pub fn connect(&self, target: String) -> Result<RouteDecision, GoldDustError> {
+        let decision = self.choose(&target)?;
+        let hook = self.hook.lock().expect("hook poisoned").clone();
+        match hook {
+            Some(hook) if !hook.before_connect(decision.clone()) => Err(GoldDustError::Refused {
+                target: decision.target,
+            }),
+            _ => Ok(decision),
+        }
+    }
+
+    /// Install (or with `None`, remove) the connect hook.
+    pub fn set_connect_hook(&self, hook: Option<Arc<dyn ConnectHook>>) {
+        *self.hook.lock().expect("hook poisoned") = hook;
+    }
+}
@@ -0,0 +1,137 @@
+use std::error::Error;
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::gossip::Report;
+use crate::stats::TrafficEntry;
+
+/// Events queued per `monitor` client; a slower one skips ahead.
+pub const EVENTS_BUFFERED: usize = 256;
+
+/// Something the dispatcher did, as published on `/api/events`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A client session ended, or was refused.
+    Decision(TrafficEntry),
+    /// A health report was accepted (gossip or feed).
+    Probe(Report),
+    /// Traffic changed course.
+    Failover(Failover),
+}
+
+/// A backend going down or coming back, a dead-peer redial, the kill switch
+/// engaging or releasing, or the egress being switched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failover {
+    pub unix: u64,
+    /// Egress, backend or `dispatcher`.
+    pub subject: String,
+    pub reason: String,
+}
+
+/// What reading the stream yields.
+#[derive(Debug, Clone)]
+pub enum Streamed {
+    Event(Box<Event>),
+    /// Events the dispatcher dropped because this client fell behind.
+    Skipped(u64),
+}
+
+impl Event {
+    /// One line for `monitor`.
+    pub fn line(&self) -> String {
+        match self {
+            Event::Decision(e) => {
+                let who: Vec<&str> = [&e.user, &e.app]
+                    .into_iter()
+                    .flatten()
+                    .map(String::as_str)
+                    .collect();
+                format!(
+                    "{} decision {}{} -> {} via {}: {}{} (up={} down={} bytes, {} ms)",
+                    clock(e.unix),
+                    e.client,
+                    match who.as_slice() {
+                        [] => String::new(),
+                        who => format!(" ({})", who.join(", ")),
+                    },
+                    e.target,
+                    e.egress.as_deref().unwrap_or("-"),
+                    e.outcome,
+                    match &e.audit {
+                        Some(would) => format!(" (audit: would be {})", would),
+                        None => String::new(),
+                    },
+                    e.bytes_up,
+                    e.bytes_down,
+                    e.duration_ms
+                )
+            }
+            Event::Probe(r) => format!(
+                "{} probe    {} [{}] from {}: latency={:.1} ms failure_rate={:.3} enabled={}",
+                clock(r.observed_unix),
+                r.health.name,
+                r.health.kind.as_str(),
+                r.node,
+                r.health.latency_ms,
+                r.health.failure_rate,
+                r.health.enabled
+            ),
+            Event::Failover(f) => {
+                format!("{} failover {}: {}", clock(f.unix), f.subject, f.reason)
+            }
+        }
+    }
+}
+
+/// `HH:MM:SS` (UTC).
+fn clock(unix: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        unix / 3_600 % 24,
+        unix / 60 % 60,
+        unix % 60
+    )
+}
+
+/// Follow the dispatcher's event stream on its dashboard listener at `addr`
+/// until the dispatcher goes away, with the `[api]` token if it wants one.
+pub async fn follow(
+    addr: SocketAddr,
+    token: Option<&str>,
+    mut on_event: impl FnMut(Streamed),
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let auth = token
+        .map(|token| format!("Authorization: Bearer {token}\r\n"))
+        .unwrap_or_default();
+    // HTTP/1.0: the body is the rest of the connection, not chunked
+    let request = format!(
+        "GET /api/events HTTP/1.0\r\nHost: {addr}\r\n{auth}Accept: text/event-stream\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut lines = BufReader::new(stream).lines();
+
+    let status = lines.next_line().await?.ok_or("no response")?;
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("event stream refused: {status}").into());
+    }
+    while let Some(line) = lines.next_line().await? {
+        if line.is_empty() {
+            break;
+        }
+    }
+    while let Some(line) = lines.next_line().await? {
+        if let Some(data) = line.strip_prefix("data:") {
+            let event = serde_json::from_str(data.trim_start())?;
+            on_event(Streamed::Event(Box::new(event)));
+        } else if let Some(skipped) = line.strip_prefix(": skipped ") {
+            on_event(Streamed::Skipped(skipped.trim().parse().unwrap_or(0)));
+        }
+    }
+    Ok(())
+}
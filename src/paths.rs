@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+
+use serde_json::Value;
+
+use crate::config::TorControlConfig;
+use crate::lokinet;
+use crate::torctl::{self, Control};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// One relay on a Tor circuit.
+#[derive(Debug, Clone)]
+pub struct Relay {
+    pub fingerprint: String,
+    pub nickname: Option<String>,
+    /// Its OR address, from the consensus.
+    pub address: Option<String>,
+    /// Two-letter code from Tor's GeoIP database.
+    pub country: Option<String>,
+}
+
+/// A built Tor circuit (`GETINFO circuit-status`).
+#[derive(Debug, Clone)]
+pub struct TorCircuit {
+    pub id: String,
+    /// `GENERAL`, `HS_CLIENT_REND`, ...
+    pub purpose: String,
+    /// Guard first.
+    pub hops: Vec<Relay>,
+    /// Streams attached to it right now.
+    pub streams: usize,
+}
+
+impl TorCircuit {
+    /// What hop `i` does: guard, middle, and exit for general circuits (an
+    /// onion service circuit ends at a rendezvous or intro point, not an exit).
+    pub fn role(&self, i: usize) -> &'static str {
+        match i {
+            0 => "guard",
+            i if i + 1 < self.hops.len() => "middle",
+            _ if self.purpose == "GENERAL" => "exit",
+            _ => "last",
+        }
+    }
+}
+
+/// One lokinet path, from the daemon's status.
+#[derive(Debug, Clone)]
+pub struct LokinetPath {
+    /// Endpoint (or session) the path belongs to.
+    pub owner: String,
+    /// Router IDs, first hop first.
+    pub hops: Vec<String>,
+    pub ready: Option<bool>,
+}
+
+/// Built circuits of the Tor behind `cfg`, with where each relay is.
+pub async fn tor_circuits(cfg: &TorControlConfig) -> Result<Vec<TorCircuit>, BoxError> {
+    let mut control = Control::connect(cfg).await?;
+    let mut streams: HashMap<String, usize> = HashMap::new();
+    for line in control.command("GETINFO stream-status").await? {
+        // <id> <status> <circuit> <target>
+        if let Some(circuit) = line.trim_start_matches("stream-status=").split(' ').nth(2) {
+            *streams.entry(circuit.to_string()).or_default() += 1;
+        }
+    }
+
+    let mut circuits = Vec::new();
+    let mut relays: HashMap<String, Relay> = HashMap::new();
+    for line in control.command("GETINFO circuit-status").await? {
+        // <id> BUILT <path> [key=value ...]
+        let words: Vec<&str> = line
+            .trim_start_matches("circuit-status=")
+            .split(' ')
+            .collect();
+        let [id, "BUILT", path, ref rest @ ..] = words[..] else {
+            continue;
+        };
+        let mut hops = Vec::new();
+        for hop in path.split(',') {
+            let mut parts = hop.splitn(2, ['~', '=']);
+            let Some(fingerprint) = parts.next().and_then(torctl::fingerprint) else {
+                continue;
+            };
+            if !relays.contains_key(&fingerprint) {
+                let relay = locate(&mut control, &fingerprint, parts.next()).await?;
+                relays.insert(fingerprint.clone(), relay);
+            }
+            hops.push(relays[&fingerprint].clone());
+        }
+        circuits.push(TorCircuit {
+            id: id.to_string(),
+            purpose: rest
+                .iter()
+                .find_map(|w| w.strip_prefix("PURPOSE="))
+                .unwrap_or("GENERAL")
+                .to_string(),
+            hops,
+            streams: streams.get(id).copied().unwrap_or(0),
+        });
+    }
+    Ok(circuits)
+}
+
+/// A relay's address (its consensus `r` line) and country, where Tor
+/// knows them.
+async fn locate(
+    control: &mut Control,
+    fingerprint: &str,
+    nickname: Option<&str>,
+) -> Result<Relay, BoxError> {
+    let mut relay = Relay {
+        fingerprint: fingerprint.to_string(),
+        nickname: nickname.map(str::to_string),
+        address: None,
+        country: None,
+    };
+    let status = control
+        .try_command(&format!("GETINFO ns/id/{}", &fingerprint[1..]))
+        .await?;
+    // r <nickname> <identity> [<digest>] <date> <time> <ip> <orport> <dirport>
+    let lines = status.unwrap_or_default();
+    let Some(r) = lines.iter().find(|l| l.starts_with("r ")) else {
+        return Ok(relay);
+    };
+    let fields: Vec<&str> = r.split(' ').collect();
+    if relay.nickname.is_none() {
+        relay.nickname = fields.get(1).map(|n| n.to_string());
+    }
+    let Some(ip) = fields.iter().skip(3).find(|f| f.parse::<IpAddr>().is_ok()) else {
+        return Ok(relay);
+    };
+    let ip = ip.to_string();
+    let country = control
+        .try_command(&format!("GETINFO ip-to-country/{}", ip))
+        .await?;
+    // ip-to-country/<ip>=<cc>; `??` when unknown, refused without GeoIP
+    relay.country = country
+        .unwrap_or_default()
+        .first()
+        .and_then(|l| l.rsplit('=').next())
+        .filter(|cc| cc.len() == 2 && *cc != "??")
+        .map(str::to_string);
+    relay.address = Some(ip);
+    Ok(relay)
+}
+
+/// Paths of the lokinet daemon at `rpc`, from its status. The layout
+/// varies between lokinet versions, so paths are found as any object with a
+/// `hops` list.
+pub async fn lokinet_paths(rpc: SocketAddr) -> Result<Vec<LokinetPath>, BoxError> {
+    let status = lokinet::status(rpc).await?;
+    let mut paths = Vec::new();
+    collect_paths(&status, "lokinet", &mut paths);
+    Ok(paths)
+}
+
+/// Every object with a `hops` list under `value`, owned by the nearest
+/// enclosing key that names something (not `paths` or an index).
+fn collect_paths(value: &Value, owner: &str, paths: &mut Vec<LokinetPath>) {
+    match value {
+        Value::Object(map) => {
+            if let Some(Value::Array(hops)) = map.get("hops") {
+                paths.push(LokinetPath {
+                    owner: owner.to_string(),
+                    hops: hops
+                        .iter()
+                        .filter_map(|hop| match hop {
+                            Value::String(router) => Some(router.clone()),
+                            hop => hop["router"].as_str().map(str::to_string),
+                        })
+                        .collect(),
+                    ready: map.get("ready").and_then(Value::as_bool),
+                });
+                return;
+            }
+            for (key, value) in map {
+                let owner = match key.as_str() {
+                    "paths" | "result" | "services" => owner,
+                    key => key,
+                };
+                collect_paths(value, owner, paths);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                collect_paths(item, owner, paths);
+            }
+        }
+        _ => {}
+    }
+}
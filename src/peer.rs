@@ -0,0 +1,80 @@
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+
+/// Socket tables of the dispatcher's network namespace. Under `/proc/self`,
+/// which the sandbox leaves readable.
+const TCP_TABLES: [&str; 2] = ["/proc/self/net/tcp", "/proc/self/net/tcp6"];
+
+/// Local user behind a loopback TCP connection: the owner of the client's
+/// socket, i.e. the one whose local address is `peer` and remote address
+/// `local`. `None` off Linux, or once the client is gone.
+pub fn tcp_uid(local: SocketAddr, peer: SocketAddr) -> Option<u32> {
+    let (ours, theirs) = (hex(peer), hex(local));
+    TCP_TABLES.iter().find_map(|table| {
+        let text = fs::read_to_string(table).ok()?;
+        text.lines().skip(1).find_map(|line| {
+            // sl local_address rem_address st queues timer retrnsmt uid ...
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [_, l, r, _, _, _, _, uid, ..] if *l == ours && *r == theirs => uid.parse().ok(),
+                _ => None,
+            }
+        })
+    })
+}
+
+/// Login name for `uid`, from `/etc/passwd`.
+pub fn user_name(uid: u32) -> Option<String> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let id = fields.nth(1)?;
+        (id.parse() == Ok(uid)).then(|| name.to_string())
+    })
+}
+
+/// Groups `uid` belongs to, by name: its primary group from `/etc/passwd`
+/// and those `/etc/group` lists it in.
+pub fn groups(uid: u32) -> Vec<String> {
+    let passwd = fs::read_to_string("/etc/passwd").unwrap_or_default();
+    let Some((name, gid)) = passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.as_slice() {
+            [name, _, id, gid, ..] if id.parse() == Ok(uid) => Some((*name, *gid)),
+            _ => None,
+        }
+    }) else {
+        return vec![];
+    };
+    let group = fs::read_to_string("/etc/group").unwrap_or_default();
+    group
+        .lines()
+        .filter_map(|line| {
+            // name:password:gid:member,member
+            let fields: Vec<&str> = line.split(':').collect();
+            match fields.as_slice() {
+                [group, _, id, members, ..]
+                    if *id == gid || members.split(',').any(|m| m == name) =>
+                {
+                    Some(group.to_string())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// An address as `/proc/net/tcp` prints it: the address in 32-bit words of
+/// host byte order, then the port, in uppercase hex.
+fn hex(addr: SocketAddr) -> String {
+    let octets = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    let words: String = octets
+        .chunks(4)
+        .map(|w| format!("{:08X}", u32::from_ne_bytes([w[0], w[1], w[2], w[3]])))
+        .collect();
+    format!("{}:{:04X}", words, addr.port())
+}
@@ -0,0 +1,201 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::thread;
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+/// Pidfile the dispatcher holds an exclusive lock on while it runs.
+pub const PID_PATH: &str = "gold-dust-dispatcher.pid";
+
+/// How long a replaced instance gets to exit after SIGTERM before SIGKILL.
+#[cfg(unix)]
+const TAKEOVER_GRACE: Duration = Duration::from_secs(10);
+
+/// Held pidfile lock: one daemon per state directory. Released when dropped
+/// (or when the process dies, so a crashed instance never blocks a restart).
+#[derive(Debug)]
+pub struct PidLock {
+    _file: File,
+}
+
+impl PidLock {
+    /// Lock `path` and write our pid into it.
+    ///
+    /// If another live instance holds the lock this fails, unless `takeover`
+    /// is set: then that instance is sent SIGTERM (SIGKILL after a grace
+    /// period) and the lock is taken once it is gone. The pid is only
+    /// signalled while it still names a process running this program.
+    pub fn acquire<P: AsRef<Path>>(path: P, takeover: bool) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut file = open(path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = read_pid(&mut file);
+                let who = pid.map_or("unknown pid".to_string(), |p| format!("pid {p}"));
+                if !takeover {
+                    return Err(format!(
+                        "another instance ({who}) holds {}; stop it or pass --takeover",
+                        path.display()
+                    ));
+                }
+                let pid = pid.ok_or_else(|| {
+                    format!(
+                        "{} is locked but holds no pid to take over from",
+                        path.display()
+                    )
+                })?;
+                take_over(&file, pid)?;
+            }
+            Err(TryLockError::Error(e)) => return Err(format!("{}: {}", path.display(), e)),
+        }
+
+        claim(file, path)
+    }
+
+    /// Open `path` to take its lock once the instance holding it exits,
+    /// for a successor that already has that instance's sockets (see
+    /// [`handover`](crate::handover)). Opened now, while the file can still
+    /// be reached.
+    pub fn inherit<P: AsRef<Path>>(path: P) -> Result<InheritedLock, String> {
+        let path = path.as_ref();
+        Ok(InheritedLock {
+            file: open(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+/// A pidfile whose lock is still held by the instance we took over from.
+#[derive(Debug)]
+pub struct InheritedLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl InheritedLock {
+    /// Block until the previous instance is gone, then lock the pidfile and
+    /// write our pid into it.
+    pub fn wait(self) -> Result<PidLock, String> {
+        self.file
+            .lock()
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        claim(self.file, &self.path)
+    }
+}
+
+fn open(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Write our pid into `file`, whose lock we hold.
+fn claim(mut file: File, path: &Path) -> Result<PidLock, String> {
+    file.set_len(0)
+        .and_then(|()| file.seek(SeekFrom::Start(0)).map(drop))
+        .and_then(|()| writeln!(file, "{}", std::process::id()))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(PidLock { _file: file })
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut text = String::new();
+    file.read_to_string(&mut text).ok()?;
+    text.trim().parse().ok()
+}
+
+/// Stop `pid` and wait for its lock on `file` to go away.
+#[cfg(unix)]
+fn take_over(file: &File, pid: u32) -> Result<(), String> {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    // 0 and negative pids address process groups, not a process
+    let target = i32::try_from(pid)
+        .ok()
+        .filter(|&p| p > 1 && pid != std::process::id())
+        .map(Pid::from_raw)
+        .ok_or_else(|| format!("refusing to signal pid {pid}"))?;
+    for signal in [Signal::SIGTERM, Signal::SIGKILL] {
+        // The pid may have been reused since it was written, or since the
+        // last signal
+        same_program(pid)?;
+        kill(target, signal).map_err(|e| format!("{signal} to pid {pid}: {e}"))?;
+
+        let started = Instant::now();
+        while started.elapsed() < TAKEOVER_GRACE {
+            match file.try_lock() {
+                Ok(()) => return Ok(()),
+                Err(TryLockError::WouldBlock) => thread::sleep(Duration::from_millis(200)),
+                Err(TryLockError::Error(e)) => return Err(e.to_string()),
+            }
+        }
+    }
+    Err(format!("pid {pid} still holds the lock after SIGKILL"))
+}
+
+#[cfg(not(unix))]
+fn take_over(_file: &File, pid: u32) -> Result<(), String> {
+    Err(format!(
+        "--takeover is not supported here; stop pid {pid} first"
+    ))
+}
+
+/// Is `pid` running the same program as us?
+#[cfg(target_os = "linux")]
+fn same_program(pid: u32) -> Result<(), String> {
+    let comm = |pid: &str| {
+        std::fs::read_to_string(format!("/proc/{pid}/comm")).map(|s| s.trim_end().to_string())
+    };
+    let ours = comm("self").map_err(|e| format!("/proc/self/comm: {e}"))?;
+    let theirs = comm(&pid.to_string()).map_err(|e| format!("pid {pid}: {e}"))?;
+    if theirs == ours {
+        Ok(())
+    } else {
+        Err(format!(
+            "pid {pid} is `{theirs}`, not `{ours}`; the pidfile is stale, not signalling it"
+        ))
+    }
+}
+
+/// Without `/proc` there is no cheap way to tell, so never guess.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn same_program(pid: u32) -> Result<(), String> {
+    Err(format!(
+        "can't confirm pid {pid} is a dispatcher on this platform; stop it first"
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_our_own_program_is_signalled() {
+        let child = std::process::Command::new("sleep").arg("30").spawn();
+        let Ok(mut child) = child else {
+            return;
+        };
+        let err = same_program(child.id()).expect_err("sleep is another program");
+        assert!(err.contains("`sleep`"), "{err}");
+        child.kill().ok();
+        child.wait().ok();
+
+        assert!(same_program(std::process::id()).is_ok());
+        let path = std::env::temp_dir().join(format!("gd-pid-{}", std::process::id()));
+        let file = open(&path).unwrap();
+        assert!(take_over(&file, 0).unwrap_err().starts_with("refusing"));
+        assert!(take_over(&file, std::process::id())
+            .unwrap_err()
+            .starts_with("refusing"));
+        std::fs::remove_file(path).ok();
+    }
+}
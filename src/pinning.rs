@@ -0,0 +1,240 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ring::digest;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use serde::Deserialize;
+
+/// SHA-256 of a certificate's public key (its DER SubjectPublicKeyInfo):
+/// `sha256//<base64>` as curl's `--pinnedpubkey` takes it, or 64 hex digits
+/// as `openssl dgst -sha256` prints it.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SpkiPin(pub [u8; 32]);
+
+impl SpkiPin {
+    /// The pin of `cert`'s key, if its DER can be read.
+    pub fn of(cert: &[u8]) -> Option<Self> {
+        let spki = subject_public_key_info(cert)?;
+        let mut pin = [0u8; 32];
+        pin.copy_from_slice(digest::digest(&digest::SHA256, spki).as_ref());
+        Some(Self(pin))
+    }
+}
+
+impl FromStr for SpkiPin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = match s.strip_prefix("sha256//") {
+            Some(b64) => base64(b64),
+            None if s.len() == 64 => (0..64)
+                .step_by(2)
+                .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+                .collect(),
+            None => None,
+        };
+        bytes
+            .and_then(|b| <[u8; 32]>::try_from(b).ok())
+            .map(Self)
+            .ok_or_else(|| {
+                format!("`{s}` is not a SHA-256 key pin (sha256//<base64> or 64 hex digits)")
+            })
+    }
+}
+
+impl TryFrom<String> for SpkiPin {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl fmt::Debug for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SpkiPin({self})")
+    }
+}
+
+/// Standard base64, padding optional.
+fn base64(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in s.trim_end_matches('=').bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = acc << 6 | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// One DER element.
+struct Element<'a> {
+    tag: u8,
+    /// Header and contents.
+    whole: &'a [u8],
+    contents: &'a [u8],
+    /// What follows it.
+    rest: &'a [u8],
+}
+
+fn element(der: &[u8]) -> Option<Element<'_>> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        n if n < 0x80 => (n as usize, rest),
+        n => {
+            let count = (n & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0usize, |l, b| l << 8 | *b as usize);
+            (len, &rest[count..])
+        }
+    };
+    let header = der.len() - rest.len();
+    Some(Element {
+        tag,
+        whole: der.get(..header + len)?,
+        contents: rest.get(..len)?,
+        rest: &rest[len..],
+    })
+}
+
+/// The SubjectPublicKeyInfo of an X.509 certificate: the seventh field of
+/// its TBSCertificate, counting the optional `[0]` version.
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    const SEQUENCE: u8 = 0x30;
+    const VERSION: u8 = 0xa0;
+    let sequence = |der| element(der).filter(|e| e.tag == SEQUENCE);
+    let cert = sequence(cert)?.contents;
+    let mut fields = sequence(cert)?.contents;
+    if fields.first() == Some(&VERSION) {
+        fields = element(fields)?.rest;
+    }
+    // serial, signature, issuer, validity, subject
+    for _ in 0..5 {
+        fields = element(fields)?.rest;
+    }
+    Some(sequence(fields)?.whole)
+}
+
+/// Accepts a server whose leaf certificate carries one of the pinned keys;
+/// a match is trusted on its own (no CA chain, no name check), so
+/// self-signed certificates work. Without pins, any key is accepted: the
+/// connection is encrypted, not authenticated.
+#[derive(Debug)]
+struct PinnedVerifier {
+    pins: Option<Vec<SpkiPin>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let Some(pins) = &self.pins else {
+            return Ok(ServerCertVerified::assertion());
+        };
+        match SpkiPin::of(end_entity) {
+            Some(pin) if pins.contains(&pin) => Ok(ServerCertVerified::assertion()),
+            Some(pin) => Err(rustls::Error::General(format!(
+                "server key {pin} matches no pin"
+            ))),
+            None => Err(rustls::Error::General(
+                "server certificate is not valid X.509".to_string(),
+            )),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// A rustls client config that trusts exactly the servers holding one of
+/// `pins`.
+pub fn tls_config(pins: &[SpkiPin]) -> Arc<ClientConfig> {
+    verifying_config(Some(pins.to_vec()))
+}
+
+/// A rustls client config that takes any certificate, for opportunistic
+/// encryption where a cleartext fallback would be the alternative.
+pub fn unauthenticated_tls_config() -> Arc<ClientConfig> {
+    verifying_config(None)
+}
+
+fn verifying_config(pins: Option<Vec<SpkiPin>>) -> Arc<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = PinnedVerifier {
+        pins,
+        provider: provider.clone(),
+    };
+    Arc::new(
+        ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default TLS versions")
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth(),
+    )
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::router::BackendHealth;
+use crate::target::Target;
+
+/// Custom routing logic: rank the candidate backends for a target.
+pub trait RoutingPolicy: fmt::Debug + Send {
+    /// Backend names, best first. Unknown or disabled names are skipped.
+    fn rank(&mut self, target: &Target, backends: &[BackendHealth]) -> Result<Vec<String>, String>;
+}
+
+/// JSON document handed to a policy.
+pub fn policy_input(target: &Target, backends: &[BackendHealth]) -> String {
+    let backends: Vec<_> = backends
+        .iter()
+        .map(|b| {
+            serde_json::json!({
+                "name": b.name,
+                "kind": b.kind.as_str(),
+                "latency_ms": b.latency_ms,
+                "failure_rate": b.failure_rate,
+                "enabled": b.enabled,
+                "ipv6": b.ipv6,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "target": {
+            "host": target.host_str(),
+            "port": target.port,
+            "ipv6": target.is_ipv6(),
+        },
+        "backends": backends,
+    })
+    .to_string()
+}
+
+/// Load the policy module at `path`.
+#[cfg(feature = "wasm-plugins")]
+pub fn load(path: &Path) -> Result<Box<dyn RoutingPolicy>, String> {
+    Ok(Box::new(wasm::WasmPolicy::load(path)?))
+}
+
+/// Load the policy module at `path`.
+#[cfg(not(feature = "wasm-plugins"))]
+pub fn load(path: &Path) -> Result<Box<dyn RoutingPolicy>, String> {
+    Err(format!(
+        "routing policy {}: built without the `wasm-plugins` feature",
+        path.display()
+    ))
+}
+
+#[cfg(feature = "wasm-plugins")]
+mod wasm {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::SystemTime;
+
+    use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+    use super::{policy_input, RoutingPolicy};
+    use crate::router::BackendHealth;
+    use crate::target::Target;
+
+    /// Instructions one `rank` call may execute.
+    const FUEL_PER_CALL: u64 = 10_000_000;
+    /// Linear memory ceiling per instance.
+    const MAX_MEMORY: usize = 16 * 1024 * 1024;
+
+    /// A WASM routing policy, recompiled when the file changes.
+    ///
+    /// Modules get no imports (no WASI, no host calls) and a fresh instance
+    /// per call, bounded by fuel and memory limits. They must export:
+    ///
+    /// * `memory`
+    /// * `alloc(len: i32) -> i32`: room for the input
+    /// * `rank(ptr: i32, len: i32) -> i64`: JSON input in, `(ptr << 32) | len`
+    ///   of a JSON array of backend names out
+    pub struct WasmPolicy {
+        path: PathBuf,
+        modified: Option<SystemTime>,
+        engine: Engine,
+        module: Module,
+    }
+
+    impl std::fmt::Debug for WasmPolicy {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("WasmPolicy")
+                .field("path", &self.path)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl WasmPolicy {
+        pub fn load(path: &Path) -> Result<Self, String> {
+            let mut config = Config::default();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config);
+            let (module, modified) = compile(&engine, path)?;
+            Ok(Self {
+                path: path.to_path_buf(),
+                modified,
+                engine,
+                module,
+            })
+        }
+
+        /// Pick up a rewritten module. A broken rewrite is retried (and
+        /// reported) on every call until it compiles.
+        fn reload_if_changed(&mut self) -> Result<(), String> {
+            let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+            if modified == self.modified {
+                return Ok(());
+            }
+            let (module, modified) = compile(&self.engine, &self.path)?;
+            self.module = module;
+            self.modified = modified;
+            Ok(())
+        }
+    }
+
+    fn compile(engine: &Engine, path: &Path) -> Result<(Module, Option<SystemTime>), String> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let module =
+            Module::new(engine, bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok((module, modified))
+    }
+
+    impl RoutingPolicy for WasmPolicy {
+        fn rank(
+            &mut self,
+            target: &Target,
+            backends: &[BackendHealth],
+        ) -> Result<Vec<String>, String> {
+            self.reload_if_changed()?;
+            let input = policy_input(target, backends);
+
+            let limits: StoreLimits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build();
+            let mut store = Store::new(&self.engine, limits);
+            store.limiter(|limits| limits);
+            store.set_fuel(FUEL_PER_CALL).map_err(|e| e.to_string())?;
+
+            let err = |e: wasmi::Error| format!("{}: {}", self.path.display(), e);
+            let instance = Linker::new(&self.engine)
+                .instantiate_and_start(&mut store, &self.module)
+                .map_err(err)?;
+            let memory = instance
+                .get_memory(&store, "memory")
+                .ok_or_else(|| format!("{}: no `memory` export", self.path.display()))?;
+            let alloc = instance
+                .get_typed_func::<i32, i32>(&store, "alloc")
+                .map_err(err)?;
+            let rank = instance
+                .get_typed_func::<(i32, i32), i64>(&store, "rank")
+                .map_err(err)?;
+
+            let len = input.len() as i32;
+            let ptr = alloc.call(&mut store, len).map_err(err)?;
+            memory
+                .write(&mut store, ptr as u32 as usize, input.as_bytes())
+                .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+            let packed = rank.call(&mut store, (ptr, len)).map_err(err)? as u64;
+
+            let mut out = vec![0u8; (packed & 0xffff_ffff) as usize];
+            memory
+                .read(&store, (packed >> 32) as usize, &mut out)
+                .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+            serde_json::from_slice(&out)
+                .map_err(|e| format!("{}: bad ranking: {}", self.path.display(), e))
+        }
+    }
+}
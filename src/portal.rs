@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::PortalConfig;
+use crate::http::{self, Url};
+use crate::matcher::registrable_domain;
+use crate::stats::{now_unix, write_atomic};
+use crate::target::Host;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Portal mode, written by `portal login`, read by the dispatcher per
+/// session.
+pub const PORTAL_PATH: &str = "gold-dust-portal.json";
+
+/// A captive portal that intercepted the connectivity probe.
+#[derive(Debug, Clone)]
+pub struct Portal {
+    /// Where the probe was redirected.
+    pub login_url: String,
+    pub host: String,
+}
+
+/// Fetch the probe URL directly. `None` if it came back as expected (the
+/// network is open), the portal if it redirected elsewhere; any other
+/// answer is an error, since it can't say which host to let through.
+pub async fn detect(cfg: &PortalConfig) -> Result<Option<Portal>, BoxError> {
+    let probe = Url::parse(&cfg.probe_url)?;
+    let resp = http::request("GET", &probe, &[], &[]).await?;
+    if resp.status == cfg.expect_status {
+        return Ok(None);
+    }
+    let location = resp
+        .header("Location")
+        .filter(|_| (300..400).contains(&resp.status))
+        .ok_or_else(|| format!("probe answered HTTP {} without a redirect", resp.status))?;
+    let host = match Url::parse(location) {
+        Ok(url) => url.host,
+        // Relative: the portal answers for the probe's own host
+        Err(_) => probe.host.clone(),
+    };
+    Ok(Some(Portal {
+        login_url: location.to_string(),
+        host: host.to_ascii_lowercase(),
+    }))
+}
+
+/// Direct access to a portal's hosts, until a deadline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortalMode {
+    /// Hosts let through; each covers its registrable domain, since portals
+    /// hop between e.g. `login.` and `auth.` names.
+    pub hosts: Vec<String>,
+    pub login_url: Option<String>,
+    pub until_unix: u64,
+}
+
+impl PortalMode {
+    /// The mode in force, if any: an expired one counts as ended.
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let text = fs::read_to_string(path).ok()?;
+        serde_json::from_str::<Self>(&text)
+            .ok()
+            .filter(|m| m.until_unix > now_unix())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        write_atomic(path.as_ref(), &json)
+    }
+
+    /// Returns whether a mode was on (expired or not).
+    pub fn end<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+        match fs::remove_file(path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Seconds left.
+    pub fn remaining_secs(&self) -> u64 {
+        self.until_unix.saturating_sub(now_unix())
+    }
+
+    /// Does `host` belong to the portal?
+    pub fn allows(&self, host: &Host) -> bool {
+        let name = host.to_string();
+        self.hosts.iter().any(|h| match host {
+            Host::Domain(_) if h.parse::<IpAddr>().is_err() => {
+                let domain = registrable_domain(h).unwrap_or(h);
+                name == domain
+                    || name
+                        .strip_suffix(domain)
+                        .is_some_and(|rest| rest.ends_with('.'))
+            }
+            _ => name == *h,
+        })
+    }
+}
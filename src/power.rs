@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::config::{PowerConfig, PowerMode};
+
+/// Where Linux lists batteries and chargers.
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Is the machine running on battery? `None` where the OS doesn't say (no
+/// power supply class, or not Linux).
+pub fn on_battery() -> Option<bool> {
+    let read = |dir: &Path, attr: &str| {
+        fs::read_to_string(dir.join(attr))
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    };
+    let mut discharging = None;
+    for entry in fs::read_dir(POWER_SUPPLY_DIR).ok()?.flatten() {
+        let dir = entry.path();
+        match read(&dir, "type").as_str() {
+            "Mains" | "USB" if read(&dir, "online") == "1" => return Some(false),
+            // A mouse or headset battery says nothing about the machine
+            "Battery" if read(&dir, "scope") != "Device" => {
+                discharging =
+                    Some(discharging.unwrap_or(false) || read(&dir, "status") == "Discharging");
+            }
+            _ => {}
+        }
+    }
+    discharging
+}
+
+/// The dispatcher's power profile: whether it is probing less right now.
+#[derive(Debug)]
+pub struct Power {
+    cfg: PowerConfig,
+    low: AtomicBool,
+    /// Origin of the low-power batching grid.
+    epoch: Instant,
+}
+
+impl Power {
+    pub fn new(cfg: &PowerConfig) -> Self {
+        let power = Self {
+            cfg: cfg.clone(),
+            low: AtomicBool::new(cfg.mode == PowerMode::Low),
+            epoch: Instant::now(),
+        };
+        power.refresh();
+        power
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.low.load(Ordering::SeqCst)
+    }
+
+    /// Switch profiles from outside, for embedders whose platform reports
+    /// the power state itself.
+    pub fn set_low(&self, low: bool) {
+        self.low.store(low, Ordering::SeqCst);
+    }
+
+    /// In `auto` mode, look at the power supply again. Returns the new
+    /// setting if it changed.
+    pub fn refresh(&self) -> Option<bool> {
+        if self.cfg.mode != PowerMode::Auto {
+            return None;
+        }
+        let low = on_battery().unwrap_or(false);
+        (self.low.swap(low, Ordering::SeqCst) != low).then_some(low)
+    }
+
+    /// Ticks every `period`, batched onto the grid in low power.
+    pub fn ticker(self: &Arc<Self>, period: Duration) -> Ticker {
+        Ticker::new(self.clone(), period, false)
+    }
+
+    /// Like `ticker`, and `period` is stretched by `probe_stretch` in low
+    /// power.
+    pub fn probe_ticker(self: &Arc<Self>, period: Duration) -> Ticker {
+        Ticker::new(self.clone(), period, true)
+    }
+
+    /// How far apart ticks of a `ticker(period)` fall right now.
+    pub fn interval(&self, period: Duration) -> Duration {
+        self.spacing(period, false)
+    }
+
+    /// In low power, `period` stretched (probes only) and rounded up to
+    /// whole batches.
+    fn spacing(&self, period: Duration, stretch: bool) -> Duration {
+        if !self.is_low() {
+            return period;
+        }
+        let period = match stretch {
+            true => period * self.cfg.probe_stretch.max(1),
+            false => period,
+        };
+        let batch = self.cfg.batch_secs.max(1);
+        Duration::from_secs(period.as_secs().max(1).div_ceil(batch) * batch)
+    }
+
+    /// The first grid point at or after `at`.
+    fn align(&self, at: Instant) -> Instant {
+        let batch = self.cfg.batch_secs.max(1);
+        let since = at.saturating_duration_since(self.epoch).as_secs();
+        self.epoch + Duration::from_secs(since.div_ceil(batch) * batch)
+    }
+}
+
+/// Like `tokio::time::interval` (the first tick is immediate, missed ticks
+/// are skipped), but follows the power profile at every tick.
+#[derive(Debug)]
+pub struct Ticker {
+    power: Arc<Power>,
+    period: Duration,
+    stretch: bool,
+    last: Option<Instant>,
+}
+
+impl Ticker {
+    fn new(power: Arc<Power>, period: Duration, stretch: bool) -> Self {
+        Self {
+            power,
+            period,
+            stretch,
+            last: None,
+        }
+    }
+
+    pub async fn tick(&mut self) {
+        let now = Instant::now();
+        let Some(last) = self.last else {
+            self.last = Some(now);
+            return;
+        };
+        let next = last + self.power.spacing(self.period, self.stretch);
+        let next = match self.power.is_low() {
+            true => self.power.align(next),
+            false => next,
+        };
+        let next = next.max(now);
+        tokio::time::sleep_until(next).await;
+        self.last = Some(next);
+    }
+
+    /// How far apart ticks fall right now.
+    pub fn period(&self) -> Duration {
+        self.power.spacing(self.period, self.stretch)
+    }
+}
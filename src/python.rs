@@ -0,0 +1,190 @@
+//! Python module (`python` feature) for driving routing simulations and
+//! collecting decision logs from notebooks.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::config::GoldDustConfig;
+use crate::policy;
+use crate::router::{BackendChoice, HealthUpdate, Router, NO_BACKENDS};
+use crate::simulate::Scenario;
+use crate::target::Target;
+
+/// Gold Dust config (`gold-dust-gateway.toml`).
+#[pyclass(name = "Config", frozen)]
+pub struct PyConfig {
+    inner: GoldDustConfig,
+}
+
+#[pymethods]
+impl PyConfig {
+    /// Load and validate a config TOML file.
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        GoldDustConfig::load(path)
+            .map(|inner| Self { inner })
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// Parse config TOML text.
+    #[staticmethod]
+    fn from_toml(text: &str) -> PyResult<Self> {
+        GoldDustConfig::parse(text)
+            .map(|inner| Self { inner })
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// The built-in demo config (simulated Oxen and Tor backends).
+    #[staticmethod]
+    fn demo() -> Self {
+        Self {
+            inner: GoldDustConfig::default_for_demo(),
+        }
+    }
+}
+
+/// Backend router over the simulated backends of a config.
+#[pyclass(name = "Router")]
+pub struct PyRouter {
+    inner: Mutex<Router>,
+}
+
+fn choice_dict<'py>(
+    py: Python<'py>,
+    target: &Target,
+    choice: &BackendChoice,
+) -> PyResult<Bound<'py, PyDict>> {
+    let d = PyDict::new(py);
+    d.set_item("target", target.to_string())?;
+    d.set_item("backend", &choice.name)?;
+    d.set_item("kind", choice.kind.as_str())?;
+    d.set_item("latency_ms", choice.latency_ms)?;
+    d.set_item("failure_rate", choice.failure_rate)?;
+    d.set_item("ipv6", choice.ipv6)?;
+    Ok(d)
+}
+
+fn parse_target(target: &str) -> PyResult<Target> {
+    target
+        .parse()
+        .map_err(|e: crate::target::TargetError| PyValueError::new_err(e.to_string()))
+}
+
+impl PyRouter {
+    fn router(&self) -> std::sync::MutexGuard<'_, Router> {
+        self.inner.lock().expect("router poisoned")
+    }
+}
+
+#[pymethods]
+impl PyRouter {
+    /// Build a router; `seed` overrides `[routing] seed` for reproducible runs.
+    #[new]
+    #[pyo3(signature = (config, seed=None))]
+    fn new(config: &PyConfig, seed: Option<u64>) -> PyResult<Self> {
+        let mut cfg = config.inner.clone();
+        if seed.is_some() {
+            cfg.routing.seed = seed;
+        }
+        let mut router = Router::from_config(&cfg);
+        if router.is_empty() {
+            return Err(PyValueError::new_err(NO_BACKENDS));
+        }
+        if let Some(path) = &cfg.routing.policy {
+            router.set_policy(policy::load(path).map_err(PyValueError::new_err)?);
+        }
+        Ok(Self {
+            inner: Mutex::new(router),
+        })
+    }
+
+    /// Choose a backend for `target`; returns a dict.
+    fn route<'py>(&self, py: Python<'py>, target: &str) -> PyResult<Bound<'py, PyDict>> {
+        let target = parse_target(target)?;
+        let choice = self
+            .router()
+            .choose_backend_for(&target)
+            .ok_or_else(|| PyRuntimeError::new_err(NO_BACKENDS))?;
+        choice_dict(py, &target, &choice)
+    }
+
+    /// Current backend health (and decisions so far, `picks`) as a list of
+    /// dicts.
+    fn status<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let router = self.router();
+        router
+            .backend_health()
+            .iter()
+            .map(|b| {
+                let d = PyDict::new(py);
+                d.set_item("name", &b.name)?;
+                d.set_item("kind", b.kind.as_str())?;
+                d.set_item("latency_ms", b.latency_ms)?;
+                d.set_item("failure_rate", b.failure_rate)?;
+                d.set_item("enabled", b.enabled)?;
+                d.set_item("ipv6", b.ipv6)?;
+                d.set_item("picks", router.picks().get(&b.name).copied().unwrap_or(0))?;
+                Ok(d)
+            })
+            .collect()
+    }
+
+    /// Change one backend's health. Returns `False` if no backend has that name.
+    #[pyo3(signature = (backend, latency_ms=None, failure_rate=None, enabled=None))]
+    fn apply(
+        &self,
+        backend: String,
+        latency_ms: Option<f64>,
+        failure_rate: Option<f64>,
+        enabled: Option<bool>,
+    ) -> bool {
+        self.router().apply(&HealthUpdate {
+            backend,
+            latency_ms,
+            failure_rate,
+            enabled,
+        })
+    }
+
+    /// Restart randomized selection from `seed`.
+    fn reseed(&self, seed: u64) {
+        self.router().reseed(seed);
+    }
+
+    /// Replay a scenario file; returns one dict per decision (ready for
+    /// `pandas.DataFrame`), with the step's `t` and `note`.
+    fn simulate<'py>(
+        &self,
+        py: Python<'py>,
+        scenario: PathBuf,
+    ) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let scenario =
+            Scenario::load(&scenario).map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        let steps = scenario
+            .run(&mut self.router())
+            .map_err(PyRuntimeError::new_err)?;
+
+        let mut rows = Vec::new();
+        for step in &steps {
+            for (target, choice) in &step.decisions {
+                let d = choice_dict(py, target, choice)?;
+                d.set_item("t", step.t)?;
+                d.set_item("note", step.note.as_deref())?;
+                rows.push(d);
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// Gold Dust Gateway routing core.
+#[pymodule]
+fn gold_dust_gateway(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyConfig>()?;
+    m.add_class::<PyRouter>()?;
+    Ok(())
+}
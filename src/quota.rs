@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{EgressKind, GoldDustConfig, LimitConfig};
+use crate::router::BackendKind;
+use crate::seal::{self, Sealer};
+use crate::stats::now_unix;
+
+/// Where the dispatcher persists cumulative usage across restarts.
+pub const USAGE_PATH: &str = "gold-dust-usage.json";
+
+/// The same, per local user (`[users]`), keyed by their config name.
+pub const USER_USAGE_PATH: &str = "gold-dust-user-usage.json";
+
+/// Usage of one egress within the current quota period.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeriodUsage {
+    /// First day of the period (`YYYY-MM-DD`, UTC).
+    pub period_start: String,
+    pub bytes: u64,
+}
+
+/// Per-egress usage ledger written to [`USAGE_PATH`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageLedger {
+    pub egress: BTreeMap<String, PeriodUsage>,
+}
+
+impl UsageLedger {
+    /// Read the ledger, starting empty if missing/unreadable (or sealed
+    /// under another key).
+    pub fn load<P: AsRef<Path>>(path: P, sealer: Option<&Sealer>) -> Self {
+        seal::read(path.as_ref(), sealer)
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the ledger, sealed if there is a `sealer`.
+    pub fn save<P: AsRef<Path>>(&self, path: P, sealer: Option<&Sealer>) -> io::Result<()> {
+        seal::write(path.as_ref(), &serde_json::to_string_pretty(self)?, sealer)
+    }
+
+    /// Add `bytes` to `egress`, rolling over to a new period if needed.
+    pub fn record(&mut self, egress: &str, bytes: u64, limits: &LimitConfig) {
+        let start = period_start(now_unix(), limits.reset_day());
+        let entry = self
+            .egress
+            .entry(egress.to_string())
+            .or_insert_with(|| PeriodUsage {
+                period_start: start.clone(),
+                bytes: 0,
+            });
+        if entry.period_start != start {
+            entry.period_start = start;
+            entry.bytes = 0;
+        }
+        entry.bytes = entry.bytes.saturating_add(bytes);
+    }
+
+    /// Bytes used by `egress` in the current period.
+    pub fn used(&self, egress: &str, limits: &LimitConfig) -> u64 {
+        let start = period_start(now_unix(), limits.reset_day());
+        self.egress
+            .get(egress)
+            .filter(|u| u.period_start == start)
+            .map(|u| u.bytes)
+            .unwrap_or(0)
+    }
+
+    /// Has `egress` hit its monthly quota?
+    pub fn exhausted(&self, egress: &str, limits: &LimitConfig) -> bool {
+        match limits.monthly_quota_mb {
+            Some(mb) => self.used(egress, limits) >= mb.saturating_mul(1024 * 1024),
+            None => false,
+        }
+    }
+
+    /// Has the egress that `kind`'s traffic leaves through hit its quota?
+    pub fn backend_exhausted(&self, cfg: &GoldDustConfig, kind: BackendKind) -> bool {
+        EgressKind::ALL
+            .iter()
+            .filter(|egress| egress.backend() == kind)
+            .any(|egress| self.exhausted(egress.as_str(), &cfg.limits_for(egress.as_str())))
+    }
+}
+
+/// Start of the quota period containing `unix` for a given reset day.
+pub fn period_start(unix: u64, reset_day: u32) -> String {
+    let (mut year, mut month, day) = civil_from_days((unix / 86_400) as i64);
+    if day < reset_day {
+        if month == 1 {
+            year -= 1;
+            month = 12;
+        } else {
+            month -= 1;
+        }
+    }
+    format!("{:04}-{:02}-{:02}", year, month, reset_day)
+}
+
+/// Days since 1970-01-01 → (year, month, day). Howard Hinnant's algorithm.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 86_400;
+
+    #[test]
+    fn civil_dates_cross_month_and_year_ends() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_722), (2023, 12, 31));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19_783), (2024, 3, 1));
+    }
+
+    #[test]
+    fn period_starts_on_the_last_reset_day() {
+        assert_eq!(period_start(19_796 * DAY, 15), "2024-02-15");
+        assert_eq!(period_start(19_797 * DAY, 15), "2024-03-15");
+        assert_eq!(period_start(19_797 * DAY + DAY - 1, 15), "2024-03-15");
+        assert_eq!(period_start(19_732 * DAY, 15), "2023-12-15");
+        assert_eq!(period_start(19_723 * DAY, 1), "2024-01-01");
+        assert_eq!(period_start(19_722 * DAY, 1), "2023-12-01");
+    }
+
+    #[test]
+    fn huge_quota_does_not_overflow() {
+        let limits = LimitConfig {
+            monthly_quota_mb: Some(u64::MAX),
+            ..LimitConfig::default()
+        };
+        let mut usage = UsageLedger::default();
+        usage.record("tor", u64::MAX / 2, &limits);
+        assert!(!usage.exhausted("tor", &limits));
+    }
+
+    #[test]
+    fn direct_quota_drains_oxen() {
+        let cfg = GoldDustConfig::parse(
+            "[backends]\noxen_enabled = true\ntor_enabled = true\n\n\
+             [limits.direct]\nmonthly_quota_mb = 1\n",
+        )
+        .unwrap();
+        let mut usage = UsageLedger::default();
+        usage.record("direct", 1024 * 1024, &cfg.limits_for("direct"));
+        assert!(usage.backend_exhausted(&cfg, BackendKind::Oxen));
+        assert!(!usage.backend_exhausted(&cfg, BackendKind::Tor));
+        assert!(!usage.backend_exhausted(&cfg, BackendKind::Chain));
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{ApiConfig, RuleConfig};
+use crate::matcher::RuleMatcher;
+use crate::script::Conditions;
+use crate::target::Target;
+
+/// Token bucket shared between the tasks of one or more connections.
+pub type SharedBucket = Arc<Mutex<TokenBucket>>;
+
+/// Classic token bucket: holds up to `capacity` tokens, refilled at `rate`
+/// tokens per second.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// New bucket, starting full.
+    pub fn new(capacity: f64, rate: f64) -> Self {
+        Self {
+            capacity,
+            tokens: capacity,
+            rate,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+
+    /// Take `n` tokens if they are available right now.
+    pub fn try_take(&mut self, n: f64) -> bool {
+        self.refill();
+        if self.tokens >= n {
+            self.tokens -= n;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// How long until `n` tokens are available.
+    pub fn wait(&mut self, n: f64) -> Duration {
+        self.refill();
+        if self.tokens >= n || self.rate <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((n - self.tokens) / self.rate)
+        }
+    }
+
+    /// Take `n` tokens unconditionally, going into debt if needed.
+    ///
+    /// Returns how long the caller should wait for the debt to be repaid.
+    pub fn take(&mut self, n: f64) -> Duration {
+        self.refill();
+        self.tokens -= n;
+        if self.tokens >= 0.0 || self.rate <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Bucket shaping traffic to `kbps` kilobits per second, with one second of
+/// burst.
+pub fn bandwidth_bucket(kbps: u64) -> SharedBucket {
+    let bytes = bytes_per_sec(kbps) as f64;
+    Arc::new(Mutex::new(TokenBucket::new(bytes, bytes)))
+}
+
+/// Bytes per second in `kbps` kilobits per second.
+fn bytes_per_sec(kbps: u64) -> u64 {
+    kbps.saturating_mul(1000) / 8
+}
+
+/// Limits compiled from a single `[[rules]]` entry.
+#[derive(Debug)]
+struct RuleLimits {
+    pattern: String,
+    connections: Option<Mutex<TokenBucket>>,
+    bandwidth: Option<SharedBucket>,
+}
+
+/// Per-destination limiter used by the dispatcher.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    matcher: RuleMatcher,
+    conditions: Conditions,
+    rules: Vec<RuleLimits>,
+}
+
+impl RateLimiter {
+    /// Compile rule patterns and build buckets for every rule's limits.
+    pub fn from_rules(rules: &[RuleConfig]) -> Result<Self, String> {
+        let matcher = RuleMatcher::compile(rules.iter().map(|r| r.host.as_str()))?
+            .with_priorities(rules.iter().map(|r| r.priority).collect());
+        let conditions = Conditions::compile(rules)?;
+        let rules = rules
+            .iter()
+            .map(|r| RuleLimits {
+                pattern: r.host.clone(),
+                connections: r.connections_per_minute.map(|per_min| {
+                    let per_min = per_min as f64;
+                    Mutex::new(TokenBucket::new(per_min, per_min / 60.0))
+                }),
+                bandwidth: r.bandwidth_kbps.map(bandwidth_bucket),
+            })
+            .collect();
+        Ok(Self {
+            matcher,
+            conditions,
+            rules,
+        })
+    }
+
+    /// Admit a new connection to `target`.
+    ///
+    /// Returns `Err(pattern)` when the first matching rule has spent its
+    /// connection budget, otherwise that rule's bandwidth bucket (if any)
+    /// for the relay to draw from.
+    pub fn admit(
+        &self,
+        target: &Target,
+        resolved: &[IpAddr],
+    ) -> Result<Option<SharedBucket>, String> {
+        let Some(limits) = self
+            .conditions
+            .first_match(&self.matcher, target, resolved)
+            .map(|i| &self.rules[i])
+        else {
+            return Ok(None);
+        };
+
+        if let Some(bucket) = &limits.connections {
+            let mut bucket = bucket.lock().expect("rate limit bucket poisoned");
+            if !bucket.try_take(1.0) {
+                return Err(limits.pattern.clone());
+            }
+        }
+
+        Ok(limits.bandwidth.clone())
+    }
+}
+
+/// How many API clients are tracked at once; past that, the one seen least
+/// recently (and not locked out, if possible) is forgotten.
+const API_CLIENTS: usize = 256;
+
+/// Why an API request was turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// Over the client's request or stream budget.
+    Throttled(Duration),
+    /// Too many failed authentications; refused whatever it sends.
+    LockedOut(Duration),
+}
+
+impl Refusal {
+    /// When the client may try again.
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            Refusal::Throttled(wait) | Refusal::LockedOut(wait) => *wait,
+        }
+    }
+}
+
+/// What one API client has done, for metrics.
+#[derive(Debug, Clone, Default)]
+pub struct ApiCounts {
+    pub requests: u64,
+    pub throttled: u64,
+    pub auth_failures: u64,
+    /// Locked out right now.
+    pub locked_out: bool,
+}
+
+#[derive(Debug)]
+struct ApiClient {
+    requests: Option<TokenBucket>,
+    streams: Option<TokenBucket>,
+    /// Failed authentications since the last success.
+    failures: u32,
+    locked_until: Option<Instant>,
+    seen: Instant,
+    counts: ApiCounts,
+}
+
+/// Per-client limits on the dispatcher's HTTP APIs (`[api]`): a request
+/// budget, a smaller one for opening event streams, and a lockout after
+/// repeated failed authentication. Clients are named by the caller (a
+/// local user, an admin certificate, an address).
+#[derive(Debug)]
+pub struct ApiThrottle {
+    requests_per_minute: u32,
+    streams_per_minute: u32,
+    max_auth_failures: u32,
+    lockout: Duration,
+    clients: Mutex<HashMap<String, ApiClient>>,
+}
+
+fn per_minute(n: u32) -> Option<TokenBucket> {
+    (n > 0).then(|| TokenBucket::new(n as f64, n as f64 / 60.0))
+}
+
+impl ApiThrottle {
+    pub fn new(cfg: &ApiConfig) -> Self {
+        Self {
+            requests_per_minute: cfg.requests_per_minute,
+            streams_per_minute: cfg.streams_per_minute,
+            max_auth_failures: cfg.max_auth_failures,
+            lockout: Duration::from_secs(cfg.lockout_secs),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long a lockout lasts.
+    pub fn lockout(&self) -> Duration {
+        self.lockout
+    }
+
+    fn with_client<T>(&self, name: &str, f: impl FnOnce(&mut ApiClient) -> T) -> T {
+        let mut clients = self.clients.lock().expect("api clients poisoned");
+        let now = Instant::now();
+        if !clients.contains_key(name) && clients.len() >= API_CLIENTS {
+            let forget = clients
+                .iter()
+                .min_by_key(|(_, c)| (c.locked_until.is_some_and(|t| t > now), c.seen))
+                .map(|(name, _)| name.clone());
+            if let Some(forget) = forget {
+                clients.remove(&forget);
+            }
+        }
+        let client = clients
+            .entry(name.to_string())
+            .or_insert_with(|| ApiClient {
+                requests: per_minute(self.requests_per_minute),
+                streams: per_minute(self.streams_per_minute),
+                failures: 0,
+                locked_until: None,
+                seen: now,
+                counts: ApiCounts::default(),
+            });
+        client.seen = now;
+        f(client)
+    }
+
+    /// Count a request from `client`, opening an event stream if `stream`,
+    /// unless it is locked out or over budget.
+    pub fn admit(&self, client: &str, stream: bool) -> Result<(), Refusal> {
+        self.with_client(client, |c| {
+            c.counts.requests += 1;
+            let now = Instant::now();
+            if let Some(until) = c.locked_until {
+                if until > now {
+                    c.counts.throttled += 1;
+                    return Err(Refusal::LockedOut(until - now));
+                }
+                c.locked_until = None;
+            }
+            let buckets = [Some(&mut c.requests), stream.then_some(&mut c.streams)];
+            for bucket in buckets.into_iter().flatten().flatten() {
+                if !bucket.try_take(1.0) {
+                    c.counts.throttled += 1;
+                    return Err(Refusal::Throttled(bucket.wait(1.0)));
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// `client` failed to authenticate. True if that locked it out.
+    pub fn failed(&self, client: &str) -> bool {
+        self.with_client(client, |c| {
+            c.counts.auth_failures += 1;
+            c.failures += 1;
+            if self.max_auth_failures == 0 || c.failures < self.max_auth_failures {
+                return false;
+            }
+            c.failures = 0;
+            c.locked_until = Some(Instant::now() + self.lockout);
+            true
+        })
+    }
+
+    /// `client` authenticated: its failures are forgiven.
+    pub fn succeeded(&self, client: &str) {
+        self.with_client(client, |c| c.failures = 0);
+    }
+
+    /// Every tracked client's counts, by name.
+    pub fn counts(&self) -> Vec<(String, ApiCounts)> {
+        let now = Instant::now();
+        let clients = self.clients.lock().expect("api clients poisoned");
+        let mut counts: Vec<_> = clients
+            .iter()
+            .map(|(name, c)| {
+                let mut counts = c.counts.clone();
+                counts.locked_out = c.locked_until.is_some_and(|t| t > now);
+                (name.clone(), counts)
+            })
+            .collect();
+        counts.sort_by(|a, b| a.0.cmp(&b.0));
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(max_auth_failures: u32) -> ApiThrottle {
+        ApiThrottle::new(&ApiConfig {
+            requests_per_minute: 0,
+            streams_per_minute: 0,
+            max_auth_failures,
+            lockout_secs: 300,
+            ..ApiConfig::default()
+        })
+    }
+
+    fn locked_out(throttle: &ApiThrottle, client: &str) -> bool {
+        matches!(throttle.admit(client, false), Err(Refusal::LockedOut(_)))
+    }
+
+    #[test]
+    fn locks_out_after_max_auth_failures() {
+        let throttle = throttle(3);
+        assert!(!throttle.failed("alice"));
+        assert!(!throttle.failed("alice"));
+        assert!(!locked_out(&throttle, "alice"));
+        assert!(throttle.failed("alice"));
+        match throttle.admit("alice", false) {
+            Err(Refusal::LockedOut(wait)) => assert!(wait <= throttle.lockout()),
+            other => panic!("expected a lockout, got {other:?}"),
+        }
+        // Others are unaffected
+        assert_eq!(throttle.admit("bob", false), Ok(()));
+    }
+
+    #[test]
+    fn zero_max_auth_failures_never_locks_out() {
+        let throttle = throttle(0);
+        for _ in 0..100 {
+            assert!(!throttle.failed("alice"));
+        }
+        assert_eq!(throttle.admit("alice", false), Ok(()));
+    }
+
+    #[test]
+    fn lockout_expires() {
+        let throttle = throttle(1);
+        assert!(throttle.failed("alice"));
+        assert!(locked_out(&throttle, "alice"));
+        throttle
+            .clients
+            .lock()
+            .unwrap()
+            .get_mut("alice")
+            .unwrap()
+            .locked_until = Some(Instant::now());
+        assert_eq!(throttle.admit("alice", false), Ok(()));
+        assert!(!throttle.counts()[0].1.locked_out);
+    }
+
+    #[test]
+    fn success_forgives_failures() {
+        let throttle = throttle(3);
+        throttle.failed("alice");
+        throttle.failed("alice");
+        throttle.succeeded("alice");
+        assert!(!throttle.failed("alice"));
+        assert!(!throttle.failed("alice"));
+        assert!(!locked_out(&throttle, "alice"));
+        assert!(throttle.failed("alice"));
+    }
+
+    #[test]
+    fn eviction_keeps_locked_out_clients() {
+        let throttle = throttle(1);
+        assert!(throttle.failed("attacker"));
+        for i in 1..API_CLIENTS {
+            throttle.admit(&format!("client-{i}"), false).unwrap();
+        }
+        // Seen in order: the locked-out client longest ago
+        let start = Instant::now();
+        for (name, client) in throttle.clients.lock().unwrap().iter_mut() {
+            let i = name
+                .strip_prefix("client-")
+                .map_or(0, |i| i.parse().unwrap());
+            client.seen = start + Duration::from_millis(i);
+        }
+
+        throttle.admit("newcomer", false).unwrap();
+        let names: Vec<String> = throttle.counts().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names.len(), API_CLIENTS);
+        assert!(names.contains(&"attacker".to_string()));
+        assert!(names.contains(&"newcomer".to_string()));
+        assert!(!names.contains(&"client-1".to_string()));
+        assert!(locked_out(&throttle, "attacker"));
+    }
+
+    #[test]
+    fn eviction_falls_back_to_the_oldest_lockout() {
+        let throttle = throttle(1);
+        for i in 0..API_CLIENTS {
+            assert!(throttle.failed(&format!("client-{i}")));
+        }
+        let start = Instant::now();
+        for (name, client) in throttle.clients.lock().unwrap().iter_mut() {
+            let i: u64 = name["client-".len()..].parse().unwrap();
+            client.seen = start + Duration::from_millis(i);
+        }
+
+        throttle.admit("newcomer", false).unwrap();
+        let names: Vec<String> = throttle.counts().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names.len(), API_CLIENTS);
+        assert!(!names.contains(&"client-0".to_string()));
+        assert!(names.contains(&"client-1".to_string()));
+    }
+
+    #[test]
+    fn bandwidth_is_in_kilobits() {
+        assert_eq!(bytes_per_sec(8), 1000);
+        assert_eq!(bytes_per_sec(2000), 250_000);
+        assert_eq!(bytes_per_sec(u64::MAX), u64::MAX / 8);
+
+        let bucket = bandwidth_bucket(8);
+        let mut bucket = bucket.lock().unwrap();
+        assert!(bucket.try_take(1000.0));
+        let wait = bucket.wait(500.0);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    }
+}
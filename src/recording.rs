@@ -0,0 +1,122 @@
+//! Decision recording (`[routing] record`): every decision the router makes,
+//! with everything it was made from, one JSON line each. `replay --verify`
+//! puts a router back where each one found it and decides again, so "why did
+//! it pick Tor at 3am?" can be answered after the fact, and a changed answer
+//! shows up as a difference rather than a guess.
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Balance;
+use crate::logfile;
+use crate::router::{BackendKind, Requirements, Router, RouterSnapshot};
+use crate::target::Target;
+
+/// One decision and its inputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded {
+    pub unix: u64,
+    pub target: Target,
+    pub needs: Requirements,
+    /// Health, session loads and round-robin state, just before.
+    pub snapshot: RouterSnapshot,
+    /// What `[[rules]]` and `[routing]` said about the target.
+    pub rules: Matched,
+    /// Each backend's score, as the weighted modes saw it.
+    pub weights: BTreeMap<String, f64>,
+    /// Seed of this decision's own draws.
+    pub seed: u64,
+    /// The backend chosen.
+    pub chosen: String,
+    pub kind: BackendKind,
+    /// Why the policy was passed over, if it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_error: Option<String>,
+}
+
+impl Recorded {
+    /// When, in RFC 3339.
+    pub fn time(&self) -> String {
+        crate::syslog::timestamp(UNIX_EPOCH + Duration::from_secs(self.unix))
+    }
+}
+
+/// The rules that applied to a target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Matched {
+    pub balance: Balance,
+    /// A rule's `group`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// The `[routing] vantage` group, whether or not the decision leaned
+    /// toward it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nearest: Option<String>,
+}
+
+/// How a recorded decision came out the second time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Identical,
+    /// Another backend, or the policy failed where it hadn't (or the other
+    /// way round).
+    Changed {
+        chosen: String,
+        kind: BackendKind,
+        policy_error: Option<String>,
+    },
+    /// The config now matches other rules to the target, so the decision
+    /// can't be made the same way.
+    RulesChanged(Matched),
+}
+
+/// Append `recorded` to `path`, creating it (readable by us alone) if need
+/// be. One write per line, so routers sharing the file can't interleave.
+pub fn append(path: &Path, recorded: &Recorded) -> io::Result<()> {
+    let mut line = serde_json::to_string(recorded).map_err(io::Error::other)?;
+    line.push('\n');
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(line.as_bytes())
+}
+
+/// Every decision recorded in `path` (and any rotated copies), oldest
+/// first. Lines that don't parse (a torn last one) are skipped.
+pub fn read(path: &Path) -> Result<Vec<Recorded>, String> {
+    let lines = logfile::read_lines(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(lines
+        .iter()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Make `recorded` again with `router`, rewound to its inputs. Rules,
+/// groups, chains, capabilities and the policy are the router's own, from
+/// the current config. `None` if the router has no backends.
+pub fn verify(router: &mut Router, recorded: &Recorded) -> Option<Verdict> {
+    router.rewind(recorded);
+    let rules = router.matched(&recorded.target);
+    if rules != recorded.rules {
+        return Some(Verdict::RulesChanged(rules));
+    }
+    let choice = router.choose_backend_with(&recorded.target, &recorded.needs)?;
+    let policy_error = router.take_policy_error();
+    if choice.name == recorded.chosen
+        && choice.kind == recorded.kind
+        && policy_error == recorded.policy_error
+    {
+        return Some(Verdict::Identical);
+    }
+    Some(Verdict::Changed {
+        chosen: choice.name,
+        kind: choice.kind,
+        policy_error,
+    })
+}
@@ -0,0 +1,534 @@
+use std::any::Any;
+use std::future::Future;
+use std::net::IpAddr;
+#[cfg(all(target_os = "linux", any(feature = "splice", feature = "io-uring")))]
+use std::net::Shutdown;
+#[cfg(all(target_os = "linux", any(feature = "splice", feature = "io-uring")))]
+use std::os::fd::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+#[cfg(all(target_os = "linux", any(feature = "splice", feature = "io-uring")))]
+use tokio::net::UnixStream;
+use tokio::time::Instant;
+
+use crate::config::{GoldDustConfig, RuleConfig};
+use crate::matcher::RuleMatcher;
+use crate::ratelimit::SharedBucket;
+use crate::retry::DialTimeout;
+#[cfg(all(target_os = "linux", feature = "splice"))]
+use crate::splice;
+use crate::stats::Meter;
+use crate::target::Host;
+
+pub(crate) const CHUNK: usize = 16 * 1024;
+
+/// Client bytes kept for replay on a redial; past this, no redial.
+const REPLAY_MAX: usize = 64 * 1024;
+
+/// Wait out what `n` bytes cost against `limits`.
+pub(crate) async fn shape(limits: &[SharedBucket], n: usize) {
+    let wait = limits
+        .iter()
+        .map(|b| b.lock().expect("bandwidth bucket poisoned").take(n as f64))
+        .max()
+        .unwrap_or(Duration::ZERO);
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Copy one direction, charging every chunk against `limits` and calling
+/// `moved` after each.
+async fn pump<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    mut from: R,
+    mut to: W,
+    limits: &[SharedBucket],
+    meter: &Meter,
+    moved: impl Fn(),
+) -> io::Result<u64> {
+    let mut buf = vec![0u8; CHUNK];
+    let mut total = 0u64;
+
+    loop {
+        let n = from.read(&mut buf).await?;
+        if n == 0 {
+            to.shutdown().await?;
+            return Ok(total);
+        }
+
+        shape(limits, n).await;
+        to.write_all(&buf[..n]).await?;
+        meter.add(n as u64);
+        total += n as u64;
+        moved();
+    }
+}
+
+/// Something the dispatcher can relay to (a TCP socket, or a tunnel), or
+/// from (a TCP or unix socket client).
+pub trait Upstream: AsyncRead + AsyncWrite + Unpin + Send {
+    /// The concrete stream, so the relay can tell a plain socket.
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Upstream for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Relay bytes between client and upstream until both sides close.
+///
+/// Like `tokio::io::copy_bidirectional`, but every chunk is shaped by the
+/// given bandwidth buckets and counted on `meter`. Returns `(client→upstream, upstream→client)`.
+///
+/// Each direction reads its next chunk only once the last one is written,
+/// so a slow side holds the other back (down to TCP flow control) instead
+/// of queueing in memory: a session buffers at most one chunk per
+/// direction, plus the sockets' kernel buffers (see [`cap_buffers`]).
+///
+/// Built with the `splice` feature on Linux, two plain sockets (TCP or unix,
+/// [`Tracked`] or not) are joined with `splice(2)`, so the data never leaves
+/// the kernel.
+pub async fn relay<I: Upstream, U: Upstream>(
+    inbound: I,
+    outbound: U,
+    limits: &[SharedBucket],
+    meter: &Meter,
+) -> io::Result<(u64, u64)> {
+    #[cfg(all(target_os = "linux", feature = "splice"))]
+    if let (Some(client), Some(upstream)) =
+        (Socket::of(inbound.as_any()), Socket::of(outbound.as_any()))
+    {
+        return tokio::try_join!(
+            splice::pump(client, upstream, limits, meter),
+            splice::pump(upstream, client, limits, meter),
+        );
+    }
+
+    let (in_read, in_write) = io::split(inbound);
+    let (out_read, out_write) = io::split(outbound);
+
+    tokio::try_join!(
+        pump(in_read, out_write, limits, meter, || {}),
+        pump(out_read, in_write, limits, meter, || {}),
+    )
+}
+
+/// A socket under a relayed stream, and the [`Activity`] it reports to.
+#[cfg(all(target_os = "linux", any(feature = "splice", feature = "io-uring")))]
+#[derive(Clone, Copy)]
+pub(crate) struct Socket<'a> {
+    pub(crate) io: Io<'a>,
+    pub(crate) activity: Option<&'a Activity>,
+}
+
+#[cfg(all(target_os = "linux", any(feature = "splice", feature = "io-uring")))]
+#[derive(Clone, Copy)]
+pub(crate) enum Io<'a> {
+    Tcp(&'a TcpStream),
+    Unix(&'a UnixStream),
+}
+
+#[cfg(all(target_os = "linux", any(feature = "splice", feature = "io-uring")))]
+impl<'a> Socket<'a> {
+    /// The socket `stream` is, if it is one: a TCP or unix stream, possibly
+    /// boxed or [`Tracked`].
+    pub(crate) fn of(stream: &'a dyn Any) -> Option<Self> {
+        let io = if let Some(boxed) = stream.downcast_ref::<Box<dyn Upstream>>() {
+            return Self::of((**boxed).as_any());
+        } else if let Some(tracked) = stream.downcast_ref::<Tracked<TcpStream>>() {
+            return Some(Self {
+                io: Io::Tcp(&tracked.inner),
+                activity: Some(&tracked.activity),
+            });
+        } else if let Some(tracked) = stream.downcast_ref::<Tracked<UnixStream>>() {
+            return Some(Self {
+                io: Io::Unix(&tracked.inner),
+                activity: Some(&tracked.activity),
+            });
+        } else if let Some(tcp) = stream.downcast_ref::<TcpStream>() {
+            Io::Tcp(tcp)
+        } else {
+            Io::Unix(stream.downcast_ref::<UnixStream>()?)
+        };
+        Some(Self { io, activity: None })
+    }
+
+    pub(crate) fn fd(&self) -> RawFd {
+        match self.io {
+            Io::Tcp(s) => s.as_raw_fd(),
+            Io::Unix(s) => s.as_raw_fd(),
+        }
+    }
+
+    pub(crate) fn shutdown_write(&self) -> io::Result<()> {
+        match self.io {
+            Io::Tcp(s) => SockRef::from(s).shutdown(Shutdown::Write),
+            Io::Unix(s) => SockRef::from(s).shutdown(Shutdown::Write),
+        }
+    }
+}
+
+/// Send TCP keepalive probes on `stream` after `every` of idleness.
+pub fn set_keepalive(stream: &TcpStream, every: Duration) -> io::Result<()> {
+    let probes = TcpKeepalive::new().with_time(every).with_interval(every);
+    SockRef::from(stream).set_tcp_keepalive(&probes)
+}
+
+/// Cap the kernel send and receive buffers of the socket under `stream` (a
+/// TCP or unix stream, possibly boxed) at `kib` KiB each; other streams are
+/// left alone. A full send buffer then blocks the relay's write, so the
+/// side feeding it is read no faster than the slow side drains.
+pub fn cap_buffers(stream: &dyn Any, kib: u64) -> io::Result<()> {
+    if let Some(boxed) = stream.downcast_ref::<Box<dyn Upstream>>() {
+        return cap_buffers((**boxed).as_any(), kib);
+    }
+    #[cfg(unix)]
+    let unix = stream
+        .downcast_ref::<tokio::net::UnixStream>()
+        .map(SockRef::from);
+    #[cfg(not(unix))]
+    let unix = None;
+    let Some(socket) = stream
+        .downcast_ref::<TcpStream>()
+        .map(SockRef::from)
+        .or(unix)
+    else {
+        return Ok(());
+    };
+    let bytes = usize::try_from(kib.saturating_mul(1024)).unwrap_or(usize::MAX);
+    socket.set_send_buffer_size(bytes)?;
+    socket.set_recv_buffer_size(bytes)
+}
+
+/// Dead-peer detection for [`relay_watched`].
+#[derive(Debug, Clone, Copy)]
+pub struct Watchdog {
+    /// How long client data may go unanswered (zero: no limit).
+    pub dead_after: Duration,
+    /// How long the upstream may take to send its first byte, counted from
+    /// the client's first (zero: no limit but `dead_after`).
+    pub first_byte: Duration,
+    /// Fresh upstreams to try while nothing has been answered yet.
+    pub redials: u32,
+}
+
+/// Since when the client has been waiting for an answer, if it is.
+#[derive(Default)]
+struct Silence(Mutex<Option<Instant>>);
+
+impl Silence {
+    fn sent(&self) {
+        self.0
+            .lock()
+            .expect("silence poisoned")
+            .get_or_insert_with(Instant::now);
+    }
+
+    fn answered(&self) {
+        *self.0.lock().expect("silence poisoned") = None;
+    }
+
+    fn waited(&self) -> Duration {
+        self.0
+            .lock()
+            .expect("silence poisoned")
+            .map_or(Duration::ZERO, |since| since.elapsed())
+    }
+}
+
+impl Watchdog {
+    /// How long client data may go unanswered before the first answer, and
+    /// whether that is the first-byte budget.
+    fn unanswered(&self) -> Option<(Duration, bool)> {
+        match (self.dead_after, self.first_byte) {
+            (Duration::ZERO, Duration::ZERO) => None,
+            (dead, Duration::ZERO) => Some((dead, false)),
+            (Duration::ZERO, first) => Some((first, true)),
+            (dead, first) => Some((dead.min(first), first <= dead)),
+        }
+    }
+}
+
+fn dead_peer(after: Duration) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("dead peer: no answer for {}s", after.as_secs()),
+    )
+}
+
+/// [`relay`], but a backend that silently blackholes the session doesn't
+/// leave the client hanging.
+///
+/// Until the upstream sends its first byte, what the client sent is kept
+/// (up to 64 KiB); if the upstream stays silent for `dead_after`, it is
+/// replayed to a fresh upstream from `redial(attempt)`, up to `redials`
+/// times; past `first_byte` too, and then the error is a
+/// [`DialTimeout`]. Once data has flowed back there is nothing safe to replay, so a
+/// silent upstream ends the session with `TimedOut` and the client sees the
+/// connection close.
+pub async fn relay_watched<I, F, Fut>(
+    mut inbound: I,
+    mut outbound: Box<dyn Upstream>,
+    limits: &[SharedBucket],
+    meter: &Meter,
+    watchdog: Watchdog,
+    mut redial: F,
+) -> io::Result<(u64, u64)>
+where
+    I: Upstream,
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = io::Result<Box<dyn Upstream>>>,
+{
+    let mut buf = vec![0u8; CHUNK];
+    let mut answer = vec![0u8; CHUNK];
+    let mut replay = Vec::new();
+    let mut replayable = true;
+    let mut client_done = false;
+    let mut waiting: Option<Instant> = None;
+    let mut attempt = 0;
+    let mut up = 0u64;
+
+    // Nothing answered yet: the session can still move to a fresh upstream
+    let n = loop {
+        let deadline = waiting
+            .zip(watchdog.unanswered())
+            .map(|(since, (limit, _))| since + limit);
+        tokio::select! {
+            n = inbound.read(&mut buf), if !client_done => {
+                let n = n?;
+                if n == 0 {
+                    client_done = true;
+                    outbound.shutdown().await?;
+                    continue;
+                }
+                shape(limits, n).await;
+                outbound.write_all(&buf[..n]).await?;
+                meter.add(n as u64);
+                up += n as u64;
+                waiting.get_or_insert_with(Instant::now);
+                if replayable && replay.len() + n <= REPLAY_MAX {
+                    replay.extend_from_slice(&buf[..n]);
+                } else {
+                    replayable = false;
+                    replay = Vec::new();
+                }
+            }
+            n = outbound.read(&mut answer) => break n?,
+            _ = async {
+                match deadline {
+                    Some(at) => tokio::time::sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                if !replayable || attempt >= watchdog.redials {
+                    return Err(match watchdog.unanswered() {
+                        Some((after, true)) => io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            DialTimeout {
+                                phase: "first byte",
+                                after,
+                            },
+                        ),
+                        _ => dead_peer(watchdog.dead_after),
+                    });
+                }
+                attempt += 1;
+                outbound = redial(attempt).await?;
+                outbound.write_all(&replay).await?;
+                if client_done {
+                    outbound.shutdown().await?;
+                }
+                waiting = Some(Instant::now());
+            }
+        }
+    };
+    let mut down = 0u64;
+    if n > 0 {
+        shape(limits, n).await;
+        inbound.write_all(&answer[..n]).await?;
+        meter.add(n as u64);
+        down += n as u64;
+    }
+
+    // From here on, only tear down
+    let silence = Silence::default();
+    let (in_read, in_write) = io::split(inbound);
+    let (out_read, out_write) = io::split(outbound);
+    let watch = async {
+        if watchdog.dead_after.is_zero() {
+            return std::future::pending().await;
+        }
+        let tick = (watchdog.dead_after / 4).max(Duration::from_secs(1));
+        while silence.waited() < watchdog.dead_after {
+            tokio::time::sleep(tick).await;
+        }
+    };
+    tokio::select! {
+        relayed = async {
+            tokio::try_join!(
+                pump(in_read, out_write, limits, meter, || silence.sent()),
+                pump(out_read, in_write, limits, meter, || silence.answered()),
+            )
+        } => {
+            let (u, d) = relayed?;
+            Ok((up + u, down + d))
+        }
+        _ = watch => Err(dead_peer(watchdog.dead_after)),
+    }
+}
+
+/// When a session last moved data, and how much, fed by [`Tracked`].
+#[derive(Debug)]
+pub struct Activity {
+    last: Mutex<Instant>,
+    up: AtomicU64,
+    down: AtomicU64,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self {
+            last: Mutex::new(Instant::now()),
+            up: AtomicU64::new(0),
+            down: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Activity {
+    fn moved(&self) {
+        *self.last.lock().expect("activity poisoned") = Instant::now();
+    }
+
+    /// The client sent `n` bytes.
+    pub(crate) fn sent(&self, n: usize) {
+        self.up.fetch_add(n as u64, Ordering::Relaxed);
+        self.moved();
+    }
+
+    /// The client was sent `n` bytes.
+    pub(crate) fn received(&self, n: usize) {
+        self.down.fetch_add(n as u64, Ordering::Relaxed);
+        self.moved();
+    }
+
+    /// Time since data last moved either way.
+    pub fn idle(&self) -> Duration {
+        self.last.lock().expect("activity poisoned").elapsed()
+    }
+
+    /// `(client→upstream, upstream→client)` bytes so far.
+    pub fn bytes(&self) -> (u64, u64) {
+        (
+            self.up.load(Ordering::Relaxed),
+            self.down.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Resolves once no data has moved for `limit`.
+    pub async fn idle_for(&self, limit: Duration) {
+        loop {
+            let idle = self.idle();
+            if idle >= limit {
+                return;
+            }
+            tokio::time::sleep(limit - idle).await;
+        }
+    }
+}
+
+/// A client stream that records its traffic on an [`Activity`]: what is read
+/// from it went up, what is written to it came down.
+pub struct Tracked<S> {
+    pub(crate) inner: S,
+    pub(crate) activity: Arc<Activity>,
+}
+
+impl<S> Tracked<S> {
+    pub fn new(inner: S, activity: Arc<Activity>) -> Self {
+        Self { inner, activity }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Tracked<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let polled = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let n = buf.filled().len() - before;
+        if n > 0 {
+            self.activity.sent(n);
+        }
+        polled
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Tracked<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let polled = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = polled {
+            if n > 0 {
+                self.activity.received(n);
+            }
+        }
+        polled
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Idle limits: `[dispatcher] idle_timeout_secs`, overridden for their hosts
+/// by the `[[rules]]` that set `idle_timeout_secs`.
+#[derive(Debug, Default)]
+pub struct IdleTimeouts {
+    default: u64,
+    matcher: RuleMatcher,
+    secs: Vec<u64>,
+}
+
+impl IdleTimeouts {
+    pub fn from_config(cfg: &GoldDustConfig) -> Result<Self, String> {
+        let setting: Vec<&RuleConfig> = cfg
+            .rules
+            .iter()
+            .filter(|r| r.idle_timeout_secs.is_some())
+            .collect();
+        Ok(Self {
+            default: cfg.dispatcher.idle_timeout_secs,
+            matcher: RuleMatcher::compile(setting.iter().map(|r| r.host.as_str()))?
+                .with_priorities(setting.iter().map(|r| r.priority).collect()),
+            secs: setting.iter().filter_map(|r| r.idle_timeout_secs).collect(),
+        })
+    }
+
+    /// How long a session to `host` may sit idle, if there is a limit.
+    pub fn for_host(&self, host: &Host, resolved: &[IpAddr]) -> Option<Duration> {
+        let secs = self
+            .matcher
+            .first_match(host, resolved)
+            .map_or(self.default, |i| self.secs[i]);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
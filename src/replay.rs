@@ -0,0 +1,177 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+
+use crate::config::{AppConfig, EgressKind, GoldDustConfig, LanAction, UserConfig};
+use crate::dns::Hosts;
+use crate::lan::Lan;
+use crate::stats::TrafficEntry;
+use crate::target::Target;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// What the dispatcher decided for a session, as far as config alone decides
+/// it: let through (`allowed`), `lan_rejected` or `no_relay`, and where to.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Decision {
+    pub outcome: String,
+    pub egress: Option<String>,
+}
+
+impl Decision {
+    /// The decision `entry` records: its `audit` field if it has one, else
+    /// its outcome. `None` for outcomes live state decided (rate limits,
+    /// quotas, ...) or that came before any decision.
+    pub fn logged(entry: &TrafficEntry) -> Option<Self> {
+        let outcome = match entry.audit.as_deref().unwrap_or(&entry.outcome) {
+            "allowed" | "relayed" | "established" | "connecting" | "dry_run"
+            | "exit_unavailable" => "allowed",
+            decided @ ("lan_rejected" | "no_relay") => decided,
+            _ => return None,
+        };
+        Some(Self {
+            outcome: outcome.to_string(),
+            egress: entry.egress.clone(),
+        })
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.outcome.as_str(), &self.egress) {
+            ("allowed", Some(egress)) => write!(f, "via {egress}"),
+            (outcome, _) => write!(f, "{outcome}"),
+        }
+    }
+}
+
+/// Sessions of one target, client identity and change in decision.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub target: String,
+    pub user: Option<String>,
+    pub app: Option<String>,
+    pub before: Decision,
+    pub after: Decision,
+    pub sessions: usize,
+}
+
+/// Result of replaying a traffic log.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Sessions whose decision could be re-made.
+    pub replayed: usize,
+    /// Most sessions first.
+    pub changes: Vec<Change>,
+    /// Sessions that couldn't be, by logged outcome (`error` for errors).
+    pub skipped: BTreeMap<String, usize>,
+}
+
+impl Report {
+    pub fn changed_sessions(&self) -> usize {
+        self.changes.iter().map(|c| c.sessions).sum()
+    }
+
+    pub fn changed_targets(&self) -> usize {
+        let targets: BTreeSet<&str> = self.changes.iter().map(|c| c.target.as_str()).collect();
+        targets.len()
+    }
+}
+
+/// The dispatcher's config-driven decisions (profiles, `[lan]`, the masque
+/// relay) under one config and flag-file mode.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    mode: EgressKind,
+    users: HashMap<String, UserConfig>,
+    apps: HashMap<String, AppConfig>,
+    lan: Lan,
+    hosts: Hosts,
+    masque: bool,
+}
+
+impl Replay {
+    pub fn new(cfg: &GoldDustConfig, mode: EgressKind) -> Result<Self, BoxError> {
+        Ok(Self {
+            mode,
+            users: cfg.users.clone(),
+            apps: cfg.apps.clone(),
+            lan: Lan::new(&cfg.lan).map_err(|e| format!("[lan] {e}"))?,
+            hosts: Hosts::load(&cfg.dns)?,
+            masque: cfg!(feature = "masque") && cfg.backends.masque.is_some(),
+        })
+    }
+
+    /// What the dispatcher would decide for `entry`'s session now. Names are
+    /// only resolved through `[dns.hosts]`; `None` for a target that doesn't
+    /// parse.
+    pub fn decide(&self, entry: &TrafficEntry) -> Option<Decision> {
+        let target: Target = entry.target.parse().ok()?;
+        let user = entry.user.as_ref().and_then(|u| self.users.get(u));
+        let app = entry.app.as_ref().and_then(|a| self.apps.get(a));
+        let mut egress = user
+            .and_then(|u| u.egress)
+            .or(app.and_then(|a| a.egress))
+            .unwrap_or(self.mode);
+        let resolved = self.hosts.get(&target.host_str()).unwrap_or(&[]);
+        if self.lan.check(&target.host, resolved).is_some() {
+            if self.lan.action() == LanAction::Reject {
+                return Some(Decision {
+                    outcome: "lan_rejected".to_string(),
+                    egress: None,
+                });
+            }
+            egress = EgressKind::Direct;
+        }
+        let outcome = match egress {
+            EgressKind::Masque if !self.masque => "no_relay",
+            _ => "allowed",
+        };
+        Some(Decision {
+            outcome: outcome.to_string(),
+            egress: Some(egress.as_str().to_string()),
+        })
+    }
+
+    /// Re-decide every session in `entries` and collect what differs.
+    pub fn run(&self, entries: &[TrafficEntry]) -> Report {
+        let mut report = Report::default();
+        let mut changes = BTreeMap::new();
+        for entry in entries {
+            let decided = Decision::logged(entry).zip(self.decide(entry));
+            let Some((before, after)) = decided else {
+                let outcome = match entry.outcome.starts_with("error") {
+                    true => "error",
+                    false => entry.audit.as_deref().unwrap_or(&entry.outcome),
+                };
+                *report.skipped.entry(outcome.to_string()).or_default() += 1;
+                continue;
+            };
+            report.replayed += 1;
+            if before != after {
+                let key = (
+                    entry.target.clone(),
+                    entry.user.clone(),
+                    entry.app.clone(),
+                    before,
+                    after,
+                );
+                *changes.entry(key).or_default() += 1;
+            }
+        }
+        report.changes = changes
+            .into_iter()
+            .map(|((target, user, app, before, after), sessions)| Change {
+                target,
+                user,
+                app,
+                before,
+                after,
+                sessions,
+            })
+            .collect();
+        report.changes.sort_by_key(|c| Reverse(c.sessions));
+        report
+    }
+}
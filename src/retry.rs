@@ -0,0 +1,91 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// Kinds of dial failure `[retry]` tells apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailureClass {
+    /// Over `connect_timeout_secs` or a `[timeouts]` budget, or the OS gave
+    /// up.
+    Timeout,
+    /// The destination (or the proxy on its behalf) refused the connection.
+    Refused,
+    /// SOCKS general failure: Tor couldn't build a circuit or reach an exit.
+    GeneralFailure,
+    /// Host or network unreachable.
+    Unreachable,
+    /// SOCKS TTL expired: the exit timed out reaching the destination.
+    TtlExpired,
+    Other,
+}
+
+impl FailureClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureClass::Timeout => "timeout",
+            FailureClass::Refused => "refused",
+            FailureClass::GeneralFailure => "general_failure",
+            FailureClass::Unreachable => "unreachable",
+            FailureClass::TtlExpired => "ttl_expired",
+            FailureClass::Other => "other",
+        }
+    }
+
+    /// Classify a dial error, looking through the errors it wraps.
+    pub fn of(err: &(dyn Error + 'static)) -> Self {
+        let mut next = Some(err);
+        while let Some(e) = next {
+            if e.is::<DialTimeout>() {
+                return FailureClass::Timeout;
+            }
+            if let Some(e) = e.downcast_ref::<tokio_socks::Error>() {
+                match e {
+                    tokio_socks::Error::GeneralSocksServerFailure => {
+                        return FailureClass::GeneralFailure
+                    }
+                    tokio_socks::Error::ConnectionRefused => return FailureClass::Refused,
+                    tokio_socks::Error::HostUnreachable
+                    | tokio_socks::Error::NetworkUnreachable => return FailureClass::Unreachable,
+                    tokio_socks::Error::TtlExpired => return FailureClass::TtlExpired,
+                    _ => {}
+                }
+            }
+            if let Some(e) = e.downcast_ref::<io::Error>() {
+                match e.kind() {
+                    io::ErrorKind::TimedOut => return FailureClass::Timeout,
+                    io::ErrorKind::ConnectionRefused => return FailureClass::Refused,
+                    io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+                        return FailureClass::Unreachable
+                    }
+                    _ => {}
+                }
+            }
+            next = e.source();
+        }
+        FailureClass::Other
+    }
+}
+
+impl fmt::Display for FailureClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A dial, or one phase of it, that went over its budget
+/// (`connect_timeout_secs`, `[timeouts]`).
+#[derive(Debug)]
+pub struct DialTimeout {
+    /// `dial`, `connect`, `handshake` or `first byte`.
+    pub phase: &'static str,
+    pub after: Duration,
+}
+
+impl fmt::Display for DialTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} took over {}s", self.phase, self.after.as_secs())
+    }
+}
+
+impl Error for DialTimeout {}
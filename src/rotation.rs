@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::RotationConfig;
+use crate::seal::{self, Sealer};
+
+/// Where the dispatcher keeps per-destination circuits across restarts.
+pub const ROTATION_PATH: &str = "gold-dust-rotation.json";
+
+/// Destinations tracked before idle ones are forgotten.
+const TRACKED_MAX: usize = 4096;
+
+/// Where one destination stands.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Destination {
+    /// Bumped every `every_mb`.
+    generation: u64,
+    /// Relayed since the last bump.
+    bytes: u64,
+    /// Isolation handed out last, to notice rotations.
+    last: String,
+    last_used: u64,
+}
+
+/// Scheduled Tor circuit rotation.
+///
+/// Tor puts streams with different SOCKS credentials on different circuits
+/// (`IsolateSOCKSAuth`, on by default), so each destination gets its own
+/// password, and a new one when its period or byte budget runs out. Open
+/// streams keep their circuit; only new connections move.
+#[derive(Debug)]
+pub struct Rotation {
+    cfg: RotationConfig,
+    destinations: Mutex<HashMap<String, Destination>>,
+    /// Changed since the last save.
+    dirty: AtomicBool,
+}
+
+impl Rotation {
+    pub fn new(cfg: RotationConfig) -> Self {
+        Self {
+            cfg,
+            destinations: Mutex::new(HashMap::new()),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Pick up where the last run left off, so a restart keeps each
+    /// destination on its circuit (same credentials until its period or
+    /// byte budget runs out). Starts empty if `path` is missing/unreadable;
+    /// destinations idle for too long by `now` are dropped.
+    pub fn load<P: AsRef<Path>>(
+        cfg: RotationConfig,
+        path: P,
+        now: u64,
+        sealer: Option<&Sealer>,
+    ) -> Self {
+        let rotation = Self::new(cfg);
+        let saved: HashMap<String, Destination> = seal::read(path.as_ref(), sealer)
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        let idle_after = rotation.idle_after();
+        let mut destinations = rotation.destinations.lock().expect("rotation poisoned");
+        destinations.extend(
+            saved
+                .into_iter()
+                .filter(|(_, d)| now.saturating_sub(d.last_used) < idle_after)
+                .take(TRACKED_MAX),
+        );
+        drop(destinations);
+        rotation
+    }
+
+    /// Write the destinations to `path` if anything changed since the last
+    /// save; sealed if there is a `sealer`.
+    pub fn save<P: AsRef<Path>>(&self, path: P, sealer: Option<&Sealer>) -> io::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let destinations = self.destinations.lock().expect("rotation poisoned");
+        let saved = serde_json::to_string(&*destinations);
+        drop(destinations);
+        let written = saved
+            .map_err(io::Error::from)
+            .and_then(|text| seal::write(path.as_ref(), &text, sealer));
+        if written.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        written
+    }
+
+    /// How long a destination may go unused before it is forgotten.
+    fn idle_after(&self) -> u64 {
+        self.cfg.every_mins.saturating_mul(60).max(3_600)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.cfg.enabled()
+    }
+
+    /// SOCKS password for a new connection to `host` at `now`, and whether
+    /// it differs from the one handed out before (a rotation).
+    pub fn isolation(&self, host: &str, now: u64) -> (String, bool) {
+        let epoch = match self.cfg.every_mins.saturating_mul(60) {
+            0 => 0,
+            period => {
+                // Each destination's boundary falls somewhere else in the period
+                let offset = if self.cfg.stagger {
+                    fnv1a(host) % period
+                } else {
+                    0
+                };
+                now.saturating_add(offset) / period
+            }
+        };
+        let mut destinations = self.destinations.lock().expect("rotation poisoned");
+        if destinations.len() >= TRACKED_MAX && !destinations.contains_key(host) {
+            let idle_after = self.idle_after();
+            destinations.retain(|_, d| now.saturating_sub(d.last_used) < idle_after);
+        }
+        let dest = destinations.entry(host.to_string()).or_default();
+        let password = format!("{}/{}/{}", host, epoch, dest.generation);
+        let rotated = !dest.last.is_empty() && dest.last != password;
+        dest.last.clone_from(&password);
+        dest.last_used = now;
+        self.dirty.store(true, Ordering::Relaxed);
+        (password, rotated)
+    }
+
+    /// Count bytes relayed for `host`; past `every_mb`, its next connection
+    /// gets a new circuit.
+    pub fn record(&self, host: &str, bytes: u64) {
+        if self.cfg.every_mb == 0 {
+            return;
+        }
+        let mut destinations = self.destinations.lock().expect("rotation poisoned");
+        if let Some(dest) = destinations.get_mut(host) {
+            dest.bytes = dest.bytes.saturating_add(bytes);
+            if dest.bytes >= self.cfg.every_mb.saturating_mul(1024 * 1024) {
+                dest.generation += 1;
+                dest.bytes = 0;
+            }
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Stable across runs and platforms, unlike `DefaultHasher`'s contract.
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotation(every_mins: u64, every_mb: u64) -> Rotation {
+        Rotation::new(RotationConfig {
+            every_mins,
+            every_mb,
+            stagger: false,
+        })
+    }
+
+    #[test]
+    fn rotates_after_every_mb() {
+        let rotation = rotation(0, 1);
+        let (first, _) = rotation.isolation("example.com", 0);
+        rotation.record("example.com", 1024 * 1024 - 1);
+        assert_eq!(rotation.isolation("example.com", 0), (first.clone(), false));
+        rotation.record("example.com", 1);
+        let (second, rotated) = rotation.isolation("example.com", 0);
+        assert!(rotated);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn huge_limits_do_not_overflow() {
+        let rotation = rotation(u64::MAX, u64::MAX);
+        let (first, _) = rotation.isolation("example.com", u64::MAX);
+        rotation.record("example.com", 1 << 60);
+        rotation.record("example.com", 1 << 60);
+        assert_eq!(rotation.isolation("example.com", u64::MAX), (first, false));
+    }
+}
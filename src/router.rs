@@ -1,22 +1,189 @@
-use crate::config::GoldDustConfig;
+use crate::config::{
+    Balance, CapabilityConfig, ChainConfig, GoldDustConfig, GroupConfig, RoutingConfig, RuleConfig,
+};
+use crate::gossip::LatencyHistogram;
+use crate::health::{HealthSource, StaticHealth};
+use crate::matcher::RuleMatcher;
+use crate::policy::RoutingPolicy;
+use crate::recording::{self, Matched, Recorded};
+use crate::stats::now_unix;
+use crate::target::{Host, PortRange, Target};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+/// Why a router without backends can't decide.
+pub const NO_BACKENDS: &str = "no backends to route to";
 
 /// Which family a backend belongs to.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum BackendKind {
     Oxen,
     Tor,
+    /// Several hops in a row (`[[backends.chains]]`).
+    Chain,
+    /// HTTP/3 relay (`[backends.masque]`), for networks that block Tor and lokinet.
+    Masque,
+}
+
+impl BackendKind {
+    /// Lowercase key used in config tables and stats (`oxen`, `tor`, `chain`,
+    /// `masque`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendKind::Oxen => "oxen",
+            BackendKind::Tor => "tor",
+            BackendKind::Chain => "chain",
+            BackendKind::Masque => "masque",
+        }
+    }
 }
 
 /// Health snapshot for a single backend.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackendHealth {
     pub name: String,
     pub kind: BackendKind,
     pub latency_ms: f64,
     pub failure_rate: f64,
     pub enabled: bool,
+    /// Can reach IPv6 destinations.
+    pub ipv6: bool,
+}
+
+impl BackendHealth {
+    /// Count a further share of failures, such as dials that overran a
+    /// `[timeouts]` budget, as independent of the ones already measured.
+    pub fn add_failures(&mut self, rate: f64) {
+        let ok = (1.0 - self.failure_rate) * (1.0 - rate.clamp(0.0, 1.0));
+        self.failure_rate = (1.0 - ok).clamp(0.0, 1.0);
+    }
+}
+
+/// Partial health update for one backend (scenario event, probe result, ...).
+///
+/// Fields left out keep their current value.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthUpdate {
+    pub backend: String,
+    pub latency_ms: Option<f64>,
+    pub failure_rate: Option<f64>,
+    pub enabled: Option<bool>,
+}
+
+impl HealthUpdate {
+    /// Overwrite the fields this update sets.
+    pub fn apply_to(&self, b: &mut BackendHealth) {
+        if let Some(latency_ms) = self.latency_ms {
+            b.latency_ms = latency_ms;
+        }
+        if let Some(failure_rate) = self.failure_rate {
+            b.failure_rate = failure_rate;
+        }
+        if let Some(enabled) = self.enabled {
+            b.enabled = enabled;
+        }
+    }
+}
+
+/// Open sessions against a `max_sessions` cap, for a backend or kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Load {
+    pub active: usize,
+    pub max: Option<usize>,
+}
+
+impl Load {
+    pub fn saturated(&self) -> bool {
+        self.max.is_some_and(|max| self.active >= max)
+    }
+}
+
+/// What a backend can carry: its kind's defaults with `[capabilities]`
+/// applied. IPv6 reach comes from health unless declared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub udp: bool,
+    pub onion: bool,
+    pub loki: bool,
+    pub ipv6: bool,
+    /// Empty: every port.
+    pub ports: Vec<PortRange>,
+}
+
+impl Capabilities {
+    /// Lokinet carries any IP traffic to `.loki`; Tor streams TCP to onions;
+    /// the HTTP/3 relay only opens TCP tunnels (no CONNECT-UDP).
+    pub fn for_kind(kind: BackendKind, ipv6: bool) -> Self {
+        let (udp, onion, loki) = match kind {
+            BackendKind::Oxen => (true, false, true),
+            BackendKind::Tor => (false, true, false),
+            BackendKind::Chain | BackendKind::Masque => (false, false, false),
+        };
+        Self {
+            udp,
+            onion,
+            loki,
+            ipv6,
+            ports: Vec::new(),
+        }
+    }
+
+    fn apply(&mut self, declared: &CapabilityConfig) {
+        self.udp = declared.udp.unwrap_or(self.udp);
+        self.onion = declared.onion.unwrap_or(self.onion);
+        self.loki = declared.loki.unwrap_or(self.loki);
+        self.ipv6 = declared.ipv6.unwrap_or(self.ipv6);
+        if let Some(ports) = &declared.ports {
+            self.ports = ports.clone();
+        }
+    }
+
+    /// The first thing `needs` asks for that this backend can't do.
+    pub fn lacks(&self, needs: &Requirements) -> Option<String> {
+        if needs.udp && !self.udp {
+            Some("udp".to_string())
+        } else if needs.onion && !self.onion {
+            Some(".onion".to_string())
+        } else if needs.loki && !self.loki {
+            Some(".loki".to_string())
+        } else if !self.ports.is_empty() && !self.ports.iter().any(|r| r.contains(needs.port)) {
+            Some(format!("port {}", needs.port))
+        } else {
+            None
+        }
+    }
+}
+
+/// What a target needs from a backend. IPv6 is a preference, not a
+/// requirement (see [`Router::choose_backend_for`]).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Requirements {
+    pub udp: bool,
+    pub onion: bool,
+    pub loki: bool,
+    pub port: u16,
+}
+
+impl Requirements {
+    /// TCP to `target`; set `udp` for datagrams.
+    pub fn of(target: &Target) -> Self {
+        let tld = |suffix: &str| match &target.host {
+            Host::Domain(d) => d.ends_with(suffix),
+            _ => false,
+        };
+        Self {
+            udp: false,
+            onion: tld(".onion"),
+            loki: tld(".loki"),
+            port: target.port,
+        }
+    }
 }
 
 /// The router’s choice for a given target.
@@ -26,47 +193,533 @@ pub struct BackendChoice {
     pub kind: BackendKind,
     pub latency_ms: f64,
     pub failure_rate: f64,
+    pub ipv6: bool,
+}
+
+impl From<&BackendHealth> for BackendChoice {
+    fn from(b: &BackendHealth) -> Self {
+        Self {
+            name: b.name.clone(),
+            kind: b.kind,
+            latency_ms: b.latency_ms,
+            failure_rate: b.failure_rate,
+            ipv6: b.ipv6,
+        }
+    }
+}
+
+/// A `[groups]` entry as the router sees it.
+#[derive(Debug, Clone)]
+pub struct GroupHealth {
+    pub name: String,
+    pub members: usize,
+    /// Members enabled and below their session cap.
+    pub up: usize,
+    /// Members no health source reports.
+    pub missing: Vec<String>,
+    /// Fastest usable member.
+    pub best_latency_ms: Option<f64>,
+    /// Mean over usable members; 1.0 when there is none.
+    pub failure_rate: f64,
+}
+
+/// Why a backend did not get a decision (`why-not`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    /// Reported down, drained, or a chain with a hop that is.
+    Disabled,
+    /// At its `max_sessions` cap, or its kind is.
+    Saturated,
+    /// Can't carry what the target needs (`udp`, `.onion`, a port, ...).
+    Lacks(String),
+    /// The routing policy ranked another usable backend higher.
+    Policy,
+    /// A rule prefers this group for the target, and a member was usable.
+    Group(String),
+    /// The nearest group (`[routing] vantage`) was tried first, and a member
+    /// was usable.
+    Nearest(String),
+    /// IPv6 target, and a backend that reaches IPv6 was usable.
+    NoIpv6,
+    /// A usable backend of an earlier kind in the built-in order.
+    Order(BackendKind),
+    /// Same pool, and the balancing went to another member.
+    Balance {
+        balance: Balance,
+        score: f64,
+        chosen_score: f64,
+    },
+}
+
+/// Combined health of a chain over `backends`: each hop uses its best enabled
+/// backend of that kind; latencies add up, any hop failing fails the chain,
+/// and IPv6 reach is the exit hop's.
+pub fn chain_health(chain: &ChainConfig, backends: &[BackendHealth]) -> BackendHealth {
+    let mut health = BackendHealth {
+        name: chain.name(),
+        kind: BackendKind::Chain,
+        latency_ms: 0.0,
+        failure_rate: 0.0,
+        enabled: true,
+        ipv6: false,
+    };
+    let mut success = 1.0;
+    for kind in &chain.chain {
+        let best = backends
+            .iter()
+            .filter(|b| b.kind == *kind && b.enabled)
+            .min_by(|a, b| a.latency_ms.total_cmp(&b.latency_ms));
+        match best {
+            Some(hop) => {
+                health.latency_ms += hop.latency_ms;
+                success *= 1.0 - hop.failure_rate.clamp(0.0, 1.0);
+                health.ipv6 = hop.ipv6;
+            }
+            None => {
+                health.enabled = false;
+                success = 0.0;
+                health.ipv6 = false;
+            }
+        }
+    }
+    health.failure_rate = 1.0 - success;
+    health
+}
+
+/// Weight of a backend for balancing: fast and reliable scores high.
+pub fn score(b: &BackendHealth) -> f64 {
+    (1.0 - b.failure_rate).clamp(0.0, 1.0) * 1000.0 / b.latency_ms.max(1.0)
+}
+
+/// What decisions depended on at one moment, as written by `status --json`
+/// and replayed with `--snapshot`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouterSnapshot {
+    pub taken_unix: u64,
+    pub backends: Vec<BackendHealth>,
+    /// Open sessions against caps, by backend name or kind.
+    #[serde(default)]
+    pub loads: BTreeMap<String, Load>,
+    /// Round-robin position per backend kind.
+    #[serde(default)]
+    pub rr_cursor: BTreeMap<String, usize>,
+    /// Weighted round-robin state per backend name.
+    #[serde(default)]
+    pub rr_weights: BTreeMap<String, f64>,
+    /// Seed of randomized selection, if it was seeded.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Latency histograms from the health board (for analysis, not routing).
+    #[serde(default)]
+    pub histograms: BTreeMap<String, LatencyHistogram>,
 }
 
-/// Simple in-memory router: Oxen-first, Tor-fallback.
+impl HealthSource for RouterSnapshot {
+    fn snapshot(&mut self) -> Vec<BackendHealth> {
+        self.backends.clone()
+    }
+}
+
+/// How a backend moved between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Better,
+    Worse,
+    Unchanged,
+    /// Only in the newer snapshot.
+    Added,
+    /// Only in the older snapshot.
+    Removed,
+}
+
+/// One backend across two snapshots.
+#[derive(Debug, Clone)]
+pub struct BackendDiff {
+    pub name: String,
+    pub before: Option<BackendHealth>,
+    pub after: Option<BackendHealth>,
+    pub trend: Trend,
+}
+
+/// Score changes smaller than this fraction are measurement noise.
+const DIFF_TOLERANCE: f64 = 0.05;
+
+impl RouterSnapshot {
+    /// Compare against an `earlier` snapshot, matching backends by name.
+    /// Backends in this snapshot come first, in order, then removed ones.
+    pub fn diff(&self, earlier: &RouterSnapshot) -> Vec<BackendDiff> {
+        let mut diffs: Vec<BackendDiff> = self
+            .backends
+            .iter()
+            .map(|after| {
+                let before = earlier.backends.iter().find(|b| b.name == after.name);
+                BackendDiff {
+                    name: after.name.clone(),
+                    trend: before.map_or(Trend::Added, |before| trend(before, after)),
+                    before: before.cloned(),
+                    after: Some(after.clone()),
+                }
+            })
+            .collect();
+        diffs.extend(
+            earlier
+                .backends
+                .iter()
+                .filter(|b| !self.backends.iter().any(|a| a.name == b.name))
+                .map(|before| BackendDiff {
+                    name: before.name.clone(),
+                    before: Some(before.clone()),
+                    after: None,
+                    trend: Trend::Removed,
+                }),
+        );
+        diffs
+    }
+}
+
+/// Going up or down decides; otherwise the routing score, with some slack.
+fn trend(before: &BackendHealth, after: &BackendHealth) -> Trend {
+    match (before.enabled, after.enabled) {
+        (false, true) => Trend::Better,
+        (true, false) => Trend::Worse,
+        (false, false) => Trend::Unchanged,
+        (true, true) => {
+            let (was, now) = (score(before), score(after));
+            if now > was * (1.0 + DIFF_TOLERANCE) {
+                Trend::Better
+            } else if now < was * (1.0 - DIFF_TOLERANCE) {
+                Trend::Worse
+            } else {
+                Trend::Unchanged
+            }
+        }
+    }
+}
+
+/// Smooth weighted round-robin step (as in nginx): every candidate gains its
+/// weight, the highest is picked and pays back the total. Over any window
+/// each candidate is picked in proportion to its score, interleaved.
+fn weighted_rr(current: &mut BTreeMap<String, f64>, pool: &[&BackendHealth]) -> usize {
+    let total: f64 = pool.iter().map(|b| score(b)).sum();
+    let mut best = 0;
+    let mut best_weight = f64::MIN;
+    for (i, b) in pool.iter().enumerate() {
+        let weight = current.entry(b.name.clone()).or_insert(0.0);
+        *weight += score(b);
+        if *weight > best_weight {
+            best = i;
+            best_weight = *weight;
+        }
+    }
+    if let Some(weight) = current.get_mut(&pool[best].name) {
+        *weight -= total;
+    }
+    best
+}
+
+/// Simple in-memory router: chains first, then Oxen, Tor, the HTTP/3 relay as fallbacks.
 #[derive(Debug)]
 pub struct Router {
     backends: Vec<BackendHealth>,
+    chains: Vec<ChainConfig>,
+    /// Keyed by backend name or kind (`tor`, `oxen`, `chain`).
+    loads: BTreeMap<String, Load>,
+    /// `[capabilities]`, keyed by backend name or kind.
+    capabilities: HashMap<String, CapabilityConfig>,
+    balance: Balance,
+    /// Per-target `balance` from `[[rules]]`, in rule order.
+    balance_rules: Option<(RuleMatcher, Vec<Balance>)>,
+    /// `[groups]` members, by group name.
+    groups: BTreeMap<String, Vec<String>>,
+    /// Per-target `group` from `[[rules]]`, in rule order.
+    group_rules: Option<(RuleMatcher, Vec<String>)>,
+    /// A group name or `auto` (`[routing] vantage`).
+    vantage: Option<String>,
+    vantage_bias: f64,
+    /// Weighted round-robin state per backend name.
+    rr_weights: BTreeMap<String, f64>,
+    /// Round-robin position per backend kind.
+    rr_cursor: BTreeMap<&'static str, usize>,
+    /// Decisions per backend since the router was built.
+    picks: BTreeMap<String, u64>,
+    seed: Option<u64>,
+    rng: StdRng,
+    policy: Option<Box<dyn RoutingPolicy>>,
+    policy_error: Option<String>,
+    /// Where decisions are recorded (`[routing] record`).
+    record: Option<PathBuf>,
+    record_error: Option<String>,
 }
 
 impl Router {
-    /// Build a router from config flags (oxen_enabled / tor_enabled).
+    /// Build a router over the simulated backends from config flags.
     pub fn from_config(config: &GoldDustConfig) -> Self {
-        let mut backends = Vec::new();
-
-        if config.backends.oxen_enabled {
-            backends.push(BackendHealth {
-                name: "oxen-node-1".to_string(),
-                kind: BackendKind::Oxen,
-                latency_ms: 60.0,
-                failure_rate: 0.02,
-                enabled: true,
-            });
-            backends.push(BackendHealth {
-                name: "oxen-node-2".to_string(),
-                kind: BackendKind::Oxen,
-                latency_ms: 70.0,
-                failure_rate: 0.03,
-                enabled: true,
-            });
-        }
-
-        if config.backends.tor_enabled {
-            backends.push(BackendHealth {
-                name: "tor-exit-1".to_string(),
-                kind: BackendKind::Tor,
-                latency_ms: 250.0,
-                failure_rate: 0.01,
-                enabled: true,
-            });
-        }
-
-        Self { backends }
+        let mut router = Self::from_source(&mut StaticHealth::from_config(config), &config.routing);
+        router.set_chains(config.backends.chains.clone());
+        router.set_balance_rules(&config.rules);
+        router.set_groups(&config.groups, &config.rules);
+        router.set_capabilities(config.capabilities.clone());
+        router
+    }
+
+    /// Build a router over whatever `source` reports.
+    pub fn from_source<S: HealthSource + ?Sized>(source: &mut S, routing: &RoutingConfig) -> Self {
+        let rng = match routing.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Self {
+            backends: source.snapshot(),
+            chains: Vec::new(),
+            loads: BTreeMap::new(),
+            capabilities: HashMap::new(),
+            balance: routing.balance,
+            balance_rules: None,
+            groups: BTreeMap::new(),
+            group_rules: None,
+            vantage: routing.vantage.clone(),
+            vantage_bias: routing.vantage_bias(),
+            rr_weights: BTreeMap::new(),
+            rr_cursor: BTreeMap::new(),
+            picks: BTreeMap::new(),
+            seed: routing.seed,
+            rng,
+            policy: None,
+            policy_error: None,
+            record: routing.record.clone(),
+            record_error: None,
+        }
+    }
+
+    /// Replace backend health with a fresh snapshot from `source`.
+    pub fn refresh<S: HealthSource + ?Sized>(&mut self, source: &mut S) {
+        self.backends = source.snapshot();
+        self.update_chains();
+    }
+
+    /// Use the `balance` of the winning matching rule (by host pattern and
+    /// priority) for targets it covers.
+    pub fn set_balance_rules(&mut self, rules: &[RuleConfig]) {
+        let rules: Vec<&RuleConfig> = rules.iter().filter(|r| r.balance.is_some()).collect();
+        let balances = rules.iter().filter_map(|r| r.balance).collect();
+        // Patterns were validated when the config was parsed
+        self.balance_rules = RuleMatcher::compile(rules.iter().map(|r| r.host.as_str()))
+            .ok()
+            .map(|m| m.with_priorities(rules.iter().map(|r| r.priority).collect()))
+            .filter(|m| !m.is_empty())
+            .map(|m| (m, balances));
+    }
+
+    /// Balancing that applies to `target`.
+    pub fn balance_for(&self, target: &Target) -> Balance {
+        self.balance_rules
+            .as_ref()
+            .and_then(|(m, balances)| m.first_match(&target.host, &[]).map(|i| balances[i]))
+            .unwrap_or(self.balance)
+    }
+
+    /// Prefer the members of the winning matching rule's `group` (by host
+    /// pattern and priority) for targets it covers.
+    pub fn set_groups(&mut self, groups: &HashMap<String, GroupConfig>, rules: &[RuleConfig]) {
+        self.groups = groups
+            .iter()
+            .map(|(name, g)| (name.clone(), g.backends.clone()))
+            .collect();
+        let rules: Vec<&RuleConfig> = rules.iter().filter(|r| r.group.is_some()).collect();
+        let names = rules.iter().filter_map(|r| r.group.clone()).collect();
+        self.group_rules = RuleMatcher::compile(rules.iter().map(|r| r.host.as_str()))
+            .ok()
+            .map(|m| m.with_priorities(rules.iter().map(|r| r.priority).collect()))
+            .filter(|m| !m.is_empty())
+            .map(|m| (m, names));
+    }
+
+    /// Group whose members `target` prefers, if a rule names one.
+    pub fn group_for(&self, target: &Target) -> Option<&str> {
+        self.group_rules
+            .as_ref()
+            .and_then(|(m, names)| m.first_match(&target.host, &[]).map(|i| names[i].as_str()))
+    }
+
+    /// The group this gateway leans toward (`[routing] vantage`): the
+    /// configured one, or with `auto` the one whose fastest usable member is
+    /// the fastest overall, as latency is the best hint of distance there is.
+    pub fn nearest_group(&self) -> Option<String> {
+        match self.vantage.as_deref()? {
+            "auto" => self
+                .group_health()
+                .into_iter()
+                .filter_map(|g| g.best_latency_ms.map(|latency| (g.name, latency)))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(name, _)| name),
+            group => Some(group.to_string()),
+        }
+    }
+
+    /// Share of decisions that try the nearest group first.
+    pub fn vantage_bias(&self) -> f64 {
+        self.vantage_bias
+    }
+
+    fn in_group(&self, group: &str, b: &BackendHealth) -> bool {
+        self.groups
+            .get(group)
+            .is_some_and(|members| members.contains(&b.name))
+    }
+
+    /// Health of every group, aggregated over its members, for `status`.
+    pub fn group_health(&self) -> Vec<GroupHealth> {
+        self.groups
+            .iter()
+            .map(|(name, members)| {
+                let known: Vec<&BackendHealth> = self
+                    .backends
+                    .iter()
+                    .filter(|b| members.contains(&b.name))
+                    .collect();
+                let up: Vec<&BackendHealth> = known
+                    .iter()
+                    .copied()
+                    .filter(|b| b.enabled && !self.saturated(b))
+                    .collect();
+                GroupHealth {
+                    name: name.clone(),
+                    members: members.len(),
+                    up: up.len(),
+                    missing: members
+                        .iter()
+                        .filter(|m| !known.iter().any(|b| &b.name == *m))
+                        .cloned()
+                        .collect(),
+                    best_latency_ms: up.iter().map(|b| b.latency_ms).min_by(f64::total_cmp),
+                    failure_rate: match up.len() {
+                        0 => 1.0,
+                        n => up.iter().map(|b| b.failure_rate).sum::<f64>() / n as f64,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// How many decisions went to each backend.
+    pub fn picks(&self) -> &BTreeMap<String, u64> {
+        &self.picks
+    }
+
+    /// Offer these chained backends, evaluated over the current hops.
+    pub fn set_chains(&mut self, chains: Vec<ChainConfig>) {
+        self.chains = chains;
+        self.update_chains();
+    }
+
+    /// Re-derive chain health after any hop changed.
+    fn update_chains(&mut self) {
+        self.backends.retain(|b| b.kind != BackendKind::Chain);
+        let chains: Vec<_> = self
+            .chains
+            .iter()
+            .map(|c| chain_health(c, &self.backends))
+            .collect();
+        self.backends.extend(chains);
+    }
+
+    /// Restart randomized selection from `seed` (reproducible runs).
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Let `policy` rank candidates ahead of the built-in order.
+    pub fn set_policy(&mut self, policy: Box<dyn RoutingPolicy>) {
+        self.policy = Some(policy);
+    }
+
+    /// Why the last decision fell back to the built-in order, if the policy
+    /// failed.
+    pub fn take_policy_error(&mut self) -> Option<String> {
+        self.policy_error.take()
+    }
+
+    /// Record every decision, with its inputs, to `path`; `None` stops.
+    pub fn record_to(&mut self, path: Option<PathBuf>) {
+        self.record = path;
+    }
+
+    /// Why a decision couldn't be recorded, if one couldn't since last asked.
+    pub fn take_record_error(&mut self) -> Option<String> {
+        self.record_error.take()
+    }
+
+    /// Declare what backends (by name) or whole kinds can carry.
+    pub fn set_capabilities(&mut self, capabilities: HashMap<String, CapabilityConfig>) {
+        self.capabilities = capabilities;
+    }
+
+    /// What `b` can carry. A chain takes its exit hop's kind defaults, then
+    /// anything declared for `chain` or its name.
+    pub fn capabilities(&self, b: &BackendHealth) -> Capabilities {
+        let exit = self
+            .chains
+            .iter()
+            .find(|c| b.kind == BackendKind::Chain && c.name() == b.name)
+            .and_then(|c| c.chain.last().copied());
+        let mut caps = Capabilities::for_kind(exit.unwrap_or(b.kind), b.ipv6);
+        for key in exit
+            .iter()
+            .map(|k| k.as_str())
+            .chain([b.kind.as_str(), &b.name])
+        {
+            if let Some(declared) = self.capabilities.get(key) {
+                caps.apply(declared);
+            }
+        }
+        caps
+    }
+
+    /// Backends that can't carry what `needs` asks for, and why.
+    pub fn ineligible(&self, needs: &Requirements) -> Vec<(String, String)> {
+        self.backends
+            .iter()
+            .filter_map(|b| {
+                self.capabilities(b)
+                    .lacks(needs)
+                    .map(|what| (b.name.clone(), what))
+            })
+            .collect()
+    }
+
+    /// Record how busy a backend (by name) or a whole kind is.
+    pub fn set_load(&mut self, key: &str, load: Load) {
+        self.loads.insert(key.to_string(), load);
+    }
+
+    /// Fold a share of failed dials into a backend (by name) or every
+    /// backend of a kind.
+    pub fn add_failures(&mut self, key: &str, rate: f64) {
+        for b in &mut self.backends {
+            if b.name == key || b.kind.as_str() == key {
+                b.add_failures(rate);
+            }
+        }
+        self.update_chains();
+    }
+
+    /// Is `b`, or its kind, at its session cap? A chain is also saturated
+    /// when one of its hop kinds is.
+    pub fn saturated(&self, b: &BackendHealth) -> bool {
+        let full = |key: &str| self.loads.get(key).is_some_and(Load::saturated);
+        let hops = self
+            .chains
+            .iter()
+            .filter(|c| b.kind == BackendKind::Chain && c.name() == b.name)
+            .flat_map(|c| c.chain.iter());
+        full(&b.name) || full(b.kind.as_str()) || hops.into_iter().any(|k| full(k.as_str()))
+    }
+
+    /// Whether there is no backend left to route to (e.g. all excluded).
+    pub fn is_empty(&self) -> bool {
+        self.backends.is_empty()
     }
 
     /// Return a copy of current backend health for dashboards / CLI.
@@ -74,53 +727,440 @@ impl Router {
         self.backends.clone()
     }
 
-    /// Pick a backend for this target (Oxen-first, Tor-fallback).
-    pub fn choose_backend_for(&mut self, _target: &str) -> BackendChoice {
-        let mut rng = thread_rng();
+    /// Current state, stamped with the time, for `status --json`.
+    pub fn snapshot(&self) -> RouterSnapshot {
+        RouterSnapshot {
+            taken_unix: now_unix(),
+            backends: self.backends.clone(),
+            loads: self.loads.clone(),
+            rr_cursor: self
+                .rr_cursor
+                .iter()
+                .map(|(kind, i)| (kind.to_string(), *i))
+                .collect(),
+            rr_weights: self.rr_weights.clone(),
+            seed: self.seed,
+            histograms: BTreeMap::new(),
+        }
+    }
 
-        // 1) Prefer enabled Oxen
-        if let Some(chosen) = self
-            .backends
-            .iter()
-            .filter(|b| b.enabled && matches!(b.kind, BackendKind::Oxen))
-            .collect::<Vec<_>>()
-            .choose(&mut rng)
-        {
-            return BackendChoice {
-                name: chosen.name.clone(),
-                kind: chosen.kind,
-                latency_ms: chosen.latency_ms,
-                failure_rate: chosen.failure_rate,
-            };
+    /// The rules that apply to `target`.
+    pub fn matched(&self, target: &Target) -> Matched {
+        Matched {
+            balance: self.balance_for(target),
+            group: self.group_for(target).map(str::to_string),
+            nearest: self.nearest_group(),
+        }
+    }
+
+    /// Put the router back where it was for a recorded decision: health,
+    /// loads, round-robin state and the decision's seed.
+    pub fn rewind(&mut self, recorded: &Recorded) {
+        // Chain health as it was, not re-derived from hops the config may
+        // have changed since
+        self.backends = recorded.snapshot.backends.clone();
+        self.restore(&recorded.snapshot);
+        self.rng = StdRng::seed_from_u64(recorded.seed);
+    }
+
+    /// Take over the loads and round-robin state of a snapshot (health
+    /// comes in through [`Router::from_source`]).
+    pub fn restore(&mut self, snapshot: &RouterSnapshot) {
+        self.loads = snapshot.loads.clone();
+        self.rr_weights = snapshot.rr_weights.clone();
+        self.rr_cursor = [
+            BackendKind::Oxen,
+            BackendKind::Tor,
+            BackendKind::Chain,
+            BackendKind::Masque,
+        ]
+        .iter()
+        .filter_map(|k| snapshot.rr_cursor.get(k.as_str()).map(|i| (k.as_str(), *i)))
+        .collect();
+    }
+
+    /// Apply a health update. Returns `false` if no backend has that name.
+    pub fn apply(&mut self, update: &HealthUpdate) -> bool {
+        match self.backends.iter_mut().find(|b| b.name == update.backend) {
+            Some(b) => {
+                update.apply_to(b);
+                self.update_chains();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove every backend for which `blocked` returns true.
+    ///
+    /// Returns the names of the filtered candidates.
+    pub fn exclude<F: Fn(&BackendHealth) -> bool>(&mut self, blocked: F) -> Vec<String> {
+        let mut removed = Vec::new();
+        self.backends.retain(|b| {
+            let keep = !blocked(b);
+            if !keep {
+                removed.push(b.name.clone());
+            }
+            keep
+        });
+        self.chains.retain(|c| !removed.contains(&c.name()));
+        self.update_chains();
+        removed
+    }
+
+    /// Stop routing to every backend of `kind` (e.g. quota used up).
+    pub fn drain(&mut self, kind: BackendKind) {
+        for b in self.backends.iter_mut().filter(|b| b.kind == kind) {
+            b.enabled = false;
         }
+        self.update_chains();
+    }
+
+    /// Pick a backend for this target: a configured chain if one is up,
+    /// else Oxen-first, Tor-fallback.
+    ///
+    /// IPv6 targets first look only at backends that can reach IPv6
+    /// destinations, so a v6-capable Tor exit beats a v4-only Oxen node.
+    ///
+    /// With a policy set, its first enabled pick wins instead; if it fails
+    /// or names nothing usable, the built-in order applies.
+    ///
+    /// A target covered by a rule's `group` looks at that group's members
+    /// first, in the same order, and at everyone else only if none is usable.
+    /// Other targets do the same with the nearest group (`[routing]
+    /// vantage`), for a `vantage_bias` share of decisions.
+    ///
+    /// Saturated backends (see `set_load`) are skipped, so new sessions
+    /// overflow to the next candidate, and so are backends without the
+    /// capabilities the target needs (`.onion`, `.loki`, its port).
+    ///
+    /// `None` only if the router has no backends at all (see
+    /// [`Router::is_empty`]).
+    pub fn choose_backend_for(&mut self, target: &Target) -> Option<BackendChoice> {
+        self.choose_backend_with(target, &Requirements::of(target))
+    }
 
-        // 2) Fall back to enabled Tor
-        if let Some(chosen) = self
+    /// Like [`Router::choose_backend_for`], with explicit requirements (e.g.
+    /// UDP).
+    ///
+    /// With `[routing] record`, each decision draws from a seed of its own,
+    /// taken from the router's RNG, so it can be made again alone.
+    pub fn choose_backend_with(
+        &mut self,
+        target: &Target,
+        needs: &Requirements,
+    ) -> Option<BackendChoice> {
+        let Some(path) = self.record.clone() else {
+            let choice = self.pick(target, needs)?;
+            *self.picks.entry(choice.name.clone()).or_default() += 1;
+            return Some(choice);
+        };
+        let seed = self.rng.gen();
+        self.rng = StdRng::seed_from_u64(seed);
+        let snapshot = self.snapshot();
+        let rules = self.matched(target);
+        let weights = self
             .backends
             .iter()
-            .filter(|b| b.enabled && matches!(b.kind, BackendKind::Tor))
-            .collect::<Vec<_>>()
-            .choose(&mut rng)
+            .map(|b| (b.name.clone(), score(b)))
+            .collect();
+        // An error the caller hasn't taken yet isn't this decision's
+        let earlier_error = self.policy_error.take();
+        let Some(choice) = self.pick(target, needs) else {
+            self.policy_error = earlier_error;
+            return None;
+        };
+        *self.picks.entry(choice.name.clone()).or_default() += 1;
+        let recorded = Recorded {
+            unix: snapshot.taken_unix,
+            target: target.clone(),
+            needs: needs.clone(),
+            snapshot,
+            rules,
+            weights,
+            seed,
+            chosen: choice.name.clone(),
+            kind: choice.kind,
+            policy_error: self.policy_error.clone(),
+        };
+        if self.policy_error.is_none() {
+            self.policy_error = earlier_error;
+        }
+        if let Err(e) = recording::append(&path, &recorded) {
+            self.record_error = Some(format!("{}: {}", path.display(), e));
+        }
+        Some(choice)
+    }
+
+    /// Why the backend called `name` is not `chosen`, the decision just
+    /// made for `target` (`by_policy`: the policy ranked it). Empty if it
+    /// is; `None` if there is no such backend.
+    ///
+    /// Anything that makes it unusable is listed; otherwise the step of the
+    /// decision it lost at.
+    pub fn why_not(
+        &self,
+        name: &str,
+        target: &Target,
+        needs: &Requirements,
+        chosen: &BackendChoice,
+        by_policy: bool,
+    ) -> Option<Vec<Rejection>> {
+        let b = self.backends.iter().find(|b| b.name == name)?;
+        if b.name == chosen.name {
+            return Some(Vec::new());
+        }
+        let mut unusable = Vec::new();
+        if !b.enabled {
+            unusable.push(Rejection::Disabled);
+        }
+        if self.saturated(b) {
+            unusable.push(Rejection::Saturated);
+        }
+        if let Some(what) = self.capabilities(b).lacks(needs) {
+            unusable.push(Rejection::Lacks(what));
+        }
+        if !unusable.is_empty() {
+            return Some(unusable);
+        }
+
+        let winner = self.backends.iter().find(|w| w.name == chosen.name);
+        let preferred =
+            |g: &str| !self.in_group(g, b) && winner.is_some_and(|w| self.in_group(g, w));
+        let rule_group = self.group_for(target);
+        let nearest = match rule_group {
+            Some(_) => None,
+            None => self.nearest_group().filter(|g| preferred(g)),
+        };
+        let lost = if by_policy {
+            Rejection::Policy
+        } else if let Some(group) = rule_group.filter(|g| preferred(g)) {
+            Rejection::Group(group.to_string())
+        } else if let Some(group) = nearest {
+            Rejection::Nearest(group)
+        } else if target.is_ipv6()
+            && !self.capabilities(b).ipv6
+            && winner.is_some_and(|w| self.capabilities(w).ipv6)
         {
-            return BackendChoice {
-                name: chosen.name.clone(),
-                kind: chosen.kind,
-                latency_ms: chosen.latency_ms,
-                failure_rate: chosen.failure_rate,
-            };
+            Rejection::NoIpv6
+        } else if chosen.kind != b.kind {
+            Rejection::Order(chosen.kind)
+        } else {
+            Rejection::Balance {
+                balance: self.balance_for(target),
+                score: score(b),
+                chosen_score: winner.map_or(0.0, score),
+            }
+        };
+        Some(vec![lost])
+    }
+
+    fn usable(&self, b: &BackendHealth, needs: &Requirements) -> bool {
+        b.enabled && !self.saturated(b) && self.capabilities(b).lacks(needs).is_none()
+    }
+
+    fn pick(&mut self, target: &Target, needs: &Requirements) -> Option<BackendChoice> {
+        if let Some(policy) = self.policy.as_mut() {
+            match policy.rank(target, &self.backends) {
+                Ok(ranked) => {
+                    if let Some(chosen) = ranked.iter().find_map(|name| {
+                        self.backends
+                            .iter()
+                            .find(|b| &b.name == name && self.usable(b, needs))
+                    }) {
+                        return Some(BackendChoice::from(chosen));
+                    }
+                    self.policy_error = Some("policy ranked no enabled backend".to_string());
+                }
+                Err(e) => self.policy_error = Some(e),
+            }
         }
 
-        // 3) Absolute fallback: first backend, even if disabled
+        // A rule's group narrows the built-in order to its members first,
+        // else, for a share of decisions, the nearest group does
+        let group = match self.group_for(target) {
+            Some(group) => Some(group.to_string()),
+            None => self
+                .nearest_group()
+                .filter(|_| self.rng.gen_bool(self.vantage_bias)),
+        };
+        if let Some(group) = group {
+            if let Some(chosen) = self.pick_in(target, needs, Some(&group)) {
+                return Some(chosen);
+            }
+        }
+        if let Some(chosen) = self.pick_in(target, needs, None) {
+            return Some(chosen);
+        }
+
+        // 5) Absolute fallback: first capable backend, else the first one,
+        // even if disabled; nothing only if there are no backends
         let chosen = self
             .backends
-            .first()
-            .expect("at least one backend must be configured");
+            .iter()
+            .find(|b| self.capabilities(b).lacks(needs).is_none())
+            .or(self.backends.first())?;
+
+        Some(BackendChoice::from(chosen))
+    }
+
+    /// The built-in order over usable backends (members of `group` only, if
+    /// given), or `None` if there is none.
+    fn pick_in(
+        &mut self,
+        target: &Target,
+        needs: &Requirements,
+        group: Option<&str>,
+    ) -> Option<BackendChoice> {
+        let balance = self.balance_for(target);
+        let passes: &[bool] = if target.is_ipv6() {
+            &[true, false]
+        } else {
+            &[false]
+        };
+
+        for &need_v6 in passes {
+            // 1) Prefer an enabled chain, 2) then Oxen, 3) fall back to Tor,
+            // 4) then the HTTP/3 relay
+            for kind in [
+                BackendKind::Chain,
+                BackendKind::Oxen,
+                BackendKind::Tor,
+                BackendKind::Masque,
+            ] {
+                let pool: Vec<_> = self
+                    .backends
+                    .iter()
+                    .filter(|b| {
+                        b.kind == kind
+                            && (!need_v6 || self.capabilities(b).ipv6)
+                            && group.is_none_or(|g| self.in_group(g, b))
+                            && self.usable(b, needs)
+                    })
+                    .collect();
+                if pool.is_empty() {
+                    continue;
+                }
+                let chosen = match balance {
+                    Balance::Random => *pool.choose(&mut self.rng).expect("pool is not empty"),
+                    Balance::RoundRobin => {
+                        let cursor = self.rr_cursor.entry(kind.as_str()).or_default();
+                        let i = *cursor % pool.len();
+                        *cursor = cursor.wrapping_add(1);
+                        pool[i]
+                    }
+                    Balance::WeightedRr => pool[weighted_rr(&mut self.rr_weights, &pool)],
+                    Balance::WeightedRandom => {
+                        // All-zero scores (every node failing) fall back to uniform
+                        match WeightedIndex::new(pool.iter().map(|b| score(b))) {
+                            Ok(weights) => pool[weights.sample(&mut self.rng)],
+                            Err(_) => *pool.choose(&mut self.rng).expect("pool is not empty"),
+                        }
+                    }
+                    Balance::P2c => pool
+                        .choose_multiple(&mut self.rng, 2)
+                        .copied()
+                        .max_by(|a, b| score(a).total_cmp(&score(b)))
+                        .expect("pool is not empty"),
+                };
+                return Some(BackendChoice::from(chosen));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::InMemoryHealth;
+
+    fn target(s: &str) -> Target {
+        s.parse().expect("valid target")
+    }
+
+    fn backend(name: &str, kind: BackendKind) -> BackendHealth {
+        BackendHealth {
+            name: name.to_string(),
+            kind,
+            latency_ms: 50.0,
+            failure_rate: 0.0,
+            enabled: true,
+            ipv6: false,
+        }
+    }
+
+    fn set_enabled(health: &mut InMemoryHealth, name: &str, enabled: bool) {
+        assert!(health.apply(&HealthUpdate {
+            backend: name.to_string(),
+            latency_ms: None,
+            failure_rate: None,
+            enabled: Some(enabled),
+        }));
+    }
+
+    #[test]
+    fn fails_over_as_backends_go_down_and_up() {
+        let mut health = InMemoryHealth::new(vec![
+            backend("oxen-1", BackendKind::Oxen),
+            backend("tor-1", BackendKind::Tor),
+            backend("relay", BackendKind::Masque),
+        ]);
+        let mut router = Router::from_source(&mut health, &RoutingConfig::default());
+        let target = target("example.com:443");
+        let chosen = |router: &mut Router| router.choose_backend_for(&target).unwrap().name;
+        assert_eq!(chosen(&mut router), "oxen-1");
+
+        set_enabled(&mut health, "oxen-1", false);
+        router.refresh(&mut health);
+        assert_eq!(chosen(&mut router), "tor-1");
+
+        set_enabled(&mut health, "tor-1", false);
+        router.refresh(&mut health);
+        assert_eq!(chosen(&mut router), "relay");
 
-        BackendChoice {
-            name: chosen.name.clone(),
-            kind: chosen.kind,
-            latency_ms: chosen.latency_ms,
-            failure_rate: chosen.failure_rate,
+        set_enabled(&mut health, "oxen-1", true);
+        router.refresh(&mut health);
+        assert_eq!(chosen(&mut router), "oxen-1");
+    }
+
+    #[test]
+    fn fails_over_within_a_kind() {
+        let mut health = InMemoryHealth::new(vec![
+            backend("oxen-1", BackendKind::Oxen),
+            backend("oxen-2", BackendKind::Oxen),
+            backend("tor-1", BackendKind::Tor),
+        ]);
+        let routing = RoutingConfig {
+            seed: Some(7),
+            ..RoutingConfig::default()
+        };
+        let mut router = Router::from_source(&mut health, &routing);
+        let target = target("example.com:443");
+
+        set_enabled(&mut health, "oxen-1", false);
+        router.refresh(&mut health);
+        for _ in 0..10 {
+            assert_eq!(router.choose_backend_for(&target).unwrap().name, "oxen-2");
         }
+        assert!(!health.apply(&HealthUpdate {
+            backend: "oxen-3".to_string(),
+            latency_ms: None,
+            failure_rate: None,
+            enabled: Some(false),
+        }));
+    }
+
+    #[test]
+    fn no_backends_is_no_decision() {
+        let mut router = Router::from_config(&GoldDustConfig::default_for_demo());
+        let target = target("example.com:443");
+        assert!(!router.is_empty());
+        assert!(router.choose_backend_for(&target).is_some());
+
+        router.exclude(|_| true);
+        assert!(router.is_empty());
+        assert!(router.choose_backend_for(&target).is_none());
+        assert!(router.picks().values().all(|&n| n == 1));
     }
 }
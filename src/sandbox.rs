@@ -0,0 +1,278 @@
+use std::path::PathBuf;
+
+use crate::config::SandboxConfig;
+
+/// Whether this build can confine itself (`sandbox` feature on Linux).
+pub const AVAILABLE: bool = cfg!(all(feature = "sandbox", target_os = "linux"));
+
+/// What `apply` managed to enforce.
+#[derive(Debug, Clone, Default)]
+pub struct Applied {
+    /// User switched to, if any.
+    pub user: Option<String>,
+    /// Landlock status: `full`, `partial` (older kernel ABI) or `none`.
+    pub landlock: &'static str,
+    /// The seccomp syscall allowlist is loaded.
+    pub seccomp: bool,
+}
+
+/// Drop privileges, then restrict the filesystem to `writable` (the state
+/// directory, log directories) plus what name resolution and
+/// `cfg.read_paths` need (read-only), then restrict syscalls to an allowlist.
+///
+/// Landlock only confines the calling thread and the threads it starts, so
+/// call this before the async runtime is built.
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+pub fn apply(cfg: &SandboxConfig, writable: &[PathBuf]) -> Result<Applied, String> {
+    let user = match &cfg.user {
+        Some(name) => linux::drop_to(name)?,
+        None => None,
+    };
+    let landlock = linux::landlock(writable, &cfg.read_paths)?;
+    linux::seccomp()?;
+    Ok(Applied {
+        user,
+        landlock,
+        seccomp: true,
+    })
+}
+
+#[cfg(not(all(feature = "sandbox", target_os = "linux")))]
+pub fn apply(_cfg: &SandboxConfig, _writable: &[PathBuf]) -> Result<Applied, String> {
+    Err("built without the `sandbox` feature (Linux only)".to_string())
+}
+
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+mod linux {
+    use std::collections::BTreeMap;
+    use std::ffi::CString;
+    use std::path::PathBuf;
+
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr,
+        RulesetStatus, ABI,
+    };
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+
+    /// Read-only paths every dispatcher needs: resolver config, NSS modules
+    /// glibc may load for `getaddrinfo`, and what the runtime reads to size
+    /// its thread pool.
+    const SYSTEM_READ: [&str; 8] = [
+        "/etc",
+        "/lib",
+        "/lib64",
+        "/usr/lib",
+        "/usr/lib64",
+        "/proc/self",
+        "/sys/fs/cgroup",
+        "/sys/devices/system/cpu",
+    ];
+
+    /// Switch to `name` if running as root. Returns the user switched to.
+    pub(super) fn drop_to(name: &str) -> Result<Option<String>, String> {
+        if unsafe { libc::geteuid() } != 0 {
+            return Ok(None);
+        }
+        let c_name = CString::new(name).map_err(|_| format!("bad user name `{name}`"))?;
+        // Single-threaded at this point, so the static getpwnam buffer is fine
+        let pw = unsafe { libc::getpwnam(c_name.as_ptr()) };
+        if pw.is_null() {
+            return Err(format!("no such user `{name}`"));
+        }
+        let (uid, gid) = unsafe { ((*pw).pw_uid, (*pw).pw_gid) };
+        if uid == 0 {
+            return Err(format!("`{name}` is root, nothing to drop"));
+        }
+        unsafe {
+            if libc::setgroups(0, std::ptr::null()) != 0
+                || libc::setgid(gid) != 0
+                || libc::setuid(uid) != 0
+            {
+                return Err(format!(
+                    "switching to `{name}`: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+            if libc::setuid(0) == 0 {
+                return Err("could regain root after dropping privileges".to_string());
+            }
+        }
+        Ok(Some(name.to_string()))
+    }
+
+    pub(super) fn landlock(
+        writable: &[PathBuf],
+        extra: &[PathBuf],
+    ) -> Result<&'static str, String> {
+        let abi = ABI::V3;
+        let read: Vec<PathBuf> = SYSTEM_READ
+            .iter()
+            .map(PathBuf::from)
+            .chain(extra.iter().cloned())
+            .collect();
+        let status = Ruleset::default()
+            .handle_access(AccessFs::from_all(abi))
+            .and_then(|r| r.create())
+            .and_then(|r| r.add_rules(path_beneath_rules(&read, AccessFs::from_read(abi))))
+            .and_then(|r| r.add_rules(path_beneath_rules(writable, AccessFs::from_all(abi))))
+            .and_then(|r| r.restrict_self())
+            .map_err(|e| format!("landlock: {e}"))?;
+        Ok(match status.ruleset {
+            RulesetStatus::FullyEnforced => "full",
+            RulesetStatus::PartiallyEnforced => "partial",
+            RulesetStatus::NotEnforced => "none",
+        })
+    }
+
+    pub(super) fn seccomp() -> Result<(), String> {
+        let arch = TargetArch::try_from(std::env::consts::ARCH).map_err(|e| e.to_string())?;
+        let rules: BTreeMap<i64, Vec<_>> = syscalls().iter().map(|&nr| (nr, Vec::new())).collect();
+        // Anything else fails with EPERM rather than killing the process, so
+        // a missing entry shows up as an error in the log
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Errno(libc::EPERM as u32),
+            SeccompAction::Allow,
+            arch,
+        )
+        .map_err(|e| e.to_string())?;
+        let program: BpfProgram = filter.try_into().map_err(|e| format!("seccomp: {e}"))?;
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(format!("no_new_privs: {}", std::io::Error::last_os_error()));
+        }
+        seccompiler::apply_filter(&program).map_err(|e| format!("seccomp: {e}"))
+    }
+
+    /// Syscalls the proxy, its runtime, TLS and name resolution use.
+    fn syscalls() -> Vec<libc::c_long> {
+        let mut allowed = vec![
+            // memory, threads, signals, time
+            libc::SYS_brk,
+            libc::SYS_mmap,
+            libc::SYS_munmap,
+            libc::SYS_mremap,
+            libc::SYS_mprotect,
+            libc::SYS_madvise,
+            libc::SYS_membarrier,
+            libc::SYS_clone,
+            libc::SYS_clone3,
+            libc::SYS_exit,
+            libc::SYS_exit_group,
+            libc::SYS_futex,
+            libc::SYS_set_robust_list,
+            libc::SYS_get_robust_list,
+            libc::SYS_set_tid_address,
+            libc::SYS_rseq,
+            libc::SYS_sched_yield,
+            libc::SYS_sched_getaffinity,
+            libc::SYS_gettid,
+            libc::SYS_getpid,
+            libc::SYS_getppid,
+            libc::SYS_getuid,
+            libc::SYS_geteuid,
+            libc::SYS_getgid,
+            libc::SYS_getegid,
+            libc::SYS_tgkill,
+            libc::SYS_prctl,
+            libc::SYS_prlimit64,
+            libc::SYS_getrusage,
+            libc::SYS_sysinfo,
+            libc::SYS_uname,
+            libc::SYS_getrandom,
+            libc::SYS_rt_sigaction,
+            libc::SYS_rt_sigprocmask,
+            libc::SYS_rt_sigreturn,
+            libc::SYS_sigaltstack,
+            libc::SYS_restart_syscall,
+            libc::SYS_clock_gettime,
+            libc::SYS_clock_getres,
+            libc::SYS_clock_nanosleep,
+            libc::SYS_gettimeofday,
+            libc::SYS_nanosleep,
+            // files (state JSON, config, resolver config)
+            libc::SYS_read,
+            libc::SYS_write,
+            libc::SYS_readv,
+            libc::SYS_writev,
+            libc::SYS_pread64,
+            libc::SYS_pwrite64,
+            libc::SYS_openat,
+            libc::SYS_close,
+            libc::SYS_fstat,
+            libc::SYS_newfstatat,
+            libc::SYS_statx,
+            libc::SYS_statfs,
+            libc::SYS_fstatfs,
+            libc::SYS_lseek,
+            libc::SYS_fcntl,
+            libc::SYS_ioctl,
+            libc::SYS_dup,
+            libc::SYS_dup3,
+            libc::SYS_pipe2,
+            libc::SYS_splice,
+            libc::SYS_getdents64,
+            libc::SYS_getcwd,
+            libc::SYS_readlinkat,
+            libc::SYS_faccessat,
+            libc::SYS_faccessat2,
+            libc::SYS_renameat,
+            libc::SYS_renameat2,
+            libc::SYS_unlinkat,
+            libc::SYS_mkdirat,
+            libc::SYS_ftruncate,
+            libc::SYS_fsync,
+            libc::SYS_fdatasync,
+            libc::SYS_flock,
+            // sockets and the event loop
+            libc::SYS_socket,
+            libc::SYS_socketpair,
+            libc::SYS_connect,
+            libc::SYS_accept4,
+            libc::SYS_bind,
+            libc::SYS_listen,
+            libc::SYS_getsockname,
+            libc::SYS_getpeername,
+            libc::SYS_setsockopt,
+            libc::SYS_getsockopt,
+            libc::SYS_sendto,
+            libc::SYS_recvfrom,
+            libc::SYS_sendmsg,
+            libc::SYS_recvmsg,
+            libc::SYS_sendmmsg,
+            libc::SYS_recvmmsg,
+            libc::SYS_shutdown,
+            libc::SYS_epoll_create1,
+            libc::SYS_epoll_ctl,
+            libc::SYS_epoll_pwait,
+            libc::SYS_epoll_pwait2,
+            libc::SYS_eventfd2,
+            libc::SYS_ppoll,
+            libc::SYS_pselect6,
+            libc::SYS_timerfd_create,
+            libc::SYS_timerfd_settime,
+        ];
+        #[cfg(target_arch = "x86_64")]
+        allowed.extend([
+            libc::SYS_arch_prctl,
+            libc::SYS_open,
+            libc::SYS_stat,
+            libc::SYS_lstat,
+            libc::SYS_access,
+            libc::SYS_readlink,
+            libc::SYS_rename,
+            libc::SYS_unlink,
+            libc::SYS_mkdir,
+            libc::SYS_pipe,
+            libc::SYS_dup2,
+            libc::SYS_poll,
+            libc::SYS_select,
+            libc::SYS_epoll_create,
+            libc::SYS_epoll_wait,
+            libc::SYS_time,
+        ]);
+        // The ring is set up before confinement; relaying goes on entering it
+        #[cfg(feature = "io-uring")]
+        allowed.push(libc::SYS_io_uring_enter);
+        allowed
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::config::RuleConfig;
+use crate::matcher::RuleMatcher;
+use crate::target::Target;
+
+/// Compiled `when = "..."` conditions of `[[rules]]`, one slot per rule.
+///
+/// Expressions see `target.host`, `target.port`, `target.ipv6`, `resolved`
+/// (array of address strings) and the UTC clock as `hour`, `minute` and
+/// `weekday` (0 = Monday). They must evaluate to a bool.
+pub struct Conditions {
+    #[cfg(feature = "scripting")]
+    engine: rhai::Engine,
+    #[cfg(feature = "scripting")]
+    scripts: Vec<Option<rhai::AST>>,
+}
+
+impl fmt::Debug for Conditions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Conditions").finish_non_exhaustive()
+    }
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self::compile(&[]).expect("an empty rule set always compiles")
+    }
+}
+
+impl Conditions {
+    /// Compile every rule's `when`. Fails on the first invalid expression.
+    #[cfg(feature = "scripting")]
+    pub fn compile(rules: &[RuleConfig]) -> Result<Self, String> {
+        let mut engine = rhai::Engine::new();
+        engine.on_print(|_| {});
+        engine.on_debug(|_, _, _| {});
+        engine.set_max_operations(10_000);
+        engine.set_max_expr_depths(32, 32);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(256);
+
+        let scripts = rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                rule.when
+                    .as_deref()
+                    .map(|src| {
+                        engine
+                            .compile_expression(src)
+                            .map_err(|e| format!("rule #{i}: when `{src}`: {e}"))
+                    })
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { engine, scripts })
+    }
+
+    /// Compile every rule's `when`. Fails on the first invalid expression.
+    #[cfg(not(feature = "scripting"))]
+    pub fn compile(rules: &[RuleConfig]) -> Result<Self, String> {
+        match rules.iter().position(|r| r.when.is_some()) {
+            Some(i) => Err(format!("rule #{i}: `when` needs the `scripting` feature")),
+            None => Ok(Self {}),
+        }
+    }
+
+    /// Does rule `rule`'s condition hold for `target`? Rules without one
+    /// always do.
+    #[cfg(feature = "scripting")]
+    pub fn check(&self, rule: usize, target: &Target, resolved: &[IpAddr]) -> Result<bool, String> {
+        let Some(ast) = self.scripts.get(rule).and_then(Option::as_ref) else {
+            return Ok(true);
+        };
+
+        let now = crate::stats::now_unix();
+        let mut t = rhai::Map::new();
+        t.insert("host".into(), target.host_str().into());
+        t.insert("port".into(), (target.port as i64).into());
+        t.insert("ipv6".into(), target.is_ipv6().into());
+        let resolved: rhai::Array = resolved.iter().map(|ip| ip.to_string().into()).collect();
+
+        let mut scope = rhai::Scope::new();
+        scope.push_constant("target", t);
+        scope.push_constant("resolved", resolved);
+        scope.push_constant("hour", ((now / 3600) % 24) as i64);
+        scope.push_constant("minute", ((now / 60) % 60) as i64);
+        // 1970-01-01 was a Thursday
+        scope.push_constant("weekday", ((now / 86_400 + 3) % 7) as i64);
+
+        self.engine
+            .eval_ast_with_scope::<bool>(&mut scope, ast)
+            .map_err(|e| format!("rule #{rule}: when: {e}"))
+    }
+
+    /// Does rule `rule`'s condition hold for `target`? Rules without one
+    /// always do.
+    #[cfg(not(feature = "scripting"))]
+    pub fn check(
+        &self,
+        _rule: usize,
+        _target: &Target,
+        _resolved: &[IpAddr],
+    ) -> Result<bool, String> {
+        Ok(true)
+    }
+
+    /// First rule (in `matcher` order: priority, then file order) whose host
+    /// pattern and condition both match. A condition that fails to evaluate counts as false.
+    pub fn first_match(
+        &self,
+        matcher: &RuleMatcher,
+        target: &Target,
+        resolved: &[IpAddr],
+    ) -> Option<usize> {
+        matcher
+            .matches(&target.host, resolved)
+            .into_iter()
+            .find(|&i| self.check(i, target, resolved).unwrap_or(false))
+    }
+}
@@ -0,0 +1,250 @@
+//! Encryption at rest (`[encryption]`) for what the dispatcher keeps about
+//! its traffic: the traffic, health and audit logs, a line at a time, and
+//! the per-destination and per-user snapshots. ChaCha20-Poly1305 under one
+//! key, from a key file or the platform keychain.
+//!
+//! Text without the sealed prefix reads as it is, so files written before
+//! encryption was turned on stay readable.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN};
+
+use crate::keychain;
+use crate::stats::write_atomic;
+
+/// Default key file, in the state directory.
+pub const KEY_PATH: &str = "gold-dust-state.key";
+
+/// Marks sealed text; the rest is hex of nonce, ciphertext and tag.
+const PREFIX: &str = "gdenc1:";
+
+/// Encrypts and decrypts under the state key.
+pub struct Sealer {
+    key: LessSafeKey,
+}
+
+impl std::fmt::Debug for Sealer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Sealer")
+    }
+}
+
+impl Sealer {
+    /// The key `source` names: a key file, or a `keyring:` entry. With
+    /// `create`, a new random key is stored there if there is none (a file
+    /// readable by its owner alone); the flag tells whether it was.
+    pub fn load(source: &str, create: bool) -> Result<(Self, bool), String> {
+        let new_key = || -> String { hex(&rand::random::<[u8; 32]>()) };
+        let (text, created) = match keychain::Entry::parse(source) {
+            Some(entry) => {
+                let entry = entry?;
+                match entry.get()? {
+                    Some(key) => (key, false),
+                    None if create => {
+                        let key = new_key();
+                        entry.set(&key)?;
+                        (key, true)
+                    }
+                    None => return Err(format!("{entry}: no key stored")),
+                }
+            }
+            None => {
+                let path = Path::new(source);
+                match fs::read_to_string(path) {
+                    Ok(key) => (key, false),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound && create => {
+                        let key = new_key();
+                        crate::admin::write_new(path, &format!("{key}\n"))?;
+                        (key, true)
+                    }
+                    Err(e) => return Err(format!("{source}: {e}")),
+                }
+            }
+        };
+        let bytes = unhex(text.trim())
+            .filter(|b| b.len() == 32)
+            .ok_or_else(|| format!("{source}: not a key (64 hex digits)"))?;
+        let key = UnboundKey::new(&aead::CHACHA20_POLY1305, &bytes)
+            .map_err(|_| format!("{source}: not a key"))?;
+        Ok((
+            Self {
+                key: LessSafeKey::new(key),
+            },
+            created,
+        ))
+    }
+
+    /// `plain`, sealed: one line of ASCII.
+    pub fn seal(&self, plain: &str) -> String {
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let mut sealed = plain.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .expect("sealing fits in memory");
+        format!("{}{}{}", PREFIX, hex(&nonce), hex(&sealed))
+    }
+
+    /// The plain text of `text`, which may not be sealed at all. `None` if
+    /// it was sealed under another key, or torn.
+    pub fn open(&self, text: &str) -> Option<String> {
+        let Some(sealed) = text.trim().strip_prefix(PREFIX) else {
+            return Some(text.to_string());
+        };
+        let mut bytes = unhex(sealed).filter(|b| b.len() >= NONCE_LEN)?;
+        let mut sealed = bytes.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&bytes).ok()?;
+        let plain = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut sealed)
+            .ok()?;
+        String::from_utf8(plain.to_vec()).ok()
+    }
+}
+
+/// Whether `text` is sealed.
+pub fn is_sealed(text: &str) -> bool {
+    text.trim_start().starts_with(PREFIX)
+}
+
+/// The plain lines of a log read back. Sealed lines need `sealer`; those
+/// that don't open under it (torn, or another key) are skipped, unless none
+/// does.
+pub fn open_lines(lines: Vec<String>, sealer: Option<&Sealer>) -> Result<Vec<String>, String> {
+    let sealed = lines.iter().filter(|l| is_sealed(l)).count();
+    if sealed == 0 {
+        return Ok(lines);
+    }
+    let sealer = sealer.ok_or("encrypted: set [encryption] to read it")?;
+    let opened: Vec<String> = lines.iter().filter_map(|l| sealer.open(l)).collect();
+    if opened.len() + sealed == lines.len() {
+        return Err("encrypted under another key".to_string());
+    }
+    Ok(opened)
+}
+
+/// Read a snapshot, opening it if sealed. `None` if missing, or if it
+/// doesn't open.
+pub fn read(path: &Path, sealer: Option<&Sealer>) -> Option<String> {
+    let text = fs::read_to_string(path).ok()?;
+    match (is_sealed(&text), sealer) {
+        (false, _) => Some(text),
+        (true, Some(sealer)) => sealer.open(&text),
+        (true, None) => None,
+    }
+}
+
+/// Write a snapshot atomically, sealed if there is a `sealer`.
+pub fn write(path: &Path, contents: &str, sealer: Option<&Sealer>) -> io::Result<()> {
+    match sealer {
+        Some(sealer) => write_atomic(path, &sealer.seal(contents)),
+        None => write_atomic(path, contents),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealer(byte: u8) -> Sealer {
+        let key = UnboundKey::new(&aead::CHACHA20_POLY1305, &[byte; 32]).expect("valid key");
+        Sealer {
+            key: LessSafeKey::new(key),
+        }
+    }
+
+    #[test]
+    fn seal_then_open_round_trips() {
+        let sealer = sealer(1);
+        let sealed = sealer.seal("{\"host\":\"example.com\"}");
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.contains("example.com"));
+        assert_eq!(
+            sealer.open(&sealed).as_deref(),
+            Some("{\"host\":\"example.com\"}")
+        );
+        // A fresh nonce each time
+        assert_ne!(sealer.seal("same"), sealer.seal("same"));
+    }
+
+    #[test]
+    fn another_key_does_not_open() {
+        let sealed = sealer(1).seal("secret");
+        assert_eq!(sealer(2).open(&sealed), None);
+    }
+
+    #[test]
+    fn torn_lines_do_not_open() {
+        let sealer = sealer(1);
+        let sealed = sealer.seal("secret");
+        // Whole bytes missing: the tag fails
+        assert_eq!(sealer.open(&sealed[..sealed.len() - 2]), None);
+        // Half a byte missing: not hex
+        assert_eq!(sealer.open(&sealed[..sealed.len() - 1]), None);
+        // Shorter than a nonce
+        assert_eq!(sealer.open(&format!("{PREFIX}0011")), None);
+        assert_eq!(sealer.open(&format!("{PREFIX}zz")), None);
+    }
+
+    #[test]
+    fn plain_text_passes_through() {
+        let sealer = sealer(1);
+        assert_eq!(sealer.open("not sealed").as_deref(), Some("not sealed"));
+        assert!(!is_sealed("not sealed"));
+    }
+
+    #[test]
+    fn unhex_rejects_odd_lengths_and_non_hex() {
+        assert_eq!(unhex("00ff"), Some(vec![0x00, 0xff]));
+        assert_eq!(unhex(&hex(&[1, 2, 254])), Some(vec![1, 2, 254]));
+        assert_eq!(unhex("abc"), None);
+        assert_eq!(unhex("0g"), None);
+    }
+
+    #[test]
+    fn open_lines_skips_what_does_not_open() {
+        let (sealer, other) = (sealer(1), sealer(2));
+        let lines = vec![
+            "plain".to_string(),
+            sealer.seal("sealed"),
+            sealer.seal("torn")[..20].to_string(),
+            other.seal("another key"),
+        ];
+        assert_eq!(
+            open_lines(lines, Some(&sealer)),
+            Ok(vec!["plain".to_string(), "sealed".to_string()])
+        );
+    }
+
+    #[test]
+    fn open_lines_fails_only_when_no_sealed_line_opens() {
+        let (sealer, other) = (sealer(1), sealer(2));
+        let plain = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(open_lines(plain.clone(), None), Ok(plain.clone()));
+        assert_eq!(open_lines(plain.clone(), Some(&sealer)), Ok(plain));
+
+        let foreign = vec!["plain".to_string(), other.seal("another key")];
+        assert!(open_lines(foreign.clone(), Some(&sealer)).is_err());
+        assert!(open_lines(foreign, None).is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::router::{BackendChoice, HealthUpdate, Router, NO_BACKENDS};
+use crate::target::Target;
+
+/// Scripted sequence of health events, loaded from TOML or JSON.
+///
+/// ```toml
+/// targets = ["example.com:443"]
+///
+/// [[steps]]
+/// t = 30
+/// note = "Oxen node 1 goes dark"
+/// events = [{ backend = "oxen-node-1", enabled = false }]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    /// Targets routed after every step.
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    /// RNG seed for this run; overrides the config seed.
+    pub seed: Option<u64>,
+    pub steps: Vec<Step>,
+}
+
+/// One point in scenario time.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    /// Seconds since scenario start (used for labelling only).
+    #[serde(default)]
+    pub t: u64,
+    pub note: Option<String>,
+    #[serde(default)]
+    pub events: Vec<HealthUpdate>,
+    /// Extra targets routed at this step only.
+    #[serde(default)]
+    pub route: Vec<Target>,
+}
+
+/// Result of replaying one step.
+#[derive(Debug, Clone)]
+pub struct StepOutcome {
+    pub t: u64,
+    pub note: Option<String>,
+    /// Events naming a backend the router doesn't know.
+    pub unknown_backends: Vec<String>,
+    pub decisions: Vec<(Target, BackendChoice)>,
+    /// Routing policy failures (those decisions used the built-in order).
+    pub policy_errors: Vec<String>,
+}
+
+impl Scenario {
+    /// Load a scenario; `.json` files are parsed as JSON, anything else as TOML.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let scenario = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&text)?,
+            _ => toml::from_str(&text)?,
+        };
+        Ok(scenario)
+    }
+
+    /// Feed every step into `router` and record the decisions it makes.
+    /// Fails if the router has no backends to decide between.
+    pub fn run(&self, router: &mut Router) -> Result<Vec<StepOutcome>, String> {
+        if let Some(seed) = self.seed {
+            router.reseed(seed);
+        }
+
+        self.steps
+            .iter()
+            .map(|step| {
+                let unknown_backends = step
+                    .events
+                    .iter()
+                    .filter(|e| !router.apply(e))
+                    .map(|e| e.backend.clone())
+                    .collect();
+                let mut policy_errors = Vec::new();
+                let decisions: Vec<_> = self
+                    .targets
+                    .iter()
+                    .chain(&step.route)
+                    .map(|target| {
+                        let choice = router.choose_backend_for(target).ok_or(NO_BACKENDS)?;
+                        policy_errors.extend(router.take_policy_error());
+                        Ok((target.clone(), choice))
+                    })
+                    .collect::<Result<_, &str>>()?;
+                Ok(StepOutcome {
+                    t: step.t,
+                    note: step.note.clone(),
+                    unknown_backends,
+                    decisions,
+                    policy_errors,
+                })
+            })
+            .collect()
+    }
+}
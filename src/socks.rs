@@ -0,0 +1,107 @@
+use std::error::Error;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// First byte of every SOCKS5 greeting.
+pub const VERSION: u8 = 5;
+
+/// The only command the dispatcher serves.
+pub const CMD_CONNECT: u8 = 1;
+
+const NO_AUTH: u8 = 0x00;
+const USER_PASS: u8 = 0x02;
+const NO_ACCEPTABLE: u8 = 0xff;
+
+/// Reply codes (RFC 1928 §6).
+pub const SUCCEEDED: u8 = 0x00;
+pub const GENERAL_FAILURE: u8 = 0x01;
+pub const NOT_ALLOWED: u8 = 0x02;
+pub const HOST_UNREACHABLE: u8 = 0x04;
+pub const CONNECTION_REFUSED: u8 = 0x05;
+pub const TTL_EXPIRED: u8 = 0x06;
+pub const COMMAND_NOT_SUPPORTED: u8 = 0x07;
+pub const ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+/// A client's SOCKS5 request, after the handshake.
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// From username/password auth (RFC 1929); the password is ignored.
+    pub username: Option<String>,
+    pub command: u8,
+    /// `host:port`, `1.2.3.4:port` or `[v6]:port`, as in a CONNECT line.
+    pub address: String,
+}
+
+/// Run the server side of the handshake up to (not including) the reply,
+/// after the version byte (read by the caller to tell SOCKS from HTTP).
+/// Username/password auth is preferred when offered, so apps can name
+/// themselves.
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> Result<Request, Box<dyn Error + Send + Sync>> {
+    let count = stream.read_u8().await?;
+    let mut methods = vec![0u8; count as usize];
+    stream.read_exact(&mut methods).await?;
+    let method = if methods.contains(&USER_PASS) {
+        USER_PASS
+    } else if methods.contains(&NO_AUTH) {
+        NO_AUTH
+    } else {
+        NO_ACCEPTABLE
+    };
+    stream.write_all(&[VERSION, method]).await?;
+    if method == NO_ACCEPTABLE {
+        return Err("no acceptable SOCKS auth method".into());
+    }
+
+    let mut username = None;
+    if method == USER_PASS {
+        let mut ver = [0u8; 1];
+        stream.read_exact(&mut ver).await?;
+        let user = read_short(stream).await?;
+        let _password = read_short(stream).await?;
+        stream.write_all(&[1, 0]).await?;
+        username = Some(String::from_utf8_lossy(&user).into_owned());
+    }
+
+    let mut req = [0u8; 4];
+    stream.read_exact(&mut req).await?;
+    let host = match req[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        3 => String::from_utf8_lossy(&read_short(stream).await?).into_owned(),
+        4 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            format!("[{}]", Ipv6Addr::from(ip))
+        }
+        other => {
+            stream.write_all(&reply(ADDRESS_NOT_SUPPORTED)).await?;
+            return Err(format!("SOCKS address type {other}").into());
+        }
+    };
+    let port = stream.read_u16().await?;
+    Ok(Request {
+        username: username.filter(|u| !u.is_empty()),
+        command: req[1],
+        address: format!("{host}:{port}"),
+    })
+}
+
+/// A reply with an unspecified bound address (clients ignore it for
+/// CONNECT).
+pub fn reply(code: u8) -> [u8; 10] {
+    [VERSION, code, 0, 1, 0, 0, 0, 0, 0, 0]
+}
+
+/// One length-prefixed field.
+async fn read_short<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<Vec<u8>> {
+    let len = stream.read_u8().await?;
+    let mut field = vec![0u8; len as usize];
+    stream.read_exact(&mut field).await?;
+    Ok(field)
+}
@@ -0,0 +1,105 @@
+//! Zero-copy forwarding for [`relay`](crate::relay::relay) when both ends
+//! are plain sockets: data moves socket → pipe → socket inside the kernel
+//! (`splice(2)`), never through a userspace buffer.
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use tokio::io::{self, Interest};
+
+use crate::ratelimit::SharedBucket;
+use crate::relay::{shape, Io, Socket, CHUNK};
+use crate::stats::Meter;
+
+/// Run `f` once `socket` is ready for `interest`, again each time it would
+/// block.
+async fn ready<R>(
+    socket: Socket<'_>,
+    interest: Interest,
+    f: impl FnMut() -> io::Result<R>,
+) -> io::Result<R> {
+    match socket.io {
+        Io::Tcp(s) => s.async_io(interest, f).await,
+        Io::Unix(s) => s.async_io(interest, f).await,
+    }
+}
+
+/// The two ends of a non-blocking pipe.
+struct Pipe {
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+impl Pipe {
+    fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: pipe2 just opened both, and nothing else owns them
+        Ok(unsafe {
+            Self {
+                read: OwnedFd::from_raw_fd(fds[0]),
+                write: OwnedFd::from_raw_fd(fds[1]),
+            }
+        })
+    }
+}
+
+fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+    let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+    let n = unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            len,
+            flags,
+        )
+    };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(n as usize)
+}
+
+/// One direction, as the buffered pump does it: every chunk is charged
+/// against `limits`, counted on `meter` and reported to either end's
+/// activity.
+pub(crate) async fn pump(
+    from: Socket<'_>,
+    to: Socket<'_>,
+    limits: &[SharedBucket],
+    meter: &Meter,
+) -> io::Result<u64> {
+    let pipe = Pipe::new()?;
+    let mut total = 0u64;
+
+    loop {
+        let n = ready(from, Interest::READABLE, || {
+            splice(from.fd(), pipe.write.as_raw_fd(), CHUNK)
+        })
+        .await?;
+        if n == 0 {
+            to.shutdown_write()?;
+            return Ok(total);
+        }
+        if let Some(activity) = from.activity {
+            activity.sent(n);
+        }
+
+        shape(limits, n).await;
+        let mut left = n;
+        while left > 0 {
+            left -= ready(to, Interest::WRITABLE, || {
+                splice(pipe.read.as_raw_fd(), to.fd(), left)
+            })
+            .await?;
+        }
+        if let Some(activity) = to.activity {
+            activity.received(n);
+        }
+        meter.add(n as u64);
+        total += n as u64;
+    }
+}
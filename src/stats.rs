@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ha::HaStatus;
+
+/// Where the dispatcher publishes live traffic stats for `status`.
+pub const STATS_PATH: &str = "gold-dust-stats.json";
+
+/// Stats older than this are treated as "dispatcher not running".
+const FRESH_SECS: u64 = 5;
+
+/// One line of the dispatcher's traffic log: what a client asked for, where
+/// it went and how it ended.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrafficEntry {
+    /// When the session started.
+    pub unix: u64,
+    pub client: String,
+    pub target: String,
+    /// `tor` / `direct` / `masque`, once chosen.
+    pub egress: Option<String>,
+    /// App profile, from the SOCKS username.
+    #[serde(default)]
+    pub app: Option<String>,
+    /// Local user (login name, else uid), when `[users]` is configured.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// `relayed`, `bad_request`, `rate_limited`, `quota_exhausted`,
+    /// `saturated`, `no_relay`, `chaos_killed`, `not_connect` or `error: ...`.
+    pub outcome: String,
+    /// Audit mode (`--audit`): what enforcement would have done, one of the
+    /// outcomes above or `allowed`; the session itself went direct.
+    #[serde(default)]
+    pub audit: Option<String>,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub duration_ms: u64,
+}
+
+/// Live byte counter for one egress, fed by the relay loop.
+#[derive(Debug, Default)]
+pub struct Meter {
+    bytes: AtomicU64,
+}
+
+impl Meter {
+    pub fn add(&self, n: u64) {
+        self.bytes.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// Usage of one egress (`tor`, `direct`, ...).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EgressUsage {
+    /// Bytes relayed (both directions) since the dispatcher started.
+    pub bytes_total: u64,
+    /// Current throughput in kilobits per second.
+    pub rate_kbps: f64,
+    /// Configured ceiling in kilobits per second, if any.
+    pub limit_kbps: Option<u64>,
+    /// Sessions open right now.
+    #[serde(default)]
+    pub sessions: usize,
+    /// Configured `max_sessions`, if any.
+    #[serde(default)]
+    pub max_sessions: Option<usize>,
+    /// Dials since the dispatcher started, and how many of them overran a
+    /// `[timeouts]` budget.
+    #[serde(default)]
+    pub dials: u64,
+    #[serde(default)]
+    pub overruns: u64,
+    /// Sessions closed for sitting idle (`idle_timeout_secs`).
+    #[serde(default)]
+    pub idle_closed: u64,
+}
+
+impl EgressUsage {
+    /// Current rate as a fraction of the ceiling (0.0–1.0+).
+    pub fn utilization(&self) -> Option<f64> {
+        self.limit_kbps
+            .filter(|&l| l > 0)
+            .map(|l| self.rate_kbps / l as f64)
+    }
+
+    /// Share of dials that overran a budget, once there were any.
+    pub fn overrun_rate(&self) -> Option<f64> {
+        (self.dials > 0).then(|| self.overruns as f64 / self.dials as f64)
+    }
+}
+
+/// Snapshot written to [`STATS_PATH`] by the dispatcher.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrafficSnapshot {
+    pub updated_unix: u64,
+    /// Seconds between snapshots; longer in low power (`[power]`).
+    #[serde(default)]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub low_power: bool,
+    pub egress: BTreeMap<String, EgressUsage>,
+    /// Last warm-standby canary through Tor (`[standby]`).
+    #[serde(default)]
+    pub standby: Option<Canary>,
+    /// Our role in the `[ha]` pair, when one is configured.
+    #[serde(default)]
+    pub ha: Option<HaStatus>,
+}
+
+/// One canary fetch through Tor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Canary {
+    pub unix: u64,
+    pub latency_ms: f64,
+    /// Why it failed; Tor is cold until one succeeds.
+    pub error: Option<String>,
+}
+
+/// Seconds since the Unix epoch.
+pub fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Write via temp file + rename so readers never see a torn file.
+pub fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(tmp, path)
+}
+
+impl TrafficSnapshot {
+    /// Read a snapshot, or `None` if missing/unreadable.
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let text = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        write_atomic(path.as_ref(), &serde_json::to_string_pretty(self)?)
+    }
+
+    /// Was this written recently enough to reflect a running dispatcher?
+    pub fn is_fresh(&self) -> bool {
+        now_unix().saturating_sub(self.updated_unix) <= FRESH_SECS.max(self.interval_secs * 2)
+    }
+}
@@ -0,0 +1,34 @@
+use std::time::{Duration, Instant, SystemTime};
+
+/// Notices that the machine slept: the monotonic clock stands still while
+/// it is suspended, the wall clock doesn't.
+#[derive(Debug)]
+pub struct SleepWatch {
+    mono: Instant,
+    wall: SystemTime,
+    /// Shorter gaps are put down to clock adjustments.
+    min_sleep: Duration,
+}
+
+impl SleepWatch {
+    pub fn new(min_sleep: Duration) -> Self {
+        Self {
+            mono: Instant::now(),
+            wall: SystemTime::now(),
+            min_sleep,
+        }
+    }
+
+    /// How long the machine slept since the last call, if at least
+    /// `min_sleep`. A wall clock stepped forward by as much looks the same.
+    pub fn check(&mut self) -> Option<Duration> {
+        let (mono, wall) = (Instant::now(), SystemTime::now());
+        let awake = mono.duration_since(self.mono);
+        let passed = wall.duration_since(self.wall).unwrap_or_default();
+        self.mono = mono;
+        self.wall = wall;
+        passed
+            .checked_sub(awake)
+            .filter(|slept| *slept >= self.min_sleep)
+    }
+}
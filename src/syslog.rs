@@ -0,0 +1,259 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+use crate::config::{SyslogConfig, SyslogFacility};
+use crate::quota::civil_from_days;
+
+/// Sending one message must not hold up sessions for longer than this.
+const SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Private enterprise number RFC 5612 sets aside for documentation, so the
+/// SD-ID is well-formed without registering one.
+const SD_ID: &str = "gold-dust@32473";
+
+/// Where `[logging.syslog]` sends messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogAddress {
+    Udp(String),
+    /// RFC 6587 octet counting.
+    Tcp(String),
+    /// A local socket such as `/dev/log` (unix only).
+    #[cfg(unix)]
+    Unixgram(PathBuf),
+}
+
+impl FromStr for SyslogAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let with_port = |rest: &str| match rest.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(rest.to_string())
+            }
+            _ => Err(format!("`{s}` needs host:port")),
+        };
+        if let Some(rest) = s.strip_prefix("udp://") {
+            Ok(SyslogAddress::Udp(with_port(rest)?))
+        } else if let Some(rest) = s.strip_prefix("tcp://") {
+            Ok(SyslogAddress::Tcp(with_port(rest)?))
+        } else if let Some(path) = s.strip_prefix("unixgram://") {
+            unixgram(s, path)
+        } else {
+            Err(format!(
+                "`{s}` is not udp://host:port, tcp://host:port or unixgram:///path"
+            ))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn unixgram(s: &str, path: &str) -> Result<SyslogAddress, String> {
+    match path.starts_with('/') {
+        true => Ok(SyslogAddress::Unixgram(PathBuf::from(path))),
+        false => Err(format!("`{s}` needs an absolute path")),
+    }
+}
+
+#[cfg(not(unix))]
+fn unixgram(s: &str, _path: &str) -> Result<SyslogAddress, String> {
+    Err(format!(
+        "`{s}`: no unix sockets on this system, use udp:// or tcp://"
+    ))
+}
+
+#[derive(Debug)]
+enum Transport {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unixgram(UnixDatagram),
+}
+
+/// RFC 5424 sender for the dispatcher's structured logs. Connects lazily and
+/// again after a failed send, so a syslog daemon restart costs one message.
+#[derive(Debug)]
+pub struct Syslog {
+    address: SyslogAddress,
+    transport: Option<Transport>,
+    facility: SyslogFacility,
+    app_name: String,
+    hostname: String,
+    procid: u32,
+    traffic: bool,
+    health: bool,
+    /// The last send failed.
+    failing: bool,
+}
+
+impl Syslog {
+    pub fn new(cfg: &SyslogConfig) -> Result<Self, String> {
+        let hostname = ["/etc/hostname", "/proc/sys/kernel/hostname"]
+            .iter()
+            .find_map(|p| fs::read_to_string(p).ok())
+            .map(|h| h.trim().to_string())
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| "-".to_string());
+        Ok(Self {
+            address: cfg.address.parse()?,
+            transport: None,
+            facility: cfg.facility,
+            app_name: cfg.app_name.clone(),
+            hostname,
+            procid: std::process::id(),
+            traffic: cfg.traffic,
+            health: cfg.health,
+            failing: false,
+        })
+    }
+
+    /// Does this log stream (`traffic`, `health`) go to syslog?
+    pub fn wants(&self, msgid: &str) -> bool {
+        match msgid {
+            "traffic" => self.traffic,
+            "health" => self.health,
+            _ => true,
+        }
+    }
+
+    /// Send `record` as one informational message: its scalar fields (nested
+    /// ones as `outer.inner`) as structured data, the whole as JSON text.
+    pub fn send(&mut self, msgid: &str, record: &Value) -> io::Result<()> {
+        let message = self.format(msgid, record);
+        let sent = self.transmit(message.as_bytes());
+        self.failing = sent.is_err();
+        if self.failing {
+            self.transport = None;
+        }
+        sent
+    }
+
+    pub fn failing(&self) -> bool {
+        self.failing
+    }
+
+    fn transmit(&mut self, message: &[u8]) -> io::Result<()> {
+        if self.transport.is_none() {
+            self.transport = Some(self.connect()?);
+        }
+        match self.transport.as_mut().expect("just connected") {
+            Transport::Udp(socket) => socket.send(message).map(drop),
+            #[cfg(unix)]
+            Transport::Unixgram(socket) => socket.send(message).map(drop),
+            Transport::Tcp(stream) => {
+                stream.write_all(format!("{} ", message.len()).as_bytes())?;
+                stream.write_all(message)
+            }
+        }
+    }
+
+    fn connect(&self) -> io::Result<Transport> {
+        Ok(match &self.address {
+            SyslogAddress::Udp(addr) => {
+                let to = resolve(addr)?;
+                let socket = match to.is_ipv6() {
+                    true => UdpSocket::bind("[::]:0")?,
+                    false => UdpSocket::bind("0.0.0.0:0")?,
+                };
+                socket.connect(to)?;
+                Transport::Udp(socket)
+            }
+            SyslogAddress::Tcp(addr) => {
+                let stream = TcpStream::connect_timeout(&resolve(addr)?, SEND_TIMEOUT)?;
+                stream.set_write_timeout(Some(SEND_TIMEOUT))?;
+                Transport::Tcp(stream)
+            }
+            #[cfg(unix)]
+            SyslogAddress::Unixgram(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                socket.set_write_timeout(Some(SEND_TIMEOUT))?;
+                Transport::Unixgram(socket)
+            }
+        })
+    }
+
+    fn format(&self, msgid: &str, record: &Value) -> String {
+        const INFO: u8 = 6;
+        let mut params = String::new();
+        sd_params(&mut params, "", record);
+        let data = match params.is_empty() {
+            true => "-".to_string(),
+            false => format!("[{SD_ID}{params}]"),
+        };
+        format!(
+            "<{}>1 {} {} {} {} {} {} {}",
+            self.facility.code() * 8 + INFO,
+            timestamp(SystemTime::now()),
+            self.hostname,
+            self.app_name,
+            self.procid,
+            msgid,
+            data,
+            record
+        )
+    }
+}
+
+fn resolve(addr: &str) -> io::Result<std::net::SocketAddr> {
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::other(format!("{addr}: no address")))
+}
+
+/// ` name="value"` for every scalar under `value`. Arrays are left to the
+/// JSON text.
+fn sd_params(out: &mut String, prefix: &str, value: &Value) {
+    let Value::Object(fields) = value else {
+        return;
+    };
+    for (key, field) in fields {
+        // SD-NAME: printable ASCII but `=`, space, `]` and `"`, 32 at most
+        let name: String = format!("{prefix}{key}")
+            .chars()
+            .filter(|c| c.is_ascii_graphic() && !"=]\"".contains(*c))
+            .take(32)
+            .collect();
+        let text = match field {
+            Value::Object(_) => {
+                sd_params(out, &format!("{name}."), field);
+                continue;
+            }
+            Value::Null | Value::Array(_) => continue,
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let escaped = text
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace(']', "\\]");
+        let _ = write!(out, " {name}=\"{escaped}\"");
+    }
+}
+
+/// RFC 3339 in UTC, to the millisecond.
+pub(crate) fn timestamp(at: SystemTime) -> String {
+    let since = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60,
+        since.subsec_millis()
+    )
+}
@@ -0,0 +1,513 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use tokio_socks::TargetAddr;
+
+/// Port assumed when a target has neither a port nor a scheme.
+pub const DEFAULT_PORT: u16 = 443;
+
+/// Host part of a target.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Host {
+    /// Lowercased DNS name without trailing dot.
+    Domain(String),
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+}
+
+impl Host {
+    /// The address, if this host is an IP literal.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Host::Ipv4(ip) => Some(IpAddr::V4(*ip)),
+            Host::Ipv6(ip) => Some(IpAddr::V6(*ip)),
+            Host::Domain(_) => None,
+        }
+    }
+}
+
+impl From<IpAddr> for Host {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => Host::Ipv4(ip),
+            IpAddr::V6(ip) => Host::Ipv6(ip),
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    /// Bare host: `example.com`, `1.2.3.4`, `::1` (no brackets).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Host::Domain(d) => f.write_str(d),
+            Host::Ipv4(ip) => ip.fmt(f),
+            Host::Ipv6(ip) => ip.fmt(f),
+        }
+    }
+}
+
+/// A destination the gateway is asked to reach.
+///
+/// Accepts `host:port`, `1.2.3.4:port`, `[v6]:port`, bare hosts (port
+/// [`DEFAULT_PORT`]) and URLs with an `http://`/`https://` scheme (port
+/// 80/443 unless given; any path is dropped).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Target {
+    pub host: Host,
+    pub port: u16,
+}
+
+/// Why a target string was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetError {
+    Empty,
+    UnknownScheme(String),
+    UnclosedBracket,
+    InvalidIpv6(String),
+    InvalidPort(String),
+    InvalidHost { host: String, reason: &'static str },
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetError::Empty => write!(f, "empty target"),
+            TargetError::UnknownScheme(s) => {
+                write!(f, "unsupported scheme `{s}://` (expected http or https)")
+            }
+            TargetError::UnclosedBracket => write!(f, "IPv6 address is missing its closing `]`"),
+            TargetError::InvalidIpv6(s) => write!(f, "`{s}` is not a valid IPv6 address"),
+            TargetError::InvalidPort(s) => write!(f, "`{s}` is not a valid port (1-65535)"),
+            TargetError::InvalidHost { host, reason } => {
+                write!(f, "invalid host `{host}`: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TargetError {}
+
+impl Target {
+    pub fn new(host: Host, port: u16) -> Self {
+        Self { host, port }
+    }
+
+    /// Literal socket address, if the host is an IP.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        self.host.ip().map(|ip| SocketAddr::new(ip, self.port))
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        matches!(self.host, Host::Ipv6(_))
+    }
+
+    /// SOCKS5 destination: IP literals as addresses, names passed through
+    /// unresolved (socks5h) so the backend does the lookup.
+    pub fn socks_addr(&self) -> TargetAddr<'static> {
+        match self.socket_addr() {
+            Some(addr) => TargetAddr::Ip(addr),
+            None => TargetAddr::Domain(self.host_str().into(), self.port),
+        }
+    }
+
+    /// Host as a plain string (no brackets), for rule matching and dialing.
+    pub fn host_str(&self) -> String {
+        self.host.to_string()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.host {
+            Host::Ipv6(ip) => write!(f, "[{}]:{}", ip, self.port),
+            host => write!(f, "{}:{}", host, self.port),
+        }
+    }
+}
+
+fn parse_port(s: &str) -> Result<u16, TargetError> {
+    match s.parse::<u16>() {
+        Ok(p) if p > 0 => Ok(p),
+        _ => Err(TargetError::InvalidPort(s.to_string())),
+    }
+}
+
+fn parse_domain(s: &str) -> Result<Host, TargetError> {
+    let invalid = |reason| TargetError::InvalidHost {
+        host: s.to_string(),
+        reason,
+    };
+    let name = s.strip_suffix('.').unwrap_or(s).to_ascii_lowercase();
+
+    if name.is_empty() {
+        return Err(invalid("empty host name"));
+    }
+    if name.len() > 253 {
+        return Err(invalid("longer than 253 characters"));
+    }
+    for label in name.split('.') {
+        if label.is_empty() {
+            return Err(invalid("empty label"));
+        }
+        if label.len() > 63 {
+            return Err(invalid("label longer than 63 characters"));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(invalid("label starts or ends with `-`"));
+        }
+        if !label
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(invalid("only letters, digits, `-` and `_` are allowed"));
+        }
+    }
+
+    match name.parse::<Ipv4Addr>() {
+        Ok(ip) => Ok(Host::Ipv4(ip)),
+        Err(_) => Ok(Host::Domain(name)),
+    }
+}
+
+impl FromStr for Target {
+    type Err = TargetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(TargetError::Empty);
+        }
+
+        // Optional scheme, which also picks the default port
+        let (rest, default_port) = match s.split_once("://") {
+            Some((scheme, rest)) => match scheme.to_ascii_lowercase().as_str() {
+                "http" => (rest, 80),
+                "https" => (rest, 443),
+                other => return Err(TargetError::UnknownScheme(other.to_string())),
+            },
+            None => (s, DEFAULT_PORT),
+        };
+
+        // Drop any path / query
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+        if authority.is_empty() {
+            return Err(TargetError::Empty);
+        }
+
+        // [v6] or [v6]:port
+        if let Some(inner) = authority.strip_prefix('[') {
+            let (addr, after) = inner.split_once(']').ok_or(TargetError::UnclosedBracket)?;
+            let ip = addr
+                .parse::<Ipv6Addr>()
+                .map_err(|_| TargetError::InvalidIpv6(addr.to_string()))?;
+            let port = match after {
+                "" => default_port,
+                _ => match after.strip_prefix(':') {
+                    Some(p) => parse_port(p)?,
+                    None => return Err(TargetError::InvalidPort(after.to_string())),
+                },
+            };
+            return Ok(Target::new(Host::Ipv6(ip), port));
+        }
+
+        // Bare IPv6 (more than one colon, no brackets): no port possible
+        if authority.matches(':').count() > 1 {
+            let ip = authority
+                .parse::<Ipv6Addr>()
+                .map_err(|_| TargetError::InvalidIpv6(authority.to_string()))?;
+            return Ok(Target::new(Host::Ipv6(ip), default_port));
+        }
+
+        let (host, port) = match authority.split_once(':') {
+            Some((host, port)) => (host, parse_port(port)?),
+            None => (authority, default_port),
+        };
+        Ok(Target::new(parse_domain(host)?, port))
+    }
+}
+
+impl TryFrom<String> for Target {
+    type Error = TargetError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Target> for String {
+    fn from(t: Target) -> Self {
+        t.to_string()
+    }
+}
+
+/// IPv4 or IPv6 network in CIDR notation (`10.0.0.0/8`, `2001:db8::/32`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        self.addr.is_ipv6()
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// The address as written has bits set below the prefix.
+    pub fn has_host_bits(&self) -> bool {
+        match self.addr {
+            IpAddr::V4(net) => {
+                u32::from(net) & u32::MAX.checked_shr(self.prefix as u32).unwrap_or(0) != 0
+            }
+            IpAddr::V6(net) => {
+                u128::from(net) & u128::MAX.checked_shr(self.prefix as u32).unwrap_or(0) != 0
+            }
+        }
+    }
+
+    /// Is all of `other` inside this network?
+    pub fn covers(&self, other: &Cidr) -> bool {
+        self.prefix <= other.prefix && self.contains(&other.addr)
+    }
+
+    /// Does this network contain `ip`? Families never match each other.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s
+            .split_once('/')
+            .ok_or_else(|| format!("`{s}` is not in CIDR notation (addr/prefix)"))?;
+        let addr: IpAddr = addr
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|_| format!("`{addr}` is not an IP address"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix
+            .parse::<u8>()
+            .ok()
+            .filter(|&p| p <= max)
+            .ok_or_else(|| format!("`{prefix}` is not a valid prefix length (0-{max})"))?;
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Destination ports a backend accepts: `443` or `1024-65535`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let port = |p: &str| {
+            p.trim()
+                .parse::<u16>()
+                .map_err(|_| format!("`{p}` is not a port (0-65535)"))
+        };
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (port(start)?, port(end)?),
+            None => (port(s)?, port(s)?),
+        };
+        if start > end {
+            return Err(format!("port range `{s}` ends before it starts"));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PortRange> for String {
+    fn from(r: PortRange) -> String {
+        r.to_string()
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(s: &str) -> Target {
+        s.parse().expect("valid target")
+    }
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().expect("valid CIDR")
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_host_and_port() {
+        let t = target("Example.COM.:8443");
+        assert_eq!(t.host, Host::Domain("example.com".to_string()));
+        assert_eq!(t.port, 8443);
+        assert_eq!(target("example.com").port, DEFAULT_PORT);
+        assert_eq!(target("http://example.com/path?q").port, 80);
+        assert_eq!(target("https://example.com:444/").port, 444);
+        assert_eq!(
+            target("192.0.2.1:25").host,
+            Host::Ipv4([192, 0, 2, 1].into())
+        );
+        assert_eq!(target("my_host.lan:22").to_string(), "my_host.lan:22");
+    }
+
+    #[test]
+    fn parses_ipv6() {
+        let t = target("[2001:db8::1]:8080");
+        assert_eq!(t.host, Host::Ipv6("2001:db8::1".parse().unwrap()));
+        assert_eq!(t.port, 8080);
+        assert!(t.is_ipv6());
+        assert_eq!(t.to_string(), "[2001:db8::1]:8080");
+        assert_eq!(target("[::1]").port, DEFAULT_PORT);
+        assert_eq!(target("http://[::1]/").port, 80);
+        // Unbracketed: the whole thing is the address
+        assert_eq!(target("2001:db8::1").port, DEFAULT_PORT);
+    }
+
+    #[test]
+    fn rejects_malformed_targets() {
+        let err = |s: &str| s.parse::<Target>().expect_err(s);
+        assert_eq!(err(""), TargetError::Empty);
+        assert_eq!(err("  "), TargetError::Empty);
+        assert_eq!(err("https:///path"), TargetError::Empty);
+        assert_eq!(
+            err("ftp://example.com"),
+            TargetError::UnknownScheme("ftp".into())
+        );
+        assert_eq!(err("[::1"), TargetError::UnclosedBracket);
+        assert_eq!(err("[::g]:80"), TargetError::InvalidIpv6("::g".into()));
+        assert_eq!(err("[::1]80"), TargetError::InvalidPort("80".into()));
+        assert_eq!(err("example.com:0"), TargetError::InvalidPort("0".into()));
+        assert_eq!(
+            err("example.com:65536"),
+            TargetError::InvalidPort("65536".into())
+        );
+        assert_eq!(err("example.com:"), TargetError::InvalidPort("".into()));
+        for host in ["a..b", "-a.com", "a-.com", "a b.com", &"a".repeat(64)] {
+            assert!(
+                matches!(err(host), TargetError::InvalidHost { .. }),
+                "{host}"
+            );
+        }
+        let long = vec!["a".repeat(60); 5].join(".");
+        assert!(matches!(err(&long), TargetError::InvalidHost { .. }));
+    }
+
+    #[test]
+    fn round_trips_through_strings() {
+        for s in ["example.com:443", "192.0.2.1:80", "[2001:db8::1]:22"] {
+            assert_eq!(String::from(target(s)), s);
+        }
+    }
+
+    #[test]
+    fn cidr_prefix_edges() {
+        let all = cidr("0.0.0.0/0");
+        assert!(all.contains(&ip("255.255.255.255")));
+        assert!(!all.contains(&ip("::1")));
+
+        let one = cidr("192.0.2.1/32");
+        assert!(one.contains(&ip("192.0.2.1")));
+        assert!(!one.contains(&ip("192.0.2.2")));
+
+        let all6 = cidr("::/0");
+        assert!(all6.contains(&ip("2001:db8::1")));
+        assert!(!all6.contains(&ip("10.0.0.1")));
+
+        let one6 = cidr("[2001:db8::1]/128");
+        assert_eq!(one6.prefix(), 128);
+        assert!(one6.contains(&ip("2001:db8::1")));
+        assert!(!one6.contains(&ip("2001:db8::2")));
+
+        assert!(cidr("10.0.0.0/8").covers(&cidr("10.1.0.0/16")));
+        assert!(!cidr("10.1.0.0/16").covers(&cidr("10.0.0.0/8")));
+        assert!(cidr("10.0.0.1/8").has_host_bits());
+        assert!(!cidr("10.0.0.0/8").has_host_bits());
+        assert!(!all.has_host_bits() && !one.has_host_bits() && !one6.has_host_bits());
+    }
+
+    #[test]
+    fn rejects_malformed_cidrs() {
+        for s in [
+            "10.0.0.0",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0/8",
+            "10.0.0.0/x",
+            "/8",
+        ] {
+            assert!(s.parse::<Cidr>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn port_ranges() {
+        let r: PortRange = "1024-65535".parse().unwrap();
+        assert!(r.contains(1024) && r.contains(65535) && !r.contains(443));
+        assert_eq!("443".parse::<PortRange>().unwrap().to_string(), "443");
+        assert!("2-1".parse::<PortRange>().is_err());
+        assert!("1-70000".parse::<PortRange>().is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! Bearer tokens for the dispatcher's local HTTP APIs (dashboard, health
+//! feed), so other users on the machine can't switch the egress or feed it
+//! health reports. Each lives in a file only its owner can read, or in the
+//! platform keychain (`keyring:<service>/<account>` for the file).
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use ring::hmac;
+
+use crate::keychain;
+
+/// Default token files, in the state directory: one that may change
+/// things, one that may only read.
+pub const TOKEN_PATH: &str = "gold-dust-api.token";
+pub const READ_TOKEN_PATH: &str = "gold-dust-api-read.token";
+
+/// The token a request must carry.
+pub struct ApiToken {
+    key: hmac::Key,
+    tag: hmac::Tag,
+}
+
+impl ApiToken {
+    /// The token in `path`, or a new random one written there (readable by
+    /// us alone) if there is none. The flag tells whether it was created.
+    pub fn load_or_create(path: &Path) -> Result<(Self, bool), String> {
+        let new_token = || -> String {
+            rand::random::<[u8; 32]>()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect()
+        };
+        let (token, created) = match path.to_str().and_then(keychain::Entry::parse) {
+            Some(entry) => {
+                let entry = entry?;
+                match entry.get()? {
+                    Some(token) => (token.trim().to_string(), false),
+                    None => {
+                        let token = new_token();
+                        entry.set(&token)?;
+                        (token, true)
+                    }
+                }
+            }
+            None => {
+                let err = |e: io::Error| format!("{}: {}", path.display(), e);
+                match fs::read_to_string(path) {
+                    Ok(token) => (token.trim().to_string(), false),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        let token = new_token();
+                        let mut options = OpenOptions::new();
+                        options.write(true).create_new(true);
+                        #[cfg(unix)]
+                        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                        options
+                            .open(path)
+                            .and_then(|mut file| writeln!(file, "{token}"))
+                            .map_err(err)?;
+                        (token, true)
+                    }
+                    Err(e) => return Err(err(e)),
+                }
+            }
+        };
+        if token.is_empty() {
+            return Err(format!("{}: empty", path.display()));
+        }
+        Ok((Self::new(&token), created))
+    }
+
+    pub fn new(token: &str) -> Self {
+        // Compared through an HMAC under a throwaway key, so how long a
+        // comparison takes says nothing about the token
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &rand::random::<[u8; 32]>());
+        let tag = hmac::sign(&key, token.as_bytes());
+        Self { key, tag }
+    }
+
+    pub fn matches(&self, candidate: &str) -> bool {
+        hmac::verify(&self.key, candidate.as_bytes(), self.tag.as_ref()).is_ok()
+    }
+
+    /// Whether a request carries the token: as `Authorization: Bearer`, or
+    /// as `token=` in the query, for browsers' WebSockets and event streams,
+    /// which can't set headers.
+    pub fn allows(&self, authorization: Option<&str>, query: Option<&str>) -> bool {
+        presented(authorization, query).is_some_and(|token| self.matches(token))
+    }
+}
+
+/// The token a request carries, right or wrong, where
+/// [`ApiToken::allows`] looks for it.
+pub fn presented<'a>(authorization: Option<&'a str>, query: Option<&'a str>) -> Option<&'a str> {
+    let bearer = authorization.and_then(|value| value.strip_prefix("Bearer "));
+    let queried = query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("token="));
+    bearer.or(queried).map(str::trim)
+}
+
+/// Read the token in `path`, for clients (`monitor`, `token`).
+pub fn read(path: &Path) -> Result<String, String> {
+    if let Some(value) = path.to_str().filter(|p| p.starts_with(keychain::SCHEME)) {
+        return keychain::resolve(value).map(|token| token.trim().to_string());
+    }
+    fs::read_to_string(path)
+        .map(|token| token.trim().to_string())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
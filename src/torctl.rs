@@ -0,0 +1,520 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+use crate::config::{RuleConfig, TorControlConfig};
+use crate::matcher::RuleMatcher;
+use crate::target::Host;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// A relay fingerprint as the control port writes it (`$` and 40 uppercase
+/// hex digits), or `None` if `fp` isn't one.
+pub fn fingerprint(fp: &str) -> Option<String> {
+    let hex = fp.strip_prefix('$').unwrap_or(fp);
+    (hex.len() == 40 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| format!("${}", hex.to_ascii_uppercase()))
+}
+
+/// An authenticated connection to Tor's control port (control-spec.txt).
+pub struct Control {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    /// Async events (`650`) that arrived while waiting for a reply.
+    events: VecDeque<String>,
+}
+
+impl Control {
+    pub async fn connect(cfg: &TorControlConfig) -> Result<Self, BoxError> {
+        let (reader, writer) = TcpStream::connect(cfg.address).await?.into_split();
+        let mut control = Self {
+            reader: BufReader::new(reader),
+            writer,
+            events: VecDeque::new(),
+        };
+        let auth = match (&cfg.password, &cfg.cookie_file) {
+            (Some(password), _) => format!(
+                "AUTHENTICATE \"{}\"",
+                password.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            (None, Some(path)) => {
+                let cookie = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                let hex: String = cookie.iter().map(|b| format!("{:02X}", b)).collect();
+                format!("AUTHENTICATE {}", hex)
+            }
+            (None, None) => "AUTHENTICATE".to_string(),
+        };
+        control.command(&auth).await?;
+        Ok(control)
+    }
+
+    /// Send `command`; the lines of its reply (data blocks included), or
+    /// the error Tor answered with.
+    pub async fn command(&mut self, command: &str) -> Result<Vec<String>, BoxError> {
+        Ok(self.try_command(command).await??)
+    }
+
+    /// Like `command`, but a refusal is the inner error, so callers can tell
+    /// it from a lost connection.
+    pub async fn try_command(
+        &mut self,
+        command: &str,
+    ) -> Result<Result<Vec<String>, String>, BoxError> {
+        self.writer
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+        loop {
+            let (code, lines) = self.read_reply().await?;
+            if code == "650" {
+                self.events.extend(lines.into_iter().next());
+            } else if code.starts_with('2') {
+                return Ok(Ok(lines));
+            } else {
+                // Only the verb: AUTHENTICATE carries the secret
+                let verb = command.split(' ').next().unwrap_or(command);
+                return Ok(Err(format!(
+                    "{} refused: {} {}",
+                    verb,
+                    code,
+                    lines.join(" ")
+                )));
+            }
+        }
+    }
+
+    /// The next async event (its first line), after `SETEVENTS`.
+    pub async fn event(&mut self) -> Result<String, BoxError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            let (code, lines) = self.read_reply().await?;
+            if code == "650" {
+                return Ok(lines.into_iter().next().unwrap_or_default());
+            }
+        }
+    }
+
+    /// One reply: its status code and lines, without the `NNN-` prefixes.
+    async fn read_reply(&mut self) -> Result<(String, Vec<String>), BoxError> {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            let (Some(code), Some(sep), Some(rest)) =
+                (line.get(..3), line.get(3..4), line.get(4..))
+            else {
+                return Err(format!("bad control port line {:?}", line).into());
+            };
+            lines.push(rest.to_string());
+            match sep {
+                " " => return Ok((code.to_string(), lines)),
+                // Data block up to a lone `.`, with leading dots doubled
+                "+" => loop {
+                    let data = self.read_line().await?;
+                    if data == "." {
+                        break;
+                    }
+                    lines.push(data.strip_prefix('.').unwrap_or(&data).to_string());
+                },
+                _ => {}
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<String, BoxError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err("control port closed the connection".into());
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Tor sessions pinned to exits by `tor_exits` in `[[rules]]`.
+///
+/// A session registers under the local port of its connection to Tor's
+/// SOCKS port, which Tor reports as the stream's `SOURCE_ADDR`, so `attach`
+/// can tell its stream from everyone else's.
+pub struct ExitPins {
+    matcher: RuleMatcher,
+    /// Fingerprints per pinning rule, in the matcher's order.
+    exits: Vec<Vec<String>>,
+    sessions: Mutex<HashMap<u16, Session>>,
+    attached: AtomicBool,
+}
+
+struct Session {
+    exits: Vec<String>,
+    /// Why none of the exits could be used, once `attach` gave up.
+    failed: Option<String>,
+}
+
+impl ExitPins {
+    /// `None` if no rule pins exits.
+    pub fn from_rules(rules: &[RuleConfig]) -> Result<Option<Self>, String> {
+        let pinning: Vec<&RuleConfig> = rules.iter().filter(|r| r.tor_exits.is_some()).collect();
+        if pinning.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            matcher: RuleMatcher::compile(pinning.iter().map(|r| r.host.as_str()))?
+                .with_priorities(pinning.iter().map(|r| r.priority).collect()),
+            exits: pinning
+                .iter()
+                .map(|r| {
+                    r.tor_exits
+                        .iter()
+                        .flatten()
+                        .filter_map(|fp| fingerprint(fp))
+                        .collect()
+                })
+                .collect(),
+            sessions: Mutex::new(HashMap::new()),
+            attached: AtomicBool::new(false),
+        }))
+    }
+
+    /// Exits pinned for `host` by the first pinning rule that matches it.
+    pub fn exits_for(&self, host: &Host, resolved: &[IpAddr]) -> Option<&[String]> {
+        self.matcher
+            .first_match(host, resolved)
+            .map(|i| self.exits[i].as_slice())
+    }
+
+    /// `attach` holds a control connection, so pins are honoured.
+    pub fn attached(&self) -> bool {
+        self.attached.load(Ordering::SeqCst)
+    }
+
+    /// Pin the stream Tor will see from local `port` to `exits`, until the
+    /// returned guard is dropped.
+    pub fn register(&self, port: u16, exits: &[String]) -> Registered<'_> {
+        let session = Session {
+            exits: exits.to_vec(),
+            failed: None,
+        };
+        self.lock().insert(port, session);
+        Registered { pins: self, port }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u16, Session>> {
+        self.sessions.lock().expect("exit pins poisoned")
+    }
+}
+
+/// None of a session's pinned exits could carry it.
+#[derive(Debug)]
+pub struct ExitsUnavailable(pub String);
+
+impl std::fmt::Display for ExitsUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no pinned exit available: {}", self.0)
+    }
+}
+
+impl Error for ExitsUnavailable {}
+
+/// A session's registration with `ExitPins`.
+pub struct Registered<'a> {
+    pins: &'a ExitPins,
+    port: u16,
+}
+
+impl Registered<'_> {
+    /// Why the pinned exits were unavailable, if `attach` gave up on them.
+    pub fn failure(&self) -> Option<String> {
+        self.pins.lock().get(&self.port)?.failed.clone()
+    }
+}
+
+impl Drop for Registered<'_> {
+    fn drop(&mut self) {
+        self.pins.lock().remove(&self.port);
+    }
+}
+
+/// A pinned stream in flight.
+struct Stream {
+    port: u16,
+    exits: Vec<String>,
+    /// Index of the exit to try next.
+    next: usize,
+    /// Exit of the circuit it was last put on.
+    current: Option<String>,
+    /// What went wrong with each exit tried.
+    tried: Vec<String>,
+}
+
+/// A built general-purpose circuit, from `GETINFO circuit-status`.
+struct Circuit {
+    id: String,
+    /// Fingerprints, guard first.
+    path: Vec<String>,
+}
+
+/// Attach Tor's streams for as long as `control` lasts: pinned sessions to
+/// a circuit through one of their exits, every other stream back to Tor.
+/// When none of a session's exits can be used, its stream is closed, so its
+/// SOCKS connect fails, and `Registered::failure` says why.
+///
+/// Tor leaves every new stream unattached meanwhile
+/// (`__LeaveStreamsUnattached`); see `release`.
+pub async fn attach(mut control: Control, pins: &ExitPins) -> Result<(), BoxError> {
+    control.command("SETEVENTS STREAM CIRC").await?;
+    control
+        .command("SETCONF __LeaveStreamsUnattached=1")
+        .await?;
+    // Streams left waiting while no attacher was connected
+    let status = control.command("GETINFO stream-status").await?;
+    for line in status
+        .iter()
+        .map(|l| l.trim_start_matches("stream-status="))
+    {
+        if let [id, "NEW" | "NEWRESOLVE" | "DETACHED", "0", ..] =
+            line.split(' ').collect::<Vec<_>>()[..]
+        {
+            control
+                .try_command(&format!("ATTACHSTREAM {id} 0"))
+                .await?
+                .ok();
+        }
+    }
+    pins.attached.store(true, Ordering::SeqCst);
+    let result = Attacher::default().run(&mut control, pins).await;
+    pins.attached.store(false, Ordering::SeqCst);
+    result
+}
+
+/// Hand stream attachment back to Tor, e.g. on shutdown.
+pub async fn release(cfg: &TorControlConfig) -> Result<(), BoxError> {
+    let mut control = Control::connect(cfg).await?;
+    control
+        .command("RESETCONF __LeaveStreamsUnattached")
+        .await?;
+    Ok(())
+}
+
+/// After the machine slept: wake Tor should it have gone dormant, and move
+/// new streams off circuits that died meanwhile. Returns whether Tor still
+/// counted a circuit as established.
+pub async fn wake(cfg: &TorControlConfig) -> Result<bool, BoxError> {
+    let mut control = Control::connect(cfg).await?;
+    let established = control
+        .try_command("GETINFO status/circuit-established")
+        .await?
+        .is_ok_and(|lines| lines.iter().any(|l| l == "status/circuit-established=1"));
+    control.command("SIGNAL ACTIVE").await?;
+    control.command("SIGNAL NEWNYM").await?;
+    Ok(established)
+}
+
+#[derive(Default)]
+struct Attacher {
+    /// Pinned streams by stream ID.
+    streams: HashMap<String, Stream>,
+    /// Pinned streams waiting on a circuit being built, by circuit ID.
+    building: HashMap<String, Vec<String>>,
+}
+
+impl Attacher {
+    async fn run(&mut self, control: &mut Control, pins: &ExitPins) -> Result<(), BoxError> {
+        loop {
+            let event = control.event().await?;
+            let words: Vec<&str> = event.split(' ').collect();
+            let field = |key: &str| {
+                words
+                    .iter()
+                    .find_map(|w| w.strip_prefix(key)?.strip_prefix('='))
+                    .unwrap_or("")
+            };
+            match words[..] {
+                // STREAM <id> <status> <circuit> <target> [key=value ...]
+                ["STREAM", id, "NEW", ..] => {
+                    let port = field("SOURCE_ADDR").rsplit(':').next().unwrap_or("");
+                    let pinned = port.parse().ok().and_then(|port| {
+                        let sessions = pins.lock();
+                        let session = sessions.get(&port)?;
+                        Some(Stream {
+                            port,
+                            exits: session.exits.clone(),
+                            next: 0,
+                            current: None,
+                            tried: Vec::new(),
+                        })
+                    });
+                    match pinned {
+                        Some(stream) => {
+                            self.streams.insert(id.to_string(), stream);
+                            self.route(control, id, pins).await?;
+                        }
+                        None => {
+                            control
+                                .try_command(&format!("ATTACHSTREAM {id} 0"))
+                                .await?
+                                .ok();
+                        }
+                    }
+                }
+                ["STREAM", id, "DETACHED", ..] if self.streams.contains_key(id) => {
+                    self.failed(id, format!("detached ({})", field("REASON")));
+                    self.route(control, id, pins).await?;
+                }
+                ["STREAM", id, "NEWRESOLVE" | "DETACHED", ..] => {
+                    control
+                        .try_command(&format!("ATTACHSTREAM {id} 0"))
+                        .await?
+                        .ok();
+                }
+                ["STREAM", id, "SUCCEEDED" | "FAILED" | "CLOSED", ..] => {
+                    self.streams.remove(id);
+                }
+                // CIRC <id> <status> [path] [key=value ...]
+                ["CIRC", id, "BUILT", ..] => {
+                    for stream in self.building.remove(id).unwrap_or_default() {
+                        let attach = format!("ATTACHSTREAM {stream} {id}");
+                        if let Err(e) = control.try_command(&attach).await? {
+                            self.failed(&stream, e);
+                            self.route(control, &stream, pins).await?;
+                        }
+                    }
+                }
+                ["CIRC", id, "FAILED" | "CLOSED", ..] => {
+                    for stream in self.building.remove(id).unwrap_or_default() {
+                        self.failed(&stream, format!("circuit failed ({})", field("REASON")));
+                        self.route(control, &stream, pins).await?;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Note why stream `id`'s current exit didn't work out.
+    fn failed(&mut self, id: &str, why: String) {
+        if let Some(stream) = self.streams.get_mut(id) {
+            let exit = stream.current.take().unwrap_or_default();
+            stream.tried.push(format!("{}: {}", exit, why));
+        }
+    }
+
+    /// Put pinned stream `id` on a circuit through its next usable exit:
+    /// a built one ending there, else a new one borrowing the guard and
+    /// middle of a built one (so Tor's guard choice stands). Closes the
+    /// stream once no exit is left.
+    async fn route(
+        &mut self,
+        control: &mut Control,
+        id: &str,
+        pins: &ExitPins,
+    ) -> Result<(), BoxError> {
+        let Some(stream) = self.streams.get_mut(id) else {
+            return Ok(());
+        };
+        let circuits = built_circuits(&control.command("GETINFO circuit-status").await?);
+        while let Some(exit) = stream.exits.get(stream.next).cloned() {
+            stream.next += 1;
+            let listed = control
+                .try_command(&format!("GETINFO ns/id/{}", &exit[1..]))
+                .await?;
+            let running = listed.as_ref().is_ok_and(|lines| {
+                lines
+                    .iter()
+                    .any(|l| l.starts_with("s ") && l.split(' ').any(|flag| flag == "Running"))
+            });
+            if !running {
+                let why = match listed {
+                    Ok(_) => "not running",
+                    Err(_) => "not in the consensus",
+                };
+                stream.tried.push(format!("{}: {}", exit, why));
+                continue;
+            }
+
+            if let Some(circuit) = circuits.iter().find(|c| c.path.last() == Some(&exit)) {
+                let attach = format!("ATTACHSTREAM {} {}", id, circuit.id);
+                match control.try_command(&attach).await? {
+                    Ok(_) => return Ok(()),
+                    Err(e) => {
+                        stream.tried.push(format!("{}: {}", exit, e));
+                        continue;
+                    }
+                }
+            }
+            let Some(borrowed) = circuits
+                .iter()
+                .find(|c| c.path.len() >= 3 && !c.path[..2].contains(&exit))
+            else {
+                stream.tried.push(format!(
+                    "{}: no built circuit to take a guard from yet",
+                    exit
+                ));
+                continue;
+            };
+            let extend = format!(
+                "EXTENDCIRCUIT 0 {},{},{} purpose=general",
+                borrowed.path[0], borrowed.path[1], exit
+            );
+            match control.try_command(&extend).await? {
+                Ok(lines) => {
+                    let circuit = lines
+                        .first()
+                        .and_then(|l| l.strip_prefix("EXTENDED "))
+                        .unwrap_or_default();
+                    stream.current = Some(exit);
+                    self.building
+                        .entry(circuit.to_string())
+                        .or_default()
+                        .push(id.to_string());
+                    return Ok(());
+                }
+                Err(e) => stream.tried.push(format!("{}: {}", exit, e)),
+            }
+        }
+
+        let stream = self.streams.remove(id).expect("routed stream is tracked");
+        let reason = stream.tried.join("; ");
+        if let Some(session) = pins.lock().get_mut(&stream.port) {
+            session.failed = Some(reason.clone());
+        }
+        // REASON_MISC; refused if the client already gave up
+        control
+            .try_command(&format!("CLOSESTREAM {id} 1"))
+            .await?
+            .ok();
+        Ok(())
+    }
+}
+
+/// Built general-purpose circuits in a `GETINFO circuit-status` reply.
+fn built_circuits(reply: &[String]) -> Vec<Circuit> {
+    reply
+        .iter()
+        .map(|l| l.trim_start_matches("circuit-status="))
+        .filter_map(|line| {
+            // <id> BUILT <path> BUILD_FLAGS=... PURPOSE=GENERAL ...
+            let words: Vec<&str> = line.split(' ').collect();
+            let [id, "BUILT", path, ref rest @ ..] = words[..] else {
+                return None;
+            };
+            let general = rest.contains(&"PURPOSE=GENERAL")
+                && !rest
+                    .iter()
+                    .any(|w| w.contains("IS_INTERNAL") || w.contains("ONEHOP"));
+            let path: Vec<String> = path
+                .split(',')
+                .filter_map(|hop| fingerprint(hop.split(['~', '=']).next()?))
+                .collect();
+            general.then(|| Circuit {
+                id: id.to_string(),
+                path,
+            })
+        })
+        .collect()
+}
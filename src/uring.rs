@@ -0,0 +1,279 @@
+//! An io_uring data plane for [`relay`](crate::relay::relay): both
+//! directions of every session are queued on one ring and reaped by one
+//! thread, instead of a readiness wakeup plus a `read`/`write` per chunk.
+
+/// Whether this build has the io_uring data plane (`io-uring` feature,
+/// Linux only).
+pub const AVAILABLE: bool = cfg!(all(target_os = "linux", feature = "io-uring"));
+
+/// Submissions the ring takes at once.
+pub const ENTRIES: u32 = 4096;
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use imp::Ring;
+
+/// Stand-in so callers need no `cfg`: never constructed.
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+pub struct Ring(());
+
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+impl Ring {
+    pub fn start(_entries: u32) -> Result<Self, String> {
+        Err("built without the `io-uring` feature (Linux only)".to_string())
+    }
+
+    pub async fn relay<I: crate::relay::Upstream, U: crate::relay::Upstream>(
+        &self,
+        inbound: I,
+        outbound: U,
+        limits: &[crate::ratelimit::SharedBucket],
+        meter: &crate::stats::Meter,
+    ) -> tokio::io::Result<(u64, u64)> {
+        crate::relay::relay(inbound, outbound, limits, meter).await
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod imp {
+    use std::collections::HashMap;
+    use std::ops::Range;
+    use std::os::fd::RawFd;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use io_uring::{opcode, squeue, types, IoUring};
+    use tokio::io;
+    use tokio::sync::oneshot;
+
+    use crate::ratelimit::SharedBucket;
+    use crate::relay::{shape, Socket, Upstream, CHUNK};
+    use crate::stats::Meter;
+
+    /// `user_data` of cancellations, whose completions nobody waits for.
+    const CANCEL: u64 = 0;
+
+    /// An operation the kernel holds: its buffer, kept alive until it
+    /// completes, and who to hand the result and the buffer back to.
+    struct InFlight {
+        buf: Vec<u8>,
+        done: oneshot::Sender<(i32, Vec<u8>)>,
+    }
+
+    struct Shared {
+        ring: IoUring,
+        /// Only one submitter may touch the submission queue at a time.
+        submit: Mutex<()>,
+        in_flight: Mutex<HashMap<u64, InFlight>>,
+        next: AtomicU64,
+    }
+
+    /// One io_uring and the thread reaping its completions. Any task may
+    /// submit to it.
+    #[derive(Clone)]
+    pub struct Ring(Arc<Shared>);
+
+    impl Ring {
+        /// Set up a ring taking `entries` submissions at once.
+        pub fn start(entries: u32) -> Result<Self, String> {
+            let ring = IoUring::new(entries).map_err(|e| format!("io_uring_setup: {e}"))?;
+            let shared = Arc::new(Shared {
+                ring,
+                submit: Mutex::new(()),
+                in_flight: Mutex::new(HashMap::new()),
+                next: AtomicU64::new(CANCEL + 1),
+            });
+            let reaper = shared.clone();
+            std::thread::Builder::new()
+                .name("io-uring".to_string())
+                .spawn(move || reap(&reaper))
+                .map_err(|e| format!("io_uring reaper: {e}"))?;
+            Ok(Self(shared))
+        }
+
+        /// Like [`relay`](crate::relay::relay), through the ring when both
+        /// ends are plain sockets.
+        pub async fn relay<I: Upstream, U: Upstream>(
+            &self,
+            inbound: I,
+            outbound: U,
+            limits: &[SharedBucket],
+            meter: &Meter,
+        ) -> io::Result<(u64, u64)> {
+            if let (Some(client), Some(upstream)) =
+                (Socket::of(inbound.as_any()), Socket::of(outbound.as_any()))
+            {
+                return tokio::try_join!(
+                    self.pump(client, upstream, limits, meter),
+                    self.pump(upstream, client, limits, meter),
+                );
+            }
+            crate::relay::relay(inbound, outbound, limits, meter).await
+        }
+
+        /// One direction, as the buffered pump does it: every chunk is
+        /// charged against `limits`, counted on `meter` and reported to
+        /// either end's activity.
+        async fn pump(
+            &self,
+            from: Socket<'_>,
+            to: Socket<'_>,
+            limits: &[SharedBucket],
+            meter: &Meter,
+        ) -> io::Result<u64> {
+            let mut buf = vec![0u8; CHUNK];
+            let mut total = 0u64;
+
+            loop {
+                let (read, returned) = self.recv(from.fd(), buf).await;
+                buf = returned;
+                let n = read?;
+                if n == 0 {
+                    to.shutdown_write()?;
+                    return Ok(total);
+                }
+                if let Some(activity) = from.activity {
+                    activity.sent(n);
+                }
+
+                shape(limits, n).await;
+                let mut done = 0;
+                while done < n {
+                    let (wrote, returned) = self.send(to.fd(), buf, done..n).await;
+                    buf = returned;
+                    match wrote? {
+                        0 => return Err(io::ErrorKind::WriteZero.into()),
+                        wrote => done += wrote,
+                    }
+                }
+                if let Some(activity) = to.activity {
+                    activity.received(n);
+                }
+                meter.add(n as u64);
+                total += n as u64;
+            }
+        }
+
+        async fn recv(&self, fd: RawFd, buf: Vec<u8>) -> (io::Result<usize>, Vec<u8>) {
+            self.run(buf, |buf| {
+                opcode::Recv::new(types::Fd(fd), buf.as_mut_ptr(), buf.len() as u32).build()
+            })
+            .await
+        }
+
+        async fn send(
+            &self,
+            fd: RawFd,
+            buf: Vec<u8>,
+            range: Range<usize>,
+        ) -> (io::Result<usize>, Vec<u8>) {
+            self.run(buf, |buf| {
+                let bytes = &buf[range];
+                opcode::Send::new(types::Fd(fd), bytes.as_ptr(), bytes.len() as u32)
+                    .flags(libc::MSG_NOSIGNAL)
+                    .build()
+            })
+            .await
+        }
+
+        /// Submit the operation `entry` builds on `buf`, wait for it, and
+        /// hand `buf` back. Dropped before it completes, it is cancelled.
+        async fn run(
+            &self,
+            buf: Vec<u8>,
+            entry: impl FnOnce(&mut Vec<u8>) -> squeue::Entry,
+        ) -> (io::Result<usize>, Vec<u8>) {
+            let id = self.0.next.fetch_add(1, Ordering::Relaxed);
+            let (done, result) = oneshot::channel();
+            let mut op = InFlight { buf, done };
+            // The entry points into the buffer's heap allocation, which
+            // stays put while `in_flight` owns it.
+            let entry = entry(&mut op.buf).user_data(id);
+            self.in_flight().insert(id, op);
+            if let Err(e) = self.push(&entry) {
+                let op = self.in_flight().remove(&id).expect("op just queued");
+                return (Err(e), op.buf);
+            }
+
+            let pending = Pending { ring: self, id };
+            let result = result.await;
+            std::mem::forget(pending);
+            match result {
+                Ok((res, buf)) if res < 0 => (Err(io::Error::from_raw_os_error(-res)), buf),
+                Ok((res, buf)) => (Ok(res as usize), buf),
+                Err(_) => (Err(io::Error::other("io_uring reaper stopped")), Vec::new()),
+            }
+        }
+
+        fn push(&self, entry: &squeue::Entry) -> io::Result<()> {
+            let _submitting = self.0.submit.lock().expect("io_uring poisoned");
+            {
+                // SAFETY: the lock above makes this the only handle on the
+                // submission queue, and whatever the entry points into is
+                // owned by `in_flight` until its completion is reaped
+                let mut sq = unsafe { self.0.ring.submission_shared() };
+                if unsafe { sq.push(entry) }.is_err() {
+                    sq.sync();
+                    self.0.ring.submit()?;
+                    sq.sync();
+                    unsafe { sq.push(entry) }
+                        .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+                }
+            }
+            // Queued either way: if this enter fails, the reaper's next one
+            // submits it
+            let _ = self.0.ring.submit();
+            Ok(())
+        }
+
+        fn in_flight(&self) -> std::sync::MutexGuard<'_, HashMap<u64, InFlight>> {
+            self.0.in_flight.lock().expect("io_uring poisoned")
+        }
+    }
+
+    /// Cancels an operation whose future was dropped while the kernel still
+    /// had it (a session closed for idleness, say); its buffer is freed when
+    /// the cancelled completion is reaped.
+    struct Pending<'a> {
+        ring: &'a Ring,
+        id: u64,
+    }
+
+    impl Drop for Pending<'_> {
+        fn drop(&mut self) {
+            let cancel = opcode::AsyncCancel::new(self.id).build().user_data(CANCEL);
+            let _ = self.ring.push(&cancel);
+        }
+    }
+
+    /// Wait for completions and hand each back to its submitter, until the
+    /// ring fails.
+    fn reap(shared: &Shared) {
+        loop {
+            if let Err(e) = shared.ring.submit_and_wait(1) {
+                match e.raw_os_error() {
+                    Some(libc::EINTR) | Some(libc::EBUSY) => {}
+                    _ => {
+                        eprintln!("io_uring: {e}; sessions on the ring will fail");
+                        // The kernel may still write into these buffers, so
+                        // leak them; dropping the senders fails the waiters
+                        let mut in_flight = shared.in_flight.lock().expect("io_uring poisoned");
+                        for (_, op) in in_flight.drain() {
+                            std::mem::forget(op.buf);
+                        }
+                        return;
+                    }
+                }
+            }
+            // SAFETY: this thread is the only one reading completions
+            let completed: Vec<(u64, i32)> = unsafe { shared.ring.completion_shared() }
+                .map(|cqe| (cqe.user_data(), cqe.result()))
+                .collect();
+            let mut in_flight = shared.in_flight.lock().expect("io_uring poisoned");
+            for (id, res) in completed {
+                if let Some(op) = in_flight.remove(&id) {
+                    let _ = op.done.send((res, op.buf));
+                }
+            }
+        }
+    }
+}